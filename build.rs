@@ -0,0 +1,45 @@
+//! Generates the gRPC service stubs for `proto/gateway.proto`. The messages are
+//! written by hand in `src/grpc/proto.rs`, so building needs no `protoc`; keep
+//! both in sync with the `.proto` file.
+
+use tonic_build::manual::{Builder, Method, MethodBuilder, Service};
+
+fn method(name: &str, route: &str, input: &str, output: &str) -> MethodBuilder {
+    Method::builder()
+        .name(name)
+        .route_name(route)
+        .input_type(format!("crate::grpc::proto::{input}"))
+        .output_type(format!("crate::grpc::proto::{output}"))
+        .codec_path("tonic::codec::ProstCodec")
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    let documents = Service::builder()
+        .name("Documents")
+        .package("gateway.v1")
+        .method(
+            method("find", "Find", "FindRequest", "Document")
+                .server_streaming()
+                .build(),
+        )
+        .method(method("insert", "Insert", "InsertRequest", "InsertResponse").build())
+        .method(method("update", "Update", "UpdateRequest", "UpdateResponse").build())
+        .method(method("delete", "Delete", "DeleteRequest", "DeleteResponse").build())
+        .method(
+            method(
+                "aggregate",
+                "Aggregate",
+                "AggregateRequest",
+                "AggregateResponse",
+            )
+            .build(),
+        )
+        .method(
+            method("watch", "Watch", "WatchRequest", "ChangeEvent")
+                .server_streaming()
+                .build(),
+        )
+        .build();
+    Builder::new().compile(&[documents]);
+}
@@ -0,0 +1,685 @@
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use mongodb::bson::{self, Bson, DateTime, Document};
+use mongodb::Collection;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::auth::Identity;
+use crate::cluster::Clusters;
+use crate::config::{AuditConfig, AuditOverflow, AuditSink};
+use crate::error::ApiError;
+
+pub const CORRELATION_ID_HEADER: &str = "x-correlation-id";
+
+/// Events waiting for the writer. What a write does once it is full is set
+/// by [`AuditOverflow`].
+const QUEUE_CAPACITY: usize = 10_000;
+/// Events written per file append or `insertMany`.
+const MAX_BATCH: usize = 256;
+const REDACTED: &str = "<redacted>";
+
+/// Identifies one request across the response, logs and audit records. Taken
+/// from the `X-Correlation-Id` header when the client sends one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorrelationId(pub Arc<str>);
+
+#[derive(Debug, Error)]
+pub enum AuditError {
+    #[error("failed to open audit log `{}`: {source}", path.display())]
+    Open { path: PathBuf, source: io::Error },
+    #[error("invalid audit collection: {0}")]
+    Collection(String),
+}
+
+/// Who made a request, for the audit record.
+#[derive(Debug, Clone, Default)]
+pub struct AuditContext {
+    correlation_id: Option<Arc<str>>,
+    identity: Option<Arc<str>>,
+    client: Option<SocketAddr>,
+}
+
+impl AuditContext {
+    /// Context for requests that do not come through the HTTP router.
+    pub fn new(
+        correlation_id: Option<Arc<str>>,
+        identity: Option<&Identity>,
+        client: Option<SocketAddr>,
+    ) -> Self {
+        Self {
+            correlation_id,
+            identity: identity.map(|identity| identity.name.clone()),
+            client,
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuditContext
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(AuditContext {
+            correlation_id: parts
+                .extensions
+                .get::<CorrelationId>()
+                .map(|id| id.0.clone()),
+            identity: parts
+                .extensions
+                .get::<Identity>()
+                .map(|identity| identity.name.clone()),
+            client: parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| *addr),
+        })
+    }
+}
+
+enum Message {
+    Event(Document),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Hands audit events to a background writer so recording never waits on the
+/// sink. Cheap to clone; a disabled auditor records nothing.
+#[derive(Clone, Default)]
+pub struct Auditor {
+    shared: Option<Arc<Shared>>,
+}
+
+struct Shared {
+    sender: mpsc::Sender<Message>,
+    overflow: AuditOverflow,
+    dropped: AtomicU64,
+    rejected: AtomicU64,
+}
+
+/// Audit queue counters, for `GET /api/v1/audit/stats`.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditStats {
+    pub enabled: bool,
+    pub overflow: &'static str,
+    pub queued: usize,
+    pub dropped: u64,
+    pub rejected: u64,
+}
+
+impl Auditor {
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Opens the configured sink and spawns its writer. The log file is opened
+    /// here so an unwritable path fails startup.
+    pub async fn start(config: &AuditConfig, clusters: &Clusters) -> Result<Self, AuditError> {
+        let sink = match &config.sink {
+            None => return Ok(Self::disabled()),
+            Some(AuditSink::File(path)) => Sink::File(
+                FileSink::open(path.clone(), config.max_file_bytes, config.max_files)
+                    .await
+                    .map_err(|source| AuditError::Open {
+                        path: path.clone(),
+                        source,
+                    })?,
+            ),
+            Some(AuditSink::Collection {
+                database,
+                collection,
+            }) => {
+                let (_, client) = clusters
+                    .resolve(None, database, Some(collection))
+                    .map_err(|err| AuditError::Collection(err.body().details.clone()))?;
+                Sink::Collection(client.database(database).collection(collection))
+            }
+        };
+        Ok(Self::spawn(
+            sink,
+            Redactor::new(&config.redact_fields),
+            config.overflow,
+        ))
+    }
+
+    fn spawn(sink: Sink, redactor: Redactor, overflow: AuditOverflow) -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(write_events(sink, redactor, receiver));
+        Self::with_sender(sender, overflow)
+    }
+
+    fn with_sender(sender: mpsc::Sender<Message>, overflow: AuditOverflow) -> Self {
+        Self {
+            shared: Some(Arc::new(Shared {
+                sender,
+                overflow,
+                dropped: AtomicU64::new(0),
+                rejected: AtomicU64::new(0),
+            })),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.shared.is_some()
+    }
+
+    pub fn stats(&self) -> AuditStats {
+        match &self.shared {
+            Some(shared) => AuditStats {
+                enabled: true,
+                overflow: shared.overflow.as_str(),
+                queued: shared.sender.max_capacity() - shared.sender.capacity(),
+                dropped: shared.dropped.load(Ordering::Relaxed),
+                rejected: shared.rejected.load(Ordering::Relaxed),
+            },
+            None => AuditStats {
+                enabled: false,
+                overflow: AuditOverflow::default().as_str(),
+                queued: 0,
+                dropped: 0,
+                rejected: 0,
+            },
+        }
+    }
+
+    /// Starts a record for one write against `database.collection`. Unless
+    /// overflow is set to drop, this claims the event's queue slot first, so
+    /// a full queue delays or refuses the write rather than losing its record.
+    pub async fn begin(
+        &self,
+        context: &AuditContext,
+        operation: &'static str,
+        cluster: &str,
+        database: &str,
+        collection: &str,
+    ) -> Result<AuditEntry, ApiError> {
+        let Some(shared) = &self.shared else {
+            return Ok(AuditEntry(None));
+        };
+        let permit = match shared.overflow {
+            AuditOverflow::Drop => None,
+            AuditOverflow::Block => Some(
+                shared
+                    .sender
+                    .clone()
+                    .reserve_owned()
+                    .await
+                    .map_err(|_| ApiError::unavailable("audit log is unavailable"))?,
+            ),
+            AuditOverflow::Reject => match shared.sender.clone().try_reserve_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    shared.rejected.fetch_add(1, Ordering::Relaxed);
+                    return Err(ApiError::unavailable(
+                        "audit log is backlogged; write refused",
+                    ));
+                }
+            },
+        };
+        let mut event = Document::new();
+        event.insert("operation", operation);
+        event.insert(
+            "correlation_id",
+            context.correlation_id.as_deref().map(Bson::from),
+        );
+        event.insert("identity", context.identity.as_deref().map(Bson::from));
+        event.insert(
+            "client",
+            context.client.map(|addr| Bson::from(addr.ip().to_string())),
+        );
+        event.insert("cluster", cluster);
+        event.insert("database", database);
+        event.insert("collection", collection);
+        Ok(AuditEntry(Some(Box::new(Pending {
+            auditor: self.clone(),
+            event,
+            permit: Mutex::new(permit),
+        }))))
+    }
+
+    /// Waits until every event recorded so far has been handed to the sink.
+    pub async fn flush(&self) {
+        let Some(shared) = &self.shared else {
+            return;
+        };
+        let (ack, done) = oneshot::channel();
+        if shared.sender.send(Message::Flush(ack)).await.is_ok() {
+            let _ = done.await;
+        }
+    }
+
+    fn record(&self, event: Document, permit: Option<mpsc::OwnedPermit<Message>>) {
+        let Some(shared) = &self.shared else {
+            return;
+        };
+        if let Some(permit) = permit {
+            permit.send(Message::Event(event));
+            return;
+        }
+        if shared.sender.try_send(Message::Event(event)).is_err() {
+            let dropped = shared.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            // Logging every drop would flood the log exactly when the sink is slow
+            if dropped.is_power_of_two() {
+                tracing::warn!(
+                    target = "audit",
+                    dropped,
+                    "audit queue full; dropping events"
+                );
+            }
+        }
+    }
+}
+
+/// An audit record being built for one write. Recording consumes the entry.
+pub struct AuditEntry(Option<Box<Pending>>);
+
+struct Pending {
+    auditor: Auditor,
+    event: Document,
+    /// Queue slot claimed by [`Auditor::begin`].
+    permit: Mutex<Option<mpsc::OwnedPermit<Message>>>,
+}
+
+impl AuditEntry {
+    pub fn disabled() -> Self {
+        Self(None)
+    }
+
+    pub fn filter(self, filter: &Document) -> Self {
+        self.with("filter", || filter.clone().into())
+    }
+
+    pub fn update(self, update: &Document) -> Self {
+        self.with("update", || update.clone().into())
+    }
+
+    pub fn replacement(self, replacement: &Document) -> Self {
+        self.with("replacement", || replacement.clone().into())
+    }
+
+    pub fn documents(self, documents: &[Document]) -> Self {
+        self.with("documents", || {
+            Bson::Array(documents.iter().cloned().map(Bson::from).collect())
+        })
+    }
+
+    fn with(mut self, key: &str, value: impl FnOnce() -> Bson) -> Self {
+        if let Some(pending) = self.0.as_mut() {
+            pending.event.insert(key, value());
+        }
+        self
+    }
+
+    /// Records the write's outcome as reported to the client.
+    pub fn succeeded(self, result: &impl Serialize) {
+        if let Some(pending) = self.0 {
+            let result = bson::to_bson(result).unwrap_or(Bson::Null);
+            pending.finish(200, "result", result);
+        }
+    }
+
+    /// Records a failed write and passes the error through.
+    pub fn failed(&self, error: ApiError) -> ApiError {
+        if let Some(pending) = &self.0 {
+            let body = error.body();
+            let details = bson::doc! { "error": body.error, "details": &body.details };
+            Pending {
+                auditor: pending.auditor.clone(),
+                event: pending.event.clone(),
+                permit: Mutex::new(pending.permit.lock().unwrap().take()),
+            }
+            .finish(error.status().as_u16(), "error", details.into());
+        }
+        error
+    }
+}
+
+impl Pending {
+    fn finish(mut self, status: u16, key: &str, value: Bson) {
+        self.event.insert("timestamp", DateTime::now());
+        self.event.insert("status", i32::from(status));
+        self.event.insert(key, value);
+        let permit = self.permit.into_inner().unwrap();
+        self.auditor.record(self.event, permit);
+    }
+}
+
+/// Replaces the values of configured field names anywhere in a document,
+/// including dotted paths such as `profile.ssn`.
+struct Redactor {
+    fields: HashSet<String>,
+}
+
+impl Redactor {
+    fn new(fields: &[String]) -> Self {
+        Self {
+            fields: fields.iter().cloned().collect(),
+        }
+    }
+
+    fn redact_event(&self, event: &mut Document) {
+        if self.fields.is_empty() {
+            return;
+        }
+        for key in ["filter", "update", "replacement", "documents"] {
+            if let Some(value) = event.get_mut(key) {
+                self.redact(value);
+            }
+        }
+    }
+
+    fn redact(&self, value: &mut Bson) {
+        match value {
+            Bson::Document(document) => {
+                for (key, value) in document.iter_mut() {
+                    let field = key.rsplit('.').next().unwrap_or(key);
+                    if self.fields.contains(field) {
+                        *value = Bson::String(REDACTED.to_string());
+                    } else {
+                        self.redact(value);
+                    }
+                }
+            }
+            Bson::Array(values) => values.iter_mut().for_each(|value| self.redact(value)),
+            _ => {}
+        }
+    }
+}
+
+enum Sink {
+    File(FileSink),
+    Collection(Collection<Document>),
+}
+
+impl Sink {
+    async fn write(&mut self, events: Vec<Document>) -> Result<(), String> {
+        match self {
+            Sink::File(file) => {
+                let mut lines = Vec::new();
+                for event in events {
+                    let json = Bson::Document(event).into_relaxed_extjson();
+                    serde_json::to_writer(&mut lines, &json).map_err(|err| err.to_string())?;
+                    lines.push(b'\n');
+                }
+                file.append(&lines).await.map_err(|err| err.to_string())
+            }
+            Sink::Collection(collection) => collection
+                .insert_many(events, None)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string()),
+        }
+    }
+}
+
+/// Append-only JSON-lines file rotated to `<path>.1`, `<path>.2`, ... by size.
+struct FileSink {
+    path: PathBuf,
+    file: tokio::fs::File,
+    size: u64,
+    max_bytes: u64,
+    max_files: u32,
+}
+
+impl FileSink {
+    async fn open(path: PathBuf, max_bytes: u64, max_files: u32) -> io::Result<Self> {
+        let file = open_append(&path).await?;
+        let size = file.metadata().await?.len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_bytes,
+            max_files,
+        })
+    }
+
+    async fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if self.size > 0 && self.size + bytes.len() as u64 > self.max_bytes {
+            self.rotate().await?;
+        }
+        self.file.write_all(bytes).await?;
+        self.file.flush().await?;
+        self.size += bytes.len() as u64;
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> io::Result<()> {
+        for index in (1..self.max_files).rev() {
+            match tokio::fs::rename(self.rotated(index), self.rotated(index + 1)).await {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        tokio::fs::rename(&self.path, self.rotated(1)).await?;
+        self.file = open_append(&self.path).await?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotated(&self, index: u32) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{index}"));
+        PathBuf::from(name)
+    }
+}
+
+async fn open_append(path: &Path) -> io::Result<tokio::fs::File> {
+    tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+}
+
+async fn write_events(mut sink: Sink, redactor: Redactor, mut receiver: mpsc::Receiver<Message>) {
+    while let Some(message) = receiver.recv().await {
+        let mut events = Vec::new();
+        let mut flushes = Vec::new();
+        let mut next = Some(message);
+        while let Some(message) = next.take() {
+            match message {
+                Message::Event(mut event) => {
+                    redactor.redact_event(&mut event);
+                    events.push(event);
+                }
+                Message::Flush(ack) => flushes.push(ack),
+            }
+            if events.len() < MAX_BATCH {
+                next = receiver.try_recv().ok();
+            }
+        }
+        if !events.is_empty() {
+            let count = events.len();
+            if let Err(err) = sink.write(events).await {
+                tracing::error!(
+                    target = "audit",
+                    error = %err,
+                    events = count as u64,
+                    "failed to write audit events"
+                );
+            }
+        }
+        for ack in flushes {
+            let _ = ack.send(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+
+    fn context() -> AuditContext {
+        AuditContext {
+            correlation_id: Some(Arc::from("req-1")),
+            identity: Some(Arc::from("batch-job")),
+            client: Some("10.0.0.7:5000".parse().unwrap()),
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("hello_rust_{}_audit_{name}", std::process::id()));
+        for index in 0..4 {
+            let mut rotated = path.clone().into_os_string();
+            if index > 0 {
+                rotated.push(format!(".{index}"));
+            }
+            std::fs::remove_file(PathBuf::from(rotated)).ok();
+        }
+        path
+    }
+
+    #[test]
+    fn redacts_configured_fields_at_any_depth() {
+        let redactor = Redactor::new(&["password".to_string(), "ssn".to_string()]);
+        let mut event = doc! {
+            "filter": { "profile.ssn": "123", "name": "ada" },
+            "update": { "$set": { "password": "hunter2", "tags": [{ "ssn": "456" }] } },
+            "identity": "password",
+        };
+        redactor.redact_event(&mut event);
+        assert_eq!(
+            event,
+            doc! {
+                "filter": { "profile.ssn": REDACTED, "name": "ada" },
+                "update": { "$set": { "password": REDACTED, "tags": [{ "ssn": REDACTED }] } },
+                "identity": "password",
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn disabled_auditor_builds_nothing() {
+        let entry = Auditor::disabled()
+            .begin(&context(), "insert_one", "default", "app", "users")
+            .await
+            .unwrap()
+            .filter(&doc! { "a": 1 });
+        assert!(entry.0.is_none());
+    }
+
+    #[tokio::test]
+    async fn full_queue_rejects_or_drops_by_overflow() {
+        let begin = |auditor: &Auditor| {
+            let auditor = auditor.clone();
+            async move {
+                auditor
+                    .begin(&context(), "insert_one", "default", "app", "users")
+                    .await
+            }
+        };
+
+        let (sender, _receiver) = mpsc::channel(1);
+        let auditor = Auditor::with_sender(sender, AuditOverflow::Reject);
+        let held = begin(&auditor).await.expect("slot available");
+        let err = begin(&auditor).await.err().expect("queue full");
+        assert_eq!(err.status().as_u16(), 503);
+        held.succeeded(&doc! {});
+        assert_eq!(auditor.stats().queued, 1);
+        assert_eq!(auditor.stats().rejected, 1);
+
+        let (sender, _receiver) = mpsc::channel(1);
+        let auditor = Auditor::with_sender(sender, AuditOverflow::Drop);
+        begin(&auditor).await.unwrap().succeeded(&doc! {});
+        begin(&auditor).await.unwrap().succeeded(&doc! {});
+        assert_eq!(auditor.stats().dropped, 1);
+    }
+
+    #[tokio::test]
+    async fn full_queue_blocks_until_a_slot_frees() {
+        let (sender, mut receiver) = mpsc::channel(1);
+        let auditor = Auditor::with_sender(sender, AuditOverflow::Block);
+        auditor
+            .begin(&context(), "insert_one", "default", "app", "users")
+            .await
+            .unwrap()
+            .succeeded(&doc! {});
+        let context = context();
+        let waiting = auditor.begin(&context, "insert_one", "default", "app", "users");
+        tokio::pin!(waiting);
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(20), &mut waiting)
+                .await
+                .is_err()
+        );
+        receiver.recv().await.expect("first event");
+        waiting.await.unwrap().succeeded(&doc! {});
+        assert_eq!(auditor.stats().dropped, 0);
+    }
+
+    #[tokio::test]
+    async fn writes_json_lines_with_outcome() {
+        let path = temp_path("events.jsonl");
+        let sink = FileSink::open(path.clone(), 1024 * 1024, 2).await.unwrap();
+        let auditor = Auditor::spawn(
+            Sink::File(sink),
+            Redactor::new(&["password".into()]),
+            AuditOverflow::Block,
+        );
+
+        auditor
+            .begin(&context(), "update_one", "default", "app", "users")
+            .await
+            .unwrap()
+            .filter(&doc! { "_id": 1 })
+            .update(&doc! { "$set": { "password": "hunter2" } })
+            .succeeded(&doc! { "matched_count": 1, "modified_count": 1 });
+        let entry = auditor
+            .begin(&context(), "delete_many", "default", "app", "users")
+            .await
+            .unwrap()
+            .filter(&doc! {});
+        let err = entry.failed(ApiError::driver("not primary"));
+        assert_eq!(err.status().as_u16(), 502);
+        auditor.flush().await;
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["operation"], "update_one");
+        assert_eq!(lines[0]["identity"], "batch-job");
+        assert_eq!(lines[0]["correlation_id"], "req-1");
+        assert_eq!(lines[0]["client"], "10.0.0.7");
+        assert_eq!(lines[0]["update"]["$set"]["password"], REDACTED);
+        assert_eq!(lines[0]["result"]["modified_count"], 1);
+        assert_eq!(lines[1]["status"], 502);
+        assert_eq!(lines[1]["error"]["error"], "driver_error");
+        std::fs::remove_file(path).ok();
+    }
+
+    #[tokio::test]
+    async fn rotates_files_by_size() {
+        let path = temp_path("rotate.jsonl");
+        let mut sink = FileSink::open(path.clone(), 10, 2).await.unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            sink.append(line.as_bytes()).await.unwrap();
+        }
+        let read = |suffix: &str| {
+            let mut name = path.clone().into_os_string();
+            name.push(suffix);
+            std::fs::read_to_string(PathBuf::from(name)).ok()
+        };
+        assert_eq!(read("").as_deref(), Some("fourth\n"));
+        assert_eq!(read(".1").as_deref(), Some("third\n"));
+        assert_eq!(read(".2").as_deref(), Some("second\n"));
+        assert_eq!(read(".3"), None);
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::config::AuthConfig;
+use crate::error::ApiError;
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Caller resolved from its API key or client certificate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub name: Arc<str>,
+    pub roles: Arc<[String]>,
+}
+
+impl Identity {
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|candidate| candidate == role)
+    }
+}
+
+/// Subject of the certificate a client presented over mutual TLS. Only
+/// certificates that passed chain verification are recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCertificate {
+    /// Full subject, e.g. `CN=batch-job, O=Acme`.
+    pub subject: Arc<str>,
+    pub common_name: Option<Arc<str>>,
+}
+
+impl ClientCertificate {
+    /// Reads the subject from a DER-encoded certificate.
+    pub fn from_der(der: &[u8]) -> Option<Self> {
+        let (_, certificate) = x509_parser::parse_x509_certificate(der).ok()?;
+        let subject = certificate.subject();
+        let common_name = subject
+            .iter_common_name()
+            .next()
+            .and_then(|name| name.as_str().ok())
+            .map(Arc::from);
+        Some(Self {
+            subject: Arc::from(subject.to_string()),
+            common_name,
+        })
+    }
+}
+
+/// Maps configured API keys and client certificate subjects to identities.
+#[derive(Debug, Default)]
+pub struct Authenticator {
+    keys: HashMap<String, Identity>,
+    subjects: HashMap<String, Identity>,
+}
+
+impl Authenticator {
+    pub fn new(config: &AuthConfig) -> Self {
+        let identity = |name: &str, roles: &[String]| Identity {
+            name: Arc::from(name),
+            roles: roles.to_vec().into(),
+        };
+        let keys = config
+            .api_keys
+            .iter()
+            .map(|entry| (entry.key.clone(), identity(&entry.identity, &entry.roles)))
+            .collect();
+        let subjects = config
+            .client_certs
+            .iter()
+            .map(|entry| {
+                (
+                    entry.subject.trim().to_string(),
+                    identity(&entry.identity, &entry.roles),
+                )
+            })
+            .collect();
+        Self { keys, subjects }
+    }
+
+    /// Authentication is only enforced once at least one key or certificate
+    /// subject is configured.
+    pub fn is_enabled(&self) -> bool {
+        !self.keys.is_empty() || !self.subjects.is_empty()
+    }
+
+    /// Resolves the caller, preferring a mapped client certificate over the API
+    /// key. Returns `Ok(None)` when authentication is disabled.
+    pub fn authenticate(
+        &self,
+        api_key: Option<&str>,
+        certificate: Option<&ClientCertificate>,
+    ) -> Result<Option<Identity>, ApiError> {
+        if !self.is_enabled() {
+            return Ok(None);
+        }
+        if let Some(identity) = certificate.and_then(|certificate| self.by_subject(certificate)) {
+            return Ok(Some(identity));
+        }
+        let api_key = api_key
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .ok_or_else(|| match certificate {
+                Some(certificate) => ApiError::unauthorized(format!(
+                    "client certificate `{}` is not mapped to an identity",
+                    certificate.subject
+                )),
+                None => ApiError::unauthorized("missing api key"),
+            })?;
+        self.keys
+            .get(api_key)
+            .cloned()
+            .map(Some)
+            .ok_or_else(|| ApiError::unauthorized("invalid api key"))
+    }
+
+    fn by_subject(&self, certificate: &ClientCertificate) -> Option<Identity> {
+        certificate
+            .common_name
+            .as_deref()
+            .and_then(|name| self.subjects.get(name))
+            .or_else(|| self.subjects.get(certificate.subject.as_ref()))
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ApiKeyConfig, ClientCertConfig};
+
+    fn authenticator() -> Authenticator {
+        Authenticator::new(&AuthConfig {
+            api_keys: vec![ApiKeyConfig {
+                key: "k-123".into(),
+                identity: "batch-job".into(),
+                roles: vec!["writer".into()],
+            }],
+            client_certs: vec![ClientCertConfig {
+                subject: "reporting".into(),
+                identity: "reporting-service".into(),
+                roles: vec!["reader".into()],
+            }],
+        })
+    }
+
+    fn certificate(common_name: Option<&str>, subject: &str) -> ClientCertificate {
+        ClientCertificate {
+            subject: Arc::from(subject),
+            common_name: common_name.map(Arc::from),
+        }
+    }
+
+    #[test]
+    fn disabled_without_keys() {
+        let authenticator = Authenticator::new(&AuthConfig::default());
+        assert_eq!(authenticator.authenticate(None, None).unwrap(), None);
+    }
+
+    #[test]
+    fn resolves_identity_for_known_key() {
+        let identity = authenticator()
+            .authenticate(Some("k-123"), None)
+            .unwrap()
+            .expect("identity");
+        assert_eq!(identity.name.as_ref(), "batch-job");
+        assert!(identity.has_role("writer"));
+        assert!(!identity.has_role("admin"));
+    }
+
+    #[test]
+    fn rejects_missing_and_unknown_keys() {
+        let authenticator = authenticator();
+        let missing = authenticator.authenticate(None, None).expect_err("missing");
+        assert_eq!(missing.status().as_u16(), 401);
+        let unknown = authenticator
+            .authenticate(Some("nope"), None)
+            .expect_err("unknown");
+        assert_eq!(unknown.status().as_u16(), 401);
+    }
+
+    #[test]
+    fn maps_client_certificate_subjects() {
+        let authenticator = authenticator();
+        let by_name = authenticator
+            .authenticate(None, Some(&certificate(Some("reporting"), "CN=reporting")))
+            .unwrap()
+            .expect("identity");
+        assert_eq!(by_name.name.as_ref(), "reporting-service");
+        assert!(by_name.has_role("reader"));
+
+        // An unmapped certificate still allows falling back to an API key
+        let unmapped = certificate(Some("intruder"), "CN=intruder");
+        let err = authenticator
+            .authenticate(None, Some(&unmapped))
+            .expect_err("unmapped");
+        assert_eq!(err.status().as_u16(), 401);
+        let identity = authenticator
+            .authenticate(Some("k-123"), Some(&unmapped))
+            .unwrap()
+            .expect("identity");
+        assert_eq!(identity.name.as_ref(), "batch-job");
+    }
+
+    #[test]
+    fn matches_full_subject() {
+        let authenticator = Authenticator::new(&AuthConfig {
+            api_keys: Vec::new(),
+            client_certs: vec![ClientCertConfig {
+                subject: "CN=etl, O=Acme".into(),
+                identity: "etl".into(),
+                roles: Vec::new(),
+            }],
+        });
+        assert!(authenticator.is_enabled());
+        let identity = authenticator
+            .authenticate(None, Some(&certificate(Some("etl"), "CN=etl, O=Acme")))
+            .unwrap();
+        assert_eq!(identity.unwrap().name.as_ref(), "etl");
+    }
+}
@@ -0,0 +1,476 @@
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::header::CACHE_CONTROL;
+use axum::http::request::Parts;
+use dashmap::DashMap;
+use futures::StreamExt;
+use mongodb::bson::{Bson, Document};
+use mongodb::Collection;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::Instant;
+use tokio::sync::oneshot;
+use tokio::time::Duration;
+
+use crate::cluster::NamespacePattern;
+use crate::config::CacheConfig;
+
+/// Response header telling whether a read was served from the cache.
+pub const CACHE_STATUS_HEADER: &str = "x-cache";
+/// Delay before a failed change stream is reopened.
+const WATCH_RETRY: Duration = Duration::from_secs(5);
+
+/// Configured response caches; each namespace gets its own on first use.
+#[derive(Debug, Default)]
+pub struct ResponseCache {
+    rules: Vec<(NamespacePattern, CacheConfig)>,
+    namespaces: DashMap<String, Arc<NamespaceCache>>,
+}
+
+impl ResponseCache {
+    pub fn new(caches: &[CacheConfig]) -> Self {
+        Self {
+            rules: caches
+                .iter()
+                .map(|config| (NamespacePattern::parse(&config.namespace), config.clone()))
+                .collect(),
+            namespaces: DashMap::new(),
+        }
+    }
+
+    /// The cache for the namespace called `name`, or `None` when caching is
+    /// not enabled for it.
+    pub fn namespace(
+        &self,
+        name: &str,
+        database: &str,
+        collection: &str,
+    ) -> Option<Arc<NamespaceCache>> {
+        if let Some(cache) = self.namespaces.get(name) {
+            return Some(cache.clone());
+        }
+        let (_, config) = self
+            .rules
+            .iter()
+            .find(|(pattern, _)| pattern.matches(database, Some(collection)))?;
+        let cache = self
+            .namespaces
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(NamespaceCache::new(config.clone())));
+        Some(cache.clone())
+    }
+
+    /// Drops every cached response for the namespace called `name`.
+    pub fn invalidate(&self, name: &str) {
+        if let Some(cache) = self.namespaces.get(name) {
+            cache.invalidate();
+        }
+    }
+
+    pub fn stats(&self) -> Vec<NamespaceCacheStats> {
+        let mut stats: Vec<_> = self
+            .namespaces
+            .iter()
+            .map(|entry| entry.value().stats(entry.key()))
+            .collect();
+        stats.sort_by(|a, b| a.namespace.cmp(&b.namespace));
+        stats
+    }
+}
+
+/// Cached results of one namespace, bounded by a TTL and an entry count.
+#[derive(Debug)]
+pub struct NamespaceCache {
+    config: CacheConfig,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    invalidations: AtomicU64,
+    watching: AtomicBool,
+    /// Dropped with the cache, which stops its change stream.
+    stop_watch: Mutex<Option<oneshot::Sender<()>>>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    by_key: HashMap<String, Entry>,
+    /// Keys by insertion order, oldest first.
+    order: BTreeMap<u64, String>,
+    next_sequence: u64,
+    /// Bumped by every invalidation so reads that started before a write
+    /// cannot store what they fetched.
+    generation: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    documents: Arc<[Document]>,
+    expires_at: Instant,
+    sequence: u64,
+}
+
+impl NamespaceCache {
+    fn new(config: CacheConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::default(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            invalidations: AtomicU64::new(0),
+            watching: AtomicBool::new(false),
+            stop_watch: Mutex::default(),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<Arc<[Document]>> {
+        let mut entries = self.lock();
+        let found = match entries.by_key.get(key) {
+            Some(entry) if entry.expires_at > Instant::now() => Some(entry.documents.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        };
+        let counter = if found.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    /// Taken before querying and passed to [`NamespaceCache::insert`].
+    pub fn generation(&self) -> u64 {
+        self.lock().generation
+    }
+
+    /// Caches `documents` unless the namespace was invalidated since
+    /// `generation` or the result is too large.
+    pub fn insert(&self, key: String, generation: u64, documents: &[Document]) {
+        if documents.len() > self.config.max_documents {
+            return;
+        }
+        let mut entries = self.lock();
+        if entries.generation != generation {
+            return;
+        }
+        entries.remove(&key);
+        while entries.by_key.len() >= self.config.max_entries {
+            let Some((_, oldest)) = entries.order.pop_first() else {
+                break;
+            };
+            entries.by_key.remove(&oldest);
+        }
+        let sequence = entries.next_sequence;
+        entries.next_sequence += 1;
+        entries.order.insert(sequence, key.clone());
+        entries.by_key.insert(
+            key,
+            Entry {
+                documents: documents.into(),
+                expires_at: Instant::now() + self.config.ttl(),
+                sequence,
+            },
+        );
+    }
+
+    pub fn invalidate(&self) {
+        let mut entries = self.lock();
+        entries.by_key.clear();
+        entries.order.clear();
+        entries.generation += 1;
+        self.invalidations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Whether the caller should start the change stream for this cache;
+    /// returns `true` at most once, and only when one is configured.
+    pub fn claim_watch(&self) -> bool {
+        self.config.change_stream && !self.watching.swap(true, Ordering::Relaxed)
+    }
+
+    fn stats(&self, namespace: &str) -> NamespaceCacheStats {
+        NamespaceCacheStats {
+            namespace: namespace.to_string(),
+            entries: self.lock().by_key.len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Entries> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Entries {
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.by_key.remove(key) {
+            self.order.remove(&entry.sequence);
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct NamespaceCacheStats {
+    pub namespace: String,
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub invalidations: u64,
+}
+
+/// Invalidates `cache` on every change to `collection`, including changes
+/// made outside the gateway, until the cache is dropped.
+pub fn watch(cache: &Arc<NamespaceCache>, namespace: String, collection: Collection<Document>) {
+    let (stop, mut stopped) = oneshot::channel::<()>();
+    *cache
+        .stop_watch
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(stop);
+    let cache = Arc::downgrade(cache);
+    tokio::spawn(async move {
+        loop {
+            let opened = tokio::select! {
+                _ = &mut stopped => return,
+                opened = collection.watch(None, None) => opened,
+            };
+            match opened {
+                Ok(mut changes) => {
+                    // Changes made while no stream was open went unnoticed
+                    if !invalidate(&cache) {
+                        return;
+                    }
+                    loop {
+                        let change = tokio::select! {
+                            _ = &mut stopped => return,
+                            change = changes.next() => change,
+                        };
+                        match change {
+                            Some(Ok(_)) if invalidate(&cache) => {}
+                            Some(Ok(_)) => return,
+                            Some(Err(err)) => {
+                                tracing::warn!(
+                                    target = "cache",
+                                    namespace = %namespace,
+                                    error = %err,
+                                    "change stream failed; reopening"
+                                );
+                                break;
+                            }
+                            None => break,
+                        }
+                    }
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        target = "cache",
+                        namespace = %namespace,
+                        error = %err,
+                        "failed to open change stream; retrying"
+                    );
+                }
+            }
+            if !invalidate(&cache) {
+                return;
+            }
+            tokio::select! {
+                _ = &mut stopped => return,
+                _ = tokio::time::sleep(WATCH_RETRY) => {}
+            }
+        }
+    });
+}
+
+/// `false` once the cache is gone.
+fn invalidate(cache: &Weak<NamespaceCache>) -> bool {
+    cache.upgrade().map(|cache| cache.invalidate()).is_some()
+}
+
+/// Cache key for a read. Filters are normalized so that equivalent queries
+/// written in a different key order share an entry.
+pub fn key(operation: &str, filter: &Document, options: &impl Debug) -> String {
+    format!("{operation}:{:?}:{options:?}", normalize(filter))
+}
+
+/// Sorts keys whose order does not change a query's meaning: fields ANDed
+/// together and operators on one field. Embedded documents matched by
+/// equality keep their order.
+fn normalize(filter: &Document) -> Document {
+    let mut entries: Vec<_> = filter.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+        .into_iter()
+        .map(|(key, value)| (key.clone(), normalize_value(key, value)))
+        .collect()
+}
+
+fn normalize_value(key: &str, value: &Bson) -> Bson {
+    match value {
+        Bson::Array(branches) if matches!(key, "$and" | "$or" | "$nor") => Bson::Array(
+            branches
+                .iter()
+                .map(|branch| match branch {
+                    Bson::Document(branch) => Bson::Document(normalize(branch)),
+                    other => other.clone(),
+                })
+                .collect(),
+        ),
+        Bson::Document(filter) if key == "$elemMatch" => Bson::Document(normalize(filter)),
+        Bson::Document(operators)
+            if !operators.is_empty() && operators.keys().all(|key| key.starts_with('$')) =>
+        {
+            Bson::Document(normalize(operators))
+        }
+        other => other.clone(),
+    }
+}
+
+/// `Cache-Control` request directives: `no-cache` skips cached responses and
+/// `no-store` keeps the fresh response out of the cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    pub no_cache: bool,
+    pub no_store: bool,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CacheControl
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let mut control = CacheControl::default();
+        let directives = parts
+            .headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|directive| directive.trim().to_ascii_lowercase());
+        for directive in directives {
+            match directive.as_str() {
+                "no-cache" | "max-age=0" => control.no_cache = true,
+                "no-store" => control.no_store = true,
+                _ => {}
+            }
+        }
+        Ok(control)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+    use mongodb::bson::doc;
+
+    fn config(max_entries: usize) -> CacheConfig {
+        CacheConfig {
+            namespace: "catalog.products".to_string(),
+            ttl_ms: 60_000,
+            max_entries,
+            max_documents: 2,
+            change_stream: false,
+        }
+    }
+
+    #[test]
+    fn caches_only_configured_namespaces() {
+        let cache = ResponseCache::new(&[config(10)]);
+        assert!(cache
+            .namespace("catalog.products", "catalog", "products")
+            .is_some());
+        assert!(cache
+            .namespace("catalog.orders", "catalog", "orders")
+            .is_none());
+    }
+
+    #[test]
+    fn counts_hits_and_evicts_oldest() {
+        let cache = NamespaceCache::new(config(2));
+        let generation = cache.generation();
+        cache.insert("a".to_string(), generation, &[doc! { "n": 1 }]);
+        cache.insert("b".to_string(), generation, &[doc! { "n": 2 }]);
+        cache.insert("c".to_string(), generation, &[doc! { "n": 3 }]);
+        assert!(cache.get("a").is_none());
+        assert_eq!(cache.get("c").unwrap()[0], doc! { "n": 3 });
+        // Results above `max_documents` are not stored
+        cache.insert("d".to_string(), generation, &[doc! {}, doc! {}, doc! {}]);
+        assert!(cache.get("d").is_none());
+
+        let stats = cache.stats("catalog.products");
+        assert_eq!((stats.entries, stats.hits, stats.misses), (2, 1, 2));
+    }
+
+    #[test]
+    fn invalidation_discards_entries_and_stale_inserts() {
+        let cache = NamespaceCache::new(config(10));
+        let before = cache.generation();
+        cache.insert("a".to_string(), before, &[doc! { "n": 1 }]);
+        cache.invalidate();
+        assert!(cache.get("a").is_none());
+        // A read that began before the write must not repopulate the cache
+        cache.insert("a".to_string(), before, &[doc! { "n": 1 }]);
+        assert!(cache.get("a").is_none());
+        cache.insert("a".to_string(), cache.generation(), &[doc! { "n": 2 }]);
+        assert!(cache.get("a").is_some());
+        assert_eq!(cache.stats("catalog.products").invalidations, 1);
+    }
+
+    #[test]
+    fn expired_entries_are_misses() {
+        let cache = NamespaceCache::new(CacheConfig {
+            ttl_ms: 1,
+            ..config(10)
+        });
+        cache.insert("a".to_string(), cache.generation(), &[doc! {}]);
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert!(cache.get("a").is_none());
+        assert_eq!(cache.stats("catalog.products").entries, 0);
+    }
+
+    #[test]
+    fn keys_ignore_insignificant_order() {
+        let options = Some(5);
+        let a = doc! { "price": { "$gte": 1, "$lt": 9 }, "tag": "x" };
+        let b = doc! { "tag": "x", "price": { "$lt": 9, "$gte": 1 } };
+        assert_eq!(key("find", &a, &options), key("find", &b, &options));
+        // Embedded documents compared by equality are order sensitive
+        let c = doc! { "size": { "w": 1, "h": 2 } };
+        let d = doc! { "size": { "h": 2, "w": 1 } };
+        assert_ne!(key("find", &c, &options), key("find", &d, &options));
+        assert_ne!(key("find", &a, &options), key("find_one", &a, &options));
+        assert_ne!(key("find", &a, &options), key("find", &a, &Some(6)));
+    }
+
+    #[tokio::test]
+    async fn parses_cache_control_directives() {
+        let request = Request::builder()
+            .header(CACHE_CONTROL, "No-Cache, max-age=60")
+            .header(CACHE_CONTROL, "no-store")
+            .body(())
+            .unwrap();
+        let (mut parts, _) = request.into_parts();
+        let control = CacheControl::from_request_parts(&mut parts, &())
+            .await
+            .unwrap();
+        assert_eq!(
+            control,
+            CacheControl {
+                no_cache: true,
+                no_store: true
+            }
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use mongodb::options::ClientOptions;
+use mongodb::Client;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::{ClusterConfig, Config};
+use crate::error::ApiError;
+use crate::health::PoolStats;
+
+/// Name of the cluster configured by the top-level MongoDB settings.
+pub const DEFAULT_CLUSTER: &str = "default";
+pub const CLUSTER_HEADER: &str = "x-mongodb-cluster";
+
+/// Builds a driver client from one cluster's connection settings, feeding
+/// pool events into `pool_stats`.
+pub async fn connect(
+    settings: &ClusterConfig,
+    pool_stats: Arc<PoolStats>,
+) -> Result<Client, mongodb::error::Error> {
+    let mut client_options = ClientOptions::parse(&settings.uri).await?;
+    client_options.app_name = Some("hello_rust_gateway".to_string());
+    client_options.cmap_event_handler = Some(pool_stats);
+    if let Some(min_pool_size) = settings.pool_min_size {
+        client_options.min_pool_size = Some(min_pool_size);
+    }
+    if let Some(max_pool_size) = settings.pool_max_size {
+        client_options.max_pool_size = Some(max_pool_size);
+    }
+    if let Some(timeout) = settings.connect_timeout {
+        client_options.connect_timeout = Some(timeout);
+    }
+    if let Some(timeout) = settings.server_selection_timeout {
+        client_options.server_selection_timeout = Some(timeout);
+    }
+    Client::with_options(client_options)
+}
+
+/// Driver clients by cluster name, plus the rules routing namespaces to them.
+pub struct Clusters {
+    clients: HashMap<Arc<str>, Client>,
+    pools: HashMap<Arc<str>, Arc<PoolStats>>,
+    routes: Vec<Route>,
+}
+
+struct Route {
+    pattern: NamespacePattern,
+    cluster: Arc<str>,
+}
+
+/// `<database>.<collection>` pattern where `*` matches any run of characters.
+/// A pattern without a `.` matches every collection in the database.
+#[derive(Debug, Clone)]
+pub(crate) struct NamespacePattern {
+    database: String,
+    collection: String,
+}
+
+impl NamespacePattern {
+    pub(crate) fn parse(pattern: &str) -> Self {
+        let (database, collection) = pattern.split_once('.').unwrap_or((pattern, "*"));
+        Self {
+            database: database.to_string(),
+            collection: collection.to_string(),
+        }
+    }
+
+    /// Without a collection only the database part is matched.
+    pub(crate) fn matches(&self, database: &str, collection: Option<&str>) -> bool {
+        glob_matches(&self.database, database)
+            && collection.is_none_or(|collection| glob_matches(&self.collection, collection))
+    }
+}
+
+impl Clusters {
+    /// Registers `default` as the default cluster and takes routing rules from
+    /// `config`. Named clusters are added with [`Clusters::with_cluster`].
+    pub fn new(default: Client, config: &Config) -> Self {
+        let routes = config
+            .cluster_routes
+            .iter()
+            .map(|route| Route {
+                pattern: NamespacePattern::parse(&route.namespace),
+                cluster: Arc::from(route.cluster.as_str()),
+            })
+            .collect();
+        let mut clients = HashMap::new();
+        clients.insert(Arc::from(DEFAULT_CLUSTER), default);
+        Self {
+            clients,
+            pools: HashMap::new(),
+            routes,
+        }
+    }
+
+    pub fn with_cluster(mut self, name: &str, client: Client) -> Self {
+        self.clients.insert(Arc::from(name), client);
+        self
+    }
+
+    /// Connects to the default cluster and every named cluster in `config`.
+    pub async fn connect(config: &Config) -> Result<Self, mongodb::error::Error> {
+        let default_stats = Arc::new(PoolStats::default());
+        let default = connect(&config.default_cluster(), default_stats.clone()).await?;
+        let mut clusters = Self::new(default, config);
+        clusters
+            .pools
+            .insert(Arc::from(DEFAULT_CLUSTER), default_stats);
+        for (name, settings) in &config.clusters {
+            let stats = Arc::new(PoolStats::default());
+            let client = connect(settings, stats.clone()).await?;
+            clusters = clusters.with_cluster(name, client);
+            clusters.pools.insert(Arc::from(name.as_str()), stats);
+        }
+        Ok(clusters)
+    }
+
+    pub fn default_client(&self) -> &Client {
+        &self.clients[DEFAULT_CLUSTER]
+    }
+
+    /// Every cluster with its pool counters, when the client was built by
+    /// [`Clusters::connect`].
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Client, Option<&PoolStats>)> {
+        self.clients.iter().map(|(name, client)| {
+            (
+                name.as_ref(),
+                client,
+                self.pools.get(name).map(AsRef::as_ref),
+            )
+        })
+    }
+
+    /// Closes every client, waiting up to `timeout` for open cursors and
+    /// sessions to be cleaned up before abandoning them.
+    pub async fn shutdown(&self, timeout: Duration) {
+        let closing = self.clients.iter().map(|(name, client)| async move {
+            let client = client.clone();
+            if tokio::time::timeout(timeout, client.clone().shutdown())
+                .await
+                .is_err()
+            {
+                tracing::warn!(
+                    cluster = name.as_ref(),
+                    "mongodb client did not close in time; shutting down immediately"
+                );
+                client.shutdown_immediate().await;
+            }
+        });
+        futures::future::join_all(closing).await;
+    }
+
+    /// Picks the cluster for a namespace: the one the request named, else the
+    /// first matching route, else `default`. Without a collection only the
+    /// database part of each route is matched.
+    pub fn resolve(
+        &self,
+        requested: Option<&str>,
+        database: &str,
+        collection: Option<&str>,
+    ) -> Result<(Arc<str>, &Client), ApiError> {
+        let name = match requested.map(str::trim).filter(|name| !name.is_empty()) {
+            Some(name) => name,
+            None => self
+                .routes
+                .iter()
+                .find(|route| route.pattern.matches(database, collection))
+                .map_or(DEFAULT_CLUSTER, |route| route.cluster.as_ref()),
+        };
+        self.clients
+            .get_key_value(name)
+            .map(|(name, client)| (name.clone(), client))
+            .ok_or_else(|| ApiError::validation(format!("unknown cluster `{name}`")))
+    }
+}
+
+/// Matches `text` against `pattern`, where `*` matches any run of characters.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let (pattern, text) = (pattern.as_bytes(), text.as_bytes());
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&byte| byte == b'*')
+}
+
+/// Cluster named by the `X-MongoDB-Cluster` header, if any. A `cluster` field
+/// in the request body takes precedence.
+#[derive(Debug, Clone, Default)]
+pub struct RequestedCluster(pub Option<String>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RequestedCluster
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(RequestedCluster(
+            parts
+                .headers
+                .get(CLUSTER_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{test_config, ClusterRoute};
+
+    #[test]
+    fn glob_matches_wildcards() {
+        assert!(glob_matches("*", "anything"));
+        assert!(glob_matches("events_*", "events_2024"));
+        assert!(glob_matches("*_archive", "orders_archive"));
+        assert!(glob_matches("a*b*c", "aXXbYYc"));
+        assert!(!glob_matches("events_*", "users"));
+        assert!(!glob_matches("users", "users2"));
+    }
+
+    async fn clusters() -> Clusters {
+        let client = Client::with_uri_str("mongodb://localhost:27017")
+            .await
+            .expect("client");
+        let config = crate::config::Config {
+            cluster_routes: vec![
+                ClusterRoute {
+                    namespace: "analytics.*".into(),
+                    cluster: "analytics".into(),
+                },
+                ClusterRoute {
+                    namespace: "app.events_*".into(),
+                    cluster: "analytics".into(),
+                },
+            ],
+            ..test_config()
+        };
+        Clusters::new(client.clone(), &config).with_cluster("analytics", client)
+    }
+
+    #[tokio::test]
+    async fn routes_namespaces_by_pattern() {
+        let clusters = clusters().await;
+        let resolve = |database, collection| {
+            clusters
+                .resolve(None, database, collection)
+                .unwrap()
+                .0
+                .to_string()
+        };
+        assert_eq!(resolve("analytics", Some("daily")), "analytics");
+        assert_eq!(resolve("app", Some("events_clicks")), "analytics");
+        assert_eq!(resolve("app", Some("users")), "default");
+        assert_eq!(resolve("analytics", None), "analytics");
+    }
+
+    #[tokio::test]
+    async fn requested_cluster_overrides_routes() {
+        let clusters = clusters().await;
+        let (name, _) = clusters
+            .resolve(Some("default"), "analytics", Some("daily"))
+            .unwrap();
+        assert_eq!(name.as_ref(), "default");
+        let err = clusters
+            .resolve(Some("reporting"), "app", Some("users"))
+            .expect_err("unknown cluster");
+        assert_eq!(err.status().as_u16(), 400);
+    }
+}
@@ -0,0 +1,261 @@
+use axum::async_trait;
+use axum::body::{Body, Bytes};
+use axum::extract::{FromRequest, FromRequestParts, Request};
+use axum::http::header::{ACCEPT, CONTENT_TYPE};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use futures::{Stream, TryStreamExt};
+use mongodb::bson::Document;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::convert::Infallible;
+
+use crate::error::ApiError;
+
+pub const BSON_CONTENT_TYPE: &str = "application/bson";
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// Wire format used for a request or response body.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BodyFormat {
+    #[default]
+    Json,
+    Bson,
+}
+
+impl BodyFormat {
+    /// Format of the request body, based on its `Content-Type` header.
+    pub fn from_content_type(headers: &HeaderMap) -> Self {
+        headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| media_type(value) == BSON_CONTENT_TYPE)
+            .map(|_| BodyFormat::Bson)
+            .unwrap_or_default()
+    }
+
+    /// Preferred response format, based on the first supported entry in `Accept`.
+    pub fn from_accept(headers: &HeaderMap) -> Self {
+        headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .find_map(|entry| match media_type(entry).as_str() {
+                BSON_CONTENT_TYPE => Some(BodyFormat::Bson),
+                JSON_CONTENT_TYPE => Some(BodyFormat::Json),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+}
+
+fn media_type(value: &str) -> String {
+    value
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for BodyFormat
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(BodyFormat::from_accept(&parts.headers))
+    }
+}
+
+/// Request body extractor accepting either JSON or BSON.
+///
+/// `application/bson` bodies are decoded as a single BSON document; anything
+/// else is handed to [`axum::Json`] so JSON rejections are unchanged.
+#[derive(Debug)]
+pub struct Payload<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for Payload<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        match BodyFormat::from_content_type(req.headers()) {
+            BodyFormat::Json => Json::<T>::from_request(req, state)
+                .await
+                .map(|Json(value)| Payload(value))
+                .map_err(IntoResponse::into_response),
+            BodyFormat::Bson => {
+                let bytes = Bytes::from_request(req, state)
+                    .await
+                    .map_err(IntoResponse::into_response)?;
+                mongodb::bson::from_slice::<T>(&bytes)
+                    .map(Payload)
+                    .map_err(|err| {
+                        ApiError::validation(format!("invalid bson body: {err}")).into_response()
+                    })
+            }
+        }
+    }
+}
+
+/// Response body serialized in the format negotiated from `Accept`.
+#[derive(Debug)]
+pub struct Reply<T> {
+    format: BodyFormat,
+    value: T,
+}
+
+impl<T> Reply<T> {
+    pub fn new(format: BodyFormat, value: T) -> Self {
+        Self { format, value }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Reply<T> {
+    fn into_response(self) -> Response {
+        match self.format {
+            BodyFormat::Json => Json(self.value).into_response(),
+            BodyFormat::Bson => match mongodb::bson::to_vec(&self.value) {
+                Ok(bytes) => bson_response(Body::from(bytes)),
+                Err(err) => ApiError::internal(format!("failed to encode bson response: {err}"))
+                    .into_response(),
+            },
+        }
+    }
+}
+
+/// Streams documents as concatenated BSON without buffering the full result set.
+pub fn bson_document_stream<S>(documents: S) -> Response
+where
+    S: Stream<Item = Result<Document, mongodb::error::Error>> + Send + 'static,
+{
+    let chunks = documents.and_then(|document| async move {
+        let mut buffer = Vec::new();
+        document
+            .to_writer(&mut buffer)
+            .map_err(|err| mongodb::error::Error::custom(err.to_string()))?;
+        Ok(Bytes::from(buffer))
+    });
+    bson_response(Body::from_stream(chunks))
+}
+
+fn bson_response(body: Body) -> Response {
+    (
+        StatusCode::OK,
+        [(CONTENT_TYPE, HeaderValue::from_static(BSON_CONTENT_TYPE))],
+        body,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::InsertOneRequest;
+    use mongodb::bson::{doc, Bson};
+
+    fn headers(name: axum::http::HeaderName, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn content_type_selects_bson_with_parameters() {
+        let headers = headers(CONTENT_TYPE, "Application/BSON; charset=binary");
+        assert_eq!(BodyFormat::from_content_type(&headers), BodyFormat::Bson);
+    }
+
+    #[test]
+    fn missing_content_type_defaults_to_json() {
+        assert_eq!(
+            BodyFormat::from_content_type(&HeaderMap::new()),
+            BodyFormat::Json
+        );
+    }
+
+    #[test]
+    fn accept_uses_first_supported_media_type() {
+        let bson_first = headers(ACCEPT, "text/html, application/bson, application/json");
+        assert_eq!(BodyFormat::from_accept(&bson_first), BodyFormat::Bson);
+
+        let json_first = headers(ACCEPT, "application/json, application/bson");
+        assert_eq!(BodyFormat::from_accept(&json_first), BodyFormat::Json);
+
+        let wildcard = headers(ACCEPT, "*/*");
+        assert_eq!(BodyFormat::from_accept(&wildcard), BodyFormat::Json);
+    }
+
+    #[tokio::test]
+    async fn payload_decodes_bson_body() {
+        let body = mongodb::bson::to_vec(&doc! {
+            "database": "app",
+            "collection": "users",
+            "document": { "name": "Quill", "age": 38_i64 },
+        })
+        .expect("encode");
+        let request = Request::builder()
+            .header(CONTENT_TYPE, BSON_CONTENT_TYPE)
+            .body(Body::from(body))
+            .unwrap();
+
+        let Payload(payload) = Payload::<InsertOneRequest>::from_request(request, &())
+            .await
+            .expect("bson payload");
+        assert_eq!(payload.namespace.database, "app");
+        assert_eq!(payload.document.get("age"), Some(&Bson::Int64(38)));
+    }
+
+    #[tokio::test]
+    async fn payload_rejects_malformed_bson() {
+        let request = Request::builder()
+            .header(CONTENT_TYPE, BSON_CONTENT_TYPE)
+            .body(Body::from(vec![1, 2, 3]))
+            .unwrap();
+
+        let rejection = Payload::<InsertOneRequest>::from_request(request, &())
+            .await
+            .expect_err("expected rejection");
+        assert_eq!(rejection.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn reply_encodes_bson_document() {
+        let response =
+            Reply::new(BodyFormat::Bson, doc! { "deleted_count": 2_i64 }).into_response();
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            BSON_CONTENT_TYPE
+        );
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let decoded: Document = mongodb::bson::from_slice(&bytes).expect("decode");
+        assert_eq!(decoded.get_i64("deleted_count").unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn document_stream_concatenates_documents() {
+        let documents = futures::stream::iter(vec![Ok(doc! { "n": 1 }), Ok(doc! { "n": 2 })]);
+        let response = bson_document_stream(documents);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        let mut reader = std::io::Cursor::new(bytes.to_vec());
+        let first = Document::from_reader(&mut reader).expect("first");
+        let second = Document::from_reader(&mut reader).expect("second");
+        assert_eq!(first.get_i32("n").unwrap(), 1);
+        assert_eq!(second.get_i32("n").unwrap(), 2);
+    }
+}
@@ -1,9 +1,23 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::env;
+use std::fmt;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use thiserror::Error;
 
-#[derive(Debug, Clone)]
+use crate::consistency::{ReadConcernSpec, ReadPreferenceSpec, WriteConcernSpec};
+
+mod file;
+
+use file::FileConfig;
+
+const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1:3000";
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Config {
     pub mongodb_uri: String,
     pub default_database: Option<String>,
@@ -14,37 +28,558 @@ pub struct Config {
     pub server_selection_timeout: Option<Duration>,
     pub log_level: Option<String>,
     pub bind_address: String,
+    /// Serves the gRPC document API on this address when set.
+    pub grpc_bind_address: Option<String>,
+    /// How long shutdown waits for in-flight requests before closing them;
+    /// see [`Config::shutdown_timeout`].
+    pub shutdown_timeout: Option<Duration>,
+    /// Key for ETag HMACs; a random per-process key is used when unset, so
+    /// replicas behind one load balancer need the same secret.
+    pub etag_secret: Option<String>,
+    /// Serves HTTPS instead of plain HTTP when set.
+    pub tls: Option<TlsConfig>,
+    pub limits: LimitsConfig,
+    pub query_policy: QueryPolicyConfig,
+    pub payload: PayloadConfig,
+    pub auth: AuthConfig,
+    pub audit: AuditConfig,
+    pub encryption: EncryptionConfig,
+    /// Additional named clusters; the top-level MongoDB settings form the
+    /// `default` cluster.
+    pub clusters: BTreeMap<String, ClusterConfig>,
+    pub cluster_routes: Vec<ClusterRoute>,
+    pub namespaces: Vec<NamespaceConfig>,
+    pub field_policies: Vec<FieldPolicyConfig>,
+    pub caches: Vec<CacheConfig>,
+    pub graphql: Vec<GraphqlTypeConfig>,
+}
+
+/// Certificate and key for HTTPS. Setting `client_ca_path` enables mutual TLS:
+/// client certificates must chain to that CA.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    pub client_ca_path: Option<PathBuf>,
+    /// With mutual TLS, whether connections without a client certificate are
+    /// refused during the handshake. Defaults to `true`.
+    pub client_cert_required: bool,
+}
+
+/// Where write operations are audited. Auditing is disabled without a sink.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditConfig {
+    pub sink: Option<AuditSink>,
+    /// The file sink rotates once the current file would exceed this size.
+    pub max_file_bytes: u64,
+    /// Rotated files kept next to the current one.
+    pub max_files: u32,
+    /// Field names whose values are replaced in filters, updates and documents.
+    pub redact_fields: Vec<String>,
+    pub overflow: AuditOverflow,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            sink: None,
+            max_file_bytes: DEFAULT_AUDIT_MAX_FILE_BYTES,
+            max_files: DEFAULT_AUDIT_MAX_FILES,
+            redact_fields: Vec::new(),
+            overflow: AuditOverflow::default(),
+        }
+    }
+}
+
+/// What a write does when the audit queue is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOverflow {
+    /// Wait for room in the queue before running the write.
+    #[default]
+    Block,
+    /// Refuse the write with `503 Service Unavailable`.
+    Reject,
+    /// Run the write and drop its event.
+    Drop,
+}
+
+impl AuditOverflow {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Block => "block",
+            Self::Reject => "reject",
+            Self::Drop => "drop",
+        }
+    }
+}
+
+const DEFAULT_AUDIT_MAX_FILE_BYTES: u64 = 100 * 1024 * 1024;
+const DEFAULT_AUDIT_MAX_FILES: u32 = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuditSink {
+    /// JSON lines appended to a file, rotated by size.
+    File(PathBuf),
+    /// Documents inserted into a MongoDB collection, routed like any other
+    /// namespace.
+    Collection {
+        database: String,
+        collection: String,
+    },
+}
+
+/// Field restrictions for namespaces matching `namespace`. A caller gets the
+/// first matching policy whose `roles` include one of its roles, or that lists
+/// no roles; a policy with nothing to exclude or mask grants full access.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldPolicyConfig {
+    pub namespace: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// Dotted field paths removed from every document read.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub mask: Vec<FieldMaskConfig>,
+}
+
+/// Masks a field in responses, e.g. `123-45-6789` becomes `***-**-6789` with
+/// `keep_last = 4`. Only letters and digits are masked.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldMaskConfig {
+    pub field: String,
+    #[serde(default)]
+    pub keep_last: usize,
+}
+
+/// Caches `find-one`/`find-many` responses for namespaces matching `namespace`;
+/// the first matching rule applies.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheConfig {
+    pub namespace: String,
+    #[serde(default = "default_cache_ttl_ms")]
+    pub ttl_ms: u64,
+    /// Responses kept per namespace; the oldest are evicted first.
+    #[serde(default = "default_cache_max_entries")]
+    pub max_entries: usize,
+    /// Results with more documents than this are not cached.
+    #[serde(default = "default_cache_max_documents")]
+    pub max_documents: usize,
+    /// Also invalidates on changes made outside the gateway, using a change
+    /// stream. Requires a replica set or sharded cluster.
+    #[serde(default)]
+    pub change_stream: bool,
+}
+
+impl CacheConfig {
+    pub fn ttl(&self) -> Duration {
+        Duration::from_millis(self.ttl_ms)
+    }
+}
+
+fn default_cache_ttl_ms() -> u64 {
+    60_000
+}
+
+/// Exposes the collection `namespace` (`<database>.<collection>`) as the
+/// GraphQL type `name` on `/graphql`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GraphqlTypeConfig {
+    pub name: String,
+    pub namespace: String,
+    /// Named cluster to use instead of the routing rules.
+    #[serde(default)]
+    pub cluster: Option<String>,
+    /// Field name to GraphQL type: `ID`, `String`, `Int`, `Float`, `Boolean`,
+    /// `DateTime`, `JSON`, or a list such as `[String]`. Left empty, fields
+    /// are inferred from `sample_size` sampled documents.
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+    #[serde(default = "default_graphql_sample_size")]
+    pub sample_size: u32,
+    #[serde(default)]
+    pub references: Vec<GraphqlReferenceConfig>,
+}
+
+/// Adds `field` to a GraphQL type, resolving the `_id` stored in `from` (or
+/// the array of them, with `many`) to documents of type `type`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GraphqlReferenceConfig {
+    pub field: String,
+    pub from: String,
+    #[serde(rename = "type")]
+    pub target: String,
+    #[serde(default)]
+    pub many: bool,
+}
+
+fn default_graphql_sample_size() -> u32 {
+    100
+}
+
+fn default_cache_max_entries() -> usize {
+    1_000
+}
+
+fn default_cache_max_documents() -> usize {
+    1_000
+}
+
+/// Read/write defaults for namespaces matching `namespace`; the first matching
+/// rule applies. `min_write_concern` is a floor requests cannot go below.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NamespaceConfig {
+    pub namespace: String,
+    #[serde(default)]
+    pub read_preference: Option<ReadPreferenceSpec>,
+    #[serde(default)]
+    pub read_concern: Option<ReadConcernSpec>,
+    #[serde(default)]
+    pub write_concern: Option<WriteConcernSpec>,
+    #[serde(default)]
+    pub min_write_concern: Option<WriteConcernSpec>,
+    /// Dotted field whose value identifies a document version in ETags;
+    /// without it the whole document is hashed.
+    #[serde(default)]
+    pub version_field: Option<String>,
+    /// Keeps previous versions of changed documents in `<collection>_history`.
+    #[serde(default)]
+    pub versioned: bool,
+    /// Most documents one `update-many` or `delete-many` may change in a
+    /// versioned namespace, whose prior versions are held in memory meanwhile.
+    #[serde(default)]
+    pub max_versioned_documents: Option<NonZeroU32>,
+    /// Deletes set `deleted_at` instead of removing documents.
+    #[serde(default)]
+    pub soft_delete: bool,
+    /// Stamps `createdAt`/`createdBy` and `updatedAt`/`updatedBy` on writes.
+    #[serde(default)]
+    pub timestamps: bool,
+}
+
+/// Client-side field encryption; keys are read from `key_file` at startup.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EncryptionConfig {
+    pub key_file: Option<PathBuf>,
+    pub fields: Vec<EncryptedFieldConfig>,
+}
+
+/// Encrypts `field` (a dotted path) in namespaces matching `namespace`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EncryptedFieldConfig {
+    pub namespace: String,
+    pub field: String,
+    #[serde(default)]
+    pub mode: EncryptionMode,
+}
+
+/// Deterministic encryption gives equal values equal ciphertexts, so they can
+/// be matched by equality filters; randomized encryption cannot be queried.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncryptionMode {
+    #[default]
+    Randomized,
+    Deterministic,
+}
+
+/// Connection settings for one MongoDB cluster.
+#[derive(Clone, PartialEq)]
+pub struct ClusterConfig {
+    pub uri: String,
+    pub pool_min_size: Option<u32>,
+    pub pool_max_size: Option<u32>,
+    pub connect_timeout: Option<Duration>,
+    pub server_selection_timeout: Option<Duration>,
+}
+
+impl fmt::Debug for ClusterConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClusterConfig")
+            .field("uri", &"<redacted>")
+            .field("pool_min_size", &self.pool_min_size)
+            .field("pool_max_size", &self.pool_max_size)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("server_selection_timeout", &self.server_selection_timeout)
+            .finish()
+    }
+}
+
+/// Sends namespaces matching `namespace` (a `<database>.<collection>` pattern
+/// where `*` matches any run of characters) to `cluster`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClusterRoute {
+    pub namespace: String,
+    pub cluster: String,
+}
+
+impl Default for Config {
+    /// Built-in defaults for every setting; `mongodb_uri` has none and is empty.
+    fn default() -> Self {
+        Self {
+            mongodb_uri: String::new(),
+            default_database: None,
+            default_collection: None,
+            pool_min_size: None,
+            pool_max_size: None,
+            connect_timeout: None,
+            server_selection_timeout: None,
+            log_level: None,
+            bind_address: DEFAULT_BIND_ADDRESS.to_string(),
+            grpc_bind_address: None,
+            shutdown_timeout: None,
+            etag_secret: None,
+            tls: None,
+            limits: Default::default(),
+            query_policy: Default::default(),
+            payload: Default::default(),
+            auth: Default::default(),
+            audit: Default::default(),
+            encryption: Default::default(),
+            clusters: Default::default(),
+            cluster_routes: Vec::new(),
+            namespaces: Vec::new(),
+            field_policies: Vec::new(),
+            caches: Vec::new(),
+            graphql: Vec::new(),
+        }
+    }
+}
+
+/// Defaults pointed at a local MongoDB, for unit tests.
+#[cfg(test)]
+pub(crate) fn test_config() -> Config {
+    Config {
+        mongodb_uri: "mongodb://localhost:27017".into(),
+        ..Default::default()
+    }
+}
+
+/// Serializes tests that set or remove process environment variables.
+#[cfg(test)]
+pub(crate) fn env_lock() -> std::sync::MutexGuard<'static, ()> {
+    static ENV_MUTEX: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    ENV_MUTEX
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// Request throttling settings; `None` disables the corresponding limit.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub read_per_second: Option<u32>,
+    pub read_burst: Option<u32>,
+    pub write_per_second: Option<u32>,
+    pub write_burst: Option<u32>,
+    pub max_in_flight_per_namespace: Option<u32>,
+}
+
+/// Guardrails applied to filters and find options before they reach the driver.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryPolicyConfig {
+    pub blocked_operators: Vec<String>,
+    pub max_limit: Option<u32>,
+    pub default_max_time: Option<Duration>,
+    pub max_filter_depth: Option<u32>,
+    pub max_filter_bytes: Option<u32>,
+    /// Caps exports instead of `max_limit`, which is sized for pages.
+    pub export_max_limit: Option<u32>,
+    /// `maxTimeMS` for exports; `default_max_time` applies when unset.
+    pub export_max_time: Option<Duration>,
+}
+
+impl Default for QueryPolicyConfig {
+    fn default() -> Self {
+        Self {
+            blocked_operators: DEFAULT_BLOCKED_OPERATORS
+                .iter()
+                .map(|op| op.to_string())
+                .collect(),
+            max_limit: None,
+            default_max_time: None,
+            max_filter_depth: None,
+            max_filter_bytes: None,
+            export_max_limit: None,
+            export_max_time: None,
+        }
+    }
+}
+
+const DEFAULT_BLOCKED_OPERATORS: [&str; 3] = ["$where", "$function", "$accumulator"];
+
+/// Caps on request bodies and the documents inside them, checked before
+/// anything is buffered in full or sent to the driver.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayloadConfig {
+    pub max_body_bytes: u64,
+    /// Per-route overrides of `max_body_bytes`, keyed by path (`/import`).
+    /// Streaming routes are only limited by an entry here.
+    pub route_body_bytes: BTreeMap<String, u64>,
+    pub max_insert_documents: Option<u32>,
+    pub max_document_bytes: u32,
+    pub max_depth: u32,
+}
+
+impl Default for PayloadConfig {
+    fn default() -> Self {
+        Self {
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            route_body_bytes: BTreeMap::new(),
+            max_insert_documents: None,
+            max_document_bytes: MAX_BSON_DOCUMENT_BYTES,
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+const DEFAULT_MAX_BODY_BYTES: u64 = 2 * 1024 * 1024;
+/// MongoDB's own limit on a single BSON document.
+pub const MAX_BSON_DOCUMENT_BYTES: u32 = 16 * 1024 * 1024;
+const DEFAULT_MAX_DEPTH: u32 = 100;
+
+/// API keys and client certificate subjects accepted by the gateway.
+/// Authentication is disabled when both are empty.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub api_keys: Vec<ApiKeyConfig>,
+    pub client_certs: Vec<ClientCertConfig>,
+}
+
+/// Maps a verified client certificate to an identity. `subject` matches either
+/// the certificate's common name or its full subject, e.g. `CN=batch-job, O=Acme`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientCertConfig {
+    pub subject: String,
+    pub identity: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyConfig {
+    pub key: String,
+    pub identity: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+impl fmt::Debug for ApiKeyConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ApiKeyConfig")
+            .field("key", &"<redacted>")
+            .field("identity", &self.identity)
+            .field("roles", &self.roles)
+            .finish()
+    }
 }
 
 #[derive(Debug, Error)]
 pub enum ConfigError {
     #[error("missing required environment variable `{0}`")]
     MissingEnv(&'static str),
+    #[error("missing `{key}` in `{}` (or environment variable `{env}`)", path.display())]
+    MissingFileValue {
+        path: PathBuf,
+        key: &'static str,
+        env: &'static str,
+    },
     #[error("invalid value for `{0}`: {1}")]
     InvalidEnv(&'static str, String),
+    #[error("{0}")]
+    InvalidArgument(String),
+    #[error("failed to read config file `{}`: {source}", path.display())]
+    ReadFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("unsupported config file format `{}` (expected .toml, .yaml or .yml)", .0.display())]
+    UnsupportedFormat(PathBuf),
+    #[error("failed to parse config file `{}`: {message}", path.display())]
+    ParseFile { path: PathBuf, message: String },
+    #[error("invalid value for `{key}` in `{}`: {message}", path.display())]
+    InvalidFileValue {
+        path: PathBuf,
+        key: String,
+        message: String,
+    },
+    #[error("undefined environment variable `{variable}` referenced by `{key}` in `{}`", path.display())]
+    Interpolation {
+        path: PathBuf,
+        key: String,
+        variable: String,
+    },
 }
 
 impl Config {
+    /// Loads the config file named by `--config` or `APP_CONFIG_FILE`, if any,
+    /// with environment variables overriding its values.
+    pub fn load() -> Result<Self, ConfigError> {
+        match Self::file_path()? {
+            Some(path) => Self::from_file(&path),
+            None => Self::from_env(),
+        }
+    }
+
+    /// Config file named by `--config` or `APP_CONFIG_FILE`, if any.
+    pub fn file_path() -> Result<Option<PathBuf>, ConfigError> {
+        file::locate(env::args().skip(1))
+    }
+
     pub fn from_env() -> Result<Self, ConfigError> {
-        let mongodb_uri = get_required("MONGODB_URI")?;
+        Self::from_layers(FileConfig::default(), None)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        Self::from_layers(FileConfig::read(path)?, Some(path))
+    }
+
+    /// `path` is the config file `file` was read from, if any.
+    fn from_layers(file: FileConfig, path: Option<&Path>) -> Result<Self, ConfigError> {
+        let mongodb_uri = env_string("MONGODB_URI")
+            .or(file.mongodb.uri)
+            .filter(|s| !s.is_empty())
+            .ok_or_else(|| missing(path, "mongodb.uri", "MONGODB_URI"))?;
 
-        let default_database = env::var("MONGODB_DEFAULT_DATABASE")
-            .ok()
-            .filter(|s| !s.is_empty());
-        let default_collection = env::var("MONGODB_DEFAULT_COLLECTION")
-            .ok()
-            .filter(|s| !s.is_empty());
+        let default_database =
+            env_string("MONGODB_DEFAULT_DATABASE").or(file.mongodb.default_database);
+        let default_collection =
+            env_string("MONGODB_DEFAULT_COLLECTION").or(file.mongodb.default_collection);
 
-        let pool_min_size = parse_optional_u32("MONGODB_POOL_MIN_SIZE")?;
-        let pool_max_size = parse_optional_u32("MONGODB_POOL_MAX_SIZE")?;
+        let pool_min_size =
+            parse_optional_u32("MONGODB_POOL_MIN_SIZE")?.or(file.mongodb.pool_min_size);
+        let pool_max_size =
+            parse_optional_u32("MONGODB_POOL_MAX_SIZE")?.or(file.mongodb.pool_max_size);
 
-        let connect_timeout = parse_optional_duration("MONGODB_CONNECT_TIMEOUT_MS")?;
+        let connect_timeout = parse_optional_duration("MONGODB_CONNECT_TIMEOUT_MS")?
+            .or(file.mongodb.connect_timeout_ms.map(Duration::from_millis));
         let server_selection_timeout =
-            parse_optional_duration("MONGODB_SERVER_SELECTION_TIMEOUT_MS")?;
+            parse_optional_duration("MONGODB_SERVER_SELECTION_TIMEOUT_MS")?.or(file
+                .mongodb
+                .server_selection_timeout_ms
+                .map(Duration::from_millis));
 
-        let log_level = env::var("LOG_LEVEL").ok().filter(|s| !s.is_empty());
-        let bind_address =
-            env::var("APP_BIND_ADDRESS").unwrap_or_else(|_| "127.0.0.1:3000".to_string());
+        let log_level = env_string("LOG_LEVEL").or(file.logging.level);
+        let bind_address = env_string("APP_BIND_ADDRESS")
+            .or(file.server.bind_address)
+            .unwrap_or_else(|| DEFAULT_BIND_ADDRESS.to_string());
+        let grpc_bind_address = env_string("GRPC_BIND_ADDRESS").or(file.server.grpc_bind_address);
+        let shutdown_timeout = parse_optional_duration("SHUTDOWN_TIMEOUT_MS")?
+            .or(file.server.shutdown_timeout_ms.map(Duration::from_millis));
+        let etag_secret = env_string("ETAG_SECRET").or(file.server.etag_secret);
+        let tls = TlsConfig::from_layers(file.tls, path)?;
 
         Ok(Self {
             mongodb_uri,
@@ -56,17 +591,275 @@ impl Config {
             server_selection_timeout,
             log_level,
             bind_address,
+            grpc_bind_address,
+            shutdown_timeout,
+            etag_secret,
+            tls,
+            limits: LimitsConfig::from_layers(file.limits)?,
+            query_policy: QueryPolicyConfig::from_layers(file.query)?,
+            payload: PayloadConfig::from_layers(file.payload)?,
+            auth: AuthConfig::from_layers(file.auth)?,
+            audit: AuditConfig::from_layers(file.audit)?,
+            encryption: EncryptionConfig::from_layers(file.encryption, path)?,
+            clusters: file
+                .clusters
+                .into_iter()
+                .map(|(name, cluster)| (name, cluster.into()))
+                .collect(),
+            cluster_routes: file.cluster_routes,
+            namespaces: file.namespaces,
+            field_policies: file.field_policies,
+            caches: file.caches,
+            graphql: file.graphql,
         })
     }
+
+    /// Drain deadline on SIGTERM/SIGINT, 30 seconds unless configured.
+    pub fn shutdown_timeout(&self) -> Duration {
+        self.shutdown_timeout
+            .unwrap_or(Duration::from_secs(DEFAULT_SHUTDOWN_TIMEOUT_SECS))
+    }
+
+    /// Connection settings of the `default` cluster.
+    pub fn default_cluster(&self) -> ClusterConfig {
+        ClusterConfig {
+            uri: self.mongodb_uri.clone(),
+            pool_min_size: self.pool_min_size,
+            pool_max_size: self.pool_max_size,
+            connect_timeout: self.connect_timeout,
+            server_selection_timeout: self.server_selection_timeout,
+        }
+    }
 }
 
-fn get_required(key: &'static str) -> Result<String, ConfigError> {
-    match env::var(key) {
-        Ok(value) if !value.is_empty() => Ok(value),
-        _ => Err(ConfigError::MissingEnv(key)),
+impl TlsConfig {
+    fn from_layers(
+        file: file::TlsSection,
+        path: Option<&Path>,
+    ) -> Result<Option<Self>, ConfigError> {
+        let cert_path = env_string("TLS_CERT_PATH")
+            .map(PathBuf::from)
+            .or(file.cert_path);
+        let key_path = env_string("TLS_KEY_PATH")
+            .map(PathBuf::from)
+            .or(file.key_path);
+        let client_ca_path = env_string("TLS_CLIENT_CA_PATH")
+            .map(PathBuf::from)
+            .or(file.client_ca_path);
+        let client_cert_required = parse_optional_bool("TLS_CLIENT_CERT_REQUIRED")?
+            .or(file.client_cert_required)
+            .unwrap_or(true);
+        match (cert_path, key_path) {
+            (Some(cert_path), Some(key_path)) => Ok(Some(Self {
+                cert_path,
+                key_path,
+                client_ca_path,
+                client_cert_required,
+            })),
+            (None, None) if client_ca_path.is_none() => Ok(None),
+            (Some(_), None) => Err(missing(path, "tls.key_path", "TLS_KEY_PATH")),
+            _ => Err(missing(path, "tls.cert_path", "TLS_CERT_PATH")),
+        }
     }
 }
 
+impl AuditConfig {
+    fn from_layers(file: file::AuditSection) -> Result<Self, ConfigError> {
+        let sink = match (env_string("AUDIT_LOG_PATH"), env_string("AUDIT_COLLECTION")) {
+            (Some(_), Some(_)) => {
+                return Err(ConfigError::InvalidEnv(
+                    "AUDIT_COLLECTION",
+                    "cannot be combined with `AUDIT_LOG_PATH`".to_string(),
+                ))
+            }
+            (Some(path), None) => Some(AuditSink::File(PathBuf::from(path))),
+            (None, Some(namespace)) => Some(
+                AuditSink::collection(&namespace)
+                    .ok_or(ConfigError::InvalidEnv("AUDIT_COLLECTION", namespace))?,
+            ),
+            // Both are rejected together when the file is validated
+            (None, None) => match (file.path, file.collection) {
+                (Some(path), _) => Some(AuditSink::File(path)),
+                (None, Some(namespace)) => AuditSink::collection(&namespace),
+                (None, None) => None,
+            },
+        };
+        let redact_fields = match env_string("AUDIT_REDACT_FIELDS") {
+            Some(value) => value
+                .split(',')
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .map(str::to_string)
+                .collect(),
+            None => file.redact_fields,
+        };
+        let defaults = Self::default();
+        Ok(Self {
+            sink,
+            max_file_bytes: parse_optional_nonzero_u64("AUDIT_MAX_FILE_BYTES")?
+                .or(file.max_file_bytes)
+                .unwrap_or(defaults.max_file_bytes),
+            max_files: parse_optional_nonzero_u32("AUDIT_MAX_FILES")?
+                .or(file.max_files)
+                .unwrap_or(defaults.max_files),
+            redact_fields,
+            overflow: match env_string("AUDIT_OVERFLOW") {
+                Some(value) => match value.to_ascii_lowercase().as_str() {
+                    "block" => AuditOverflow::Block,
+                    "reject" => AuditOverflow::Reject,
+                    "drop" => AuditOverflow::Drop,
+                    _ => return Err(ConfigError::InvalidEnv("AUDIT_OVERFLOW", value)),
+                },
+                None => file.overflow.unwrap_or(defaults.overflow),
+            },
+        })
+    }
+}
+
+impl EncryptionConfig {
+    fn from_layers(
+        file: file::EncryptionSection,
+        path: Option<&Path>,
+    ) -> Result<Self, ConfigError> {
+        let key_file = env_string("ENCRYPTION_KEY_FILE")
+            .map(PathBuf::from)
+            .or(file.key_file);
+        if key_file.is_none() && !file.fields.is_empty() {
+            return Err(missing(path, "encryption.key_file", "ENCRYPTION_KEY_FILE"));
+        }
+        Ok(Self {
+            key_file,
+            fields: file.fields,
+        })
+    }
+}
+
+impl AuditSink {
+    /// Parses a `<database>.<collection>` namespace.
+    fn collection(namespace: &str) -> Option<Self> {
+        let (database, collection) = namespace.split_once('.')?;
+        (!database.is_empty() && !collection.is_empty()).then(|| Self::Collection {
+            database: database.to_string(),
+            collection: collection.to_string(),
+        })
+    }
+}
+
+impl LimitsConfig {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_layers(Self::default())
+    }
+
+    fn from_layers(file: LimitsConfig) -> Result<Self, ConfigError> {
+        Ok(Self {
+            read_per_second: parse_optional_nonzero_u32("RATE_LIMIT_READ_PER_SECOND")?
+                .or(file.read_per_second),
+            read_burst: parse_optional_nonzero_u32("RATE_LIMIT_READ_BURST")?.or(file.read_burst),
+            write_per_second: parse_optional_nonzero_u32("RATE_LIMIT_WRITE_PER_SECOND")?
+                .or(file.write_per_second),
+            write_burst: parse_optional_nonzero_u32("RATE_LIMIT_WRITE_BURST")?.or(file.write_burst),
+            max_in_flight_per_namespace: parse_optional_nonzero_u32("NAMESPACE_MAX_IN_FLIGHT")?
+                .or(file.max_in_flight_per_namespace),
+        })
+    }
+}
+
+impl AuthConfig {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_layers(Self::default())
+    }
+
+    fn from_layers(file: AuthConfig) -> Result<Self, ConfigError> {
+        Ok(Self {
+            api_keys: parse_api_keys("AUTH_API_KEYS")?.unwrap_or(file.api_keys),
+            client_certs: file.client_certs,
+        })
+    }
+}
+
+impl QueryPolicyConfig {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_layers(file::QuerySection::default())
+    }
+
+    fn from_layers(file: file::QuerySection) -> Result<Self, ConfigError> {
+        let blocked_operators = match env::var("QUERY_BLOCKED_OPERATORS") {
+            Ok(value) if !value.trim().is_empty() => parse_operator_list(&value),
+            _ => match file.blocked_operators {
+                Some(operators) => normalize_operators(operators.iter().map(String::as_str)),
+                None => Self::default().blocked_operators,
+            },
+        };
+        Ok(Self {
+            blocked_operators,
+            max_limit: parse_optional_nonzero_u32("QUERY_MAX_LIMIT")?.or(file.max_limit),
+            default_max_time: parse_optional_duration("QUERY_DEFAULT_MAX_TIME_MS")?
+                .or(file.default_max_time_ms.map(Duration::from_millis)),
+            max_filter_depth: parse_optional_nonzero_u32("QUERY_MAX_FILTER_DEPTH")?
+                .or(file.max_filter_depth),
+            max_filter_bytes: parse_optional_nonzero_u32("QUERY_MAX_FILTER_BYTES")?
+                .or(file.max_filter_bytes),
+            export_max_limit: parse_optional_nonzero_u32("QUERY_EXPORT_MAX_LIMIT")?
+                .or(file.export_max_limit),
+            export_max_time: parse_optional_duration("QUERY_EXPORT_MAX_TIME_MS")?
+                .or(file.export_max_time_ms.map(Duration::from_millis)),
+        })
+    }
+}
+
+impl PayloadConfig {
+    fn from_layers(file: file::PayloadSection) -> Result<Self, ConfigError> {
+        let defaults = Self::default();
+        let max_document_bytes = parse_optional_nonzero_u32("PAYLOAD_MAX_DOCUMENT_BYTES")?
+            .or(file.max_document_bytes)
+            .unwrap_or(defaults.max_document_bytes);
+        if max_document_bytes > MAX_BSON_DOCUMENT_BYTES {
+            return Err(ConfigError::InvalidEnv(
+                "PAYLOAD_MAX_DOCUMENT_BYTES",
+                format!("must not exceed the BSON limit of {MAX_BSON_DOCUMENT_BYTES} bytes"),
+            ));
+        }
+        Ok(Self {
+            max_body_bytes: parse_optional_nonzero_u64("PAYLOAD_MAX_BODY_BYTES")?
+                .or(file.max_body_bytes)
+                .unwrap_or(defaults.max_body_bytes),
+            route_body_bytes: file.route_body_bytes,
+            max_insert_documents: parse_optional_nonzero_u32("PAYLOAD_MAX_INSERT_DOCUMENTS")?
+                .or(file.max_insert_documents),
+            max_document_bytes,
+            max_depth: parse_optional_nonzero_u32("PAYLOAD_MAX_DEPTH")?
+                .or(file.max_depth)
+                .unwrap_or(defaults.max_depth),
+        })
+    }
+}
+
+/// Parses a comma-separated operator list; `none` disables operator blocking.
+fn parse_operator_list(value: &str) -> Vec<String> {
+    if value.trim().eq_ignore_ascii_case("none") {
+        return Vec::new();
+    }
+    normalize_operators(value.split(','))
+}
+
+fn normalize_operators<'a>(operators: impl Iterator<Item = &'a str>) -> Vec<String> {
+    operators
+        .map(str::trim)
+        .filter(|op| !op.is_empty())
+        .map(|op| {
+            if op.starts_with('$') {
+                op.to_string()
+            } else {
+                format!("${op}")
+            }
+        })
+        .collect()
+}
+
+fn env_string(key: &'static str) -> Option<String> {
+    env::var(key).ok().filter(|s| !s.is_empty())
+}
+
 fn parse_optional_u32(key: &'static str) -> Result<Option<u32>, ConfigError> {
     match env::var(key) {
         Ok(value) if !value.is_empty() => value
@@ -77,6 +870,43 @@ fn parse_optional_u32(key: &'static str) -> Result<Option<u32>, ConfigError> {
     }
 }
 
+fn parse_optional_nonzero_u32(key: &'static str) -> Result<Option<u32>, ConfigError> {
+    match parse_optional_u32(key)? {
+        Some(0) => Err(ConfigError::InvalidEnv(
+            key,
+            "must be greater than zero".to_string(),
+        )),
+        value => Ok(value),
+    }
+}
+
+/// A required setting that is unset: named by its file key when a config
+/// file is in use, otherwise by its environment variable.
+fn missing(path: Option<&Path>, key: &'static str, env: &'static str) -> ConfigError {
+    match path {
+        Some(path) => ConfigError::MissingFileValue {
+            path: path.to_path_buf(),
+            key,
+            env,
+        },
+        None => ConfigError::MissingEnv(env),
+    }
+}
+
+fn parse_optional_bool(key: &'static str) -> Result<Option<bool>, ConfigError> {
+    match env::var(key) {
+        Ok(value) if !value.is_empty() => match value.to_ascii_lowercase().as_str() {
+            "true" | "1" | "yes" => Ok(Some(true)),
+            "false" | "0" | "no" => Ok(Some(false)),
+            _ => Err(ConfigError::InvalidEnv(
+                key,
+                "expected `true` or `false`".to_string(),
+            )),
+        },
+        _ => Ok(None),
+    }
+}
+
 fn parse_optional_duration(key: &'static str) -> Result<Option<Duration>, ConfigError> {
     parse_optional_u64(key).map(|opt| opt.map(Duration::from_millis))
 }
@@ -91,13 +921,63 @@ fn parse_optional_u64(key: &'static str) -> Result<Option<u64>, ConfigError> {
     }
 }
 
+fn parse_optional_nonzero_u64(key: &'static str) -> Result<Option<u64>, ConfigError> {
+    match parse_optional_u64(key)? {
+        Some(0) => Err(ConfigError::InvalidEnv(
+            key,
+            "must be greater than zero".to_string(),
+        )),
+        value => Ok(value),
+    }
+}
+
+/// Parses `key:identity[:role|role]` entries separated by commas. Errors
+/// never echo the value, which holds secrets.
+fn parse_api_keys(key: &'static str) -> Result<Option<Vec<ApiKeyConfig>>, ConfigError> {
+    let value = match env::var(key) {
+        Ok(value) if !value.trim().is_empty() => value,
+        _ => return Ok(None),
+    };
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .enumerate()
+        .map(|(index, entry)| {
+            let mut parts = entry.splitn(3, ':');
+            let api_key = parts.next().unwrap_or_default().trim();
+            let identity = parts.next().unwrap_or_default().trim();
+            if api_key.is_empty() || identity.is_empty() {
+                return Err(ConfigError::InvalidEnv(
+                    key,
+                    format!("entry {index} must look like `key:identity[:role|role]`"),
+                ));
+            }
+            let roles = parts
+                .next()
+                .map(|roles| {
+                    roles
+                        .split('|')
+                        .map(str::trim)
+                        .filter(|role| !role.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default();
+            Ok(ApiKeyConfig {
+                key: api_key.to_string(),
+                identity: identity.to_string(),
+                roles,
+            })
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::env;
-    use std::sync::{Mutex, OnceLock};
-
-    static ENV_MUTEX: OnceLock<Mutex<()>> = OnceLock::new();
 
     fn with_env(key: &str, value: &str, f: impl FnOnce()) {
         env::set_var(key, value);
@@ -107,7 +987,7 @@ mod tests {
 
     #[test]
     fn missing_required_variable_fails() {
-        let _guard = ENV_MUTEX.get_or_init(|| Mutex::new(())).lock().unwrap();
+        let _guard = env_lock();
         let old_uri = env::var("MONGODB_URI").ok();
         env::remove_var("MONGODB_URI");
         let result = Config::from_env();
@@ -122,7 +1002,7 @@ mod tests {
 
     #[test]
     fn parses_optional_values() {
-        let _guard = ENV_MUTEX.get_or_init(|| Mutex::new(())).lock().unwrap();
+        let _guard = env_lock();
         env::set_var("MONGODB_URI", "mongodb://localhost:27017");
         env::remove_var("MONGODB_POOL_MIN_SIZE");
         env::remove_var("MONGODB_CONNECT_TIMEOUT_MS");
@@ -138,7 +1018,7 @@ mod tests {
 
     #[test]
     fn rejects_invalid_u32_values() {
-        let _guard = ENV_MUTEX.get_or_init(|| Mutex::new(())).lock().unwrap();
+        let _guard = env_lock();
         env::set_var("MONGODB_URI", "mongodb://localhost:27017");
         env::remove_var("MONGODB_POOL_MIN_SIZE");
         with_env("MONGODB_POOL_MIN_SIZE", "not_a_number", || {
@@ -153,7 +1033,7 @@ mod tests {
 
     #[test]
     fn rejects_invalid_duration_values() {
-        let _guard = ENV_MUTEX.get_or_init(|| Mutex::new(())).lock().unwrap();
+        let _guard = env_lock();
         env::set_var("MONGODB_URI", "mongodb://localhost:27017");
         env::remove_var("MONGODB_CONNECT_TIMEOUT_MS");
         with_env("MONGODB_CONNECT_TIMEOUT_MS", "invalid", || {
@@ -168,7 +1048,7 @@ mod tests {
 
     #[test]
     fn filters_empty_strings_from_optional_vars() {
-        let _guard = ENV_MUTEX.get_or_init(|| Mutex::new(())).lock().unwrap();
+        let _guard = env_lock();
         env::set_var("MONGODB_URI", "mongodb://localhost:27017");
         env::remove_var("MONGODB_DEFAULT_DATABASE");
         env::remove_var("MONGODB_DEFAULT_COLLECTION");
@@ -184,7 +1064,7 @@ mod tests {
 
     #[test]
     fn uses_default_bind_address_when_not_set() {
-        let _guard = ENV_MUTEX.get_or_init(|| Mutex::new(())).lock().unwrap();
+        let _guard = env_lock();
         env::set_var("MONGODB_URI", "mongodb://localhost:27017");
         let old_bind = env::var("APP_BIND_ADDRESS").ok();
         env::remove_var("APP_BIND_ADDRESS");
@@ -198,7 +1078,7 @@ mod tests {
 
     #[test]
     fn parses_all_optional_pool_settings() {
-        let _guard = ENV_MUTEX.get_or_init(|| Mutex::new(())).lock().unwrap();
+        let _guard = env_lock();
         env::set_var("MONGODB_URI", "mongodb://localhost:27017");
         env::remove_var("MONGODB_POOL_MIN_SIZE");
         env::remove_var("MONGODB_POOL_MAX_SIZE");
@@ -219,9 +1099,563 @@ mod tests {
         env::remove_var("MONGODB_URI");
     }
 
+    #[test]
+    fn parses_rate_limits() {
+        let _guard = env_lock();
+        env::set_var("MONGODB_URI", "mongodb://localhost:27017");
+        with_env("RATE_LIMIT_WRITE_PER_SECOND", "20", || {
+            with_env("NAMESPACE_MAX_IN_FLIGHT", "8", || {
+                let config = Config::from_env().expect("config");
+                assert_eq!(config.limits.write_per_second, Some(20));
+                assert_eq!(config.limits.read_per_second, None);
+                assert_eq!(config.limits.max_in_flight_per_namespace, Some(8));
+            });
+        });
+        env::remove_var("MONGODB_URI");
+    }
+
+    #[test]
+    fn rejects_zero_rate_limit() {
+        let _guard = env_lock();
+        env::set_var("MONGODB_URI", "mongodb://localhost:27017");
+        with_env("RATE_LIMIT_READ_PER_SECOND", "0", || {
+            let result = Config::from_env();
+            assert!(matches!(
+                result,
+                Err(ConfigError::InvalidEnv("RATE_LIMIT_READ_PER_SECOND", _))
+            ));
+        });
+        for key in ["AUDIT_MAX_FILE_BYTES", "PAYLOAD_MAX_BODY_BYTES"] {
+            with_env(key, "0", || {
+                assert!(matches!(
+                    Config::from_env(),
+                    Err(ConfigError::InvalidEnv(name, _)) if name == key
+                ));
+            });
+        }
+        env::remove_var("MONGODB_URI");
+    }
+
+    #[test]
+    fn parses_api_keys() {
+        let _guard = env_lock();
+        env::set_var("MONGODB_URI", "mongodb://localhost:27017");
+        with_env(
+            "AUTH_API_KEYS",
+            "k-123:batch-job:writer|reader, k-456:dashboard",
+            || {
+                let keys = Config::from_env().expect("config").auth.api_keys;
+                assert_eq!(keys.len(), 2);
+                assert_eq!(keys[0].key, "k-123");
+                assert_eq!(keys[0].identity, "batch-job");
+                assert_eq!(keys[0].roles, vec!["writer", "reader"]);
+                assert!(keys[1].roles.is_empty());
+                assert!(!format!("{:?}", keys[0]).contains("k-123"));
+            },
+        );
+        with_env("AUTH_API_KEYS", "k-secret", || {
+            let err = Config::from_env().expect_err("missing identity");
+            assert!(matches!(err, ConfigError::InvalidEnv("AUTH_API_KEYS", _)));
+            assert!(!err.to_string().contains("k-secret"));
+        });
+        env::remove_var("MONGODB_URI");
+    }
+
+    #[test]
+    fn parses_query_policy() {
+        let _guard = env_lock();
+        env::set_var("MONGODB_URI", "mongodb://localhost:27017");
+        with_env("QUERY_BLOCKED_OPERATORS", "where, $regex", || {
+            with_env("QUERY_DEFAULT_MAX_TIME_MS", "2500", || {
+                let config = Config::from_env().expect("config");
+                assert_eq!(
+                    config.query_policy.blocked_operators,
+                    vec!["$where".to_string(), "$regex".to_string()]
+                );
+                assert_eq!(
+                    config.query_policy.default_max_time,
+                    Some(Duration::from_millis(2500))
+                );
+            });
+        });
+        let config = Config::from_env().expect("config");
+        assert_eq!(config.query_policy.blocked_operators.len(), 3);
+        with_env("QUERY_BLOCKED_OPERATORS", "none", || {
+            let config = Config::from_env().expect("config");
+            assert!(config.query_policy.blocked_operators.is_empty());
+        });
+        env::remove_var("MONGODB_URI");
+    }
+
+    fn write_config(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("hello_rust_{}_{name}", std::process::id()));
+        std::fs::write(&path, contents).expect("write config file");
+        path
+    }
+
+    #[test]
+    fn tls_requires_certificate_and_key() {
+        let _guard = env_lock();
+        env::remove_var("MONGODB_URI");
+        let path = write_config(
+            "tls.toml",
+            r#"
+[mongodb]
+uri = "mongodb://file-host:27017"
+
+[tls]
+cert_path = "/etc/gateway/tls.crt"
+client_ca_path = "/etc/gateway/clients.crt"
+client_cert_required = false
+"#,
+        );
+        assert!(matches!(
+            Config::from_file(&path),
+            Err(ConfigError::MissingFileValue { path: file, key: "tls.key_path", env: "TLS_KEY_PATH" })
+                if file == path
+        ));
+        with_env("TLS_KEY_PATH", "/run/secrets/tls.key", || {
+            let tls = Config::from_file(&path).expect("config").tls.expect("tls");
+            assert_eq!(tls.key_path, PathBuf::from("/run/secrets/tls.key"));
+            assert!(!tls.client_cert_required);
+        });
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn loads_audit_sink_and_redaction() {
+        let _guard = env_lock();
+        env::remove_var("MONGODB_URI");
+        let path = write_config(
+            "audit.toml",
+            r#"
+[mongodb]
+uri = "mongodb://file-host:27017"
+
+[audit]
+collection = "ops.audit"
+max_files = 3
+redact_fields = ["password", "ssn"]
+overflow = "reject"
+"#,
+        );
+        let audit = Config::from_file(&path).expect("config").audit;
+        assert_eq!(
+            audit.sink,
+            Some(AuditSink::Collection {
+                database: "ops".into(),
+                collection: "audit".into(),
+            })
+        );
+        assert_eq!(audit.max_files, 3);
+        assert_eq!(audit.max_file_bytes, DEFAULT_AUDIT_MAX_FILE_BYTES);
+        assert_eq!(audit.redact_fields, ["password", "ssn"]);
+        assert_eq!(audit.overflow, AuditOverflow::Reject);
+        with_env("AUDIT_OVERFLOW", "drop", || {
+            let overflow = Config::from_file(&path).expect("config").audit.overflow;
+            assert_eq!(overflow, AuditOverflow::Drop);
+        });
+        with_env("AUDIT_LOG_PATH", "/var/log/gateway/audit.jsonl", || {
+            let sink = Config::from_file(&path).expect("config").audit.sink;
+            assert_eq!(
+                sink,
+                Some(AuditSink::File(PathBuf::from(
+                    "/var/log/gateway/audit.jsonl"
+                )))
+            );
+        });
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn encrypted_fields_require_key_file() {
+        let _guard = env_lock();
+        env::remove_var("MONGODB_URI");
+        let path = write_config(
+            "encryption.toml",
+            r#"
+[mongodb]
+uri = "mongodb://file-host:27017"
+
+[[encryption.fields]]
+namespace = "hr.employees"
+field = "ssn"
+mode = "deterministic"
+"#,
+        );
+        assert!(matches!(
+            Config::from_file(&path),
+            Err(ConfigError::MissingFileValue {
+                key: "encryption.key_file",
+                ..
+            })
+        ));
+        with_env("ENCRYPTION_KEY_FILE", "/run/secrets/keys.json", || {
+            let encryption = Config::from_file(&path).expect("config").encryption;
+            assert_eq!(
+                encryption.key_file,
+                Some(PathBuf::from("/run/secrets/keys.json"))
+            );
+            assert_eq!(encryption.fields[0].mode, EncryptionMode::Deterministic);
+        });
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn loads_toml_file_with_env_overrides() {
+        let _guard = env_lock();
+        env::remove_var("MONGODB_URI");
+        let path = write_config(
+            "layered.toml",
+            r#"
+[server]
+bind_address = "0.0.0.0:8080"
+grpc_bind_address = "0.0.0.0:50051"
+shutdown_timeout_ms = 5000
+
+[mongodb]
+uri = "mongodb://file-host:27017"
+pool_max_size = 40
+connect_timeout_ms = 750
+
+[limits]
+write_per_second = 25
+
+[logging]
+level = "debug"
+"#,
+        );
+        with_env("APP_BIND_ADDRESS", "127.0.0.1:9000", || {
+            let config = Config::from_file(&path).expect("config");
+            assert_eq!(config.mongodb_uri, "mongodb://file-host:27017");
+            assert_eq!(config.bind_address, "127.0.0.1:9000");
+            assert_eq!(config.grpc_bind_address.as_deref(), Some("0.0.0.0:50051"));
+            assert_eq!(config.pool_max_size, Some(40));
+            assert_eq!(config.connect_timeout, Some(Duration::from_millis(750)));
+            assert_eq!(config.limits.write_per_second, Some(25));
+            assert_eq!(config.log_level, Some("debug".to_string()));
+            assert_eq!(config.shutdown_timeout(), Duration::from_secs(5));
+        });
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn loads_yaml_file_with_interpolated_secrets() {
+        let _guard = env_lock();
+        env::remove_var("MONGODB_URI");
+        let path = write_config(
+            "secrets.yaml",
+            r#"
+mongodb:
+  uri: "mongodb://app:${HELLO_RUST_TEST_PASSWORD}@db:27017"
+auth:
+  api_keys:
+    - key: "${HELLO_RUST_TEST_API_KEY}"
+      identity: batch-job
+      roles: [writer]
+query:
+  blocked_operators: [where, $function]
+"#,
+        );
+        with_env("HELLO_RUST_TEST_PASSWORD", "hunter2", || {
+            with_env("HELLO_RUST_TEST_API_KEY", "k-123", || {
+                let config = Config::from_file(&path).expect("config");
+                assert_eq!(config.mongodb_uri, "mongodb://app:hunter2@db:27017");
+                assert_eq!(config.auth.api_keys[0].key, "k-123");
+                assert_eq!(config.auth.api_keys[0].roles, vec!["writer".to_string()]);
+                assert_eq!(
+                    config.query_policy.blocked_operators,
+                    vec!["$where".to_string(), "$function".to_string()]
+                );
+            });
+        });
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn reports_file_path_and_key_for_invalid_values() {
+        let _guard = env_lock();
+        let path = write_config("invalid.toml", "[limits]\nread_per_second = \"fast\"\n");
+        let err = Config::from_file(&path).expect_err("invalid value");
+        match &err {
+            ConfigError::InvalidFileValue {
+                path: file, key, ..
+            } => {
+                assert_eq!(file, &path);
+                assert_eq!(key, "limits.read_per_second");
+            }
+            other => panic!("unexpected error: {other}"),
+        }
+        assert!(err.to_string().contains(&path.display().to_string()));
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn reports_undefined_interpolation_variable() {
+        let _guard = env_lock();
+        env::remove_var("HELLO_RUST_TEST_UNDEFINED");
+        let path = write_config(
+            "undefined.toml",
+            "[mongodb]\nuri = \"${HELLO_RUST_TEST_UNDEFINED}\"\n",
+        );
+        let err = Config::from_file(&path).expect_err("undefined variable");
+        assert!(matches!(
+            &err,
+            ConfigError::Interpolation { key, variable, .. }
+                if key == "mongodb.uri" && variable == "HELLO_RUST_TEST_UNDEFINED"
+        ));
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn rejects_unknown_file_keys_and_zero_limits() {
+        let _guard = env_lock();
+        let path = write_config("unknown.toml", "[mongodb]\nurl = \"mongodb://x\"\n");
+        let err = Config::from_file(&path).expect_err("unknown key");
+        assert!(err.to_string().contains("unknown field `url`"));
+        std::fs::remove_file(path).ok();
+
+        env::remove_var("MONGODB_URI");
+        let path = write_config("no_uri.toml", "[limits]\nread_burst = 5\n");
+        let err = Config::from_file(&path).expect_err("missing uri");
+        assert!(matches!(
+            &err,
+            ConfigError::MissingFileValue {
+                key: "mongodb.uri",
+                ..
+            }
+        ));
+        assert!(err.to_string().contains(&path.display().to_string()));
+        std::fs::remove_file(path).ok();
+
+        let path = write_config("zero.yaml", "limits:\n  read_burst: 0\n");
+        let err = Config::from_file(&path).expect_err("zero limit");
+        assert!(matches!(
+            &err,
+            ConfigError::InvalidFileValue { key, .. } if key == "limits.read_burst"
+        ));
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn loads_named_clusters_and_routes() {
+        let _guard = env_lock();
+        env::remove_var("MONGODB_URI");
+        let path = write_config(
+            "clusters.toml",
+            r#"
+[mongodb]
+uri = "mongodb://oltp:27017"
+
+[clusters.analytics]
+uri = "mongodb://analytics:27017"
+pool_max_size = 5
+server_selection_timeout_ms = 1500
+
+[[cluster_routes]]
+namespace = "reports.*"
+cluster = "analytics"
+"#,
+        );
+        let config = Config::from_file(&path).expect("config");
+        let analytics = &config.clusters["analytics"];
+        assert_eq!(analytics.uri, "mongodb://analytics:27017");
+        assert_eq!(analytics.pool_max_size, Some(5));
+        assert_eq!(
+            analytics.server_selection_timeout,
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(config.cluster_routes[0].namespace, "reports.*");
+        assert_eq!(config.default_cluster().uri, "mongodb://oltp:27017");
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn loads_payload_limits_with_env_overrides() {
+        let _guard = env_lock();
+        env::remove_var("MONGODB_URI");
+        let path = write_config(
+            "payload.toml",
+            r#"
+[mongodb]
+uri = "mongodb://localhost:27017"
+
+[payload]
+max_body_bytes = 1048576
+max_insert_documents = 500
+
+[payload.route_body_bytes]
+"/api/v1/documents/import" = 104857600
+"#,
+        );
+        with_env("PAYLOAD_MAX_DEPTH", "20", || {
+            let config = Config::from_file(&path).expect("config");
+            assert_eq!(config.payload.max_body_bytes, 1_048_576);
+            assert_eq!(config.payload.max_insert_documents, Some(500));
+            assert_eq!(config.payload.max_depth, 20);
+            assert_eq!(config.payload.max_document_bytes, MAX_BSON_DOCUMENT_BYTES);
+            assert_eq!(
+                config.payload.route_body_bytes["/api/v1/documents/import"],
+                104_857_600
+            );
+        });
+        std::fs::remove_file(path).ok();
+
+        let path = write_config(
+            "payload_bson.toml",
+            "[payload]\nmax_document_bytes = 20000000\n",
+        );
+        let err = Config::from_file(&path).expect_err("over the BSON limit");
+        assert!(matches!(
+            &err,
+            ConfigError::InvalidFileValue { key, .. } if key == "payload.max_document_bytes"
+        ));
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn rejects_routes_to_unknown_clusters() {
+        let _guard = env_lock();
+        let path = write_config(
+            "bad_route.yaml",
+            "cluster_routes:\n  - namespace: \"reports.*\"\n    cluster: analytics\n",
+        );
+        let err = Config::from_file(&path).expect_err("unknown cluster");
+        assert!(matches!(
+            &err,
+            ConfigError::InvalidFileValue { key, .. } if key == "cluster_routes.0.cluster"
+        ));
+        std::fs::remove_file(path).ok();
+
+        let path = write_config(
+            "reserved.toml",
+            "[clusters.default]\nuri = \"mongodb://x\"\n",
+        );
+        let err = Config::from_file(&path).expect_err("reserved name");
+        assert!(matches!(
+            &err,
+            ConfigError::InvalidFileValue { key, .. } if key == "clusters.default"
+        ));
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn loads_cache_rules_with_defaults() {
+        let _guard = env_lock();
+        env::set_var("MONGODB_URI", "mongodb://localhost:27017");
+        let path = write_config(
+            "caches.toml",
+            "[[caches]]\nnamespace = \"catalog.products\"\nttl_ms = 5000\nchange_stream = true\n",
+        );
+        let config = Config::from_file(&path).expect("config");
+        let cache = &config.caches[0];
+        assert_eq!(cache.ttl(), Duration::from_secs(5));
+        assert_eq!(cache.max_entries, 1_000);
+        assert!(cache.change_stream);
+        std::fs::remove_file(path).ok();
+
+        let path = write_config(
+            "empty_cache.toml",
+            "[[caches]]\nnamespace = \"catalog.products\"\nmax_entries = 0\n",
+        );
+        let err = Config::from_file(&path).expect_err("zero entries");
+        assert!(matches!(
+            &err,
+            ConfigError::InvalidFileValue { key, .. } if key == "caches.0.max_entries"
+        ));
+        std::fs::remove_file(path).ok();
+        env::remove_var("MONGODB_URI");
+    }
+
+    #[test]
+    fn loads_graphql_types() {
+        let _guard = env_lock();
+        env::set_var("MONGODB_URI", "mongodb://localhost:27017");
+        let path = write_config(
+            "graphql.toml",
+            "[[graphql]]\nname = \"Customer\"\nnamespace = \"shop.customers\"\n\n\
+             [[graphql]]\nname = \"Order\"\nnamespace = \"shop.orders\"\n\
+             fields = { total = \"Float\", tags = \"[String]\" }\n\
+             [[graphql.references]]\nfield = \"customer\"\nfrom = \"customer_id\"\ntype = \"Customer\"\n",
+        );
+        let config = Config::from_file(&path).expect("config");
+        assert_eq!(config.graphql.len(), 2);
+        assert_eq!(config.graphql[0].sample_size, 100);
+        let order = &config.graphql[1];
+        assert_eq!(order.fields["tags"], "[String]");
+        assert_eq!(order.references[0].target, "Customer");
+        assert!(!order.references[0].many);
+        std::fs::remove_file(path).ok();
+
+        let path = write_config(
+            "graphql_reference.toml",
+            "[[graphql]]\nname = \"Order\"\nnamespace = \"shop.orders\"\n\
+             [[graphql.references]]\nfield = \"customer\"\nfrom = \"customer_id\"\ntype = \"Customer\"\n",
+        );
+        let err = Config::from_file(&path).expect_err("unknown type");
+        assert!(matches!(
+            &err,
+            ConfigError::InvalidFileValue { key, .. } if key == "graphql.0.references.0.type"
+        ));
+        std::fs::remove_file(path).ok();
+
+        let path = write_config(
+            "graphql_field.toml",
+            "[[graphql]]\nname = \"Order\"\nnamespace = \"shop.orders\"\n\
+             fields = { total = \"Money\" }\n",
+        );
+        let err = Config::from_file(&path).expect_err("unknown field type");
+        assert!(matches!(
+            &err,
+            ConfigError::InvalidFileValue { key, .. } if key == "graphql.0.fields.total"
+        ));
+        std::fs::remove_file(path).ok();
+        env::remove_var("MONGODB_URI");
+    }
+
+    #[test]
+    fn loads_namespace_consistency_rules() {
+        let _guard = env_lock();
+        env::set_var("MONGODB_URI", "mongodb://localhost:27017");
+        let path = write_config(
+            "namespaces.yaml",
+            r#"
+namespaces:
+  - namespace: "app.orders"
+    read_preference: { mode: secondaryPreferred, max_staleness_seconds: 120 }
+    read_concern: majority
+    write_concern: { w: majority, j: true }
+    min_write_concern: majority
+    versioned: true
+    soft_delete: true
+    timestamps: true
+"#,
+        );
+        let config = Config::from_file(&path).expect("config");
+        let rule = &config.namespaces[0];
+        assert_eq!(rule.read_concern, Some(ReadConcernSpec::Majority));
+        assert!(rule.versioned);
+        assert!(rule.soft_delete);
+        assert!(rule.timestamps);
+        assert_eq!(rule.write_concern.as_ref().unwrap().journal, Some(true));
+        std::fs::remove_file(path).ok();
+
+        let path = write_config(
+            "weak_default.toml",
+            "[[namespaces]]\nnamespace = \"app.orders\"\nwrite_concern = 1\nmin_write_concern = \"majority\"\n",
+        );
+        let err = Config::from_file(&path).expect_err("default below floor");
+        assert!(matches!(
+            &err,
+            ConfigError::InvalidFileValue { key, .. } if key == "namespaces.0.write_concern"
+        ));
+        std::fs::remove_file(path).ok();
+        env::remove_var("MONGODB_URI");
+    }
+
     #[test]
     fn parses_log_level() {
-        let _guard = ENV_MUTEX.get_or_init(|| Mutex::new(())).lock().unwrap();
+        let _guard = env_lock();
         env::set_var("MONGODB_URI", "mongodb://localhost:27017");
         env::remove_var("LOG_LEVEL");
         with_env("LOG_LEVEL", "debug", || {
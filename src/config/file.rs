@@ -0,0 +1,571 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::{
+    AuditOverflow, AuthConfig, CacheConfig, ClusterConfig, ClusterRoute, ConfigError,
+    EncryptedFieldConfig, FieldPolicyConfig, GraphqlTypeConfig, LimitsConfig, NamespaceConfig,
+    MAX_BSON_DOCUMENT_BYTES,
+};
+use crate::cluster::DEFAULT_CLUSTER;
+use crate::graphql;
+
+/// Settings read from a TOML or YAML config file. Every key is optional;
+/// environment variables take precedence over anything set here.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct FileConfig {
+    pub server: ServerSection,
+    pub tls: TlsSection,
+    pub mongodb: MongoSection,
+    pub auth: AuthConfig,
+    pub audit: AuditSection,
+    pub encryption: EncryptionSection,
+    pub limits: LimitsConfig,
+    pub query: QuerySection,
+    pub payload: PayloadSection,
+    pub logging: LoggingSection,
+    pub clusters: BTreeMap<String, ClusterSection>,
+    pub cluster_routes: Vec<ClusterRoute>,
+    pub namespaces: Vec<NamespaceConfig>,
+    pub field_policies: Vec<FieldPolicyConfig>,
+    pub caches: Vec<CacheConfig>,
+    pub graphql: Vec<GraphqlTypeConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct ServerSection {
+    pub bind_address: Option<String>,
+    pub grpc_bind_address: Option<String>,
+    pub shutdown_timeout_ms: Option<u64>,
+    pub etag_secret: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct TlsSection {
+    pub cert_path: Option<PathBuf>,
+    pub key_path: Option<PathBuf>,
+    pub client_ca_path: Option<PathBuf>,
+    pub client_cert_required: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct MongoSection {
+    pub uri: Option<String>,
+    pub default_database: Option<String>,
+    pub default_collection: Option<String>,
+    pub pool_min_size: Option<u32>,
+    pub pool_max_size: Option<u32>,
+    pub connect_timeout_ms: Option<u64>,
+    pub server_selection_timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct ClusterSection {
+    pub uri: String,
+    #[serde(default)]
+    pub pool_min_size: Option<u32>,
+    #[serde(default)]
+    pub pool_max_size: Option<u32>,
+    #[serde(default)]
+    pub connect_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub server_selection_timeout_ms: Option<u64>,
+}
+
+impl From<ClusterSection> for ClusterConfig {
+    fn from(section: ClusterSection) -> Self {
+        Self {
+            uri: section.uri,
+            pool_min_size: section.pool_min_size,
+            pool_max_size: section.pool_max_size,
+            connect_timeout: section.connect_timeout_ms.map(Duration::from_millis),
+            server_selection_timeout: section
+                .server_selection_timeout_ms
+                .map(Duration::from_millis),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct AuditSection {
+    pub path: Option<PathBuf>,
+    pub collection: Option<String>,
+    pub max_file_bytes: Option<u64>,
+    pub max_files: Option<u32>,
+    pub redact_fields: Vec<String>,
+    pub overflow: Option<AuditOverflow>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct EncryptionSection {
+    pub key_file: Option<PathBuf>,
+    pub fields: Vec<EncryptedFieldConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct QuerySection {
+    pub blocked_operators: Option<Vec<String>>,
+    pub max_limit: Option<u32>,
+    pub default_max_time_ms: Option<u64>,
+    pub max_filter_depth: Option<u32>,
+    pub max_filter_bytes: Option<u32>,
+    pub export_max_limit: Option<u32>,
+    pub export_max_time_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct PayloadSection {
+    pub max_body_bytes: Option<u64>,
+    pub route_body_bytes: BTreeMap<String, u64>,
+    pub max_insert_documents: Option<u32>,
+    pub max_document_bytes: Option<u32>,
+    pub max_depth: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct LoggingSection {
+    pub level: Option<String>,
+}
+
+impl FileConfig {
+    pub fn read(path: &Path) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|source| ConfigError::ReadFile {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let mut value = parse_document(path, &text)?;
+        if value.is_null() {
+            // An empty file is a valid, if pointless, config
+            value = Value::Object(Default::default());
+        }
+        interpolate(&mut value, path, &mut Vec::new())?;
+        let file: FileConfig = serde_path_to_error::deserialize(value).map_err(|err| {
+            ConfigError::InvalidFileValue {
+                path: path.to_path_buf(),
+                key: err.path().to_string(),
+                message: err.into_inner().to_string(),
+            }
+        })?;
+        file.validate(path)?;
+        Ok(file)
+    }
+
+    fn validate(&self, path: &Path) -> Result<(), ConfigError> {
+        let nonzero = [
+            ("limits.read_per_second", self.limits.read_per_second),
+            ("limits.read_burst", self.limits.read_burst),
+            ("limits.write_per_second", self.limits.write_per_second),
+            ("limits.write_burst", self.limits.write_burst),
+            (
+                "limits.max_in_flight_per_namespace",
+                self.limits.max_in_flight_per_namespace,
+            ),
+            ("query.max_limit", self.query.max_limit),
+            ("query.max_filter_depth", self.query.max_filter_depth),
+            ("query.max_filter_bytes", self.query.max_filter_bytes),
+            ("query.export_max_limit", self.query.export_max_limit),
+            (
+                "payload.max_insert_documents",
+                self.payload.max_insert_documents,
+            ),
+            (
+                "payload.max_document_bytes",
+                self.payload.max_document_bytes,
+            ),
+            ("payload.max_depth", self.payload.max_depth),
+            ("audit.max_files", self.audit.max_files),
+        ];
+        let invalid = |key: String, message: &str| ConfigError::InvalidFileValue {
+            path: path.to_path_buf(),
+            key,
+            message: message.to_string(),
+        };
+        if let Some((key, _)) = nonzero.iter().find(|(_, value)| *value == Some(0)) {
+            return Err(invalid(key.to_string(), "must be greater than zero"));
+        }
+        if self.audit.max_file_bytes == Some(0) {
+            return Err(invalid(
+                "audit.max_file_bytes".to_string(),
+                "must be greater than zero",
+            ));
+        }
+        if self.payload.max_body_bytes == Some(0) {
+            return Err(invalid(
+                "payload.max_body_bytes".to_string(),
+                "must be greater than zero",
+            ));
+        }
+        if self
+            .payload
+            .max_document_bytes
+            .is_some_and(|bytes| bytes > MAX_BSON_DOCUMENT_BYTES)
+        {
+            return Err(invalid(
+                "payload.max_document_bytes".to_string(),
+                &format!("must not exceed the BSON limit of {MAX_BSON_DOCUMENT_BYTES} bytes"),
+            ));
+        }
+        for (route, bytes) in &self.payload.route_body_bytes {
+            if !route.starts_with('/') {
+                return Err(invalid(
+                    format!("payload.route_body_bytes.{route}"),
+                    "expected a path starting with `/`",
+                ));
+            }
+            if *bytes == 0 {
+                return Err(invalid(
+                    format!("payload.route_body_bytes.{route}"),
+                    "must be greater than zero",
+                ));
+            }
+        }
+        if let Some(namespace) = &self.audit.collection {
+            if self.audit.path.is_some() {
+                return Err(invalid(
+                    "audit.collection".to_string(),
+                    "cannot be combined with `audit.path`",
+                ));
+            }
+            if namespace
+                .split_once('.')
+                .is_none_or(|(database, collection)| database.is_empty() || collection.is_empty())
+            {
+                return Err(invalid(
+                    "audit.collection".to_string(),
+                    "expected a `<database>.<collection>` namespace",
+                ));
+            }
+        }
+        for (index, entry) in self.auth.client_certs.iter().enumerate() {
+            if entry.subject.trim().is_empty() {
+                return Err(invalid(
+                    format!("auth.client_certs.{index}.subject"),
+                    "must not be empty",
+                ));
+            }
+        }
+        for (name, cluster) in &self.clusters {
+            if name == DEFAULT_CLUSTER {
+                return Err(invalid(
+                    format!("clusters.{name}"),
+                    "`default` is reserved for the top-level [mongodb] settings",
+                ));
+            }
+            if cluster.uri.is_empty() {
+                return Err(invalid(format!("clusters.{name}.uri"), "must not be empty"));
+            }
+        }
+        for (index, route) in self.cluster_routes.iter().enumerate() {
+            if !route.namespace.contains('.') {
+                return Err(invalid(
+                    format!("cluster_routes.{index}.namespace"),
+                    "expected a `<database>.<collection>` pattern",
+                ));
+            }
+            if route.cluster != DEFAULT_CLUSTER && !self.clusters.contains_key(&route.cluster) {
+                return Err(invalid(
+                    format!("cluster_routes.{index}.cluster"),
+                    &format!("unknown cluster `{}`", route.cluster),
+                ));
+            }
+        }
+        for (index, rule) in self.namespaces.iter().enumerate() {
+            if rule.namespace.is_empty() {
+                return Err(invalid(
+                    format!("namespaces.{index}.namespace"),
+                    "expected a `<database>.<collection>` pattern",
+                ));
+            }
+            if rule
+                .version_field
+                .as_deref()
+                .is_some_and(|field| !is_field_path(field))
+            {
+                return Err(invalid(
+                    format!("namespaces.{index}.version_field"),
+                    "expected a dotted field path",
+                ));
+            }
+            if let (Some(default), Some(floor)) = (&rule.write_concern, &rule.min_write_concern) {
+                if !default.satisfies(floor) {
+                    return Err(invalid(
+                        format!("namespaces.{index}.write_concern"),
+                        "is weaker than `min_write_concern`",
+                    ));
+                }
+            }
+        }
+        for (index, field) in self.encryption.fields.iter().enumerate() {
+            if field.namespace.is_empty() {
+                return Err(invalid(
+                    format!("encryption.fields.{index}.namespace"),
+                    "expected a `<database>.<collection>` pattern",
+                ));
+            }
+            if !is_field_path(&field.field) {
+                return Err(invalid(
+                    format!("encryption.fields.{index}.field"),
+                    "expected a dotted field path",
+                ));
+            }
+        }
+        for (index, policy) in self.field_policies.iter().enumerate() {
+            if policy.namespace.is_empty() {
+                return Err(invalid(
+                    format!("field_policies.{index}.namespace"),
+                    "expected a `<database>.<collection>` pattern",
+                ));
+            }
+            let fields =
+                policy
+                    .exclude
+                    .iter()
+                    .enumerate()
+                    .map(|(position, field)| (format!("exclude.{position}"), field))
+                    .chain(
+                        policy.mask.iter().enumerate().map(|(position, mask)| {
+                            (format!("mask.{position}.field"), &mask.field)
+                        }),
+                    );
+            for (key, field) in fields {
+                if !is_field_path(field) {
+                    return Err(invalid(
+                        format!("field_policies.{index}.{key}"),
+                        "expected a dotted field path",
+                    ));
+                }
+            }
+        }
+        for (index, cache) in self.caches.iter().enumerate() {
+            if cache.namespace.is_empty() {
+                return Err(invalid(
+                    format!("caches.{index}.namespace"),
+                    "expected a `<database>.<collection>` pattern",
+                ));
+            }
+            let nonzero = [
+                ("ttl_ms", cache.ttl_ms == 0),
+                ("max_entries", cache.max_entries == 0),
+                ("max_documents", cache.max_documents == 0),
+            ];
+            if let Some((key, _)) = nonzero.iter().find(|(_, zero)| *zero) {
+                return Err(invalid(
+                    format!("caches.{index}.{key}"),
+                    "must be greater than zero",
+                ));
+            }
+        }
+        let names: Vec<&str> = self.graphql.iter().map(|ty| ty.name.as_str()).collect();
+        for (index, ty) in self.graphql.iter().enumerate() {
+            if !graphql::is_type_name(&ty.name) || names[..index].contains(&ty.name.as_str()) {
+                return Err(invalid(
+                    format!("graphql.{index}.name"),
+                    "expected a unique GraphQL type name that is not built in",
+                ));
+            }
+            if graphql::split_namespace(&ty.namespace).is_none() {
+                return Err(invalid(
+                    format!("graphql.{index}.namespace"),
+                    "expected `<database>.<collection>`",
+                ));
+            }
+            if ty.sample_size == 0 {
+                return Err(invalid(
+                    format!("graphql.{index}.sample_size"),
+                    "must be greater than zero",
+                ));
+            }
+            for (field, kind) in &ty.fields {
+                if !graphql::is_field_name(field) {
+                    return Err(invalid(
+                        format!("graphql.{index}.fields.{field}"),
+                        "expected a GraphQL field name",
+                    ));
+                }
+                if graphql::FieldType::parse(kind).is_none() {
+                    return Err(invalid(
+                        format!("graphql.{index}.fields.{field}"),
+                        "expected ID, String, Int, Float, Boolean, DateTime, JSON or a list of one",
+                    ));
+                }
+            }
+            for (position, reference) in ty.references.iter().enumerate() {
+                let key = |name: &str| format!("graphql.{index}.references.{position}.{name}");
+                if !graphql::is_field_name(&reference.field)
+                    || ty.fields.contains_key(&reference.field)
+                {
+                    return Err(invalid(
+                        key("field"),
+                        "expected a GraphQL field name not used by `fields`",
+                    ));
+                }
+                if reference.from.is_empty() || reference.from.starts_with('$') {
+                    return Err(invalid(key("from"), "expected a field name"));
+                }
+                if !names.contains(&reference.target.as_str()) {
+                    return Err(invalid(
+                        key("type"),
+                        "expected the name of a `[[graphql]]` type",
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_field_path(field: &str) -> bool {
+    !field.is_empty() && !field.starts_with('$') && !field.split('.').any(str::is_empty)
+}
+
+fn parse_document(path: &Path, text: &str) -> Result<Value, ConfigError> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+    let parsed = match extension.as_deref() {
+        Some("toml") => toml::from_str::<Value>(text).map_err(|err| err.to_string()),
+        Some("yaml" | "yml") => serde_yaml::from_str::<Value>(text).map_err(|err| err.to_string()),
+        _ => return Err(ConfigError::UnsupportedFormat(path.to_path_buf())),
+    };
+    parsed.map_err(|message| ConfigError::ParseFile {
+        path: path.to_path_buf(),
+        message: message.trim_end().to_string(),
+    })
+}
+
+/// Replaces `${VAR}` in every string value with the environment variable's
+/// value. `$${` produces a literal `${`.
+fn interpolate(value: &mut Value, path: &Path, key: &mut Vec<String>) -> Result<(), ConfigError> {
+    match value {
+        Value::String(text) => {
+            *text = interpolate_str(text).map_err(|variable| ConfigError::Interpolation {
+                path: path.to_path_buf(),
+                key: key.join("."),
+                variable,
+            })?;
+        }
+        Value::Array(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                key.push(index.to_string());
+                interpolate(item, path, key)?;
+                key.pop();
+            }
+        }
+        Value::Object(entries) => {
+            for (name, item) in entries.iter_mut() {
+                key.push(name.clone());
+                interpolate(item, path, key)?;
+                key.pop();
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn interpolate_str(text: &str) -> Result<String, String> {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        if let Some(escaped) = tail.strip_prefix("$${") {
+            output.push_str("${");
+            rest = escaped;
+        } else if let Some((reference, end)) = tail
+            .strip_prefix("${")
+            .and_then(|reference| reference.find('}').map(|end| (reference, end)))
+        {
+            let name = &reference[..end];
+            let resolved = env::var(name).map_err(|_| name.to_string())?;
+            output.push_str(&resolved);
+            rest = &reference[end + 1..];
+        } else {
+            output.push('$');
+            rest = &tail[1..];
+        }
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Config file named on the command line (`--config <path>` or
+/// `--config=<path>`), falling back to `APP_CONFIG_FILE`.
+pub(super) fn locate(
+    args: impl IntoIterator<Item = String>,
+) -> Result<Option<PathBuf>, ConfigError> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return match args.next() {
+                Some(path) if !path.is_empty() => Ok(Some(PathBuf::from(path))),
+                _ => Err(ConfigError::InvalidArgument(
+                    "`--config` requires a file path".to_string(),
+                )),
+            };
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Ok(Some(PathBuf::from(path)));
+        }
+    }
+    Ok(env::var("APP_CONFIG_FILE")
+        .ok()
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn interpolates_variables_and_escapes() {
+        let _guard = crate::config::env_lock();
+        env::set_var("CONFIG_FILE_TEST_SECRET", "s3cret");
+        assert_eq!(
+            interpolate_str("mongodb://app:${CONFIG_FILE_TEST_SECRET}@db").unwrap(),
+            "mongodb://app:s3cret@db"
+        );
+        assert_eq!(interpolate_str("$${literal} $5").unwrap(), "${literal} $5");
+        env::remove_var("CONFIG_FILE_TEST_SECRET");
+    }
+
+    #[test]
+    fn reports_undefined_variable() {
+        let err = interpolate_str("${CONFIG_FILE_TEST_UNSET}").expect_err("undefined");
+        assert_eq!(err, "CONFIG_FILE_TEST_UNSET");
+    }
+
+    #[test]
+    fn locates_config_from_arguments() {
+        let path = locate(args(&["gateway", "--config", "gateway.toml"])).unwrap();
+        assert_eq!(path, Some(PathBuf::from("gateway.toml")));
+        let path = locate(args(&["gateway", "--config=/etc/gateway.yaml"])).unwrap();
+        assert_eq!(path, Some(PathBuf::from("/etc/gateway.yaml")));
+        assert!(locate(args(&["gateway", "--config"])).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_extension() {
+        let err = parse_document(Path::new("gateway.ini"), "").expect_err("unsupported");
+        assert!(matches!(err, ConfigError::UnsupportedFormat(_)));
+    }
+}
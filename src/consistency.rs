@@ -0,0 +1,496 @@
+use mongodb::options::{
+    Acknowledgment, CollectionOptions, ReadConcern, ReadPreference, ReadPreferenceOptions,
+    SelectionCriteria, TagSet, WriteConcern,
+};
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::cluster::NamespacePattern;
+use crate::config::NamespaceConfig;
+use crate::error::ApiError;
+use crate::history::{self, VERSION_FIELD};
+
+/// Smallest `maxStalenessSeconds` the server accepts.
+const MIN_MAX_STALENESS_SECS: u64 = 90;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReadMode {
+    Primary,
+    PrimaryPreferred,
+    Secondary,
+    SecondaryPreferred,
+    Nearest,
+}
+
+/// Read preference given either as a bare mode (`"secondary"`) or as
+/// `{ "mode", "tag_sets", "max_staleness_seconds" }`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "ReadPreferenceRepr")]
+pub struct ReadPreferenceSpec {
+    pub mode: ReadMode,
+    pub tag_sets: Vec<TagSet>,
+    pub max_staleness: Option<Duration>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ReadPreferenceRepr {
+    Mode(ReadMode),
+    Detailed(ReadPreferenceFields),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReadPreferenceFields {
+    mode: ReadMode,
+    #[serde(default)]
+    tag_sets: Vec<TagSet>,
+    #[serde(default)]
+    max_staleness_seconds: Option<u64>,
+}
+
+impl TryFrom<ReadPreferenceRepr> for ReadPreferenceSpec {
+    type Error = String;
+
+    fn try_from(repr: ReadPreferenceRepr) -> Result<Self, Self::Error> {
+        let fields = match repr {
+            ReadPreferenceRepr::Mode(mode) => ReadPreferenceFields {
+                mode,
+                tag_sets: Vec::new(),
+                max_staleness_seconds: None,
+            },
+            ReadPreferenceRepr::Detailed(fields) => fields,
+        };
+        if fields.mode == ReadMode::Primary
+            && (!fields.tag_sets.is_empty() || fields.max_staleness_seconds.is_some())
+        {
+            return Err(
+                "read preference `primary` cannot have tag_sets or max_staleness_seconds"
+                    .to_string(),
+            );
+        }
+        if let Some(seconds) = fields.max_staleness_seconds {
+            if seconds < MIN_MAX_STALENESS_SECS {
+                return Err(format!(
+                    "max_staleness_seconds must be at least {MIN_MAX_STALENESS_SECS}"
+                ));
+            }
+        }
+        Ok(Self {
+            mode: fields.mode,
+            tag_sets: fields.tag_sets,
+            max_staleness: fields.max_staleness_seconds.map(Duration::from_secs),
+        })
+    }
+}
+
+impl ReadPreferenceSpec {
+    pub fn to_selection_criteria(&self) -> SelectionCriteria {
+        let options = ReadPreferenceOptions::builder()
+            .tag_sets((!self.tag_sets.is_empty()).then(|| self.tag_sets.clone()))
+            .max_staleness(self.max_staleness)
+            .build();
+        let preference = match self.mode {
+            ReadMode::Primary => ReadPreference::Primary,
+            ReadMode::PrimaryPreferred => ReadPreference::PrimaryPreferred { options },
+            ReadMode::Secondary => ReadPreference::Secondary { options },
+            ReadMode::SecondaryPreferred => ReadPreference::SecondaryPreferred { options },
+            ReadMode::Nearest => ReadPreference::Nearest { options },
+        };
+        SelectionCriteria::ReadPreference(preference)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadConcernSpec {
+    Local,
+    Available,
+    Majority,
+    Linearizable,
+    Snapshot,
+}
+
+impl ReadConcernSpec {
+    pub fn to_read_concern(self) -> ReadConcern {
+        match self {
+            ReadConcernSpec::Local => ReadConcern::local(),
+            ReadConcernSpec::Available => ReadConcern::available(),
+            ReadConcernSpec::Majority => ReadConcern::majority(),
+            ReadConcernSpec::Linearizable => ReadConcern::linearizable(),
+            ReadConcernSpec::Snapshot => ReadConcern::snapshot(),
+        }
+    }
+}
+
+/// Write concern given either as a bare `w` (`"majority"`, `2`, a tag name) or
+/// as `{ "w", "j", "wtimeout_ms" }`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "WriteConcernRepr")]
+pub struct WriteConcernSpec {
+    pub w: Option<Acknowledgment>,
+    pub journal: Option<bool>,
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WriteConcernRepr {
+    W(WValue),
+    Detailed(WriteConcernFields),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WValue {
+    Nodes(u32),
+    Tag(String),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WriteConcernFields {
+    #[serde(default)]
+    w: Option<WValue>,
+    #[serde(default)]
+    j: Option<bool>,
+    #[serde(default)]
+    wtimeout_ms: Option<u64>,
+}
+
+impl TryFrom<WriteConcernRepr> for WriteConcernSpec {
+    type Error = String;
+
+    fn try_from(repr: WriteConcernRepr) -> Result<Self, Self::Error> {
+        let fields = match repr {
+            WriteConcernRepr::W(w) => WriteConcernFields {
+                w: Some(w),
+                j: None,
+                wtimeout_ms: None,
+            },
+            WriteConcernRepr::Detailed(fields) => fields,
+        };
+        let w = match fields.w {
+            Some(WValue::Nodes(0)) => {
+                return Err("unacknowledged writes (`w: 0`) are not supported".to_string())
+            }
+            Some(WValue::Nodes(nodes)) => Some(Acknowledgment::Nodes(nodes)),
+            Some(WValue::Tag(tag)) if tag == "majority" => Some(Acknowledgment::Majority),
+            Some(WValue::Tag(tag)) if tag.is_empty() => {
+                return Err("write concern `w` must not be empty".to_string())
+            }
+            Some(WValue::Tag(tag)) => Some(Acknowledgment::Custom(tag)),
+            None => None,
+        };
+        Ok(Self {
+            w,
+            journal: fields.j,
+            timeout: fields.wtimeout_ms.map(Duration::from_millis),
+        })
+    }
+}
+
+impl WriteConcernSpec {
+    pub fn to_write_concern(&self) -> WriteConcern {
+        WriteConcern::builder()
+            .w(self.w.clone())
+            .journal(self.journal)
+            .w_timeout(self.timeout)
+            .build()
+    }
+
+    /// Whether this write concern is at least as strong as `floor`. An unset
+    /// `w` counts as the server default of `1`; custom tags only satisfy the
+    /// same tag.
+    pub fn satisfies(&self, floor: &WriteConcernSpec) -> bool {
+        let rank = |w: Option<&Acknowledgment>| match w {
+            None => Some(1),
+            Some(Acknowledgment::Nodes(nodes)) => Some(u64::from(*nodes)),
+            Some(Acknowledgment::Majority) => Some(u64::MAX),
+            Some(_) => None,
+        };
+        let w_ok = match (rank(self.w.as_ref()), rank(floor.w.as_ref())) {
+            (Some(actual), Some(required)) => actual >= required,
+            _ => self.w == floor.w,
+        };
+        let journal_ok = floor.journal != Some(true) || self.journal == Some(true);
+        w_ok && journal_ok
+    }
+}
+
+/// Per-request overrides, accepted alongside the namespace in every request.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Consistency {
+    #[serde(default)]
+    pub read_preference: Option<ReadPreferenceSpec>,
+    #[serde(default)]
+    pub read_concern: Option<ReadConcernSpec>,
+    #[serde(default)]
+    pub write_concern: Option<WriteConcernSpec>,
+}
+
+impl Consistency {
+    pub fn is_empty(&self) -> bool {
+        self.read_preference.is_none()
+            && self.read_concern.is_none()
+            && self.write_concern.is_none()
+    }
+}
+
+/// Namespace-level defaults and write concern floors from config.
+#[derive(Debug, Default)]
+pub struct ConsistencyRules {
+    rules: Vec<(NamespacePattern, NamespaceConfig)>,
+}
+
+impl ConsistencyRules {
+    pub fn new(namespaces: &[NamespaceConfig]) -> Self {
+        Self {
+            rules: namespaces
+                .iter()
+                .map(|rule| (NamespacePattern::parse(&rule.namespace), rule.clone()))
+                .collect(),
+        }
+    }
+
+    /// Builds collection options for a namespace: request overrides on top of
+    /// the first matching rule's defaults. Rejects write concerns below the
+    /// rule's `min_write_concern`.
+    pub fn options(
+        &self,
+        database: &str,
+        collection: &str,
+        request: &Consistency,
+    ) -> Result<CollectionOptions, ApiError> {
+        let rule = self.rule(database, collection);
+        let read_preference = request
+            .read_preference
+            .as_ref()
+            .or(rule.and_then(|rule| rule.read_preference.as_ref()));
+        let read_concern = request
+            .read_concern
+            .or(rule.and_then(|rule| rule.read_concern));
+        let floor = rule.and_then(|rule| rule.min_write_concern.as_ref());
+        let write_concern = request
+            .write_concern
+            .as_ref()
+            .or(rule.and_then(|rule| rule.write_concern.as_ref()))
+            .or(floor);
+        if let (Some(write_concern), Some(floor)) = (write_concern, floor) {
+            if !write_concern.satisfies(floor) {
+                return Err(ApiError::validation(format!(
+                    "write_concern for `{database}.{collection}` is weaker than the required \
+                     minimum {}",
+                    describe(floor)
+                )));
+            }
+        }
+        Ok(CollectionOptions::builder()
+            .selection_criteria(read_preference.map(ReadPreferenceSpec::to_selection_criteria))
+            .read_concern(read_concern.map(ReadConcernSpec::to_read_concern))
+            .write_concern(write_concern.map(WriteConcernSpec::to_write_concern))
+            .build())
+    }
+
+    /// The first matching rule's `version_field`, which defaults to the
+    /// version counter in versioned namespaces.
+    pub fn version_field(&self, database: &str, collection: &str) -> Option<&str> {
+        let rule = self.rule(database, collection)?;
+        rule.version_field
+            .as_deref()
+            .or(rule.versioned.then_some(VERSION_FIELD))
+    }
+
+    /// Whether the first matching rule enables document history.
+    pub fn versioned(&self, database: &str, collection: &str) -> bool {
+        self.rule(database, collection)
+            .is_some_and(|rule| rule.versioned)
+    }
+
+    /// Most documents one versioned write may change in the namespace.
+    pub fn max_versioned_documents(&self, database: &str, collection: &str) -> usize {
+        self.rule(database, collection)
+            .and_then(|rule| rule.max_versioned_documents)
+            .map_or(history::DEFAULT_MAX_DOCUMENTS, |max| max.get() as usize)
+    }
+
+    /// Whether `collection` holds the history of a versioned namespace.
+    pub fn is_history(&self, database: &str, collection: &str) -> bool {
+        history::source_collection(collection)
+            .is_some_and(|source| self.versioned(database, source))
+    }
+
+    /// Whether the first matching rule turns deletes into soft deletes.
+    pub fn soft_delete(&self, database: &str, collection: &str) -> bool {
+        self.rule(database, collection)
+            .is_some_and(|rule| rule.soft_delete)
+    }
+
+    /// Whether the first matching rule stamps writes.
+    pub fn timestamps(&self, database: &str, collection: &str) -> bool {
+        self.rule(database, collection)
+            .is_some_and(|rule| rule.timestamps)
+    }
+
+    fn rule(&self, database: &str, collection: &str) -> Option<&NamespaceConfig> {
+        self.rules
+            .iter()
+            .find(|(pattern, _)| pattern.matches(database, Some(collection)))
+            .map(|(_, rule)| rule)
+    }
+}
+
+fn describe(write_concern: &WriteConcernSpec) -> String {
+    let w = match &write_concern.w {
+        None => "1".to_string(),
+        Some(Acknowledgment::Nodes(nodes)) => nodes.to_string(),
+        Some(Acknowledgment::Majority) => "majority".to_string(),
+        Some(Acknowledgment::Custom(tag)) => tag.clone(),
+        Some(other) => format!("{other:?}"),
+    };
+    match write_concern.journal {
+        Some(true) => format!("w: {w}, j: true"),
+        _ => format!("w: {w}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::num::NonZeroU32;
+
+    fn write_concern(value: serde_json::Value) -> WriteConcernSpec {
+        serde_json::from_value(value).expect("write concern")
+    }
+
+    #[test]
+    fn parses_read_preference_forms() {
+        let mode: ReadPreferenceSpec = serde_json::from_value(json!("secondaryPreferred")).unwrap();
+        assert_eq!(mode.mode, ReadMode::SecondaryPreferred);
+
+        let detailed: ReadPreferenceSpec = serde_json::from_value(json!({
+            "mode": "secondary",
+            "tag_sets": [{ "dc": "east" }, {}],
+            "max_staleness_seconds": 120
+        }))
+        .unwrap();
+        assert_eq!(detailed.tag_sets.len(), 2);
+        assert_eq!(detailed.max_staleness, Some(Duration::from_secs(120)));
+        assert!(matches!(
+            detailed.to_selection_criteria(),
+            SelectionCriteria::ReadPreference(ReadPreference::Secondary { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_read_preferences() {
+        let low = serde_json::from_value::<ReadPreferenceSpec>(
+            json!({ "mode": "nearest", "max_staleness_seconds": 10 }),
+        );
+        assert!(low.unwrap_err().to_string().contains("at least 90"));
+        let primary = serde_json::from_value::<ReadPreferenceSpec>(
+            json!({ "mode": "primary", "tag_sets": [{ "dc": "east" }] }),
+        );
+        assert!(primary.is_err());
+    }
+
+    #[test]
+    fn parses_write_concern_forms() {
+        assert_eq!(
+            write_concern(json!("majority")).w,
+            Some(Acknowledgment::Majority)
+        );
+        assert_eq!(write_concern(json!(2)).w, Some(Acknowledgment::Nodes(2)));
+        let detailed = write_concern(json!({ "w": "dc-east", "j": true, "wtimeout_ms": 500 }));
+        assert_eq!(detailed.w, Some(Acknowledgment::Custom("dc-east".into())));
+        assert_eq!(detailed.journal, Some(true));
+        assert_eq!(detailed.timeout, Some(Duration::from_millis(500)));
+        assert!(serde_json::from_value::<WriteConcernSpec>(json!(0)).is_err());
+    }
+
+    #[test]
+    fn write_concern_floor_ordering() {
+        let majority = write_concern(json!({ "w": "majority", "j": true }));
+        assert!(majority.satisfies(&write_concern(json!(3))));
+        assert!(!write_concern(json!(1)).satisfies(&write_concern(json!("majority"))));
+        assert!(!write_concern(json!("majority")).satisfies(&majority));
+        assert!(write_concern(json!({ "j": true })).satisfies(&write_concern(json!(1))));
+        assert!(!write_concern(json!("dc-east")).satisfies(&write_concern(json!("dc-west"))));
+    }
+
+    fn rules() -> ConsistencyRules {
+        ConsistencyRules::new(&[NamespaceConfig {
+            namespace: "app.orders".into(),
+            read_preference: Some(serde_json::from_value(json!("secondaryPreferred")).unwrap()),
+            read_concern: Some(ReadConcernSpec::Majority),
+            write_concern: None,
+            min_write_concern: Some(write_concern(json!("majority"))),
+            version_field: Some("meta.rev".into()),
+            versioned: false,
+            max_versioned_documents: None,
+            soft_delete: false,
+            timestamps: false,
+        }])
+    }
+
+    #[test]
+    fn version_field_comes_from_matching_rule() {
+        assert_eq!(rules().version_field("app", "orders"), Some("meta.rev"));
+        assert_eq!(rules().version_field("app", "users"), None);
+
+        let versioned = ConsistencyRules::new(&[NamespaceConfig {
+            namespace: "app.users".into(),
+            read_preference: None,
+            read_concern: None,
+            write_concern: None,
+            min_write_concern: None,
+            version_field: None,
+            versioned: true,
+            max_versioned_documents: Some(NonZeroU32::new(50).unwrap()),
+            soft_delete: true,
+            timestamps: true,
+        }]);
+        assert!(versioned.versioned("app", "users"));
+        assert!(!rules().versioned("app", "orders"));
+        assert!(versioned.soft_delete("app", "users"));
+        assert!(!rules().soft_delete("app", "orders"));
+        assert!(versioned.timestamps("app", "users"));
+        assert!(!rules().timestamps("app", "orders"));
+        assert_eq!(versioned.version_field("app", "users"), Some(VERSION_FIELD));
+        assert_eq!(versioned.max_versioned_documents("app", "users"), 50);
+        assert_eq!(
+            rules().max_versioned_documents("app", "orders"),
+            history::DEFAULT_MAX_DOCUMENTS
+        );
+    }
+
+    #[test]
+    fn namespace_defaults_apply_and_floor_fills_in() {
+        let options = rules()
+            .options("app", "orders", &Consistency::default())
+            .unwrap();
+        assert!(options.selection_criteria.is_some());
+        assert_eq!(options.read_concern, Some(ReadConcern::majority()));
+        assert_eq!(options.write_concern, Some(WriteConcern::MAJORITY));
+
+        let unmatched = rules()
+            .options("app", "users", &Consistency::default())
+            .unwrap();
+        assert!(unmatched.write_concern.is_none());
+    }
+
+    #[test]
+    fn request_cannot_downgrade_below_floor() {
+        let request = Consistency {
+            write_concern: Some(write_concern(json!(1))),
+            ..Default::default()
+        };
+        let err = rules()
+            .options("app", "orders", &request)
+            .expect_err("downgrade");
+        assert_eq!(err.status().as_u16(), 400);
+        assert!(rules().options("app", "users", &request).is_ok());
+    }
+}
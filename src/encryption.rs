@@ -0,0 +1,726 @@
+use base64::Engine;
+use mongodb::bson::spec::BinarySubtype;
+use mongodb::bson::{self, doc, Binary, Bson, Document};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::cluster::NamespacePattern;
+use crate::config::{EncryptionConfig, EncryptionMode};
+use crate::error::ApiError;
+use crate::fields::{overlaps, visit_path, within};
+
+/// Binary subtype marking values encrypted by the gateway.
+const SUBTYPE: u8 = 0x80;
+const FORMAT_VERSION: u8 = 1;
+const KEY_LEN: usize = 32;
+
+#[derive(Debug, Error)]
+pub enum EncryptionError {
+    #[error("failed to read key file `{}`: {source}", path.display())]
+    Read { path: PathBuf, source: io::Error },
+    #[error("invalid key file `{}`: {message}", path.display())]
+    Invalid { path: PathBuf, message: String },
+}
+
+/// `{ "active_key": "2026-10", "keys": { "2026-10": "<base64>", ... } }`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyFile {
+    active_key: String,
+    keys: BTreeMap<String, String>,
+}
+
+struct DataKey {
+    id: Arc<str>,
+    cipher: LessSafeKey,
+    nonce: hmac::Key,
+}
+
+/// Data keys from the key file. The active key encrypts; every key decrypts,
+/// so retired keys stay in the file until their data has been rewritten.
+struct Keyring {
+    /// Active key first.
+    keys: Vec<DataKey>,
+}
+
+impl Keyring {
+    fn load(path: &Path) -> Result<Self, EncryptionError> {
+        let invalid = |message: String| EncryptionError::Invalid {
+            path: path.to_path_buf(),
+            message,
+        };
+        let text = std::fs::read_to_string(path).map_err(|source| EncryptionError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let file: KeyFile = serde_json::from_str(&text).map_err(|err| invalid(err.to_string()))?;
+        if !file.keys.contains_key(&file.active_key) {
+            return Err(invalid(format!(
+                "active key `{}` is not defined in `keys`",
+                file.active_key
+            )));
+        }
+        let mut keys = Vec::with_capacity(file.keys.len());
+        for (id, encoded) in &file.keys {
+            if id.is_empty() || id.len() > usize::from(u8::MAX) {
+                return Err(invalid(format!("key id `{id}` must be 1-255 bytes")));
+            }
+            let material = base64::engine::general_purpose::STANDARD
+                .decode(encoded.trim())
+                .ok()
+                .filter(|material| material.len() == KEY_LEN)
+                .ok_or_else(|| invalid(format!("key `{id}` must be {KEY_LEN} bytes of base64")))?;
+            keys.push(DataKey::derive(id, &material));
+        }
+        keys.sort_by_key(|key| key.id.as_ref() != file.active_key);
+        Ok(Self { keys })
+    }
+
+    fn active(&self) -> &DataKey {
+        &self.keys[0]
+    }
+
+    fn get(&self, id: &str) -> Option<&DataKey> {
+        self.keys.iter().find(|key| key.id.as_ref() == id)
+    }
+}
+
+impl DataKey {
+    /// Derives separate subkeys for AES-256-GCM and for deterministic nonces.
+    fn derive(id: &str, material: &[u8]) -> Self {
+        let master = hmac::Key::new(hmac::HMAC_SHA256, material);
+        let cipher = hmac::sign(&master, b"field-encryption");
+        let nonce = hmac::sign(&master, b"deterministic-nonce");
+        Self {
+            id: Arc::from(id),
+            cipher: LessSafeKey::new(
+                UnboundKey::new(&AES_256_GCM, cipher.as_ref())
+                    .expect("HMAC-SHA256 yields 32 bytes"),
+            ),
+            nonce: hmac::Key::new(hmac::HMAC_SHA256, nonce.as_ref()),
+        }
+    }
+
+    /// Layout: format version, key id length, key id, nonce, ciphertext and tag.
+    fn encrypt(&self, field: &str, value: &Bson, mode: EncryptionMode) -> Result<Bson, ApiError> {
+        let mut buffer = bson::to_vec(&doc! { "v": value }).map_err(|err| {
+            ApiError::validation(format!("cannot encrypt field `{field}`: {err}"))
+        })?;
+        let mut nonce = [0u8; NONCE_LEN];
+        match mode {
+            EncryptionMode::Deterministic => {
+                // Equal values in the same field share a nonce, and so a ciphertext
+                let mut context = hmac::Context::with_key(&self.nonce);
+                context.update(field.as_bytes());
+                context.update(&[0]);
+                context.update(&buffer);
+                nonce.copy_from_slice(&context.sign().as_ref()[..NONCE_LEN]);
+            }
+            EncryptionMode::Randomized => SystemRandom::new()
+                .fill(&mut nonce)
+                .map_err(|_| ApiError::internal("failed to generate an encryption nonce"))?,
+        }
+        self.cipher
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(self.id.as_bytes()),
+                &mut buffer,
+            )
+            .map_err(|_| ApiError::internal(format!("failed to encrypt field `{field}`")))?;
+        let mut bytes = Vec::with_capacity(2 + self.id.len() + NONCE_LEN + buffer.len());
+        bytes.push(FORMAT_VERSION);
+        bytes.push(self.id.len() as u8);
+        bytes.extend_from_slice(self.id.as_bytes());
+        bytes.extend_from_slice(&nonce);
+        bytes.extend_from_slice(&buffer);
+        Ok(Bson::Binary(Binary {
+            subtype: BinarySubtype::UserDefined(SUBTYPE),
+            bytes,
+        }))
+    }
+}
+
+/// Encrypted fields from config and the key ring they are encrypted with.
+#[derive(Default)]
+pub struct Encryptor {
+    keyring: Option<Arc<Keyring>>,
+    fields: Vec<EncryptedField>,
+}
+
+struct EncryptedField {
+    pattern: NamespacePattern,
+    path: Arc<str>,
+    mode: EncryptionMode,
+}
+
+impl Encryptor {
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Reads the key file; encryption stays disabled without one.
+    pub fn load(config: &EncryptionConfig) -> Result<Self, EncryptionError> {
+        let Some(path) = &config.key_file else {
+            return Ok(Self::disabled());
+        };
+        Ok(Self {
+            keyring: Some(Arc::new(Keyring::load(path)?)),
+            fields: config
+                .fields
+                .iter()
+                .map(|field| EncryptedField {
+                    pattern: NamespacePattern::parse(&field.namespace),
+                    path: Arc::from(field.field.as_str()),
+                    mode: field.mode,
+                })
+                .collect(),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.keyring.is_some()
+    }
+
+    /// Id of the key new values are encrypted with.
+    pub fn active_key(&self) -> Option<&str> {
+        self.keyring
+            .as_ref()
+            .map(|keyring| keyring.active().id.as_ref())
+    }
+
+    /// The encrypted fields of one namespace. Reads are decrypted whatever the
+    /// namespace, so fields removed from config can still be read back.
+    pub fn cipher(&self, database: &str, collection: &str) -> FieldCipher {
+        FieldCipher {
+            keyring: self.keyring.clone(),
+            fields: self
+                .fields
+                .iter()
+                .filter(|field| field.pattern.matches(database, Some(collection)))
+                .map(|field| (field.path.clone(), field.mode))
+                .collect(),
+        }
+    }
+}
+
+/// Encrypts request values and decrypts results for one namespace.
+#[derive(Clone, Default)]
+pub struct FieldCipher {
+    keyring: Option<Arc<Keyring>>,
+    fields: Vec<(Arc<str>, EncryptionMode)>,
+}
+
+impl FieldCipher {
+    /// Encrypts the configured fields of a document being inserted or used as
+    /// a replacement. Missing and `null` fields are left as they are.
+    pub fn encrypt_document(&self, document: &mut Document) -> Result<(), ApiError> {
+        let Some(keyring) = self.keyring() else {
+            return Ok(());
+        };
+        for (field, mode) in &self.fields {
+            encrypt_path(keyring.active(), document, field, field, *mode)?;
+        }
+        Ok(())
+    }
+
+    /// Encrypts values given to `$set` and `$setOnInsert`. Other operators
+    /// cannot work on ciphertext and are rejected for encrypted fields.
+    pub fn encrypt_update(&self, update: &mut Document) -> Result<(), ApiError> {
+        let Some(keyring) = self.keyring() else {
+            return Ok(());
+        };
+        for (operator, assignments) in update.iter_mut() {
+            let Bson::Document(assignments) = assignments else {
+                continue;
+            };
+            let setter = matches!(operator.as_str(), "$set" | "$setOnInsert");
+            for (target, value) in assignments.iter_mut() {
+                let path = normalize(target);
+                for (field, mode) in &self.fields {
+                    if !overlaps(field, &path) || operator == "$unset" {
+                        continue;
+                    }
+                    if !setter {
+                        return Err(ApiError::validation(format!(
+                            "`{operator}` cannot be applied to encrypted field `{field}`"
+                        )));
+                    }
+                    if path == field.as_ref() {
+                        encrypt_value(keyring.active(), value, field, *mode)?;
+                    } else if within(field, &path) {
+                        // Setting a parent: encrypt the field inside the new value
+                        let rest = &field[path.len() + 1..];
+                        encrypt_nested(keyring.active(), value, rest, field, *mode)?;
+                    } else {
+                        return Err(ApiError::validation(format!(
+                            "cannot set `{target}` inside encrypted field `{field}`"
+                        )));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Rewrites equality conditions on deterministic fields to match the
+    /// ciphertext under every key, so values written before a key rotation
+    /// still match.
+    pub fn encrypt_filter(&self, filter: &mut Document) -> Result<(), ApiError> {
+        if self.fields.is_empty() {
+            return Ok(());
+        }
+        self.encrypt_filter_at(filter, None)
+    }
+
+    /// Applies [`FieldCipher::encrypt_filter`] to the pipeline's `$match` stages.
+    pub fn encrypt_pipeline(&self, pipeline: &mut [Document]) -> Result<(), ApiError> {
+        for stage in pipeline {
+            if let Some(Bson::Document(filter)) = stage.get_mut("$match") {
+                self.encrypt_filter(filter)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Decrypts every value the gateway encrypted, wherever it appears.
+    pub fn decrypt(&self, document: &mut Document) -> Result<(), ApiError> {
+        let Some(keyring) = self.keyring() else {
+            return Ok(());
+        };
+        document
+            .iter_mut()
+            .try_for_each(|(key, value)| decrypt_value(keyring, key, value))
+    }
+
+    fn keyring(&self) -> Option<&Keyring> {
+        self.keyring.as_deref()
+    }
+
+    fn encrypt_filter_at(
+        &self,
+        filter: &mut Document,
+        prefix: Option<&str>,
+    ) -> Result<(), ApiError> {
+        for (key, value) in filter.iter_mut() {
+            if key == "$expr" {
+                if let Some(field) = self.referenced_field(value) {
+                    return Err(ApiError::validation(format!(
+                        "encrypted field `{field}` cannot be used in `$expr`"
+                    )));
+                }
+                continue;
+            }
+            if key.starts_with('$') {
+                // `$and`, `$or` and `$nor` hold nested filters
+                match value {
+                    Bson::Array(items) => {
+                        for item in items {
+                            if let Bson::Document(nested) = item {
+                                self.encrypt_filter_at(nested, prefix)?;
+                            }
+                        }
+                    }
+                    Bson::Document(nested) => self.encrypt_filter_at(nested, prefix)?,
+                    _ => {}
+                }
+                continue;
+            }
+            let path = match prefix {
+                Some(prefix) => format!("{prefix}.{}", normalize(key)),
+                None => normalize(key),
+            };
+            for (field, mode) in &self.fields {
+                if path == field.as_ref() {
+                    self.encrypt_condition(field, *mode, value)?;
+                } else if within(&path, field) {
+                    return Err(ApiError::validation(format!(
+                        "cannot query `{key}` inside encrypted field `{field}`"
+                    )));
+                } else if within(field, &path) {
+                    if let Some(Bson::Document(inner)) = value
+                        .as_document_mut()
+                        .and_then(|operators| operators.get_mut("$elemMatch"))
+                    {
+                        self.encrypt_filter_at(inner, Some(&path))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn encrypt_condition(
+        &self,
+        field: &str,
+        mode: EncryptionMode,
+        condition: &mut Bson,
+    ) -> Result<(), ApiError> {
+        let unsupported = || match mode {
+            EncryptionMode::Randomized => ApiError::validation(format!(
+                "field `{field}` uses randomized encryption and can only be queried with `$exists`"
+            )),
+            EncryptionMode::Deterministic => ApiError::validation(format!(
+                "field `{field}` is encrypted; only `$eq`, `$ne`, `$in`, `$nin` and `$exists` \
+                 are supported"
+            )),
+        };
+        let operators = match condition {
+            Bson::Document(operators)
+                if operators
+                    .keys()
+                    .next()
+                    .is_some_and(|key| key.starts_with('$')) =>
+            {
+                operators
+            }
+            value => {
+                if mode == EncryptionMode::Randomized {
+                    return Err(unsupported());
+                }
+                *value =
+                    doc! { "$in": self.candidates(field, std::slice::from_ref(value))? }.into();
+                return Ok(());
+            }
+        };
+        let mut translated = Document::new();
+        for (operator, operand) in operators.iter() {
+            let (target, values) = match (operator.as_str(), mode, operand) {
+                ("$exists", _, _) => {
+                    translated.insert(operator, operand.clone());
+                    continue;
+                }
+                ("$eq", EncryptionMode::Deterministic, value) => {
+                    ("$in", std::slice::from_ref(value))
+                }
+                ("$ne", EncryptionMode::Deterministic, value) => {
+                    ("$nin", std::slice::from_ref(value))
+                }
+                ("$in", EncryptionMode::Deterministic, Bson::Array(values)) => ("$in", &values[..]),
+                ("$nin", EncryptionMode::Deterministic, Bson::Array(values)) => {
+                    ("$nin", &values[..])
+                }
+                _ => return Err(unsupported()),
+            };
+            if translated.contains_key(target) {
+                return Err(ApiError::validation(format!(
+                    "combine the conditions on encrypted field `{field}` into a single `{target}`"
+                )));
+            }
+            translated.insert(target, self.candidates(field, values)?);
+        }
+        *operators = translated;
+        Ok(())
+    }
+
+    /// Ciphertexts of `values` under every key; `null` matches itself.
+    fn candidates(&self, field: &str, values: &[Bson]) -> Result<Vec<Bson>, ApiError> {
+        let Some(keyring) = self.keyring() else {
+            return Ok(values.to_vec());
+        };
+        let mut candidates = Vec::with_capacity(values.len() * keyring.keys.len());
+        for value in values {
+            if value == &Bson::Null {
+                candidates.push(Bson::Null);
+                continue;
+            }
+            for key in &keyring.keys {
+                candidates.push(key.encrypt(field, value, EncryptionMode::Deterministic)?);
+            }
+        }
+        Ok(candidates)
+    }
+
+    /// An encrypted field referenced as `$field` inside an expression.
+    fn referenced_field(&self, value: &Bson) -> Option<&str> {
+        match value {
+            Bson::String(text) if text.starts_with('$') && !text.starts_with("$$") => self
+                .fields
+                .iter()
+                .find(|(field, _)| overlaps(field, &text[1..]))
+                .map(|(field, _)| field.as_ref()),
+            Bson::Document(document) => document
+                .values()
+                .find_map(|value| self.referenced_field(value)),
+            Bson::Array(items) => items.iter().find_map(|value| self.referenced_field(value)),
+            _ => None,
+        }
+    }
+}
+
+/// Drops array indexes and positional operators, e.g. `items.$.ssn` becomes
+/// `items.ssn`.
+fn normalize(path: &str) -> String {
+    path.split('.')
+        .filter(|segment| !segment.starts_with('$') && !segment.chars().all(|c| c.is_ascii_digit()))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn encrypt_path(
+    key: &DataKey,
+    document: &mut Document,
+    path: &str,
+    field: &str,
+    mode: EncryptionMode,
+) -> Result<(), ApiError> {
+    let mut result = Ok(());
+    visit_path(document, path, &mut |parent, name| {
+        if result.is_ok() {
+            if let Some(value) = parent.get_mut(name) {
+                result = encrypt_value(key, value, field, mode);
+            }
+        }
+    });
+    result
+}
+
+fn encrypt_nested(
+    key: &DataKey,
+    value: &mut Bson,
+    rest: &str,
+    field: &str,
+    mode: EncryptionMode,
+) -> Result<(), ApiError> {
+    match value {
+        Bson::Document(document) => encrypt_path(key, document, rest, field, mode),
+        Bson::Array(items) => items
+            .iter_mut()
+            .try_for_each(|item| encrypt_nested(key, item, rest, field, mode)),
+        _ => Ok(()),
+    }
+}
+
+fn encrypt_value(
+    key: &DataKey,
+    value: &mut Bson,
+    field: &str,
+    mode: EncryptionMode,
+) -> Result<(), ApiError> {
+    if *value != Bson::Null {
+        *value = key.encrypt(field, value, mode)?;
+    }
+    Ok(())
+}
+
+fn decrypt_value(keyring: &Keyring, name: &str, value: &mut Bson) -> Result<(), ApiError> {
+    match value {
+        Bson::Binary(binary) if binary.subtype == BinarySubtype::UserDefined(SUBTYPE) => {
+            *value = decrypt_bytes(keyring, name, &binary.bytes)?;
+            Ok(())
+        }
+        Bson::Document(document) => document
+            .iter_mut()
+            .try_for_each(|(key, value)| decrypt_value(keyring, key, value)),
+        Bson::Array(items) => items
+            .iter_mut()
+            .try_for_each(|item| decrypt_value(keyring, name, item)),
+        _ => Ok(()),
+    }
+}
+
+fn decrypt_bytes(keyring: &Keyring, name: &str, bytes: &[u8]) -> Result<Bson, ApiError> {
+    let malformed = || ApiError::internal(format!("encrypted field `{name}` is malformed"));
+    let (&version, rest) = bytes.split_first().ok_or_else(malformed)?;
+    if version != FORMAT_VERSION {
+        return Err(ApiError::internal(format!(
+            "encrypted field `{name}` uses unsupported format version {version}"
+        )));
+    }
+    let (&id_len, rest) = rest.split_first().ok_or_else(malformed)?;
+    let id_len = usize::from(id_len);
+    if rest.len() < id_len + NONCE_LEN {
+        return Err(malformed());
+    }
+    let (id, rest) = rest.split_at(id_len);
+    let id = std::str::from_utf8(id).map_err(|_| malformed())?;
+    let key = keyring.get(id).ok_or_else(|| {
+        ApiError::internal(format!(
+            "field `{name}` is encrypted with key `{id}`, which is not in the key file"
+        ))
+    })?;
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| malformed())?;
+    let mut buffer = ciphertext.to_vec();
+    let plaintext = key
+        .cipher
+        .open_in_place(nonce, Aad::from(id.as_bytes()), &mut buffer)
+        .map_err(|_| ApiError::internal(format!("failed to decrypt field `{name}`")))?;
+    let document = Document::from_reader(&plaintext[..]).map_err(|_| malformed())?;
+    document.get("v").cloned().ok_or_else(malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EncryptedFieldConfig;
+
+    fn key_file(name: &str, active: &str, keys: &[(&str, u8)]) -> PathBuf {
+        let keys: BTreeMap<&str, String> = keys
+            .iter()
+            .map(|(id, fill)| {
+                (
+                    *id,
+                    base64::engine::general_purpose::STANDARD.encode([*fill; KEY_LEN]),
+                )
+            })
+            .collect();
+        let path = std::env::temp_dir().join(format!(
+            "hello_rust_keys_{}_{name}.json",
+            std::process::id()
+        ));
+        let body = serde_json::json!({ "active_key": active, "keys": keys });
+        std::fs::write(&path, body.to_string()).unwrap();
+        path
+    }
+
+    fn encryptor(path: &Path) -> Encryptor {
+        let field = |field: &str, mode| EncryptedFieldConfig {
+            namespace: "hr.employees".into(),
+            field: field.into(),
+            mode,
+        };
+        Encryptor::load(&EncryptionConfig {
+            key_file: Some(path.to_path_buf()),
+            fields: vec![
+                field("ssn", EncryptionMode::Deterministic),
+                field("profile.notes", EncryptionMode::Randomized),
+            ],
+        })
+        .expect("encryptor")
+    }
+
+    #[test]
+    fn round_trips_documents() {
+        let path = key_file("round_trip", "k1", &[("k1", 1)]);
+        let cipher = encryptor(&path).cipher("hr", "employees");
+        let original = doc! {
+            "name": "ada",
+            "ssn": "123-45-6789",
+            "profile": { "notes": ["private"], "team": "core" },
+        };
+        let mut document = original.clone();
+        cipher.encrypt_document(&mut document).unwrap();
+        assert!(matches!(document.get("ssn"), Some(Bson::Binary(_))));
+        assert!(matches!(
+            document.get_document("profile").unwrap().get("notes"),
+            Some(Bson::Binary(_))
+        ));
+        assert_eq!(document.get_str("name").unwrap(), "ada");
+
+        // Deterministic values repeat; randomized ones do not
+        let mut again = original.clone();
+        cipher.encrypt_document(&mut again).unwrap();
+        assert_eq!(document.get("ssn"), again.get("ssn"));
+        assert_ne!(
+            document.get_document("profile").unwrap().get("notes"),
+            again.get_document("profile").unwrap().get("notes")
+        );
+
+        cipher.decrypt(&mut document).unwrap();
+        assert_eq!(document, original);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn equality_filters_match_every_key() {
+        let old = key_file("rotation_old", "k1", &[("k1", 1)]);
+        let mut stored = doc! { "ssn": "123-45-6789" };
+        encryptor(&old)
+            .cipher("hr", "employees")
+            .encrypt_document(&mut stored)
+            .unwrap();
+
+        let rotated = key_file("rotation_new", "k2", &[("k1", 1), ("k2", 2)]);
+        let cipher = encryptor(&rotated).cipher("hr", "employees");
+        let mut filter =
+            doc! { "$or": [{ "ssn": "123-45-6789" }, { "ssn": { "$exists": false } }] };
+        cipher.encrypt_filter(&mut filter).unwrap();
+        let condition = filter.get_array("$or").unwrap()[0]
+            .as_document()
+            .unwrap()
+            .get_document("ssn")
+            .unwrap();
+        let candidates = condition.get_array("$in").unwrap();
+        assert_eq!(candidates.len(), 2);
+        assert!(candidates.contains(stored.get("ssn").unwrap()));
+
+        let mut newer = doc! { "ssn": "123-45-6789" };
+        cipher.encrypt_document(&mut newer).unwrap();
+        assert_eq!(&candidates[0], newer.get("ssn").unwrap());
+        cipher.decrypt(&mut stored).unwrap();
+        assert_eq!(stored.get_str("ssn").unwrap(), "123-45-6789");
+        std::fs::remove_file(old).ok();
+        std::fs::remove_file(rotated).ok();
+    }
+
+    #[test]
+    fn rejects_unsupported_queries_and_updates() {
+        let path = key_file("rejects", "k1", &[("k1", 1)]);
+        let cipher = encryptor(&path).cipher("hr", "employees");
+        for filter in [
+            doc! { "ssn": { "$gt": "1" } },
+            doc! { "profile.notes": "private" },
+            doc! { "$expr": { "$eq": ["$ssn", "x"] } },
+        ] {
+            assert!(
+                cipher.encrypt_filter(&mut filter.clone()).is_err(),
+                "{filter}"
+            );
+        }
+        assert!(cipher
+            .encrypt_update(&mut doc! { "$push": { "profile.notes": "x" } })
+            .is_err());
+
+        let mut update = doc! {
+            "$set": { "ssn": "987-65-4321", "profile": { "notes": "n", "team": "t" } },
+            "$unset": { "ssn": "" },
+        };
+        cipher.encrypt_update(&mut update).unwrap();
+        let set = update.get_document("$set").unwrap();
+        assert!(matches!(set.get("ssn"), Some(Bson::Binary(_))));
+        assert!(matches!(
+            set.get_document("profile").unwrap().get("notes"),
+            Some(Bson::Binary(_))
+        ));
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn reports_missing_keys() {
+        let path = key_file("missing", "k2", &[("k2", 2)]);
+        let old = key_file("missing_old", "k1", &[("k1", 1)]);
+        let mut document = doc! { "ssn": "123" };
+        encryptor(&old)
+            .cipher("hr", "employees")
+            .encrypt_document(&mut document)
+            .unwrap();
+        let err = encryptor(&path)
+            .cipher("other", "namespace")
+            .decrypt(&mut document)
+            .expect_err("missing key");
+        assert!(
+            err.body().details.contains("key `k1`"),
+            "{}",
+            err.body().details
+        );
+
+        let bad = key_file("bad_active", "k9", &[("k1", 1)]);
+        let err = Encryptor::load(&EncryptionConfig {
+            key_file: Some(bad.clone()),
+            fields: Vec::new(),
+        })
+        .err()
+        .expect("invalid key file");
+        assert!(err.to_string().contains("active key `k9`"));
+        for path in [path, old, bad] {
+            std::fs::remove_file(path).ok();
+        }
+    }
+}
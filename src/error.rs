@@ -1,7 +1,9 @@
-use axum::http::StatusCode;
+use axum::http::header::RETRY_AFTER;
+use axum::http::{HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::Json;
 use serde::Serialize;
+use std::time::Duration;
 use uuid::Uuid;
 
 #[derive(Debug, Serialize)]
@@ -16,6 +18,7 @@ pub struct ErrorResponse {
 pub struct ApiError {
     status: StatusCode,
     body: ErrorResponse,
+    retry_after: Option<Duration>,
 }
 
 impl ApiError {
@@ -27,6 +30,7 @@ impl ApiError {
                 details: details.into(),
                 correlation_id: None,
             },
+            retry_after: None,
         }
     }
 
@@ -38,6 +42,67 @@ impl ApiError {
                 details: details.into(),
                 correlation_id: None,
             },
+            retry_after: None,
+        }
+    }
+
+    pub fn unauthorized(details: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::UNAUTHORIZED,
+            body: ErrorResponse {
+                error: "unauthorized",
+                details: details.into(),
+                correlation_id: None,
+            },
+            retry_after: None,
+        }
+    }
+
+    pub fn forbidden(details: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::FORBIDDEN,
+            body: ErrorResponse {
+                error: "forbidden",
+                details: details.into(),
+                correlation_id: None,
+            },
+            retry_after: None,
+        }
+    }
+
+    pub fn precondition_failed(details: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::PRECONDITION_FAILED,
+            body: ErrorResponse {
+                error: "precondition_failed",
+                details: details.into(),
+                correlation_id: None,
+            },
+            retry_after: None,
+        }
+    }
+
+    pub fn range_not_satisfiable(details: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::RANGE_NOT_SATISFIABLE,
+            body: ErrorResponse {
+                error: "range_not_satisfiable",
+                details: details.into(),
+                correlation_id: None,
+            },
+            retry_after: None,
+        }
+    }
+
+    pub fn payload_too_large(details: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+            body: ErrorResponse {
+                error: "payload_too_large",
+                details: details.into(),
+                correlation_id: None,
+            },
+            retry_after: None,
         }
     }
 
@@ -50,17 +115,75 @@ impl ApiError {
                 details: details.into(),
                 correlation_id: Some(correlation_id),
             },
+            retry_after: None,
+        }
+    }
+
+    pub fn internal(details: impl Into<String>) -> Self {
+        let correlation_id = Uuid::new_v4().to_string();
+        Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            body: ErrorResponse {
+                error: "internal_error",
+                details: details.into(),
+                correlation_id: Some(correlation_id),
+            },
+            retry_after: None,
+        }
+    }
+
+    pub fn rate_limited(details: impl Into<String>, retry_after: Duration) -> Self {
+        Self {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            body: ErrorResponse {
+                error: "rate_limited",
+                details: details.into(),
+                correlation_id: None,
+            },
+            retry_after: Some(retry_after),
+        }
+    }
+
+    pub fn unavailable(details: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            body: ErrorResponse {
+                error: "service_unavailable",
+                details: details.into(),
+                correlation_id: None,
+            },
+            retry_after: None,
         }
     }
 
     pub fn status(&self) -> StatusCode {
         self.status
     }
+
+    pub fn body(&self) -> &ErrorResponse {
+        &self.body
+    }
+
+    /// Whole seconds to wait before retrying, rounded up so clients never
+    /// retry early.
+    pub fn retry_after_secs(&self) -> Option<u64> {
+        self.retry_after.map(|retry_after| {
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            seconds.max(1)
+        })
+    }
 }
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
-        (self.status, Json(self.body)).into_response()
+        let retry_after = self.retry_after_secs();
+        let mut response = (self.status, Json(self.body)).into_response();
+        if let Some(seconds) = retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response
     }
 }
 
@@ -85,6 +208,63 @@ mod tests {
         assert!(error.body.correlation_id.is_some());
     }
 
+    #[test]
+    fn internal_error_provides_correlation_id() {
+        let error = ApiError::internal("encode");
+        assert_eq!(error.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error.body.error, "internal_error");
+        assert!(error.body.correlation_id.is_some());
+    }
+
+    #[test]
+    fn rate_limited_error_sets_retry_after_header() {
+        let error = ApiError::rate_limited("slow down", Duration::from_millis(1500));
+        assert_eq!(error.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(error.body.error, "rate_limited");
+        let response = error.into_response();
+        assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "2");
+    }
+
+    #[test]
+    fn unavailable_error_has_expected_shape() {
+        let error = ApiError::unavailable("shutting down");
+        assert_eq!(error.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(error.body.error, "service_unavailable");
+        assert!(error.body.correlation_id.is_none());
+    }
+
+    #[test]
+    fn forbidden_error_has_expected_shape() {
+        let error = ApiError::forbidden("restricted field");
+        assert_eq!(error.status(), StatusCode::FORBIDDEN);
+        assert_eq!(error.body.error, "forbidden");
+        assert!(error.body.correlation_id.is_none());
+    }
+
+    #[test]
+    fn precondition_failed_error_has_expected_shape() {
+        let error = ApiError::precondition_failed("document has changed");
+        assert_eq!(error.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(error.body.error, "precondition_failed");
+        assert!(error.body.correlation_id.is_none());
+    }
+
+    #[test]
+    fn range_not_satisfiable_error_has_expected_shape() {
+        let error = ApiError::range_not_satisfiable("range starts past the end of the file");
+        assert_eq!(error.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(error.body.error, "range_not_satisfiable");
+        assert!(error.body.correlation_id.is_none());
+    }
+
+    #[test]
+    fn payload_too_large_error_has_expected_shape() {
+        let error = ApiError::payload_too_large("request body exceeds the limit of 1024 bytes");
+        assert_eq!(error.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(error.body.error, "payload_too_large");
+        assert!(error.body.correlation_id.is_none());
+    }
+
     #[test]
     fn not_found_error_has_expected_shape() {
         let error = ApiError::not_found("document not found");
@@ -0,0 +1,232 @@
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::header::{IF_MATCH, IF_NONE_MATCH};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderName};
+use mongodb::bson::{doc, Bson, Document};
+use ring::hmac;
+use ring::rand::SystemRandom;
+use std::convert::Infallible;
+use std::fmt::Write;
+
+/// Bytes of the HMAC-SHA256 tag kept in an ETag.
+const ETAG_BYTES: usize = 16;
+
+/// Server secret ETags are keyed with. Tags cover fields a caller may not be
+/// allowed to read, so an unkeyed hash would let them be brute-forced.
+#[derive(Debug, Clone)]
+pub struct EtagKey(hmac::Key);
+
+impl EtagKey {
+    /// Key derived from `secret`, or a random one for this process.
+    pub fn new(secret: Option<&str>) -> Self {
+        let key = match secret {
+            Some(secret) => hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()),
+            None => hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+                .expect("system random source is available"),
+        };
+        Self(key)
+    }
+}
+
+/// How a document version is identified for `ETag`, `If-Match` and
+/// `If-None-Match`: by a configured version field or, without one (or when a
+/// document lacks it), by the whole document.
+#[derive(Debug, Clone)]
+pub struct Versioning {
+    field: Option<String>,
+    key: EtagKey,
+}
+
+impl Versioning {
+    pub fn new(field: Option<String>, key: EtagKey) -> Self {
+        Self { field, key }
+    }
+
+    /// Strong ETag of a decrypted document as stored, before projections and
+    /// field policies, so every caller and every precondition check sees the
+    /// same tag.
+    pub fn etag(&self, document: &Document) -> String {
+        let hashed = match self.version(document) {
+            Some(version) => doc! { "version": version.clone() },
+            None => document.clone(),
+        };
+        let bytes = mongodb::bson::to_vec(&hashed).unwrap_or_default();
+        let tag = hmac::sign(&self.key.0, &bytes);
+        let mut etag = String::with_capacity(ETAG_BYTES * 2 + 2);
+        etag.push('"');
+        for byte in &tag.as_ref()[..ETAG_BYTES] {
+            let _ = write!(etag, "{byte:02x}");
+        }
+        etag.push('"');
+        etag
+    }
+
+    /// Filter that matches `stored`, as read from MongoDB, only while it is
+    /// still at the same version.
+    pub fn guard(&self, stored: &Document) -> Document {
+        let mut guard = Document::new();
+        if let Some(id) = stored.get("_id") {
+            guard.insert("_id", id.clone());
+        }
+        match (&self.field, self.version(stored)) {
+            (Some(field), Some(version)) => {
+                guard.insert(field.clone(), version.clone());
+            }
+            _ => {
+                guard.insert(
+                    "$expr",
+                    doc! { "$eq": ["$$ROOT", { "$literal": stored.clone() }] },
+                );
+            }
+        }
+        guard
+    }
+
+    fn version<'a>(&self, document: &'a Document) -> Option<&'a Bson> {
+        let field = self.field.as_deref()?;
+        let (parents, last) = match field.rsplit_once('.') {
+            Some((parents, last)) => (Some(parents), last),
+            None => (None, field),
+        };
+        let mut current = document;
+        for segment in parents.into_iter().flat_map(|parents| parents.split('.')) {
+            current = current.get_document(segment).ok()?;
+        }
+        current.get(last)
+    }
+}
+
+/// An `If-Match` or `If-None-Match` header value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntityTags {
+    /// `*`, which matches any existing document.
+    Any,
+    Tags(Vec<String>),
+}
+
+impl EntityTags {
+    fn parse(headers: &HeaderMap, name: HeaderName) -> Option<Self> {
+        let tags: Vec<String> = headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(str::to_string)
+            .collect();
+        if tags.is_empty() {
+            None
+        } else if tags.iter().any(|tag| tag == "*") {
+            Some(Self::Any)
+        } else {
+            Some(Self::Tags(tags))
+        }
+    }
+
+    /// Strong comparison, used for `If-Match`: weak tags never match.
+    pub fn matches_strong(&self, etag: &str) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Tags(tags) => tags.iter().any(|tag| tag == etag),
+        }
+    }
+
+    /// Weak comparison, used for `If-None-Match`.
+    pub fn matches_weak(&self, etag: &str) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Tags(tags) => tags
+                .iter()
+                .any(|tag| tag.strip_prefix("W/").unwrap_or(tag) == etag),
+        }
+    }
+}
+
+/// Conditional request headers.
+#[derive(Debug, Clone, Default)]
+pub struct Preconditions {
+    pub if_match: Option<EntityTags>,
+    pub if_none_match: Option<EntityTags>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Preconditions
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Preconditions {
+            if_match: EntityTags::parse(&parts.headers, IF_MATCH),
+            if_none_match: EntityTags::parse(&parts.headers, IF_NONE_MATCH),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn key() -> EtagKey {
+        EtagKey::new(Some("test-secret"))
+    }
+
+    #[test]
+    fn etag_follows_version_field_or_contents() {
+        let by_contents = Versioning::new(None, key());
+        let a = doc! { "_id": 1, "name": "lamp" };
+        let b = doc! { "_id": 1, "name": "desk" };
+        assert_ne!(by_contents.etag(&a), by_contents.etag(&b));
+        assert_eq!(by_contents.etag(&a), by_contents.etag(&a.clone()));
+        assert!(by_contents.etag(&a).starts_with('"'));
+
+        let by_version = Versioning::new(Some("meta.rev".to_string()), key());
+        let c = doc! { "_id": 1, "name": "lamp", "meta": { "rev": 3 } };
+        let d = doc! { "_id": 1, "name": "desk", "meta": { "rev": 3 } };
+        assert_eq!(by_version.etag(&c), by_version.etag(&d));
+        // Documents without the field fall back to their contents
+        assert_eq!(by_version.etag(&a), by_contents.etag(&a));
+    }
+
+    #[test]
+    fn etag_depends_on_the_secret() {
+        let a = doc! { "_id": 1, "ssn": "123-45-6789" };
+        let tag = Versioning::new(None, key()).etag(&a);
+        assert_eq!(tag, Versioning::new(None, key()).etag(&a));
+        let other = EtagKey::new(Some("other-secret"));
+        assert_ne!(tag, Versioning::new(None, other).etag(&a));
+        assert_ne!(tag, Versioning::new(None, EtagKey::new(None)).etag(&a));
+    }
+
+    #[test]
+    fn guard_pins_the_stored_version() {
+        let stored = doc! { "_id": 7, "rev": 2, "$odd": "x" };
+        let guard = Versioning::new(Some("rev".to_string()), key()).guard(&stored);
+        assert_eq!(guard, doc! { "_id": 7, "rev": 2 });
+
+        let guard = Versioning::new(None, key()).guard(&stored);
+        assert_eq!(
+            guard,
+            doc! { "_id": 7, "$expr": { "$eq": ["$$ROOT", { "$literal": stored.clone() }] } }
+        );
+    }
+
+    #[test]
+    fn compares_entity_tags() {
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_MATCH, HeaderValue::from_static(r#"W/"a", "b""#));
+        let tags = EntityTags::parse(&headers, IF_MATCH).unwrap();
+        assert!(tags.matches_strong(r#""b""#));
+        assert!(!tags.matches_strong(r#""a""#));
+        assert!(tags.matches_weak(r#""a""#));
+
+        headers.insert(IF_NONE_MATCH, HeaderValue::from_static("*"));
+        let any = EntityTags::parse(&headers, IF_NONE_MATCH).unwrap();
+        assert_eq!(any, EntityTags::Any);
+        assert!(EntityTags::parse(&HeaderMap::new(), IF_MATCH).is_none());
+    }
+}
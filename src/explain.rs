@@ -0,0 +1,327 @@
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::{
+    AggregateOptions, Collation, CountOptions, DeleteOptions, FindOneOptions, FindOptions, Hint,
+    ReplaceOptions, UpdateOptions,
+};
+use mongodb::Collection;
+use std::time::Duration;
+
+use crate::models::ExplainVerbosity;
+
+/// Runs `explain` for `command` against the collection's database.
+pub async fn run(
+    collection: &Collection<Document>,
+    command: Document,
+    verbosity: ExplainVerbosity,
+) -> Result<Document, mongodb::error::Error> {
+    let namespace = collection.namespace();
+    collection
+        .client()
+        .database(&namespace.db)
+        .run_command(
+            doc! { "explain": command, "verbosity": verbosity.as_str() },
+            None,
+        )
+        .await
+}
+
+pub fn find_command(
+    collection: &str,
+    filter: &Document,
+    options: Option<&FindOptions>,
+) -> Document {
+    let mut command = doc! { "find": collection, "filter": filter.clone() };
+    if let Some(options) = options {
+        insert_opt(&mut command, "projection", options.projection.clone());
+        insert_opt(&mut command, "sort", options.sort.clone());
+        insert_opt(&mut command, "skip", options.skip.map(saturating_i64));
+        // A negative limit asks for a single batch; the command takes its size
+        insert_opt(
+            &mut command,
+            "limit",
+            options
+                .limit
+                .map(|limit| saturating_i64(limit.unsigned_abs())),
+        );
+        insert_common(
+            &mut command,
+            options.hint.as_ref(),
+            options.collation.as_ref(),
+            options.max_time,
+        );
+    }
+    command
+}
+
+pub fn find_one_command(
+    collection: &str,
+    filter: &Document,
+    options: Option<&FindOneOptions>,
+) -> Document {
+    let mut command = doc! {
+        "find": collection,
+        "filter": filter.clone(),
+        "limit": 1_i64,
+        "singleBatch": true,
+    };
+    if let Some(options) = options {
+        insert_opt(&mut command, "projection", options.projection.clone());
+        insert_opt(&mut command, "sort", options.sort.clone());
+        insert_opt(&mut command, "skip", options.skip.map(saturating_i64));
+        insert_common(
+            &mut command,
+            options.hint.as_ref(),
+            options.collation.as_ref(),
+            options.max_time,
+        );
+    }
+    command
+}
+
+pub fn aggregate_command(
+    collection: &str,
+    pipeline: &[Document],
+    options: Option<&AggregateOptions>,
+) -> Document {
+    let mut command = doc! {
+        "aggregate": collection,
+        "pipeline": pipeline.to_vec(),
+        "cursor": {},
+    };
+    if let Some(options) = options {
+        insert_opt(&mut command, "allowDiskUse", options.allow_disk_use);
+        insert_common(
+            &mut command,
+            options.hint.as_ref(),
+            options.collation.as_ref(),
+            options.max_time,
+        );
+    }
+    command
+}
+
+/// The aggregate `count_documents` runs: the driver counts with a
+/// `$match`/`$group` pipeline rather than the `count` command.
+pub fn count_command(
+    collection: &str,
+    filter: &Document,
+    options: Option<&CountOptions>,
+) -> Document {
+    let mut pipeline = vec![doc! { "$match": filter.clone() }];
+    if let Some(skip) = options.and_then(|options| options.skip) {
+        pipeline.push(doc! { "$skip": saturating_i64(skip) });
+    }
+    if let Some(limit) = options.and_then(|options| options.limit) {
+        pipeline.push(doc! { "$limit": saturating_i64(limit) });
+    }
+    pipeline.push(doc! { "$group": { "_id": 1, "n": { "$sum": 1 } } });
+    let mut command = doc! {
+        "aggregate": collection,
+        "pipeline": pipeline,
+        "cursor": {},
+    };
+    if let Some(options) = options {
+        insert_common(
+            &mut command,
+            options.hint.as_ref(),
+            options.collation.as_ref(),
+            options.max_time,
+        );
+    }
+    command
+}
+
+pub fn update_command(
+    collection: &str,
+    filter: &Document,
+    update: &Document,
+    options: Option<&UpdateOptions>,
+    multi: bool,
+) -> Document {
+    let mut statement = doc! { "q": filter.clone(), "u": update.clone(), "multi": multi };
+    if let Some(options) = options {
+        insert_opt(&mut statement, "upsert", options.upsert);
+        insert_opt(
+            &mut statement,
+            "arrayFilters",
+            options.array_filters.clone(),
+        );
+        insert_common(
+            &mut statement,
+            options.hint.as_ref(),
+            options.collation.as_ref(),
+            None,
+        );
+    }
+    doc! { "update": collection, "updates": [statement] }
+}
+
+pub fn replace_command(
+    collection: &str,
+    filter: &Document,
+    replacement: &Document,
+    options: Option<&ReplaceOptions>,
+) -> Document {
+    let mut statement = doc! { "q": filter.clone(), "u": replacement.clone(), "multi": false };
+    if let Some(options) = options {
+        insert_opt(&mut statement, "upsert", options.upsert);
+        insert_common(
+            &mut statement,
+            options.hint.as_ref(),
+            options.collation.as_ref(),
+            None,
+        );
+    }
+    doc! { "update": collection, "updates": [statement] }
+}
+
+pub fn delete_command(
+    collection: &str,
+    filter: &Document,
+    options: Option<&DeleteOptions>,
+    multi: bool,
+) -> Document {
+    let limit = if multi { 0_i32 } else { 1_i32 };
+    let mut statement = doc! { "q": filter.clone(), "limit": limit };
+    if let Some(options) = options {
+        insert_common(
+            &mut statement,
+            options.hint.as_ref(),
+            options.collation.as_ref(),
+            None,
+        );
+    }
+    doc! { "delete": collection, "deletes": [statement] }
+}
+
+/// Counts past `i64::MAX` already mean "all of them", so they are clamped
+/// rather than wrapped into negative values.
+fn saturating_i64(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+fn insert_opt(document: &mut Document, key: &str, value: Option<impl Into<Bson>>) {
+    if let Some(value) = value {
+        document.insert(key, value);
+    }
+}
+
+fn insert_common(
+    document: &mut Document,
+    hint: Option<&Hint>,
+    collation: Option<&Collation>,
+    max_time: Option<Duration>,
+) {
+    if let Some(hint) = hint.and_then(|hint| mongodb::bson::to_bson(hint).ok()) {
+        document.insert("hint", hint);
+    }
+    if let Some(collation) = collation.and_then(|collation| mongodb::bson::to_bson(collation).ok())
+    {
+        document.insert("collation", collation);
+    }
+    insert_opt(
+        document,
+        "maxTimeMS",
+        max_time.map(|max_time| max_time.as_millis() as i64),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_command_maps_options() {
+        let options = FindOptions::builder()
+            .projection(doc! { "name": 1 })
+            .limit(-5)
+            .hint(Hint::Name("name_1".into()))
+            .max_time(Duration::from_millis(250))
+            .build();
+        let command = find_command("users", &doc! { "a": 1 }, Some(&options));
+        assert_eq!(command.get_str("find").unwrap(), "users");
+        assert_eq!(command.get_i64("limit").unwrap(), 5);
+        assert_eq!(command.get_str("hint").unwrap(), "name_1");
+        assert_eq!(command.get_i64("maxTimeMS").unwrap(), 250);
+        assert_eq!(
+            command.get_document("projection").unwrap(),
+            &doc! { "name": 1 }
+        );
+    }
+
+    #[test]
+    fn find_command_clamps_out_of_range_counts() {
+        let options = FindOptions::builder()
+            .limit(i64::MIN)
+            .skip(u64::MAX)
+            .build();
+        let command = find_command("users", &doc! {}, Some(&options));
+        assert_eq!(command.get_i64("limit").unwrap(), i64::MAX);
+        assert_eq!(command.get_i64("skip").unwrap(), i64::MAX);
+    }
+
+    #[test]
+    fn update_command_builds_single_statement() {
+        let options = UpdateOptions::builder().upsert(true).build();
+        let command = update_command(
+            "users",
+            &doc! { "a": 1 },
+            &doc! { "$set": { "b": 2 } },
+            Some(&options),
+            true,
+        );
+        let statement = command.get_array("updates").unwrap()[0]
+            .as_document()
+            .unwrap()
+            .clone();
+        assert!(statement.get_bool("multi").unwrap());
+        assert!(statement.get_bool("upsert").unwrap());
+        assert_eq!(statement.get_document("q").unwrap(), &doc! { "a": 1 });
+    }
+
+    #[test]
+    fn delete_command_limits_single_deletes() {
+        let one = delete_command("users", &doc! {}, None, false);
+        let many = delete_command("users", &doc! {}, None, true);
+        let limit = |command: &Document| {
+            command.get_array("deletes").unwrap()[0]
+                .as_document()
+                .unwrap()
+                .get_i32("limit")
+                .unwrap()
+        };
+        assert_eq!(limit(&one), 1);
+        assert_eq!(limit(&many), 0);
+    }
+
+    #[test]
+    fn aggregate_command_includes_cursor() {
+        let command = aggregate_command("users", &[doc! { "$match": { "a": 1 } }], None);
+        assert!(command.get_document("cursor").unwrap().is_empty());
+        assert_eq!(command.get_array("pipeline").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn count_command_explains_the_count_documents_pipeline() {
+        let options = CountOptions::builder()
+            .skip(5)
+            .limit(1)
+            .max_time(Duration::from_millis(50))
+            .build();
+        let command = count_command("users", &doc! { "active": true }, Some(&options));
+        assert_eq!(
+            command,
+            doc! {
+                "aggregate": "users",
+                "pipeline": [
+                    { "$match": { "active": true } },
+                    { "$skip": 5_i64 },
+                    { "$limit": 1_i64 },
+                    { "$group": { "_id": 1, "n": { "$sum": 1 } } },
+                ],
+                "cursor": {},
+                "maxTimeMS": 50_i64,
+            }
+        );
+    }
+}
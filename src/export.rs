@@ -0,0 +1,254 @@
+use axum::body::Bytes;
+use flate2::write::GzEncoder;
+use flate2::Compression as GzipLevel;
+use futures::{stream, Stream, StreamExt};
+use mongodb::bson::{Bson, Document};
+use serde::Deserialize;
+use std::io::{self, Write};
+
+/// Layout of an export.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// One relaxed extended JSON document per line.
+    #[default]
+    Ndjson,
+    /// A header row, then one row per document with nested fields flattened.
+    Csv,
+    /// Concatenated BSON documents, the layout of a mongodump `.bson` file.
+    Bson,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Bson => "application/bson",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Bson => "bson",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+}
+
+/// Encodes `documents` as they arrive. CSV columns default to the flattened
+/// fields of the first document.
+pub fn encode<S>(
+    documents: S,
+    format: ExportFormat,
+    columns: Option<Vec<String>>,
+) -> impl Stream<Item = io::Result<Bytes>>
+where
+    S: Stream<Item = io::Result<Document>>,
+{
+    let mut columns = columns;
+    let mut header = format == ExportFormat::Csv;
+    documents.map(move |document| {
+        let document = document?;
+        let mut buffer = Vec::new();
+        match format {
+            ExportFormat::Ndjson => {
+                let json = Bson::Document(document).into_relaxed_extjson();
+                serde_json::to_writer(&mut buffer, &json)?;
+                buffer.push(b'\n');
+            }
+            ExportFormat::Bson => document
+                .to_writer(&mut buffer)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            ExportFormat::Csv => {
+                let columns = columns.get_or_insert_with(|| flatten_paths(&document));
+                if header {
+                    header = false;
+                    write_row(&mut buffer, columns.iter().map(String::as_str));
+                }
+                let cells: Vec<_> = columns
+                    .iter()
+                    .map(|path| get_path(&document, path).map_or_else(String::new, cell))
+                    .collect();
+                write_row(&mut buffer, cells.iter().map(String::as_str));
+            }
+        }
+        Ok(Bytes::from(buffer))
+    })
+}
+
+/// Gzip-compresses `chunks` as a stream, emitting output whenever the
+/// compressor has some.
+pub fn gzip<S>(chunks: S) -> impl Stream<Item = io::Result<Bytes>>
+where
+    S: Stream<Item = io::Result<Bytes>> + Send + 'static,
+{
+    let encoder = GzEncoder::new(Vec::new(), GzipLevel::default());
+    stream::unfold(Some((chunks.boxed(), encoder)), |state| async move {
+        let (mut chunks, mut encoder) = state?;
+        loop {
+            match chunks.next().await {
+                Some(Ok(chunk)) => {
+                    if let Err(err) = encoder.write_all(&chunk) {
+                        return Some((Err(err), None));
+                    }
+                    let compressed = std::mem::take(encoder.get_mut());
+                    if !compressed.is_empty() {
+                        return Some((Ok(Bytes::from(compressed)), Some((chunks, encoder))));
+                    }
+                }
+                Some(Err(err)) => return Some((Err(err), None)),
+                None => return Some((encoder.finish().map(Bytes::from), None)),
+            }
+        }
+    })
+}
+
+/// Dotted paths of every value in `document` that is not itself a document.
+/// Arrays count as values.
+pub fn flatten_paths(document: &Document) -> Vec<String> {
+    let mut paths = Vec::new();
+    collect_paths(document, "", &mut paths);
+    paths
+}
+
+fn collect_paths(document: &Document, prefix: &str, paths: &mut Vec<String>) {
+    for (key, value) in document {
+        let path = format!("{prefix}{key}");
+        match value {
+            Bson::Document(child) if !child.is_empty() => {
+                collect_paths(child, &format!("{path}."), paths)
+            }
+            _ => paths.push(path),
+        }
+    }
+}
+
+/// Value at a dotted path; numeric segments index into arrays.
+fn get_path<'a>(document: &'a Document, path: &str) -> Option<&'a Bson> {
+    let mut segments = path.split('.');
+    let mut value = document.get(segments.next()?)?;
+    for segment in segments {
+        value = match value {
+            Bson::Document(child) => child.get(segment)?,
+            Bson::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+/// Text of a CSV cell. Scalars are written plainly, documents and arrays as
+/// relaxed extended JSON.
+fn cell(value: &Bson) -> String {
+    match value {
+        Bson::Null | Bson::Undefined => String::new(),
+        Bson::String(text) => text.clone(),
+        Bson::Boolean(flag) => flag.to_string(),
+        Bson::Int32(number) => number.to_string(),
+        Bson::Int64(number) => number.to_string(),
+        Bson::Double(number) => number.to_string(),
+        Bson::Decimal128(number) => number.to_string(),
+        Bson::ObjectId(id) => id.to_hex(),
+        Bson::DateTime(date) => date
+            .try_to_rfc3339_string()
+            .unwrap_or_else(|_| date.timestamp_millis().to_string()),
+        other => other.clone().into_relaxed_extjson().to_string(),
+    }
+}
+
+fn write_row<'a>(buffer: &mut Vec<u8>, cells: impl Iterator<Item = &'a str>) {
+    for (index, cell) in cells.enumerate() {
+        if index > 0 {
+            buffer.push(b',');
+        }
+        if cell.contains([',', '"', '\n', '\r']) {
+            buffer.push(b'"');
+            buffer.extend_from_slice(cell.replace('"', "\"\"").as_bytes());
+            buffer.push(b'"');
+        } else {
+            buffer.extend_from_slice(cell.as_bytes());
+        }
+    }
+    buffer.extend_from_slice(b"\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::TryStreamExt;
+    use mongodb::bson::doc;
+    use std::io::Read;
+
+    async fn export(
+        documents: Vec<Document>,
+        format: ExportFormat,
+        columns: Option<Vec<String>>,
+    ) -> Vec<u8> {
+        let documents = stream::iter(documents.into_iter().map(Ok));
+        let chunks: Vec<Bytes> = encode(documents, format, columns)
+            .try_collect()
+            .await
+            .unwrap();
+        chunks.concat()
+    }
+
+    #[tokio::test]
+    async fn csv_flattens_nested_fields_of_first_document() {
+        let documents = vec![
+            doc! { "name": "Ada, L.", "address": { "city": "London", "zip": "N1" }, "tags": ["a"] },
+            doc! { "name": "Grace \"Amazing\"", "address": { "city": "NYC" } },
+        ];
+        let csv = export(documents, ExportFormat::Csv, None).await;
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "name,address.city,address.zip,tags\r\n\
+             \"Ada, L.\",London,N1,\"[\"\"a\"\"]\"\r\n\
+             \"Grace \"\"Amazing\"\"\",NYC,,\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn csv_uses_selected_columns_and_array_indexes() {
+        let documents = vec![doc! { "sku": "a1", "items": [{ "qty": 2 }], "price": 1.5 }];
+        let columns = vec!["items.0.qty".to_string(), "sku".to_string()];
+        let csv = export(documents, ExportFormat::Csv, Some(columns)).await;
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "items.0.qty,sku\r\n2,a1\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn ndjson_and_bson_write_one_document_each() {
+        let documents = vec![doc! { "n": 1 }, doc! { "n": 2 }];
+        let ndjson = export(documents.clone(), ExportFormat::Ndjson, None).await;
+        assert_eq!(String::from_utf8(ndjson).unwrap(), "{\"n\":1}\n{\"n\":2}\n");
+
+        let bson = export(documents, ExportFormat::Bson, None).await;
+        let mut reader = io::Cursor::new(bson);
+        assert_eq!(Document::from_reader(&mut reader).unwrap(), doc! { "n": 1 });
+        assert_eq!(Document::from_reader(&mut reader).unwrap(), doc! { "n": 2 });
+    }
+
+    #[tokio::test]
+    async fn gzip_round_trips() {
+        let chunks = stream::iter(vec![
+            Ok(Bytes::from_static(b"hello ")),
+            Ok(Bytes::from_static(b"world")),
+        ]);
+        let compressed: Vec<Bytes> = gzip(chunks).try_collect().await.unwrap();
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(compressed.concat().as_slice())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "hello world");
+    }
+}
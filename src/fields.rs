@@ -0,0 +1,733 @@
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::{FindOneOptions, FindOptions};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use crate::auth::Identity;
+use crate::cluster::NamespacePattern;
+use crate::config::FieldPolicyConfig;
+use crate::error::ApiError;
+
+/// Replaces masked values that are not strings or numbers.
+const MASKED: &str = "****";
+/// Stages that must stay first in a pipeline; exclusions are applied after them.
+pub(crate) const LEADING_STAGES: [&str; 4] =
+    ["$geoNear", "$search", "$searchMeta", "$vectorSearch"];
+
+/// Configured field policies, selected by namespace and caller role.
+#[derive(Debug, Default)]
+pub struct FieldPolicies {
+    rules: Vec<Rule>,
+    unrestricted: Arc<FieldPolicy>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    pattern: NamespacePattern,
+    roles: Vec<String>,
+    policy: Arc<FieldPolicy>,
+}
+
+impl FieldPolicies {
+    pub fn new(policies: &[FieldPolicyConfig]) -> Self {
+        let rules = policies
+            .iter()
+            .map(|config| Rule {
+                pattern: NamespacePattern::parse(&config.namespace),
+                roles: config.roles.clone(),
+                policy: Arc::new(FieldPolicy {
+                    exclude: config.exclude.clone(),
+                    mask: config
+                        .mask
+                        .iter()
+                        .map(|mask| (mask.field.clone(), mask.keep_last))
+                        .collect(),
+                }),
+            })
+            .collect();
+        Self {
+            rules,
+            unrestricted: Arc::default(),
+        }
+    }
+
+    /// The first policy matching the namespace whose roles the caller holds;
+    /// anonymous callers only match policies without roles.
+    pub fn resolve(
+        &self,
+        database: &str,
+        collection: &str,
+        identity: Option<&Identity>,
+    ) -> Arc<FieldPolicy> {
+        self.rules
+            .iter()
+            .find(|rule| {
+                rule.pattern.matches(database, Some(collection))
+                    && (rule.roles.is_empty()
+                        || identity.is_some_and(|identity| {
+                            rule.roles.iter().any(|r| identity.has_role(r))
+                        }))
+            })
+            .map_or_else(|| self.unrestricted.clone(), |rule| rule.policy.clone())
+    }
+
+    /// Fields that some policy on the namespace excludes or masks, whichever
+    /// roles it applies to.
+    pub fn restricted_fields(&self, database: &str, collection: &str) -> BTreeSet<String> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.pattern.matches(database, Some(collection)))
+            .flat_map(|rule| rule.policy.restricted().map(str::to_string))
+            .collect()
+    }
+
+    /// Rejects pipelines that read a namespace on which the caller has
+    /// restricted fields through `$lookup`, `$graphLookup` or `$unionWith`,
+    /// at any depth. Joined documents are not redacted with that namespace's
+    /// policy, so they may not be joined at all.
+    pub fn check_joins(
+        &self,
+        database: &str,
+        pipeline: &[Document],
+        identity: Option<&Identity>,
+    ) -> Result<(), ApiError> {
+        for join in joins(database, pipeline) {
+            if !self
+                .resolve(join.database, join.collection, identity)
+                .is_unrestricted()
+            {
+                return Err(ApiError::forbidden(format!(
+                    "{}: `{}` reads `{}.{}`, which has restricted fields",
+                    join.location, join.stage, join.database, join.collection
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A namespace read by a `$lookup`, `$graphLookup` or `$unionWith` stage.
+#[derive(Debug)]
+pub struct Join<'a> {
+    /// Path of the stage in the request, e.g. `pipeline.2.$facet.all.0`.
+    pub location: String,
+    pub stage: &'a str,
+    pub database: &'a str,
+    pub collection: &'a str,
+}
+
+/// Namespaces joined by `pipeline`, run against `database`, at any depth.
+pub fn joins<'a>(database: &'a str, pipeline: &'a [Document]) -> Vec<Join<'a>> {
+    let mut joins = Vec::new();
+    let stages: Vec<&Document> = pipeline.iter().collect();
+    walk_joins(database, &stages, "pipeline", &mut joins);
+    joins
+}
+
+fn walk_joins<'a>(
+    database: &'a str,
+    pipeline: &[&'a Document],
+    location: &str,
+    joins: &mut Vec<Join<'a>>,
+) {
+    for (index, stage) in pipeline.iter().enumerate() {
+        let location = format!("{location}.{index}");
+        for (name, value) in stage.iter() {
+            let (target, nested) = match (name.as_str(), value) {
+                ("$lookup" | "$graphLookup", Bson::Document(spec)) => {
+                    (spec.get("from"), spec.get_array("pipeline").ok())
+                }
+                ("$unionWith", Bson::String(_)) => (Some(value), None),
+                ("$unionWith", Bson::Document(spec)) => {
+                    (spec.get("coll"), spec.get_array("pipeline").ok())
+                }
+                ("$facet", Bson::Document(facets)) => {
+                    for (facet, stages) in facets {
+                        if let Bson::Array(stages) = stages {
+                            let location = format!("{location}.$facet.{facet}");
+                            walk_joins(database, &documents(stages), &location, joins);
+                        }
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            let target = match target {
+                Some(Bson::String(collection)) => Some((database, collection.as_str())),
+                // `{ db, coll }` names a collection in another database
+                Some(Bson::Document(namespace)) => Some((
+                    namespace.get_str("db").unwrap_or(database),
+                    namespace.get_str("coll").unwrap_or_default(),
+                )),
+                _ => None,
+            };
+            if let Some((database, collection)) = target {
+                joins.push(Join {
+                    location: location.clone(),
+                    stage: name,
+                    database,
+                    collection,
+                });
+            }
+            if let Some(stages) = nested {
+                let location = format!("{location}.{name}.pipeline");
+                walk_joins(database, &documents(stages), &location, joins);
+            }
+        }
+    }
+}
+
+fn documents(items: &[Bson]) -> Vec<&Document> {
+    items.iter().filter_map(Bson::as_document).collect()
+}
+
+/// Fields one caller may not read on one namespace. Excluded fields are
+/// removed from results, masked fields are obscured, and neither may be
+/// filtered or sorted on.
+#[derive(Debug, Default)]
+pub struct FieldPolicy {
+    exclude: Vec<String>,
+    mask: Vec<(String, usize)>,
+}
+
+impl FieldPolicy {
+    pub fn is_unrestricted(&self) -> bool {
+        self.exclude.is_empty() && self.mask.is_empty()
+    }
+
+    /// Rejects filters on restricted fields, which would reveal their values
+    /// through the documents or counts matched.
+    pub fn check_filter(&self, filter: &Document) -> Result<(), ApiError> {
+        if self.is_unrestricted() {
+            return Ok(());
+        }
+        self.walk_filter(filter, None, "filter")
+    }
+
+    /// Rejects updates that write a restricted field or `$rename` one into a
+    /// readable field, where a later read would reveal it.
+    pub fn check_update(&self, update: &Document) -> Result<(), ApiError> {
+        if self.is_unrestricted() {
+            return Ok(());
+        }
+        for (operator, value) in update {
+            if !operator.starts_with('$') {
+                self.check_path(operator, "update")?;
+                continue;
+            }
+            let Bson::Document(targets) = value else {
+                continue;
+            };
+            let location = format!("update.{operator}");
+            for (path, value) in targets {
+                self.check_path(&target_path(path), &location)?;
+                match (operator.as_str(), value) {
+                    ("$rename", Bson::String(to)) => self.check_path(to, &location)?,
+                    _ => self.check_expression(value, &location)?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejects pipelines that read restricted fields, reference the whole
+    /// document, or write results elsewhere with `$out`/`$merge`.
+    pub fn check_pipeline(&self, pipeline: &[Document]) -> Result<(), ApiError> {
+        if self.is_unrestricted() {
+            return Ok(());
+        }
+        for (index, stage) in pipeline.iter().enumerate() {
+            let location = format!("pipeline.{index}");
+            for (name, value) in stage {
+                match (name.as_str(), value) {
+                    ("$out" | "$merge", _) => {
+                        return Err(ApiError::forbidden(format!(
+                            "{location}: `{name}` is not allowed on a namespace with a field policy"
+                        )))
+                    }
+                    ("$match", Bson::Document(filter)) => {
+                        self.walk_filter(filter, None, &location)?
+                    }
+                    ("$sort", Bson::Document(sort)) => self.check_sort(sort, &location)?,
+                    ("$lookup", Bson::Document(lookup)) => {
+                        if let Ok(field) = lookup.get_str("localField") {
+                            self.check_path(field, &location)?;
+                        }
+                        self.check_expression(value, &location)?;
+                    }
+                    _ => self.check_expression(value, &location)?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes excluded fields before any stage of the pipeline runs.
+    pub fn apply_pipeline(&self, mut pipeline: Vec<Document>) -> Vec<Document> {
+        if self.exclude.is_empty() {
+            return pipeline;
+        }
+        let position = usize::from(
+            pipeline
+                .first()
+                .and_then(|stage| stage.keys().next())
+                .is_some_and(|name| LEADING_STAGES.contains(&name.as_str())),
+        );
+        pipeline.insert(position, doc! { "$unset": &self.exclude });
+        pipeline
+    }
+
+    /// Merges the forced exclusions into the projection and checks the sort.
+    pub fn apply_find_options(
+        &self,
+        options: Option<FindOptions>,
+    ) -> Result<Option<FindOptions>, ApiError> {
+        if self.is_unrestricted() {
+            return Ok(options);
+        }
+        let mut options = options.unwrap_or_default();
+        options.projection = self.merge_projection(options.projection.take())?;
+        if let Some(sort) = &options.sort {
+            self.check_sort(sort, "options.sort")?;
+        }
+        Ok(Some(options))
+    }
+
+    pub fn apply_find_one_options(
+        &self,
+        options: Option<FindOneOptions>,
+    ) -> Result<Option<FindOneOptions>, ApiError> {
+        if self.is_unrestricted() {
+            return Ok(options);
+        }
+        let mut options = options.unwrap_or_default();
+        options.projection = self.merge_projection(options.projection.take())?;
+        if let Some(sort) = &options.sort {
+            self.check_sort(sort, "options.sort")?;
+        }
+        Ok(Some(options))
+    }
+
+    /// Strips excluded fields and masks the rest in a result document. Runs on
+    /// every response, so inclusion projections of a parent field and
+    /// pipeline outputs are covered too.
+    pub fn redact(&self, document: &mut Document) {
+        for field in &self.exclude {
+            visit_path(document, field, &mut |parent, key| {
+                parent.remove(key);
+            });
+        }
+        for (field, keep_last) in &self.mask {
+            visit_path(document, field, &mut |parent, key| {
+                if let Some(value) = parent.get_mut(key) {
+                    mask_value(value, *keep_last);
+                }
+            });
+        }
+    }
+
+    fn restricted(&self) -> impl Iterator<Item = &str> {
+        self.exclude
+            .iter()
+            .map(String::as_str)
+            .chain(self.mask.iter().map(|(field, _)| field.as_str()))
+    }
+
+    /// Paths that name a restricted field, one of its children, or a parent
+    /// holding it are all rejected.
+    fn check_path(&self, path: &str, location: &str) -> Result<(), ApiError> {
+        match self.restricted().find(|field| overlaps(field, path)) {
+            Some(field) => Err(ApiError::forbidden(format!(
+                "{location} references restricted field `{field}`"
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn check_sort(&self, sort: &Document, location: &str) -> Result<(), ApiError> {
+        sort.keys()
+            .try_for_each(|field| self.check_path(field, location))
+    }
+
+    fn walk_filter(
+        &self,
+        filter: &Document,
+        prefix: Option<&str>,
+        location: &str,
+    ) -> Result<(), ApiError> {
+        for (key, value) in filter {
+            if key == "$expr" {
+                self.check_expression(value, location)?;
+                continue;
+            }
+            if key.starts_with('$') {
+                // `$and`, `$or` and `$nor` hold nested filters
+                let nested = match value {
+                    Bson::Array(items) => items.iter().collect(),
+                    other => vec![other],
+                };
+                for filter in nested {
+                    if let Bson::Document(filter) = filter {
+                        self.walk_filter(filter, prefix, location)?;
+                    }
+                }
+                continue;
+            }
+            let path = match prefix {
+                Some(prefix) => format!("{prefix}.{key}"),
+                None => key.clone(),
+            };
+            self.check_path(&path, location)?;
+            if let Some(Bson::Document(inner)) = value
+                .as_document()
+                .and_then(|operators| operators.get("$elemMatch"))
+            {
+                self.walk_filter(inner, Some(&path), location)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Walks an aggregation expression for `$field` paths and `$$ROOT`.
+    fn check_expression(&self, value: &Bson, location: &str) -> Result<(), ApiError> {
+        match value {
+            Bson::String(text) if text.starts_with("$$") => {
+                let variable = text[2..].split('.').next().unwrap_or_default();
+                if variable == "ROOT" || variable == "CURRENT" {
+                    return Err(ApiError::forbidden(format!(
+                        "{location}: `$${variable}` is not allowed on a namespace with a field policy"
+                    )));
+                }
+                Ok(())
+            }
+            Bson::String(text) if text.starts_with('$') => self.check_path(&text[1..], location),
+            Bson::Document(document) => document
+                .values()
+                .try_for_each(|value| self.check_expression(value, location)),
+            Bson::Array(items) => items
+                .iter()
+                .try_for_each(|value| self.check_expression(value, location)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Exclusion projections gain the forced exclusions; inclusion projections
+    /// may not name an excluded field.
+    fn merge_projection(&self, projection: Option<Document>) -> Result<Option<Document>, ApiError> {
+        let mut projection = projection.unwrap_or_default();
+        for value in projection.values() {
+            self.check_expression(value, "options.projection")?;
+        }
+        let inclusive = projection
+            .iter()
+            .any(|(key, value)| key != "_id" && includes(value));
+        if inclusive {
+            for key in projection.keys() {
+                if let Some(field) = self.exclude.iter().find(|field| within(key, field)) {
+                    return Err(ApiError::forbidden(format!(
+                        "options.projection includes restricted field `{field}`"
+                    )));
+                }
+            }
+        } else {
+            for field in &self.exclude {
+                // Overlapping paths would collide in the server's projection
+                if projection.keys().any(|key| within(field, key)) {
+                    continue;
+                }
+                let nested: Vec<String> = projection
+                    .keys()
+                    .filter(|key| within(key, field))
+                    .cloned()
+                    .collect();
+                for key in nested {
+                    projection.remove(&key);
+                }
+                projection.insert(field.clone(), 0);
+            }
+        }
+        Ok((!projection.is_empty()).then_some(projection))
+    }
+}
+
+/// An update path without positional segments such as `$`, `$[]` or
+/// `$[item]`, so `items.$[].secret` is checked as `items.secret`.
+fn target_path(path: &str) -> String {
+    path.split('.')
+        .filter(|segment| !segment.starts_with('$'))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Whether `path` is `field` or lies inside it.
+pub(crate) fn within(path: &str, field: &str) -> bool {
+    path == field
+        || path
+            .strip_prefix(field)
+            .is_some_and(|rest| rest.starts_with('.'))
+}
+
+pub(crate) fn overlaps(field: &str, path: &str) -> bool {
+    within(path, field) || within(field, path)
+}
+
+/// Whether a projection value includes its field rather than excluding it.
+fn includes(value: &Bson) -> bool {
+    match value {
+        Bson::Boolean(include) => *include,
+        Bson::Int32(number) => *number != 0,
+        Bson::Int64(number) => *number != 0,
+        Bson::Double(number) => *number != 0.0,
+        // `$slice` projections are allowed alongside exclusions
+        Bson::Document(operator) => !operator.contains_key("$slice"),
+        _ => true,
+    }
+}
+
+/// Calls `apply` with the document holding the last segment of `path`,
+/// descending into arrays of subdocuments along the way.
+pub(crate) fn visit_path(
+    document: &mut Document,
+    path: &str,
+    apply: &mut dyn FnMut(&mut Document, &str),
+) {
+    let Some((head, rest)) = path.split_once('.') else {
+        apply(document, path);
+        return;
+    };
+    match document.get_mut(head) {
+        Some(Bson::Document(child)) => visit_path(child, rest, apply),
+        Some(Bson::Array(items)) => {
+            for item in items {
+                if let Bson::Document(child) = item {
+                    visit_path(child, rest, apply);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn mask_value(value: &mut Bson, keep_last: usize) {
+    match value {
+        Bson::Null => {}
+        Bson::String(text) => *text = mask_str(text, keep_last),
+        Bson::Int32(number) => *value = Bson::String(mask_str(&number.to_string(), keep_last)),
+        Bson::Int64(number) => *value = Bson::String(mask_str(&number.to_string(), keep_last)),
+        Bson::Array(items) => items
+            .iter_mut()
+            .for_each(|item| mask_value(item, keep_last)),
+        _ => *value = Bson::String(MASKED.to_string()),
+    }
+}
+
+/// Replaces letters and digits with `*`, keeping the last `keep_last` of them
+/// and any separators.
+fn mask_str(text: &str, keep_last: usize) -> String {
+    let total = text.chars().filter(|c| c.is_alphanumeric()).count();
+    let hidden = total.saturating_sub(keep_last);
+    let mut seen = 0;
+    text.chars()
+        .map(|c| {
+            if !c.is_alphanumeric() {
+                return c;
+            }
+            seen += 1;
+            if seen <= hidden {
+                '*'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FieldMaskConfig;
+
+    fn policies() -> FieldPolicies {
+        FieldPolicies::new(&[
+            FieldPolicyConfig {
+                namespace: "hr.employees".into(),
+                roles: vec!["pii-reader".into()],
+                exclude: Vec::new(),
+                mask: Vec::new(),
+            },
+            FieldPolicyConfig {
+                namespace: "hr.employees".into(),
+                roles: Vec::new(),
+                exclude: vec!["salary".into(), "bank.account".into()],
+                mask: vec![FieldMaskConfig {
+                    field: "ssn".into(),
+                    keep_last: 4,
+                }],
+            },
+        ])
+    }
+
+    fn identity(roles: &[&str]) -> Identity {
+        Identity {
+            name: Arc::from("caller"),
+            roles: roles.iter().map(|role| role.to_string()).collect(),
+        }
+    }
+
+    fn restricted() -> Arc<FieldPolicy> {
+        policies().resolve("hr", "employees", None)
+    }
+
+    #[test]
+    fn lists_fields_restricted_for_any_role() {
+        let fields = policies().restricted_fields("hr", "employees");
+        let fields: Vec<&str> = fields.iter().map(String::as_str).collect();
+        assert_eq!(fields, ["bank.account", "salary", "ssn"]);
+        assert!(policies().restricted_fields("hr", "payroll").is_empty());
+    }
+
+    #[test]
+    fn selects_policy_by_role() {
+        let policies = policies();
+        assert!(policies
+            .resolve("hr", "employees", Some(&identity(&["pii-reader"])))
+            .is_unrestricted());
+        assert!(!policies
+            .resolve("hr", "employees", Some(&identity(&["reader"])))
+            .is_unrestricted());
+        assert!(policies.resolve("hr", "payroll", None).is_unrestricted());
+    }
+
+    #[test]
+    fn rejects_filters_on_restricted_fields() {
+        let policy = restricted();
+        assert!(policy.check_filter(&doc! { "name": "ada" }).is_ok());
+        for filter in [
+            doc! { "ssn": "123-45-6789" },
+            doc! { "$or": [{ "name": "ada" }, { "salary": { "$gt": 100 } }] },
+            doc! { "bank": { "account": "1" } },
+            doc! { "bank.account.iban": "x" },
+            doc! { "$expr": { "$gt": ["$salary", 100] } },
+        ] {
+            let err = policy.check_filter(&filter).expect_err("restricted");
+            assert_eq!(err.status().as_u16(), 403, "{filter}");
+        }
+    }
+
+    #[test]
+    fn merges_exclusions_into_projection() {
+        let policy = restricted();
+        let options = policy.apply_find_options(None).unwrap().unwrap();
+        assert_eq!(
+            options.projection,
+            Some(doc! { "salary": 0, "bank.account": 0 })
+        );
+
+        let options = FindOptions::builder()
+            .projection(doc! { "bank": 0, "salary.base": 0 })
+            .build();
+        let merged = policy.apply_find_options(Some(options)).unwrap().unwrap();
+        assert_eq!(merged.projection, Some(doc! { "bank": 0, "salary": 0 }));
+
+        let inclusion = FindOptions::builder()
+            .projection(doc! { "name": 1 })
+            .build();
+        let merged = policy.apply_find_options(Some(inclusion)).unwrap().unwrap();
+        assert_eq!(merged.projection, Some(doc! { "name": 1 }));
+
+        let leaking = FindOptions::builder()
+            .projection(doc! { "name": 1, "salary": 1 })
+            .build();
+        assert!(policy.apply_find_options(Some(leaking)).is_err());
+        let sorted = FindOptions::builder().sort(doc! { "ssn": 1 }).build();
+        assert!(policy.apply_find_options(Some(sorted)).is_err());
+    }
+
+    #[test]
+    fn guards_aggregate_pipelines() {
+        let policy = restricted();
+        let pipeline = vec![doc! { "$match": { "name": "ada" } }];
+        policy.check_pipeline(&pipeline).unwrap();
+        assert_eq!(
+            policy.apply_pipeline(pipeline)[0],
+            doc! { "$unset": ["salary", "bank.account"] }
+        );
+        for stage in [
+            doc! { "$project": { "copy": "$ssn" } },
+            doc! { "$replaceRoot": { "newRoot": { "all": "$$ROOT" } } },
+            doc! { "$out": "elsewhere" },
+            doc! { "$match": { "ssn": { "$exists": true } } },
+        ] {
+            assert!(
+                policy.check_pipeline(std::slice::from_ref(&stage)).is_err(),
+                "{stage}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_updates_touching_restricted_fields() {
+        let policy = restricted();
+        assert!(policy
+            .check_update(&doc! { "$set": { "name": "ada" }, "$inc": { "visits": 1 } })
+            .is_ok());
+        for update in [
+            doc! { "$rename": { "ssn": "notes" } },
+            doc! { "$rename": { "notes": "bank.account" } },
+            doc! { "$set": { "salary": 1 } },
+            doc! { "$unset": { "bank": "" } },
+            doc! { "$set": { "notes": "$ssn" } },
+            doc! { "$push": { "bank.account.$[].tags": "x" } },
+        ] {
+            let err = policy.check_update(&update).expect_err("restricted");
+            assert_eq!(err.status().as_u16(), 403, "{update}");
+        }
+    }
+
+    #[test]
+    fn rejects_joins_into_restricted_namespaces() {
+        let policies = policies();
+        let open = [
+            doc! { "$lookup": { "from": "teams", "localField": "team", "foreignField": "_id", "as": "t" } },
+            doc! { "$unionWith": "contractors" },
+        ];
+        policies.check_joins("hr", &open, None).unwrap();
+        for stage in [
+            doc! { "$lookup": { "from": "employees", "localField": "id", "foreignField": "_id", "as": "e" } },
+            doc! { "$graphLookup": { "from": "employees", "startWith": "$boss", "connectFromField": "boss", "connectToField": "_id", "as": "chain" } },
+            doc! { "$unionWith": { "coll": "employees" } },
+            doc! { "$unionWith": { "coll": "teams", "pipeline": [{ "$lookup": { "from": "employees", "pipeline": [], "as": "e" } }] } },
+            doc! { "$facet": { "all": [{ "$unionWith": "employees" }] } },
+        ] {
+            let err = policies
+                .check_joins("hr", std::slice::from_ref(&stage), None)
+                .expect_err("restricted join");
+            assert_eq!(err.status().as_u16(), 403, "{stage}");
+        }
+        // Callers allowed to read the joined namespace may join it
+        let reader = identity(&["pii-reader"]);
+        let join = [doc! { "$unionWith": "employees" }];
+        policies.check_joins("hr", &join, Some(&reader)).unwrap();
+        assert!(policies.check_joins("payroll", &join, None).is_ok());
+    }
+
+    #[test]
+    fn redacts_and_masks_results() {
+        let policy = restricted();
+        let mut document = doc! {
+            "name": "ada",
+            "ssn": "123-45-6789",
+            "salary": 100,
+            "bank": { "account": "DE89", "name": "acme" },
+        };
+        policy.redact(&mut document);
+        assert_eq!(
+            document,
+            doc! { "name": "ada", "ssn": "***-**-6789", "bank": { "name": "acme" } }
+        );
+        assert_eq!(mask_str("ada@example.com", 0), "***@*******.***");
+        let mut number = Bson::Int64(123456789);
+        mask_value(&mut number, 2);
+        assert_eq!(number, Bson::String("*******89".into()));
+    }
+}
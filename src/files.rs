@@ -0,0 +1,516 @@
+use axum::body::Bytes;
+use futures::io::{AsyncReadExt, AsyncWriteExt};
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use mongodb::error::{ErrorKind, GridFsErrorKind};
+use mongodb::gridfs::{FilesCollectionDocument, GridFsBucket};
+use mongodb::options::{CollectionOptions, FindOptions, GridFsBucketOptions, GridFsUploadOptions};
+use mongodb::{Collection, Database};
+use std::fmt::Display;
+use std::io;
+use std::pin::pin;
+
+use crate::error::ApiError;
+use crate::models::FileUploadResponse;
+
+/// Bucket used when a request does not name one.
+pub const DEFAULT_BUCKET: &str = "fs";
+/// Key under a file's `metadata` holding the `Content-Type` it was uploaded
+/// with, as the GridFS spec recommends.
+pub const CONTENT_TYPE_FIELD: &str = "contentType";
+pub const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+/// Bytes read from a download stream at a time.
+const READ_BUFFER_BYTES: usize = 64 * 1024;
+/// Limit on the headers of one multipart part.
+const MAX_PART_HEADER_BYTES: usize = 8 * 1024;
+/// Limit on a multipart `metadata` part; the files document must fit in BSON.
+const MAX_METADATA_BYTES: usize = 1024 * 1024;
+
+/// The GridFS bucket whose files collection is `files`, sharing its read and
+/// write settings.
+pub fn bucket(files: &Collection<Document>, name: &str) -> GridFsBucket {
+    database(files).gridfs_bucket(
+        GridFsBucketOptions::builder()
+            .bucket_name(name.to_string())
+            .read_concern(files.read_concern().cloned())
+            .write_concern(files.write_concern().cloned())
+            .selection_criteria(files.selection_criteria().cloned())
+            .build(),
+    )
+}
+
+fn database(files: &Collection<Document>) -> Database {
+    files.client().database(&files.namespace().db)
+}
+
+/// Parses a file id from a URL: an ObjectId when it looks like one,
+/// otherwise the string itself.
+pub fn parse_id(id: &str) -> Bson {
+    ObjectId::parse_str(id).map_or_else(|_| Bson::String(id.to_string()), Bson::ObjectId)
+}
+
+/// The stored `Content-Type` of `file`.
+pub fn content_type(file: &FilesCollectionDocument) -> &str {
+    file.metadata
+        .as_ref()
+        .and_then(|metadata| metadata.get_str(CONTENT_TYPE_FIELD).ok())
+        .unwrap_or(DEFAULT_CONTENT_TYPE)
+}
+
+pub fn is_not_found(err: &mongodb::error::Error) -> bool {
+    matches!(
+        err.kind.as_ref(),
+        ErrorKind::GridFs {
+            0: GridFsErrorKind::FileNotFound { .. },
+            ..
+        }
+    )
+}
+
+/// An inclusive byte range of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    /// Parses a `Range` header for a file of `length` bytes. Returns `None`
+    /// for anything but a single byte range, which is then ignored and the
+    /// whole file is sent, and an error when the range lies past the end.
+    pub fn parse(header: &str, length: u64) -> Option<Result<Self, ApiError>> {
+        let spec = header.trim().strip_prefix("bytes=")?;
+        if spec.contains(',') {
+            return None;
+        }
+        let (start, end) = spec.split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+        let range = if start.is_empty() {
+            let suffix: u64 = end.parse().ok()?;
+            if suffix == 0 {
+                return Some(Err(unsatisfiable(length)));
+            }
+            Self {
+                start: length.saturating_sub(suffix),
+                end: length.checked_sub(1)?,
+            }
+        } else {
+            let start: u64 = start.parse().ok()?;
+            let end = match end {
+                "" => u64::MAX,
+                end => end.parse().ok()?,
+            };
+            if end < start {
+                return None;
+            }
+            Self {
+                start,
+                end: end.min(length.saturating_sub(1)),
+            }
+        };
+        if range.start >= length {
+            return Some(Err(unsatisfiable(length)));
+        }
+        Some(Ok(range))
+    }
+
+    pub fn byte_count(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// `Content-Range` value for a file of `length` bytes.
+    pub fn content_range(&self, length: u64) -> String {
+        format!("bytes {}-{}/{length}", self.start, self.end)
+    }
+}
+
+fn unsatisfiable(length: u64) -> ApiError {
+    ApiError::range_not_satisfiable(format!("range is outside the file's {length} bytes"))
+}
+
+/// Streams the whole of `file` through the driver's download stream.
+pub async fn download(
+    bucket: &GridFsBucket,
+    file: &FilesCollectionDocument,
+) -> Result<impl Stream<Item = io::Result<Bytes>>, mongodb::error::Error> {
+    let reader = bucket.open_download_stream(file.id.clone()).await?;
+    Ok(stream::try_unfold(reader, |mut reader| async move {
+        let mut buffer = vec![0; READ_BUFFER_BYTES];
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        buffer.truncate(read);
+        Ok(Some((Bytes::from(buffer), reader)))
+    }))
+}
+
+/// Streams `range` of `file`, reading only the chunks it covers from the
+/// bucket's chunks collection.
+pub async fn download_range(
+    files: &Collection<Document>,
+    bucket_name: &str,
+    file: &FilesCollectionDocument,
+    range: ByteRange,
+) -> Result<impl Stream<Item = io::Result<Bytes>>, mongodb::error::Error> {
+    let chunk_size = u64::from(file.chunk_size_bytes.max(1));
+    let (first, last) = (range.start / chunk_size, range.end / chunk_size);
+    let chunks = database(files).collection_with_options::<Document>(
+        &format!("{bucket_name}.chunks"),
+        CollectionOptions::builder()
+            .read_concern(files.read_concern().cloned())
+            .selection_criteria(files.selection_criteria().cloned())
+            .build(),
+    );
+    let cursor = chunks
+        .find(
+            doc! {
+                "files_id": file.id.clone(),
+                "n": { "$gte": first as i64, "$lte": last as i64 },
+            },
+            FindOptions::builder().sort(doc! { "n": 1 }).build(),
+        )
+        .await?;
+    Ok(cursor
+        .map_err(io::Error::other)
+        .and_then(move |chunk| async move {
+            let n = chunk.get_i32("n").map_or(0, |n| n.max(0) as u64);
+            let data = match chunk.get("data") {
+                Some(Bson::Binary(binary)) => binary.bytes.as_slice(),
+                _ => &[],
+            };
+            let offset = n * chunk_size;
+            let from = range.start.saturating_sub(offset).min(data.len() as u64) as usize;
+            let to = (range.end + 1 - offset).min(data.len() as u64) as usize;
+            Ok(Bytes::copy_from_slice(&data[from..to.max(from)]))
+        }))
+}
+
+/// Writes `contents` to a new file in `bucket`. A failed upload removes the
+/// chunks already written.
+pub async fn store<S, B>(
+    bucket: &GridFsBucket,
+    filename: String,
+    content_type: String,
+    metadata: Option<Document>,
+    contents: S,
+) -> Result<FileUploadResponse, ApiError>
+where
+    S: Stream<Item = Result<B, ApiError>>,
+    B: AsRef<[u8]>,
+{
+    let mut metadata = metadata.unwrap_or_default();
+    metadata.insert(CONTENT_TYPE_FIELD, content_type.as_str());
+    let options = GridFsUploadOptions::builder().metadata(metadata).build();
+    let mut upload = bucket.open_upload_stream(&filename, options);
+    let mut contents = pin!(contents);
+    let mut length = 0;
+    while let Some(chunk) = contents.next().await {
+        let written = match chunk {
+            Ok(chunk) => {
+                length += chunk.as_ref().len() as u64;
+                upload.write_all(chunk.as_ref()).await.map_err(map_io_error)
+            }
+            Err(err) => Err(err),
+        };
+        if let Err(err) = written {
+            if let Err(abort) = upload.abort().await {
+                tracing::warn!(target = "files", error = %abort, "failed to abort upload");
+            }
+            return Err(err);
+        }
+    }
+    // Writes the last chunk and the files document
+    upload.close().await.map_err(map_io_error)?;
+    Ok(FileUploadResponse {
+        id: upload.id().clone(),
+        filename,
+        length,
+        content_type,
+    })
+}
+
+/// Stores the `file` part of a `multipart/form-data` body. A `metadata`
+/// part, a JSON object, must come before it; other parts are ignored.
+pub async fn store_multipart<S, E>(
+    bucket: &GridFsBucket,
+    mut form: Multipart<S>,
+    filename: Option<String>,
+    mut metadata: Option<Document>,
+) -> Result<FileUploadResponse, ApiError>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Display,
+{
+    while let Some(part) = form.next_part().await? {
+        match part.name.as_deref() {
+            Some("metadata") => {
+                let contents = form.collect(MAX_METADATA_BYTES).await?;
+                metadata = Some(parse_metadata(&contents)?);
+            }
+            Some("file") => {
+                let filename = part.filename.or(filename).ok_or(ApiError::validation(
+                    "the file part has no filename; pass `filename` in the query",
+                ))?;
+                let content_type = part
+                    .content_type
+                    .unwrap_or_else(|| DEFAULT_CONTENT_TYPE.to_string());
+                let contents = stream::try_unfold(&mut form, |form| async move {
+                    Ok(form.chunk().await?.map(|chunk| (chunk, form)))
+                });
+                return store(bucket, filename, content_type, metadata, contents).await;
+            }
+            _ => {}
+        }
+    }
+    Err(ApiError::validation("multipart body has no `file` part"))
+}
+
+/// Parses file metadata given as a JSON object.
+pub fn parse_metadata(json: &[u8]) -> Result<Document, ApiError> {
+    serde_json::from_slice(json)
+        .map_err(|err| ApiError::validation(format!("metadata must be a JSON object: {err}")))
+}
+
+fn map_io_error(err: io::Error) -> ApiError {
+    ApiError::driver(format!("mongodb error: {err}"))
+}
+
+/// `boundary` parameter of a `multipart/form-data` content type.
+pub fn multipart_boundary(content_type: &str) -> Option<&str> {
+    let mut params = content_type.split(';');
+    let essence = params.next()?.trim();
+    if !essence.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"'))
+        .filter(|boundary| !boundary.is_empty())
+}
+
+/// Headers of one `multipart/form-data` part.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Part {
+    pub name: Option<String>,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+}
+
+impl Part {
+    fn parse(headers: &str) -> Self {
+        let mut part = Part::default();
+        for line in headers.split("\r\n") {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            if name.trim().eq_ignore_ascii_case("content-type") {
+                part.content_type = Some(value.to_string());
+            } else if name.trim().eq_ignore_ascii_case("content-disposition") {
+                for param in value.split(';').skip(1) {
+                    let Some((key, value)) = param.split_once('=') else {
+                        continue;
+                    };
+                    let value = value.trim().trim_matches('"').to_string();
+                    match key.trim() {
+                        "name" => part.name = Some(value),
+                        "filename" => part.filename = Some(value),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        part
+    }
+}
+
+/// Reads a `multipart/form-data` body part by part without buffering part
+/// contents, so large files stream straight into GridFS.
+pub struct Multipart<S> {
+    body: S,
+    buffer: Vec<u8>,
+    delimiter: Vec<u8>,
+    in_part: bool,
+    finished: bool,
+}
+
+impl<S, E> Multipart<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Display,
+{
+    pub fn new(body: S, boundary: &str) -> Self {
+        Self {
+            body,
+            // The first delimiter has no line break before it
+            buffer: b"\r\n".to_vec(),
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            // Whatever precedes the first delimiter is skipped like a part
+            in_part: true,
+            finished: false,
+        }
+    }
+
+    /// Moves to the next part, skipping what is left of the current one.
+    pub async fn next_part(&mut self) -> Result<Option<Part>, ApiError> {
+        while self.chunk().await?.is_some() {}
+        if self.finished {
+            return Ok(None);
+        }
+        while self.buffer.len() < 2 {
+            self.fill().await?;
+        }
+        if self.buffer.starts_with(b"--") {
+            self.finished = true;
+            return Ok(None);
+        }
+        let end = loop {
+            if let Some(end) = find(&self.buffer, b"\r\n\r\n") {
+                break end;
+            }
+            if self.buffer.len() > MAX_PART_HEADER_BYTES {
+                return Err(ApiError::validation("multipart part headers are too large"));
+            }
+            self.fill().await?;
+        };
+        let part = Part::parse(&String::from_utf8_lossy(&self.buffer[..end]));
+        self.buffer.drain(..end + 4);
+        self.in_part = true;
+        Ok(Some(part))
+    }
+
+    /// Next piece of the current part's contents, or `None` at its end.
+    pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>, ApiError> {
+        if !self.in_part {
+            return Ok(None);
+        }
+        loop {
+            if let Some(at) = find(&self.buffer, &self.delimiter) {
+                if at > 0 {
+                    return Ok(Some(self.buffer.drain(..at).collect()));
+                }
+                self.buffer.drain(..self.delimiter.len());
+                self.in_part = false;
+                return Ok(None);
+            }
+            // Keep enough to recognise a delimiter split across reads
+            let keep = self.delimiter.len() - 1;
+            if self.buffer.len() > keep {
+                let ready = self.buffer.len() - keep;
+                return Ok(Some(self.buffer.drain(..ready).collect()));
+            }
+            self.fill().await?;
+        }
+    }
+
+    /// All of the current part's contents, up to `limit` bytes.
+    pub async fn collect(&mut self, limit: usize) -> Result<Vec<u8>, ApiError> {
+        let mut contents = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            contents.extend_from_slice(&chunk);
+            if contents.len() > limit {
+                return Err(ApiError::validation(format!(
+                    "multipart field is larger than {limit} bytes"
+                )));
+            }
+        }
+        Ok(contents)
+    }
+
+    async fn fill(&mut self) -> Result<(), ApiError> {
+        match self.body.next().await {
+            Some(Ok(bytes)) => {
+                self.buffer.extend_from_slice(&bytes);
+                Ok(())
+            }
+            Some(Err(err)) => Err(ApiError::validation(format!(
+                "failed to read request body: {err}"
+            ))),
+            None => Err(ApiError::validation("multipart body ended unexpectedly")),
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+
+    #[test]
+    fn parses_single_byte_ranges() {
+        let range = |header| ByteRange::parse(header, 100).map(|range| range.ok());
+        assert_eq!(
+            range("bytes=0-9"),
+            Some(Some(ByteRange { start: 0, end: 9 }))
+        );
+        assert_eq!(
+            range("bytes=90-"),
+            Some(Some(ByteRange { start: 90, end: 99 }))
+        );
+        assert_eq!(
+            range("bytes=-10"),
+            Some(Some(ByteRange { start: 90, end: 99 }))
+        );
+        assert_eq!(
+            range("bytes=50-500"),
+            Some(Some(ByteRange { start: 50, end: 99 }))
+        );
+        assert_eq!(range("bytes=100-"), Some(None));
+        assert_eq!(range("bytes=0-1,5-6"), None);
+        assert_eq!(range("items=0-1"), None);
+        assert_eq!(
+            ByteRange { start: 90, end: 99 }.content_range(100),
+            "bytes 90-99/100"
+        );
+    }
+
+    #[test]
+    fn reads_boundary() {
+        assert_eq!(
+            multipart_boundary("multipart/form-data; boundary=\"abc\""),
+            Some("abc")
+        );
+        assert_eq!(multipart_boundary("application/json"), None);
+    }
+
+    #[tokio::test]
+    async fn streams_multipart_parts_split_across_reads() {
+        let body = "preamble\r\n--xyz\r\n\
+             Content-Disposition: form-data; name=\"metadata\"\r\n\r\n\
+             {\"owner\":\"ada\"}\r\n--xyz\r\n\
+             Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             hello\r\n-- world\r\n--xyz--\r\n";
+        // One byte per read exercises delimiters split across reads
+        let reads: Vec<Result<Bytes, Infallible>> = body
+            .bytes()
+            .map(|byte| Ok(Bytes::copy_from_slice(&[byte])))
+            .collect();
+        let mut multipart = Multipart::new(stream::iter(reads), "xyz");
+
+        let part = multipart.next_part().await.unwrap().unwrap();
+        assert_eq!(part.name.as_deref(), Some("metadata"));
+        assert_eq!(
+            multipart.collect(1024).await.unwrap(),
+            br#"{"owner":"ada"}"#
+        );
+
+        let part = multipart.next_part().await.unwrap().unwrap();
+        assert_eq!(part.filename.as_deref(), Some("a.txt"));
+        assert_eq!(part.content_type.as_deref(), Some("text/plain"));
+        let mut contents = Vec::new();
+        while let Some(chunk) = multipart.chunk().await.unwrap() {
+            contents.extend(chunk);
+        }
+        assert_eq!(contents, b"hello\r\n-- world");
+        assert!(multipart.next_part().await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,1013 @@
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::dynamic::{
+    Field, FieldFuture, FieldValue, InputValue, Object, ResolverContext, Scalar, Schema, TypeRef,
+};
+use async_graphql::parser::types::OperationType;
+use async_graphql::{Error, ErrorExtensions, Value};
+use futures::TryStreamExt;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::{FindOptions, UpdateOptions};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+
+use crate::audit::AuditContext;
+use crate::auth::Identity;
+use crate::config::GraphqlTypeConfig;
+use crate::consistency::Consistency;
+use crate::error::ApiError;
+use crate::files;
+use crate::history;
+use crate::limits::AccessKind;
+use crate::models::{DeleteResponse, InsertOneResponse, NamespacePayload, UpdateResponse};
+use crate::soft_delete;
+use crate::state::AppState;
+
+pub const GRAPHQL_PATH: &str = "/graphql";
+
+/// Type names the schema defines itself.
+const RESERVED_TYPES: &[&str] = &[
+    "Query",
+    "Mutation",
+    "ID",
+    "String",
+    "Int",
+    "Float",
+    "Boolean",
+    "DateTime",
+    "JSON",
+    "UpdateResult",
+    "DeleteResult",
+];
+
+/// Whether `name` can name a `[[graphql]]` type.
+pub fn is_type_name(name: &str) -> bool {
+    is_field_name(name) && !RESERVED_TYPES.contains(&name)
+}
+
+/// Whether `name` is a GraphQL name outside the reserved `__` prefix.
+pub fn is_field_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first == '_' || first.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+        && !name.starts_with("__")
+}
+
+/// `(database, collection)` of a `db.coll` namespace.
+pub fn split_namespace(namespace: &str) -> Option<(&str, &str)> {
+    namespace
+        .split_once('.')
+        .filter(|(database, collection)| !database.is_empty() && !collection.is_empty())
+}
+
+/// GraphQL type of a document field. Values of another BSON type resolve to
+/// `null`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldType {
+    Id,
+    String,
+    Int,
+    Float,
+    Boolean,
+    /// RFC 3339 timestamp.
+    DateTime,
+    /// Any value, as relaxed extended JSON.
+    Json,
+    List(Box<FieldType>),
+}
+
+impl FieldType {
+    pub fn parse(text: &str) -> Option<Self> {
+        if let Some(inner) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+            return match Self::parse(inner)? {
+                FieldType::List(_) => None,
+                item => Some(FieldType::List(Box::new(item))),
+            };
+        }
+        Some(match text {
+            "ID" => FieldType::Id,
+            "String" => FieldType::String,
+            "Int" => FieldType::Int,
+            "Float" => FieldType::Float,
+            "Boolean" => FieldType::Boolean,
+            "DateTime" => FieldType::DateTime,
+            "JSON" => FieldType::Json,
+            _ => return None,
+        })
+    }
+
+    /// Type of a sampled value; `None` for nulls, which say nothing.
+    fn infer(value: &Bson) -> Option<Self> {
+        Some(match value {
+            Bson::Null | Bson::Undefined => return None,
+            Bson::ObjectId(_) => FieldType::Id,
+            Bson::String(_) | Bson::Symbol(_) => FieldType::String,
+            Bson::Int32(_) | Bson::Int64(_) => FieldType::Int,
+            Bson::Double(_) | Bson::Decimal128(_) => FieldType::Float,
+            Bson::Boolean(_) => FieldType::Boolean,
+            Bson::DateTime(_) => FieldType::DateTime,
+            Bson::Array(items) => FieldType::List(Box::new(
+                items
+                    .iter()
+                    .filter_map(Self::infer)
+                    .map(|item| match item {
+                        FieldType::List(_) => FieldType::Json,
+                        item => item,
+                    })
+                    .reduce(Self::merge)
+                    .unwrap_or(FieldType::Json),
+            )),
+            _ => FieldType::Json,
+        })
+    }
+
+    /// A type covering both `self` and `other`.
+    fn merge(self, other: Self) -> Self {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (FieldType::Int, FieldType::Float) | (FieldType::Float, FieldType::Int) => {
+                FieldType::Float
+            }
+            (FieldType::List(a), FieldType::List(b)) => FieldType::List(Box::new(a.merge(*b))),
+            _ => FieldType::Json,
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            FieldType::Id => TypeRef::ID,
+            FieldType::String => TypeRef::STRING,
+            FieldType::Int => TypeRef::INT,
+            FieldType::Float => TypeRef::FLOAT,
+            FieldType::Boolean => TypeRef::BOOLEAN,
+            FieldType::DateTime => "DateTime",
+            FieldType::Json => "JSON",
+            FieldType::List(item) => item.type_name(),
+        }
+    }
+
+    fn type_ref(&self) -> TypeRef {
+        match self {
+            FieldType::List(item) => TypeRef::named_list(item.type_name()),
+            other => TypeRef::named(other.type_name()),
+        }
+    }
+
+    fn to_value(&self, value: &Bson) -> Option<Value> {
+        Some(match (self, value) {
+            (FieldType::Id, Bson::ObjectId(id)) => Value::from(id.to_hex()),
+            (FieldType::Id, Bson::String(id)) => Value::from(id.as_str()),
+            (FieldType::Id, Bson::Int32(id)) => Value::from(id.to_string()),
+            (FieldType::Id, Bson::Int64(id)) => Value::from(id.to_string()),
+            (FieldType::String, Bson::String(text) | Bson::Symbol(text)) => {
+                Value::from(text.as_str())
+            }
+            (FieldType::String, Bson::ObjectId(id)) => Value::from(id.to_hex()),
+            (FieldType::Int, Bson::Int32(number)) => Value::from(*number),
+            (FieldType::Int, Bson::Int64(number)) => Value::from(*number),
+            (FieldType::Float, Bson::Double(number)) => Value::from(*number),
+            (FieldType::Float, Bson::Int32(number)) => Value::from(f64::from(*number)),
+            (FieldType::Float, Bson::Int64(number)) => Value::from(*number as f64),
+            (FieldType::Float, Bson::Decimal128(number)) => {
+                Value::from(number.to_string().parse::<f64>().ok()?)
+            }
+            (FieldType::Boolean, Bson::Boolean(flag)) => Value::from(*flag),
+            (FieldType::DateTime, Bson::DateTime(date)) => {
+                Value::from(date.try_to_rfc3339_string().ok()?)
+            }
+            (FieldType::Json, value) => json_value(value),
+            (FieldType::List(item), Bson::Array(values)) => Value::List(
+                values
+                    .iter()
+                    .map(|value| item.to_value(value).unwrap_or(Value::Null))
+                    .collect(),
+            ),
+            _ => return None,
+        })
+    }
+}
+
+fn json_value(value: &Bson) -> Value {
+    Value::from_json(value.clone().into_relaxed_extjson()).unwrap_or(Value::Null)
+}
+
+/// Adds the top-level fields of a sampled `document` to `fields`, widening
+/// any whose type differs from earlier samples.
+pub fn infer_fields(document: &Document, fields: &mut BTreeMap<String, FieldType>) {
+    for (key, value) in document {
+        if !is_field_name(key) {
+            continue;
+        }
+        if let Some(kind) = FieldType::infer(value) {
+            let merged = match fields.remove(key) {
+                Some(known) => known.merge(kind),
+                None => kind,
+            };
+            fields.insert(key.clone(), merged);
+        }
+    }
+}
+
+/// Whether `query` contains a mutation, so it is rate limited as a write.
+/// Unparseable queries count as reads; execution reports the syntax error.
+pub fn access_kind(query: &str) -> AccessKind {
+    let mutates = async_graphql::parser::parse_query(query).is_ok_and(|document| {
+        document
+            .operations
+            .iter()
+            .any(|(_, operation)| operation.node.ty == OperationType::Mutation)
+    });
+    if mutates {
+        AccessKind::Write
+    } else {
+        AccessKind::Read
+    }
+}
+
+/// The caller a GraphQL request runs for, shared by its resolvers.
+pub struct RequestScope {
+    pub state: AppState,
+    pub identity: Option<Identity>,
+    pub context: AuditContext,
+    /// Cluster requested with `X-Cluster`, for types that do not name one.
+    pub cluster: Option<String>,
+}
+
+/// The `/graphql` schema for the `[[graphql]]` types. It is built on first
+/// use, since types without `fields` are inferred from sampled documents, and
+/// rebuilt when those settings or the field policies are reloaded. Nothing
+/// about the first caller goes into it.
+pub struct GraphqlSchema {
+    types: Arc<[GraphqlTypeConfig]>,
+    schema: OnceCell<Schema>,
+}
+
+impl GraphqlSchema {
+    pub fn new(types: &[GraphqlTypeConfig]) -> Self {
+        Self {
+            types: types.into(),
+            schema: OnceCell::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+
+    /// Runs `request` on behalf of `scope`. Reference fields are loaded in
+    /// batches per request.
+    pub async fn execute(
+        &self,
+        scope: RequestScope,
+        request: async_graphql::Request,
+    ) -> Result<async_graphql::Response, ApiError> {
+        let schema = self
+            .schema
+            .get_or_try_init(|| build(&scope.state, &self.types))
+            .await?;
+        let scope = Arc::new(scope);
+        let loader = DataLoader::new(
+            DocumentLoader {
+                scope: scope.clone(),
+                types: self.types.clone(),
+            },
+            tokio::spawn,
+        );
+        Ok(schema.execute(request.data(scope).data(loader)).await)
+    }
+}
+
+fn namespace(ty: &GraphqlTypeConfig) -> NamespacePayload {
+    let (database, collection) = split_namespace(&ty.namespace).unwrap_or_default();
+    NamespacePayload {
+        database: database.to_string(),
+        collection: collection.to_string(),
+        cluster: ty.cluster.clone(),
+        consistency: Consistency::default(),
+    }
+}
+
+/// `order` for `Order`, the prefix of the type's query fields.
+fn query_name(name: &str) -> String {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map(|first| first.to_ascii_lowercase().to_string() + chars.as_str())
+        .unwrap_or_default()
+}
+
+async fn build(state: &AppState, types: &[GraphqlTypeConfig]) -> Result<Schema, ApiError> {
+    if types.is_empty() {
+        return Err(ApiError::not_found("no GraphQL types are configured"));
+    }
+    let mut builder = Schema::build("Query", Some("Mutation"), None)
+        .register(Scalar::new("JSON"))
+        .register(Scalar::new("DateTime"))
+        .register(
+            Object::new("UpdateResult")
+                .field(result_field("matched_count", |result: &UpdateResponse| {
+                    Value::from(result.matched_count)
+                }))
+                .field(result_field("modified_count", |result: &UpdateResponse| {
+                    Value::from(result.modified_count)
+                }))
+                .field(Field::new(
+                    "upserted_id",
+                    TypeRef::named(TypeRef::ID),
+                    |ctx| {
+                        FieldFuture::from_value(
+                            ctx.parent_value
+                                .downcast_ref::<UpdateResponse>()
+                                .and_then(|result| result.upserted_id.as_ref())
+                                .and_then(|id| FieldType::Id.to_value(id)),
+                        )
+                    },
+                )),
+        )
+        .register(
+            Object::new("DeleteResult")
+                .field(result_field("deleted_count", |result: &DeleteResponse| {
+                    Value::from(result.deleted_count)
+                })),
+        );
+    let mut query = Object::new("Query");
+    let mut mutation = Object::new("Mutation");
+    for ty in types {
+        let fields = match ty.fields.is_empty() {
+            true => sample(state, ty).await?,
+            false => ty
+                .fields
+                .iter()
+                .filter_map(|(name, kind)| Some((name.clone(), FieldType::parse(kind)?)))
+                .collect(),
+        };
+        builder = builder.register(object_type(ty, fields));
+        let ty = Arc::new(ty.clone());
+        let prefix = query_name(&ty.name);
+        query = query
+            .field(
+                field(&prefix, TypeRef::named(&ty.name), &ty, |ctx, ty| {
+                    FieldFuture::new(async move { find_by_id(&ctx, &ty).await })
+                })
+                .argument(InputValue::new("id", TypeRef::named_nn(TypeRef::ID))),
+            )
+            .field(
+                field(
+                    &format!("{prefix}List"),
+                    TypeRef::named_nn_list_nn(&ty.name),
+                    &ty,
+                    |ctx, ty| FieldFuture::new(async move { find(&ctx, &ty).await }),
+                )
+                .argument(InputValue::new("filter", TypeRef::named("JSON")))
+                .argument(InputValue::new("sort", TypeRef::named("JSON")))
+                .argument(InputValue::new("limit", TypeRef::named(TypeRef::INT)))
+                .argument(InputValue::new("skip", TypeRef::named(TypeRef::INT))),
+            );
+        mutation = mutation
+            .field(
+                field(
+                    &format!("insert{}", ty.name),
+                    TypeRef::named_nn(&ty.name),
+                    &ty,
+                    |ctx, ty| FieldFuture::new(async move { insert(&ctx, &ty).await }),
+                )
+                .argument(InputValue::new("document", TypeRef::named_nn("JSON"))),
+            )
+            .field(
+                field(
+                    &format!("update{}", ty.name),
+                    TypeRef::named_nn("UpdateResult"),
+                    &ty,
+                    |ctx, ty| FieldFuture::new(async move { update(&ctx, &ty).await }),
+                )
+                .argument(InputValue::new("filter", TypeRef::named_nn("JSON")))
+                .argument(InputValue::new("update", TypeRef::named_nn("JSON")))
+                .argument(flag("many"))
+                .argument(flag("upsert"))
+                .argument(flag("confirm_all")),
+            )
+            .field(
+                field(
+                    &format!("delete{}", ty.name),
+                    TypeRef::named_nn("DeleteResult"),
+                    &ty,
+                    |ctx, ty| FieldFuture::new(async move { delete(&ctx, &ty).await }),
+                )
+                .argument(InputValue::new("filter", TypeRef::named_nn("JSON")))
+                .argument(flag("many"))
+                .argument(flag("confirm_all")),
+            );
+    }
+    builder
+        .register(query)
+        .register(mutation)
+        .finish()
+        .map_err(|err| ApiError::internal(format!("invalid GraphQL schema: {err}")))
+}
+
+fn result_field<T: 'static>(name: &str, value: fn(&T) -> Value) -> Field {
+    Field::new(name, TypeRef::named_nn(TypeRef::INT), move |ctx| {
+        FieldFuture::from_value(ctx.parent_value.downcast_ref::<T>().map(value))
+    })
+}
+
+/// Infers the fields of `ty` from a `$sample` of its documents on the type's
+/// own cluster. The schema is shared by every caller, so fields any field
+/// policy restricts are left out.
+async fn sample(
+    state: &AppState,
+    ty: &GraphqlTypeConfig,
+) -> Result<BTreeMap<String, FieldType>, ApiError> {
+    let namespace = namespace(ty);
+    let (collection, _in_flight) = state.checkout_collection(&namespace, None)?;
+    let cipher = state.encryption(&namespace);
+    let pipeline = [doc! { "$sample": { "size": i64::from(ty.sample_size) } }];
+    let mut cursor = collection
+        .aggregate(pipeline, None)
+        .await
+        .map_err(driver_error)?;
+    let mut fields = BTreeMap::new();
+    while let Some(mut document) = cursor.try_next().await.map_err(driver_error)? {
+        cipher.decrypt(&mut document)?;
+        infer_fields(&document, &mut fields);
+    }
+    for field in state.restricted_fields(&namespace) {
+        fields.remove(&field);
+    }
+    Ok(fields)
+}
+
+fn object_type(ty: &GraphqlTypeConfig, mut fields: BTreeMap<String, FieldType>) -> Object {
+    fields.entry("_id".to_string()).or_insert(FieldType::Id);
+    let mut object = Object::new(&ty.name);
+    for (name, kind) in fields {
+        let key = name.clone();
+        object = object.field(Field::new(name, kind.type_ref(), move |ctx| {
+            FieldFuture::from_value(
+                ctx.parent_value
+                    .downcast_ref::<Document>()
+                    .and_then(|document| document.get(&key))
+                    .and_then(|value| kind.to_value(value)),
+            )
+        }));
+    }
+    for reference in &ty.references {
+        let reference = Arc::new(reference.clone());
+        let kind = match reference.many {
+            true => TypeRef::named_nn_list(&reference.target),
+            false => TypeRef::named(&reference.target),
+        };
+        object = object.field(Field::new(reference.field.clone(), kind, move |ctx| {
+            let reference = reference.clone();
+            FieldFuture::new(async move {
+                let loader = ctx.data::<DataLoader<DocumentLoader>>()?;
+                let document = ctx.parent_value.try_downcast_ref::<Document>()?;
+                let key = |id: &Bson| DocumentKey::new(&reference.target, id.clone());
+                match document.get(&reference.from) {
+                    Some(Bson::Array(ids)) if reference.many => {
+                        let keys: Vec<_> = ids.iter().map(key).collect();
+                        let mut found = loader.load_many(keys.iter().cloned()).await?;
+                        Ok(Some(FieldValue::list(
+                            keys.iter()
+                                .filter_map(|key| found.remove(key))
+                                .map(FieldValue::owned_any),
+                        )))
+                    }
+                    Some(Bson::Array(_)) | Some(Bson::Null) | None => Ok(None),
+                    Some(_) if reference.many => Ok(None),
+                    Some(id) => Ok(loader.load_one(key(id)).await?.map(FieldValue::owned_any)),
+                }
+            })
+        }));
+    }
+    object
+}
+
+/// A `Query` or `Mutation` field resolved against `ty`.
+fn field<F>(name: &str, kind: TypeRef, ty: &Arc<GraphqlTypeConfig>, resolve: F) -> Field
+where
+    F: for<'a> Fn(ResolverContext<'a>, Arc<GraphqlTypeConfig>) -> FieldFuture<'a>
+        + Send
+        + Sync
+        + 'static,
+{
+    let ty = ty.clone();
+    Field::new(name, kind, move |ctx| resolve(ctx, ty.clone()))
+}
+
+fn flag(name: &str) -> InputValue {
+    InputValue::new(name, TypeRef::named(TypeRef::BOOLEAN)).default_value(false)
+}
+
+impl From<ApiError> for Error {
+    fn from(err: ApiError) -> Self {
+        let status = err.status().as_u16();
+        let body = err.body();
+        Error::new(body.details.clone()).extend_with(|_, extensions| {
+            extensions.set("code", body.error);
+            extensions.set("status", status);
+            if let Some(correlation_id) = &body.correlation_id {
+                extensions.set("correlation_id", correlation_id.as_str());
+            }
+        })
+    }
+}
+
+fn driver_error(err: mongodb::error::Error) -> ApiError {
+    history::rejection(&err).unwrap_or_else(|| ApiError::driver(format!("mongodb error: {err}")))
+}
+
+fn scope<'a>(ctx: &ResolverContext<'a>) -> async_graphql::Result<&'a Arc<RequestScope>> {
+    ctx.data::<Arc<RequestScope>>()
+}
+
+/// A JSON argument as a document; extended JSON such as `{"$oid": ...}` is
+/// decoded to BSON.
+fn document_arg(ctx: &ResolverContext<'_>, name: &str) -> Result<Option<Document>, ApiError> {
+    let Some(value) = ctx.args.get(name).filter(|value| !value.is_null()) else {
+        return Ok(None);
+    };
+    let json = value
+        .deserialize::<serde_json::Value>()
+        .map_err(|err| ApiError::validation(format!("invalid {name}: {}", err.message)))?;
+    match Bson::try_from(json) {
+        Ok(Bson::Document(document)) => Ok(Some(document)),
+        Ok(_) => Err(ApiError::validation(format!("{name} must be an object"))),
+        Err(err) => Err(ApiError::validation(format!("invalid {name}: {err}"))),
+    }
+}
+
+fn count_arg(ctx: &ResolverContext<'_>, name: &str) -> Result<Option<u64>, ApiError> {
+    match ctx.args.get(name).filter(|value| !value.is_null()) {
+        Some(value) => value
+            .u64()
+            .map(Some)
+            .map_err(|_| ApiError::validation(format!("{name} must not be negative"))),
+        None => Ok(None),
+    }
+}
+
+/// `limit` as the driver takes it; values past `i64::MAX` are rejected rather
+/// than wrapped into negative limits.
+fn limit_arg(ctx: &ResolverContext<'_>) -> Result<Option<i64>, ApiError> {
+    count_arg(ctx, "limit")?
+        .map(|limit| {
+            i64::try_from(limit)
+                .map_err(|_| ApiError::validation(format!("limit must be at most {}", i64::MAX)))
+        })
+        .transpose()
+}
+
+fn flag_arg(ctx: &ResolverContext<'_>, name: &str) -> bool {
+    ctx.args
+        .get(name)
+        .and_then(|value| value.boolean().ok())
+        .unwrap_or(false)
+}
+
+async fn find_by_id(
+    ctx: &ResolverContext<'_>,
+    ty: &GraphqlTypeConfig,
+) -> async_graphql::Result<Option<FieldValue<'static>>> {
+    let id = files::parse_id(ctx.args.try_get("id")?.string()?);
+    let loader = ctx.data::<DataLoader<DocumentLoader>>()?;
+    let document = loader.load_one(DocumentKey::new(&ty.name, id)).await?;
+    Ok(document.map(FieldValue::owned_any))
+}
+
+async fn find(
+    ctx: &ResolverContext<'_>,
+    ty: &GraphqlTypeConfig,
+) -> async_graphql::Result<Option<FieldValue<'static>>> {
+    let scope = scope(ctx)?;
+    let state = &scope.state;
+    let namespace = namespace(ty);
+    let mut filter = document_arg(ctx, "filter")?.unwrap_or_default();
+    let options = FindOptions::builder()
+        .sort(document_arg(ctx, "sort")?)
+        .limit(limit_arg(ctx)?)
+        .skip(count_arg(ctx, "skip")?)
+        .build();
+    let policy = state.query_policy();
+    let fields = state.field_policy(&namespace, scope.identity.as_ref());
+    let options = policy
+        .check_filter(&filter)
+        .and_then(|()| fields.check_filter(&filter))
+        .and_then(|()| policy.apply_find_options(Some(options)))
+        .and_then(|options| fields.apply_find_options(options))?;
+    let cipher = state.encryption(&namespace);
+    cipher.encrypt_filter(&mut filter)?;
+    if state.soft_delete(&namespace) {
+        filter = soft_delete::exclude_deleted(filter);
+    }
+    let (collection, _in_flight) =
+        state.checkout_collection(&namespace, scope.cluster.as_deref())?;
+    let mut cursor = collection
+        .find(filter, options)
+        .await
+        .map_err(driver_error)?;
+    let mut documents = Vec::new();
+    while let Some(mut document) = cursor.try_next().await.map_err(driver_error)? {
+        cipher.decrypt(&mut document)?;
+        fields.redact(&mut document);
+        documents.push(FieldValue::owned_any(document));
+    }
+    Ok(Some(FieldValue::list(documents)))
+}
+
+/// Inserts `document` and returns it as stored, with its `_id`.
+async fn insert(
+    ctx: &ResolverContext<'_>,
+    ty: &GraphqlTypeConfig,
+) -> async_graphql::Result<Option<FieldValue<'static>>> {
+    let scope = scope(ctx)?;
+    let state = &scope.state;
+    let namespace = namespace(ty);
+    let mut document = document_arg(ctx, "document")?.unwrap_or_default();
+    if let Some(stamps) = state.stamps(&namespace, scope.identity.as_ref()) {
+        stamps.insert(&mut document);
+    }
+    let mut inserted = document.clone();
+    state
+        .encryption(&namespace)
+        .encrypt_document(&mut document)?;
+    state
+        .payload_limits()
+        .check_document(&document, "document")?;
+    let (collection, _in_flight) =
+        state.checkout_collection(&namespace, scope.cluster.as_deref())?;
+    let audit = state
+        .begin_audit(
+            &scope.context,
+            "insert_one",
+            &namespace,
+            scope.cluster.as_deref(),
+        )
+        .await?
+        .documents(std::slice::from_ref(&document));
+    let result = collection.insert_one(document, None).await;
+    // Invalidate even on failure; a failed write may have partly applied
+    state.invalidate_cache(&namespace, scope.cluster.as_deref());
+    let result = result.map_err(|err| audit.failed(driver_error(err)))?;
+    let response = InsertOneResponse {
+        inserted_id: result.inserted_id,
+    };
+    audit.succeeded(&response);
+    if !inserted.contains_key("_id") {
+        inserted.insert("_id", response.inserted_id);
+    }
+    state
+        .field_policy(&namespace, scope.identity.as_ref())
+        .redact(&mut inserted);
+    Ok(Some(FieldValue::owned_any(inserted)))
+}
+
+async fn update(
+    ctx: &ResolverContext<'_>,
+    ty: &GraphqlTypeConfig,
+) -> async_graphql::Result<Option<FieldValue<'static>>> {
+    let scope = scope(ctx)?;
+    let state = &scope.state;
+    let namespace = namespace(ty);
+    let mut filter = document_arg(ctx, "filter")?.unwrap_or_default();
+    let mut update = document_arg(ctx, "update")?.unwrap_or_default();
+    let many = flag_arg(ctx, "many");
+    let operation = if many { "update_many" } else { "update_one" };
+    let policy = state.query_policy();
+    let fields = state.field_policy(&namespace, scope.identity.as_ref());
+    if many {
+        policy.check_bounded(operation, &filter, flag_arg(ctx, "confirm_all"))?;
+    }
+    policy
+        .check_filter(&filter)
+        .and_then(|()| fields.check_filter(&filter))
+        .and_then(|()| fields.check_update(&update))
+        .and_then(|()| policy.check_document(&update, "update"))
+        .and_then(|()| state.payload_limits().check_document(&update, "update"))?;
+    if let Some(stamps) = state.stamps(&namespace, scope.identity.as_ref()) {
+        stamps.update(&mut update);
+    }
+    let cipher = state.encryption(&namespace);
+    cipher
+        .encrypt_filter(&mut filter)
+        .and_then(|()| cipher.encrypt_update(&mut update))?;
+    let (collection, _in_flight) =
+        state.checkout_collection(&namespace, scope.cluster.as_deref())?;
+    let versioned = state
+        .history(
+            &namespace,
+            scope.cluster.as_deref(),
+            &collection,
+            scope.identity.as_ref(),
+        )
+        .await;
+    if versioned.is_some() {
+        history::check_update(&update)?;
+    }
+    let audit = state
+        .begin_audit(
+            &scope.context,
+            operation,
+            &namespace,
+            scope.cluster.as_deref(),
+        )
+        .await?
+        .filter(&filter)
+        .update(&update);
+    let options = flag_arg(ctx, "upsert").then(|| UpdateOptions::builder().upsert(true).build());
+    let result = match &versioned {
+        Some(versioned) => versioned.update(filter, update, options, many).await,
+        None if many => collection.update_many(filter, update, options).await,
+        None => collection.update_one(filter, update, options).await,
+    };
+    // Invalidate even on failure; a failed write may have partly applied
+    state.invalidate_cache(&namespace, scope.cluster.as_deref());
+    let result = result.map_err(|err| audit.failed(driver_error(err)))?;
+    let response = UpdateResponse::from_update_result(result);
+    audit.succeeded(&response);
+    Ok(Some(FieldValue::owned_any(response)))
+}
+
+/// Deletes matching documents, or marks them deleted in soft-delete namespaces.
+async fn delete(
+    ctx: &ResolverContext<'_>,
+    ty: &GraphqlTypeConfig,
+) -> async_graphql::Result<Option<FieldValue<'static>>> {
+    let scope = scope(ctx)?;
+    let state = &scope.state;
+    let namespace = namespace(ty);
+    let mut filter = document_arg(ctx, "filter")?.unwrap_or_default();
+    let many = flag_arg(ctx, "many");
+    let operation = if many { "delete_many" } else { "delete_one" };
+    let policy = state.query_policy();
+    let fields = state.field_policy(&namespace, scope.identity.as_ref());
+    if many {
+        policy.check_bounded(operation, &filter, flag_arg(ctx, "confirm_all"))?;
+    }
+    policy
+        .check_filter(&filter)
+        .and_then(|()| fields.check_filter(&filter))?;
+    state.encryption(&namespace).encrypt_filter(&mut filter)?;
+    let soft = state.soft_delete(&namespace);
+    if soft {
+        filter = soft_delete::exclude_deleted(filter);
+    }
+    let (collection, _in_flight) =
+        state.checkout_collection(&namespace, scope.cluster.as_deref())?;
+    let versioned = state
+        .history(
+            &namespace,
+            scope.cluster.as_deref(),
+            &collection,
+            scope.identity.as_ref(),
+        )
+        .await;
+    let audit = state
+        .begin_audit(
+            &scope.context,
+            operation,
+            &namespace,
+            scope.cluster.as_deref(),
+        )
+        .await?
+        .filter(&filter);
+    let result = if soft {
+        let update = soft_delete::delete_update();
+        let options = soft_delete::update_options(None);
+        match &versioned {
+            Some(versioned) => versioned.update(filter, update, options, many).await,
+            None if many => collection.update_many(filter, update, options).await,
+            None => collection.update_one(filter, update, options).await,
+        }
+        .map(|result| result.modified_count)
+    } else {
+        match &versioned {
+            Some(versioned) => versioned.delete(filter, None, many).await,
+            None if many => collection.delete_many(filter, None).await,
+            None => collection.delete_one(filter, None).await,
+        }
+        .map(|result| result.deleted_count)
+    };
+    // Invalidate even on failure; a failed write may have partly applied
+    state.invalidate_cache(&namespace, scope.cluster.as_deref());
+    let result = result.map_err(|err| audit.failed(driver_error(err)))?;
+    let response = DeleteResponse {
+        deleted_count: result,
+    };
+    audit.succeeded(&response);
+    Ok(Some(FieldValue::owned_any(response)))
+}
+
+/// A document of a `[[graphql]]` type by `_id`. BSON values cannot be hashed,
+/// so keys compare by the id's canonical extended JSON, with integers widened
+/// the way MongoDB compares them.
+#[derive(Debug, Clone)]
+struct DocumentKey {
+    ty: String,
+    key: String,
+    id: Bson,
+}
+
+impl DocumentKey {
+    fn new(ty: &str, id: Bson) -> Self {
+        Self {
+            ty: ty.to_string(),
+            key: match &id {
+                Bson::Int32(number) => Bson::Int64(i64::from(*number)),
+                other => other.clone(),
+            }
+            .into_canonical_extjson()
+            .to_string(),
+            id,
+        }
+    }
+}
+
+impl PartialEq for DocumentKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.ty == other.ty && self.key == other.key
+    }
+}
+
+impl Eq for DocumentKey {}
+
+impl std::hash::Hash for DocumentKey {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.ty.hash(state);
+        self.key.hash(state);
+    }
+}
+
+/// Loads the documents a request looks up by `_id`, with one `$in` query per
+/// type and batch. Reads get the same field policy, decryption and soft
+/// delete handling as `find`.
+struct DocumentLoader {
+    scope: Arc<RequestScope>,
+    types: Arc<[GraphqlTypeConfig]>,
+}
+
+impl DocumentLoader {
+    async fn load_type(
+        &self,
+        ty: &GraphqlTypeConfig,
+        ids: Vec<Bson>,
+    ) -> Result<Vec<Document>, ApiError> {
+        let state = &self.scope.state;
+        let namespace = namespace(ty);
+        let fields = state.field_policy(&namespace, self.scope.identity.as_ref());
+        let options = fields.apply_find_options(None)?;
+        let mut filter = doc! { "_id": { "$in": ids } };
+        if state.soft_delete(&namespace) {
+            filter = soft_delete::exclude_deleted(filter);
+        }
+        let cipher = state.encryption(&namespace);
+        let (collection, _in_flight) =
+            state.checkout_collection(&namespace, self.scope.cluster.as_deref())?;
+        let mut cursor = collection
+            .find(filter, options)
+            .await
+            .map_err(driver_error)?;
+        let mut documents = Vec::new();
+        while let Some(mut document) = cursor.try_next().await.map_err(driver_error)? {
+            cipher.decrypt(&mut document)?;
+            documents.push(document);
+        }
+        Ok(documents)
+    }
+}
+
+impl Loader<DocumentKey> for DocumentLoader {
+    type Value = Document;
+    type Error = Error;
+
+    async fn load(&self, keys: &[DocumentKey]) -> Result<HashMap<DocumentKey, Document>, Error> {
+        let mut found = HashMap::new();
+        for ty in self.types.iter() {
+            let ids: Vec<Bson> = keys
+                .iter()
+                .filter(|key| key.ty == ty.name)
+                .map(|key| key.id.clone())
+                .collect();
+            if ids.is_empty() {
+                continue;
+            }
+            let fields = self
+                .scope
+                .state
+                .field_policy(&namespace(ty), self.scope.identity.as_ref());
+            for mut document in self.load_type(ty, ids).await? {
+                let Some(id) = document.get("_id").cloned() else {
+                    continue;
+                };
+                fields.redact(&mut document);
+                found.insert(DocumentKey::new(&ty.name, id), document);
+            }
+        }
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::oid::ObjectId;
+
+    #[test]
+    fn parses_field_types() {
+        assert_eq!(FieldType::parse("Float"), Some(FieldType::Float));
+        assert_eq!(
+            FieldType::parse("[String]"),
+            Some(FieldType::List(Box::new(FieldType::String)))
+        );
+        assert_eq!(FieldType::parse("[[String]]"), None);
+        assert_eq!(FieldType::parse("String!"), None);
+        assert_eq!(FieldType::parse("Money"), None);
+    }
+
+    #[test]
+    fn infers_and_widens_sampled_fields() {
+        let mut fields = BTreeMap::new();
+        infer_fields(
+            &doc! { "_id": ObjectId::new(), "qty": 1, "tags": ["a"], "note": null, "bad-name": 1 },
+            &mut fields,
+        );
+        infer_fields(
+            &doc! { "qty": 1.5, "tags": [1], "note": "n", "address": { "city": "x" } },
+            &mut fields,
+        );
+        assert_eq!(fields["_id"], FieldType::Id);
+        assert_eq!(fields["qty"], FieldType::Float);
+        assert_eq!(fields["tags"], FieldType::List(Box::new(FieldType::Json)));
+        assert_eq!(fields["note"], FieldType::String);
+        assert_eq!(fields["address"], FieldType::Json);
+        assert!(!fields.contains_key("bad-name"));
+    }
+
+    #[test]
+    fn converts_values_and_nulls_mismatches() {
+        let id = ObjectId::new();
+        assert_eq!(
+            FieldType::Id.to_value(&Bson::ObjectId(id)),
+            Some(Value::from(id.to_hex()))
+        );
+        assert_eq!(
+            FieldType::Float.to_value(&Bson::Int32(2)),
+            Some(Value::from(2.0))
+        );
+        assert_eq!(FieldType::Int.to_value(&Bson::String("2".into())), None);
+        assert_eq!(
+            FieldType::List(Box::new(FieldType::Int))
+                .to_value(&Bson::Array(vec![Bson::Int32(1), Bson::String("x".into())])),
+            Some(Value::List(vec![Value::from(1), Value::Null]))
+        );
+        assert_eq!(
+            FieldType::Json.to_value(&Bson::Document(doc! { "a": 1 })),
+            Value::from_json(serde_json::json!({ "a": 1 })).ok()
+        );
+    }
+
+    #[test]
+    fn names_and_namespaces() {
+        assert!(is_type_name("Order"));
+        assert!(!is_type_name("Query"));
+        assert!(!is_type_name("__Order"));
+        assert!(is_field_name("_id"));
+        assert!(!is_field_name("1st"));
+        assert!(!is_field_name("a.b"));
+        assert_eq!(query_name("OrderLine"), "orderLine");
+        assert_eq!(
+            split_namespace("shop.orders.archive"),
+            Some(("shop", "orders.archive"))
+        );
+        assert_eq!(split_namespace("shop."), None);
+    }
+
+    #[test]
+    fn mutations_are_rate_limited_as_writes() {
+        assert_eq!(access_kind("{ orderList { _id } }"), AccessKind::Read);
+        assert_eq!(
+            access_kind("mutation { deleteOrder(filter: {}) { deleted_count } }"),
+            AccessKind::Write
+        );
+        assert_eq!(access_kind("not graphql"), AccessKind::Read);
+    }
+
+    #[test]
+    fn document_keys_compare_by_id_value() {
+        let id = ObjectId::new();
+        assert_eq!(
+            DocumentKey::new("Order", Bson::ObjectId(id)),
+            DocumentKey::new("Order", Bson::ObjectId(id))
+        );
+        assert_eq!(
+            DocumentKey::new("Order", Bson::Int32(1)),
+            DocumentKey::new("Order", Bson::Int64(1))
+        );
+        assert_ne!(
+            DocumentKey::new("Order", Bson::Int32(1)),
+            DocumentKey::new("Customer", Bson::Int32(1))
+        );
+    }
+}
@@ -0,0 +1,932 @@
+// Calls fail with `tonic::Status`, which the generated service requires
+#![allow(clippy::result_large_err)]
+
+use axum::http::StatusCode;
+use futures::stream::{self, BoxStream};
+use futures::{Future, StreamExt, TryStreamExt};
+use mongodb::bson::{self, doc, Bson, Document};
+use mongodb::change_stream::event::ResumeToken;
+use mongodb::options::{ChangeStreamOptions, FindOptions, FullDocumentType, UpdateOptions};
+use std::io;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+use tonic::{Code, Request, Response, Status};
+use uuid::Uuid;
+
+use crate::audit::{AuditContext, CORRELATION_ID_HEADER};
+use crate::auth::{ClientCertificate, Identity, API_KEY_HEADER};
+use crate::consistency::Consistency;
+use crate::error::ApiError;
+use crate::history;
+use crate::limits::AccessKind;
+use crate::models::{DeleteResponse, InsertManyResponse, NamespacePayload};
+use crate::policy::LIMIT_APPLIED_HEADER;
+use crate::shutdown::DrainGuard;
+use crate::soft_delete;
+use crate::state::AppState;
+use crate::tls::{self, ReloadingAcceptor};
+
+pub mod proto;
+
+use self::proto::documents_server::{Documents, DocumentsServer};
+
+/// Gateway error code of a failed call, such as `validation_error`.
+pub const ERROR_CODE_METADATA: &str = "x-error-code";
+/// Correlation id of a driver or internal error, for matching the gateway log.
+pub const ERROR_CORRELATION_ID_METADATA: &str = "x-error-correlation-id";
+pub const RETRY_AFTER_METADATA: &str = "retry-after";
+
+type ReplyStream<T> = BoxStream<'static, Result<T, Status>>;
+
+/// Serves the gRPC document API on `listener` until `shutdown` resolves, then
+/// waits for open calls to finish. With `tls`, calls use the same certificate
+/// and client verification as the HTTP API; without it, a gateway configured
+/// for TLS refuses to start rather than accept API keys in cleartext.
+pub async fn serve(
+    state: AppState,
+    listener: TcpListener,
+    tls: Option<Arc<ReloadingAcceptor>>,
+    shutdown: impl Future<Output = ()>,
+) -> io::Result<()> {
+    let server = Server::builder().add_service(DocumentService::new(state.clone()).into_server());
+    match tls {
+        Some(tls) => server
+            .serve_with_incoming_shutdown(tls::incoming(listener, tls), shutdown)
+            .await
+            .map_err(io::Error::other),
+        None if state.config().tls.is_some() => Err(io::Error::other(
+            "gRPC cannot be served in plaintext while TLS is configured",
+        )),
+        None => {
+            let incoming =
+                TcpIncoming::from_listener(listener, true, None).map_err(io::Error::other)?;
+            server
+                .serve_with_incoming_shutdown(incoming, shutdown)
+                .await
+                .map_err(io::Error::other)
+        }
+    }
+}
+
+/// The `gateway.v1.Documents` service. Calls get the same authentication,
+/// rate limits, policies, encryption and auditing as the HTTP API.
+#[derive(Clone)]
+pub struct DocumentService {
+    state: AppState,
+}
+
+/// One authenticated call. It counts as in flight for shutdown draining until
+/// dropped, which for streaming calls is when the stream ends.
+struct Call {
+    method: &'static str,
+    identity: Option<Identity>,
+    context: AuditContext,
+    correlation_id: Arc<str>,
+    _drain: DrainGuard,
+}
+
+impl DocumentService {
+    pub fn new(state: AppState) -> Self {
+        Self { state }
+    }
+
+    /// Builds the tonic service. Request messages are capped at
+    /// `payload.max_body_bytes` as configured at startup.
+    pub fn into_server(self) -> DocumentsServer<Self> {
+        let limit =
+            usize::try_from(self.state.config().payload.max_body_bytes).unwrap_or(usize::MAX);
+        DocumentsServer::new(self).max_decoding_message_size(limit)
+    }
+
+    /// Admits a call: assigns its correlation id, then checks draining, the
+    /// API key or client certificate and the rate limit for `kind`.
+    fn begin<T>(
+        &self,
+        method: &'static str,
+        request: &Request<T>,
+        kind: AccessKind,
+    ) -> Result<Call, Status> {
+        let metadata = request.metadata();
+        let correlation_id: Arc<str> = metadata
+            .get(CORRELATION_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| {
+                (1..=128).contains(&value.len())
+                    && value.bytes().all(|byte| byte.is_ascii_graphic())
+            })
+            .map_or_else(|| Uuid::new_v4().to_string(), str::to_string)
+            .into();
+        let fail = |err| to_status(&log_call_failure(method, err), &correlation_id);
+        let drain = self
+            .state
+            .drain()
+            .enter()
+            .ok_or_else(|| fail(ApiError::unavailable("gateway is shutting down")))?;
+        let api_key = metadata
+            .get(API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok());
+        // Only certificates that passed the TLS verifier reach the call
+        let certificate = request.peer_certs().and_then(|certs| {
+            certs
+                .first()
+                .and_then(|cert| ClientCertificate::from_der(cert))
+        });
+        let peer = match request.remote_addr() {
+            Some(addr) => format!("ip:{}", addr.ip()),
+            None => "anonymous".to_string(),
+        };
+        let identity = match self.state.authenticate(api_key, certificate.as_ref()) {
+            Ok(identity) => identity,
+            // Failed attempts cost the peer tokens, as over HTTP
+            Err(err) => {
+                self.state.check_rate_limit(&peer, kind).map_err(fail)?;
+                return Err(fail(err));
+            }
+        };
+        let client = match &identity {
+            Some(identity) => format!("identity:{}", identity.name),
+            None => peer,
+        };
+        self.state.check_rate_limit(&client, kind).map_err(fail)?;
+        Ok(Call {
+            method,
+            context: AuditContext::new(
+                Some(correlation_id.clone()),
+                identity.as_ref(),
+                request.remote_addr(),
+            ),
+            identity,
+            correlation_id,
+            _drain: drain,
+        })
+    }
+
+    async fn find_documents(
+        &self,
+        call: &Call,
+        request: proto::FindRequest,
+    ) -> Result<BoxStream<'static, Result<proto::Document, ApiError>>, ApiError> {
+        let namespace = namespace(request.namespace)?;
+        call.received(&namespace, None);
+        let state = &self.state;
+        let mut filter = decode(&request.filter, "filter")?;
+        let options = FindOptions::builder()
+            .projection(decode_optional(&request.projection, "projection")?)
+            .sort(decode_optional(&request.sort, "sort")?)
+            .limit(request.limit)
+            .skip(request.skip)
+            .build();
+        let policy = state.query_policy();
+        let fields = state.field_policy(&namespace, call.identity.as_ref());
+        let options = policy
+            .check_filter(&filter)
+            .and_then(|()| fields.check_filter(&filter))
+            .and_then(|()| policy.apply_find_options(Some(options)))
+            .and_then(|options| fields.apply_find_options(options))?;
+        let cipher = state.encryption(&namespace);
+        cipher.encrypt_filter(&mut filter)?;
+        if !request.include_deleted && state.soft_delete(&namespace) {
+            filter = soft_delete::exclude_deleted(filter);
+        }
+        let (collection, in_flight) = state.checkout_collection(&namespace, None)?;
+        let cursor = collection
+            .find(filter, options)
+            .await
+            .map_err(driver_error)?;
+        // The namespace slot is held until the client stops reading
+        let documents = cursor.map(move |result| {
+            let _in_flight = &in_flight;
+            let mut document = result.map_err(driver_error)?;
+            cipher.decrypt(&mut document)?;
+            fields.redact(&mut document);
+            Ok(proto::Document {
+                bson: encode(&document)?,
+            })
+        });
+        call.succeeded(&namespace, None);
+        Ok(documents.boxed())
+    }
+
+    async fn insert_documents(
+        &self,
+        call: &Call,
+        request: proto::InsertRequest,
+    ) -> Result<proto::InsertResponse, ApiError> {
+        let namespace = namespace(request.namespace)?;
+        call.received(&namespace, Some(request.documents.len()));
+        if request.documents.is_empty() {
+            return Err(ApiError::validation("documents must not be empty"));
+        }
+        let state = &self.state;
+        let limits = state.payload_limits();
+        limits.check_insert_count(request.documents.len())?;
+        let cipher = state.encryption(&namespace);
+        let stamps = state.stamps(&namespace, call.identity.as_ref());
+        let documents = request
+            .documents
+            .iter()
+            .enumerate()
+            .map(|(index, bytes)| {
+                let path = format!("documents.{index}");
+                let mut document = decode(bytes, &path)?;
+                if let Some(stamps) = &stamps {
+                    stamps.insert(&mut document);
+                }
+                cipher.encrypt_document(&mut document)?;
+                limits.check_document(&document, &path)?;
+                Ok(document)
+            })
+            .collect::<Result<Vec<_>, ApiError>>()?;
+        let (collection, _in_flight) = state.checkout_collection(&namespace, None)?;
+        let audit = state
+            .begin_audit(&call.context, "insert_many", &namespace, None)
+            .await?
+            .documents(&documents);
+        let result = collection.insert_many(documents, None).await;
+        // Invalidate even on failure; a failed write may have partly applied
+        state.invalidate_cache(&namespace, None);
+        let result = result.map_err(|err| audit.failed(driver_error(err)))?;
+        let response = InsertManyResponse::from_result(result);
+        audit.succeeded(&response);
+        call.succeeded(&namespace, Some(response.inserted_ids.len() as u64));
+        Ok(proto::InsertResponse {
+            inserted_ids: response
+                .inserted_ids
+                .into_iter()
+                .map(|id| encode(&doc! { "_id": id }))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    async fn update_documents(
+        &self,
+        call: &Call,
+        request: proto::UpdateRequest,
+    ) -> Result<proto::UpdateResponse, ApiError> {
+        let namespace = namespace(request.namespace)?;
+        call.received(&namespace, None);
+        let state = &self.state;
+        let mut filter = decode(&request.filter, "filter")?;
+        let mut update = decode(&request.update, "update")?;
+        let operation = if request.many {
+            "update_many"
+        } else {
+            "update_one"
+        };
+        let policy = state.query_policy();
+        let fields = state.field_policy(&namespace, call.identity.as_ref());
+        if request.many {
+            policy.check_bounded(operation, &filter, request.confirm_all)?;
+        }
+        policy
+            .check_filter(&filter)
+            .and_then(|()| fields.check_filter(&filter))
+            .and_then(|()| fields.check_update(&update))
+            .and_then(|()| policy.check_document(&update, "update"))
+            .and_then(|()| state.payload_limits().check_document(&update, "update"))?;
+        if let Some(stamps) = state.stamps(&namespace, call.identity.as_ref()) {
+            stamps.update(&mut update);
+        }
+        let cipher = state.encryption(&namespace);
+        cipher
+            .encrypt_filter(&mut filter)
+            .and_then(|()| cipher.encrypt_update(&mut update))?;
+        let (collection, _in_flight) = state.checkout_collection(&namespace, None)?;
+        let versioned = state
+            .history(&namespace, None, &collection, call.identity.as_ref())
+            .await;
+        if versioned.is_some() {
+            history::check_update(&update)?;
+        }
+        let audit = state
+            .begin_audit(&call.context, operation, &namespace, None)
+            .await?
+            .filter(&filter)
+            .update(&update);
+        let options = request
+            .upsert
+            .then(|| UpdateOptions::builder().upsert(true).build());
+        let result = match &versioned {
+            Some(versioned) => {
+                versioned
+                    .update(filter, update, options, request.many)
+                    .await
+            }
+            None if request.many => collection.update_many(filter, update, options).await,
+            None => collection.update_one(filter, update, options).await,
+        };
+        // Invalidate even on failure; a failed write may have partly applied
+        state.invalidate_cache(&namespace, None);
+        let result = result.map_err(|err| audit.failed(driver_error(err)))?;
+        let response = crate::models::UpdateResponse::from_update_result(result);
+        audit.succeeded(&response);
+        call.succeeded(&namespace, Some(response.modified_count));
+        Ok(proto::UpdateResponse {
+            matched_count: response.matched_count,
+            modified_count: response.modified_count,
+            upserted_id: match response.upserted_id {
+                Some(id) => encode(&doc! { "_id": id })?,
+                None => Vec::new(),
+            },
+        })
+    }
+
+    async fn delete_documents(
+        &self,
+        call: &Call,
+        request: proto::DeleteRequest,
+    ) -> Result<proto::DeleteResponse, ApiError> {
+        let namespace = namespace(request.namespace)?;
+        call.received(&namespace, None);
+        let state = &self.state;
+        let mut filter = decode(&request.filter, "filter")?;
+        let operation = if request.many {
+            "delete_many"
+        } else {
+            "delete_one"
+        };
+        let policy = state.query_policy();
+        let fields = state.field_policy(&namespace, call.identity.as_ref());
+        if request.many {
+            policy.check_bounded(operation, &filter, request.confirm_all)?;
+        }
+        policy
+            .check_filter(&filter)
+            .and_then(|()| fields.check_filter(&filter))?;
+        state.encryption(&namespace).encrypt_filter(&mut filter)?;
+        let soft = state.soft_delete(&namespace);
+        if soft {
+            filter = soft_delete::exclude_deleted(filter);
+        }
+        let (collection, _in_flight) = state.checkout_collection(&namespace, None)?;
+        let versioned = state
+            .history(&namespace, None, &collection, call.identity.as_ref())
+            .await;
+        let audit = state
+            .begin_audit(&call.context, operation, &namespace, None)
+            .await?
+            .filter(&filter);
+        let many = request.many;
+        let result = if soft {
+            let update = soft_delete::delete_update();
+            let options = soft_delete::update_options(None);
+            match &versioned {
+                Some(versioned) => versioned.update(filter, update, options, many).await,
+                None if many => collection.update_many(filter, update, options).await,
+                None => collection.update_one(filter, update, options).await,
+            }
+            .map(|result| result.modified_count)
+        } else {
+            match &versioned {
+                Some(versioned) => versioned.delete(filter, None, many).await,
+                None if many => collection.delete_many(filter, None).await,
+                None => collection.delete_one(filter, None).await,
+            }
+            .map(|result| result.deleted_count)
+        };
+        // Invalidate even on failure; a failed write may have partly applied
+        state.invalidate_cache(&namespace, None);
+        let result = result.map_err(|err| audit.failed(driver_error(err)))?;
+        let response = DeleteResponse {
+            deleted_count: result,
+        };
+        audit.succeeded(&response);
+        call.succeeded(&namespace, Some(response.deleted_count));
+        Ok(proto::DeleteResponse {
+            deleted_count: response.deleted_count,
+        })
+    }
+
+    async fn aggregate_documents(
+        &self,
+        call: &Call,
+        request: proto::AggregateRequest,
+    ) -> Result<proto::AggregateResponse, ApiError> {
+        let namespace = namespace(request.namespace)?;
+        call.received(&namespace, Some(request.pipeline.len()));
+        let state = &self.state;
+        let pipeline = decode_pipeline(&request.pipeline)?;
+        let policy = state.query_policy();
+        let fields = state.field_policy(&namespace, call.identity.as_ref());
+        policy
+            .check_pipeline(&pipeline)
+            .and_then(|()| fields.check_pipeline(&pipeline))
+            .and_then(|()| state.check_joins(&namespace, &pipeline, call.identity.as_ref()))?;
+        let mut pipeline = fields.apply_pipeline(pipeline);
+        if !request.include_deleted && state.soft_delete(&namespace) {
+            soft_delete::exclude_deleted_stages(&mut pipeline);
+        }
+        let cipher = state.encryption(&namespace);
+        cipher.encrypt_pipeline(&mut pipeline)?;
+        let (collection, _in_flight) = state.checkout_collection(&namespace, None)?;
+        let mut cursor = collection
+            .aggregate(pipeline, None)
+            .await
+            .map_err(driver_error)?;
+        let mut documents = Vec::new();
+        while let Some(mut document) = cursor.try_next().await.map_err(driver_error)? {
+            cipher.decrypt(&mut document)?;
+            fields.redact(&mut document);
+            documents.push(encode(&document)?);
+        }
+        call.succeeded(&namespace, Some(documents.len() as u64));
+        Ok(proto::AggregateResponse { documents })
+    }
+
+    /// Opens a change stream on the namespace. Callers under a field policy
+    /// get redacted documents, no `updateDescription` and no custom stages,
+    /// which could read restricted fields.
+    async fn watch_changes(
+        &self,
+        call: &Call,
+        request: proto::WatchRequest,
+    ) -> Result<BoxStream<'static, Result<proto::ChangeEvent, ApiError>>, ApiError> {
+        let namespace = namespace(request.namespace)?;
+        call.received(&namespace, Some(request.pipeline.len()));
+        let state = &self.state;
+        let mut pipeline = decode_pipeline(&request.pipeline)?;
+        let policy = state.query_policy();
+        policy.check_pipeline(&pipeline)?;
+        let fields = state.field_policy(&namespace, call.identity.as_ref());
+        if !fields.is_unrestricted() && !pipeline.is_empty() {
+            return Err(ApiError::forbidden(
+                "change stream pipelines are not allowed on namespaces with restricted fields",
+            ));
+        }
+        let cipher = state.encryption(&namespace);
+        cipher.encrypt_pipeline(&mut pipeline)?;
+        let resume_after = match decode_optional(&request.resume_after, "resume_after")? {
+            Some(token) => Some(
+                bson::from_bson::<ResumeToken>(Bson::Document(token))
+                    .map_err(|err| ApiError::validation(format!("invalid resume_after: {err}")))?,
+            ),
+            None => None,
+        };
+        let options = ChangeStreamOptions::builder()
+            .full_document(
+                request
+                    .full_document
+                    .then_some(FullDocumentType::UpdateLookup),
+            )
+            .resume_after(resume_after)
+            .build();
+        // A change stream can stay open indefinitely, so it holds no
+        // namespace slot once opened
+        let (collection, _in_flight) = state.checkout_collection(&namespace, None)?;
+        let changes = collection
+            .watch(pipeline, options)
+            .await
+            .map_err(driver_error)?
+            .with_type::<Document>();
+        let events = stream::unfold(changes, |mut changes| async move {
+            let event = changes.next().await?;
+            let token = changes.resume_token();
+            Some(((event, token), changes))
+        })
+        .map(move |(event, token)| {
+            let mut event = event.map_err(driver_error)?;
+            for key in ["fullDocument", "fullDocumentBeforeChange"] {
+                if let Ok(document) = event.get_document_mut(key) {
+                    cipher.decrypt(document)?;
+                    fields.redact(document);
+                }
+            }
+            if !fields.is_unrestricted() {
+                event.remove("updateDescription");
+            }
+            Ok(proto::ChangeEvent {
+                event: encode(&event)?,
+                resume_token: match token.map(|token| bson::to_bson(&token)) {
+                    Some(Ok(Bson::Document(token))) => encode(&token)?,
+                    _ => Vec::new(),
+                },
+            })
+        });
+        // Watchers are closed when shutdown starts rather than at the deadline
+        let state = state.clone();
+        let events = events.take_until(async move { state.drain().started().await });
+        call.succeeded(&namespace, None);
+        Ok(events.boxed())
+    }
+}
+
+impl Call {
+    fn received(&self, namespace: &NamespacePayload, payload_items: Option<usize>) {
+        tracing::info!(
+            target = "grpc",
+            method = self.method,
+            database = %namespace.database.trim(),
+            collection = %namespace.collection.trim(),
+            payload_items = payload_items.map(|items| items as u64),
+            "received call"
+        );
+    }
+
+    fn succeeded(&self, namespace: &NamespacePayload, count: Option<u64>) {
+        tracing::info!(
+            target = "grpc",
+            method = self.method,
+            database = %namespace.database.trim(),
+            collection = %namespace.collection.trim(),
+            count,
+            "call completed"
+        );
+    }
+
+    /// The reply, or the logged failure as a gRPC status.
+    fn finish<T>(&self, result: Result<T, ApiError>) -> Result<Response<T>, Status> {
+        let mut response = Response::new(result.map_err(|err| self.status(err))?);
+        if let Ok(value) = MetadataValue::try_from(self.correlation_id.as_ref()) {
+            response.metadata_mut().insert(CORRELATION_ID_HEADER, value);
+        }
+        Ok(response)
+    }
+
+    /// Like [`Call::finish`] for streaming replies. Errors while streaming end
+    /// the stream with their status.
+    fn finish_stream<T: Send + 'static>(
+        self,
+        result: Result<BoxStream<'static, Result<T, ApiError>>, ApiError>,
+    ) -> Result<Response<ReplyStream<T>>, Status> {
+        let items = match result {
+            Ok(items) => items,
+            Err(err) => return Err(self.status(err)),
+        };
+        let correlation_id = MetadataValue::try_from(self.correlation_id.as_ref()).ok();
+        // The call, and with it the drain guard, lives as long as the stream
+        let items = items.map(move |item| {
+            item.map_err(|err| {
+                tracing::warn!(
+                    target = "grpc",
+                    method = self.method,
+                    error = ?err,
+                    "stream aborted"
+                );
+                to_status(&err, &self.correlation_id)
+            })
+        });
+        let mut response = Response::new(items.boxed());
+        if let Some(value) = correlation_id {
+            response.metadata_mut().insert(CORRELATION_ID_HEADER, value);
+        }
+        Ok(response)
+    }
+
+    fn status(&self, err: ApiError) -> Status {
+        to_status(&log_call_failure(self.method, err), &self.correlation_id)
+    }
+}
+
+fn log_call_failure(method: &'static str, error: ApiError) -> ApiError {
+    tracing::warn!(
+        target = "grpc",
+        method,
+        status = %error.status(),
+        error = ?error,
+        "call failed"
+    );
+    error
+}
+
+/// Maps `err` to the gRPC code for its HTTP status. The gateway's error code,
+/// the call's correlation id and any retry delay are sent as metadata.
+pub fn to_status(err: &ApiError, correlation_id: &str) -> Status {
+    let code = match err.status() {
+        StatusCode::BAD_REQUEST => Code::InvalidArgument,
+        StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+        StatusCode::FORBIDDEN => Code::PermissionDenied,
+        StatusCode::NOT_FOUND => Code::NotFound,
+        StatusCode::PRECONDITION_FAILED => Code::FailedPrecondition,
+        StatusCode::RANGE_NOT_SATISFIABLE => Code::OutOfRange,
+        StatusCode::PAYLOAD_TOO_LARGE | StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE => Code::Unavailable,
+        _ => Code::Internal,
+    };
+    let body = err.body();
+    let mut metadata = MetadataMap::new();
+    metadata.insert(ERROR_CODE_METADATA, MetadataValue::from_static(body.error));
+    if let Ok(value) = MetadataValue::try_from(correlation_id) {
+        metadata.insert(CORRELATION_ID_HEADER, value);
+    }
+    if let Some(value) = body
+        .correlation_id
+        .as_deref()
+        .and_then(|id| MetadataValue::try_from(id).ok())
+    {
+        metadata.insert(ERROR_CORRELATION_ID_METADATA, value);
+    }
+    if let Some(seconds) = err.retry_after_secs() {
+        metadata.insert(RETRY_AFTER_METADATA, MetadataValue::from(seconds));
+    }
+    Status::with_metadata(code, body.details.clone(), metadata)
+}
+
+fn driver_error(err: mongodb::error::Error) -> ApiError {
+    history::rejection(&err).unwrap_or_else(|| ApiError::driver(format!("mongodb error: {err}")))
+}
+
+fn namespace(namespace: Option<proto::Namespace>) -> Result<NamespacePayload, ApiError> {
+    let namespace = namespace.ok_or_else(|| ApiError::validation("namespace must be provided"))?;
+    if namespace.database.trim().is_empty() {
+        return Err(ApiError::validation("database must be provided"));
+    }
+    if namespace.collection.trim().is_empty() {
+        return Err(ApiError::validation("collection must be provided"));
+    }
+    Ok(NamespacePayload {
+        database: namespace.database,
+        collection: namespace.collection,
+        cluster: namespace.cluster,
+        consistency: Consistency::default(),
+    })
+}
+
+/// A BSON document field; empty bytes are an empty document.
+fn decode(bytes: &[u8], name: &str) -> Result<Document, ApiError> {
+    Ok(decode_optional(bytes, name)?.unwrap_or_default())
+}
+
+fn decode_optional(bytes: &[u8], name: &str) -> Result<Option<Document>, ApiError> {
+    if bytes.is_empty() {
+        return Ok(None);
+    }
+    bson::from_slice(bytes)
+        .map(Some)
+        .map_err(|err| ApiError::validation(format!("invalid {name}: {err}")))
+}
+
+fn decode_pipeline(stages: &[Vec<u8>]) -> Result<Vec<Document>, ApiError> {
+    stages
+        .iter()
+        .enumerate()
+        .map(|(index, stage)| decode(stage, &format!("pipeline.{index}")))
+        .collect()
+}
+
+fn encode(document: &Document) -> Result<Vec<u8>, ApiError> {
+    bson::to_vec(document)
+        .map_err(|err| ApiError::internal(format!("failed to encode bson response: {err}")))
+}
+
+#[tonic::async_trait]
+impl Documents for DocumentService {
+    type FindStream = ReplyStream<proto::Document>;
+    type WatchStream = ReplyStream<proto::ChangeEvent>;
+
+    async fn find(
+        &self,
+        request: Request<proto::FindRequest>,
+    ) -> Result<Response<Self::FindStream>, Status> {
+        let call = self.begin("Find", &request, AccessKind::Read)?;
+        let implied_limit = self
+            .state
+            .query_policy()
+            .implied_limit(request.get_ref().limit);
+        let result = self.find_documents(&call, request.into_inner()).await;
+        let mut response = call.finish_stream(result)?;
+        if let Some(limit) = implied_limit {
+            response
+                .metadata_mut()
+                .insert(LIMIT_APPLIED_HEADER, MetadataValue::from(limit));
+        }
+        Ok(response)
+    }
+
+    async fn insert(
+        &self,
+        request: Request<proto::InsertRequest>,
+    ) -> Result<Response<proto::InsertResponse>, Status> {
+        let call = self.begin("Insert", &request, AccessKind::Write)?;
+        let result = self.insert_documents(&call, request.into_inner()).await;
+        call.finish(result)
+    }
+
+    async fn update(
+        &self,
+        request: Request<proto::UpdateRequest>,
+    ) -> Result<Response<proto::UpdateResponse>, Status> {
+        let call = self.begin("Update", &request, AccessKind::Write)?;
+        let result = self.update_documents(&call, request.into_inner()).await;
+        call.finish(result)
+    }
+
+    async fn delete(
+        &self,
+        request: Request<proto::DeleteRequest>,
+    ) -> Result<Response<proto::DeleteResponse>, Status> {
+        let call = self.begin("Delete", &request, AccessKind::Write)?;
+        let result = self.delete_documents(&call, request.into_inner()).await;
+        call.finish(result)
+    }
+
+    async fn aggregate(
+        &self,
+        request: Request<proto::AggregateRequest>,
+    ) -> Result<Response<proto::AggregateResponse>, Status> {
+        let call = self.begin("Aggregate", &request, AccessKind::Read)?;
+        let result = self.aggregate_documents(&call, request.into_inner()).await;
+        call.finish(result)
+    }
+
+    async fn watch(
+        &self,
+        request: Request<proto::WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let call = self.begin("Watch", &request, AccessKind::Read)?;
+        let result = self.watch_changes(&call, request.into_inner()).await;
+        call.finish_stream(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{test_config, ApiKeyConfig, AuthConfig, Config};
+    use prost::Message;
+    use std::time::Duration;
+
+    fn config(auth: AuthConfig) -> Config {
+        Config {
+            auth,
+            ..test_config()
+        }
+    }
+
+    fn metadata<'a>(status: &'a Status, key: &str) -> Option<&'a str> {
+        status
+            .metadata()
+            .get(key)
+            .and_then(|value| value.to_str().ok())
+    }
+
+    #[test]
+    fn errors_map_to_grpc_codes() {
+        let cases = [
+            (ApiError::validation("bad"), Code::InvalidArgument),
+            (ApiError::unauthorized("who"), Code::Unauthenticated),
+            (ApiError::forbidden("no"), Code::PermissionDenied),
+            (ApiError::not_found("gone"), Code::NotFound),
+            (
+                ApiError::precondition_failed("changed"),
+                Code::FailedPrecondition,
+            ),
+            (ApiError::range_not_satisfiable("range"), Code::OutOfRange),
+            (
+                ApiError::rate_limited("slow", Duration::from_secs(1)),
+                Code::ResourceExhausted,
+            ),
+            (ApiError::driver("mongo"), Code::Unavailable),
+            (ApiError::unavailable("draining"), Code::Unavailable),
+            (ApiError::internal("encode"), Code::Internal),
+        ];
+        for (err, code) in cases {
+            assert_eq!(to_status(&err, "req-1").code(), code, "{err:?}");
+        }
+    }
+
+    #[test]
+    fn status_metadata_carries_error_details() {
+        let status = to_status(&ApiError::validation("filter is invalid"), "req-1");
+        assert_eq!(status.message(), "filter is invalid");
+        assert_eq!(
+            metadata(&status, ERROR_CODE_METADATA),
+            Some("validation_error")
+        );
+        assert_eq!(metadata(&status, CORRELATION_ID_HEADER), Some("req-1"));
+        assert_eq!(metadata(&status, ERROR_CORRELATION_ID_METADATA), None);
+
+        let limited = ApiError::rate_limited("slow down", Duration::from_millis(1500));
+        let status = to_status(&limited, "req-2");
+        assert_eq!(metadata(&status, RETRY_AFTER_METADATA), Some("2"));
+
+        let driver = ApiError::driver("mongo");
+        let status = to_status(&driver, "req-3");
+        assert_eq!(
+            metadata(&status, ERROR_CORRELATION_ID_METADATA),
+            driver.body().correlation_id.as_deref()
+        );
+    }
+
+    #[test]
+    fn decodes_bson_fields() {
+        assert_eq!(decode(&[], "filter").unwrap(), Document::new());
+        let bytes = bson::to_vec(&doc! { "status": "active" }).unwrap();
+        assert_eq!(
+            decode(&bytes, "filter").unwrap(),
+            doc! { "status": "active" }
+        );
+        let err = decode(&[1, 2, 3], "filter").unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+        assert!(err.body().details.starts_with("invalid filter"));
+    }
+
+    #[test]
+    fn namespace_requires_database_and_collection() {
+        assert!(namespace(None).is_err());
+        let missing = proto::Namespace {
+            database: "app".into(),
+            collection: " ".into(),
+            cluster: None,
+        };
+        assert!(namespace(Some(missing)).is_err());
+        let payload = namespace(Some(proto::Namespace {
+            database: "app".into(),
+            collection: "users".into(),
+            cluster: Some("analytics".into()),
+        }))
+        .unwrap();
+        assert_eq!(payload.collection, "users");
+        assert_eq!(payload.cluster.as_deref(), Some("analytics"));
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let request = proto::UpdateRequest {
+            namespace: Some(proto::Namespace {
+                database: "app".into(),
+                collection: "users".into(),
+                cluster: None,
+            }),
+            filter: bson::to_vec(&doc! { "_id": 1 }).unwrap(),
+            update: bson::to_vec(&doc! { "$set": { "active": true } }).unwrap(),
+            many: false,
+            upsert: true,
+            confirm_all: false,
+        };
+        let decoded = proto::UpdateRequest::decode(request.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded, request);
+    }
+
+    #[test]
+    fn resume_tokens_convert_from_and_to_documents() {
+        let token = doc! { "_data": "8263A1" };
+        let resume = bson::from_bson::<ResumeToken>(Bson::Document(token.clone())).unwrap();
+        assert_eq!(bson::to_bson(&resume).unwrap(), Bson::Document(token));
+    }
+
+    #[tokio::test]
+    async fn calls_require_a_configured_api_key() {
+        let client = mongodb::Client::with_uri_str("mongodb://localhost:27017")
+            .await
+            .expect("client");
+        let auth = AuthConfig {
+            api_keys: vec![ApiKeyConfig {
+                key: "k-123".into(),
+                identity: "batch-job".into(),
+                roles: Vec::new(),
+            }],
+            client_certs: Vec::new(),
+        };
+        let service = DocumentService::new(AppState::new(client, &config(auth)));
+        let request = |key: Option<&str>| {
+            let mut request = Request::new(proto::InsertRequest {
+                namespace: Some(proto::Namespace {
+                    database: "app".into(),
+                    collection: "users".into(),
+                    cluster: None,
+                }),
+                documents: Vec::new(),
+            });
+            if let Some(key) = key {
+                request
+                    .metadata_mut()
+                    .insert(API_KEY_HEADER, key.parse().unwrap());
+            }
+            request
+        };
+
+        let missing = service.insert(request(None)).await.unwrap_err();
+        assert_eq!(missing.code(), Code::Unauthenticated);
+        let invalid = service.insert(request(Some("nope"))).await.unwrap_err();
+        assert_eq!(invalid.code(), Code::Unauthenticated);
+        let valid = service.insert(request(Some("k-123"))).await.unwrap_err();
+        assert_eq!(valid.code(), Code::InvalidArgument);
+        assert_eq!(
+            metadata(&valid, ERROR_CODE_METADATA),
+            Some("validation_error")
+        );
+    }
+
+    #[tokio::test]
+    async fn draining_refuses_new_calls() {
+        let client = mongodb::Client::with_uri_str("mongodb://localhost:27017")
+            .await
+            .expect("client");
+        let state = AppState::new(client, &config(AuthConfig::default()));
+        let service = DocumentService::new(state.clone());
+        state.drain().begin();
+        let status = service
+            .aggregate(Request::new(proto::AggregateRequest::default()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(
+            metadata(&status, ERROR_CODE_METADATA),
+            Some("service_unavailable")
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Messages of `proto/gateway.proto`.
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Namespace {
+    #[prost(string, tag = "1")]
+    pub database: String,
+    #[prost(string, tag = "2")]
+    pub collection: String,
+    #[prost(string, optional, tag = "3")]
+    pub cluster: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Document {
+    #[prost(bytes = "vec", tag = "1")]
+    pub bson: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FindRequest {
+    #[prost(message, optional, tag = "1")]
+    pub namespace: Option<Namespace>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub filter: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub projection: Vec<u8>,
+    #[prost(bytes = "vec", tag = "4")]
+    pub sort: Vec<u8>,
+    #[prost(int64, optional, tag = "5")]
+    pub limit: Option<i64>,
+    #[prost(uint64, optional, tag = "6")]
+    pub skip: Option<u64>,
+    #[prost(bool, tag = "7")]
+    pub include_deleted: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct InsertRequest {
+    #[prost(message, optional, tag = "1")]
+    pub namespace: Option<Namespace>,
+    #[prost(bytes = "vec", repeated, tag = "2")]
+    pub documents: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct InsertResponse {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    pub inserted_ids: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct UpdateRequest {
+    #[prost(message, optional, tag = "1")]
+    pub namespace: Option<Namespace>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub filter: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub update: Vec<u8>,
+    #[prost(bool, tag = "4")]
+    pub many: bool,
+    #[prost(bool, tag = "5")]
+    pub upsert: bool,
+    #[prost(bool, tag = "6")]
+    pub confirm_all: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct UpdateResponse {
+    #[prost(uint64, tag = "1")]
+    pub matched_count: u64,
+    #[prost(uint64, tag = "2")]
+    pub modified_count: u64,
+    #[prost(bytes = "vec", tag = "3")]
+    pub upserted_id: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DeleteRequest {
+    #[prost(message, optional, tag = "1")]
+    pub namespace: Option<Namespace>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub filter: Vec<u8>,
+    #[prost(bool, tag = "3")]
+    pub many: bool,
+    #[prost(bool, tag = "4")]
+    pub confirm_all: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DeleteResponse {
+    #[prost(uint64, tag = "1")]
+    pub deleted_count: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AggregateRequest {
+    #[prost(message, optional, tag = "1")]
+    pub namespace: Option<Namespace>,
+    #[prost(bytes = "vec", repeated, tag = "2")]
+    pub pipeline: Vec<Vec<u8>>,
+    #[prost(bool, tag = "3")]
+    pub include_deleted: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AggregateResponse {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    pub documents: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WatchRequest {
+    #[prost(message, optional, tag = "1")]
+    pub namespace: Option<Namespace>,
+    #[prost(bytes = "vec", repeated, tag = "2")]
+    pub pipeline: Vec<Vec<u8>>,
+    #[prost(bool, tag = "3")]
+    pub full_document: bool,
+    #[prost(bytes = "vec", tag = "4")]
+    pub resume_after: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ChangeEvent {
+    #[prost(bytes = "vec", tag = "1")]
+    pub event: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub resume_token: Vec<u8>,
+}
+
+include!(concat!(env!("OUT_DIR"), "/gateway.v1.Documents.rs"));
@@ -0,0 +1,259 @@
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use mongodb::bson::{doc, Document};
+use mongodb::event::cmap::{
+    CmapEventHandler, ConnectionCheckedInEvent, ConnectionCheckedOutEvent,
+    ConnectionCheckoutFailedEvent, ConnectionClosedEvent, ConnectionCreatedEvent, PoolClearedEvent,
+};
+use mongodb::options::{ReadPreference, SelectionCriteria};
+use mongodb::Client;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::cluster::DEFAULT_CLUSTER;
+use crate::models::{
+    ClusterHealth, HealthResponse, PoolSnapshot, ReadinessQuery, ReadinessResponse,
+};
+use crate::state::AppState;
+
+pub const HEALTHZ_PATH: &str = "/healthz";
+pub const READYZ_PATH: &str = "/readyz";
+
+/// Upper bound on each cluster probe, so a slow server selection cannot hang
+/// the readiness probe itself.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Connection pool counters fed by the driver's CMAP events.
+#[derive(Debug, Default)]
+pub struct PoolStats {
+    open: AtomicU64,
+    in_use: AtomicU64,
+    cleared: AtomicU64,
+    checkout_failures: AtomicU64,
+}
+
+impl PoolStats {
+    pub fn snapshot(&self) -> PoolSnapshot {
+        let open = self.open.load(Ordering::Relaxed);
+        let in_use = self.in_use.load(Ordering::Relaxed);
+        PoolSnapshot {
+            open,
+            in_use,
+            idle: open.saturating_sub(in_use),
+            cleared: self.cleared.load(Ordering::Relaxed),
+            checkout_failures: self.checkout_failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn decrement(counter: &AtomicU64) {
+    // Events for connections opened before the handler saw them must not wrap
+    let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+        Some(value.saturating_sub(1))
+    });
+}
+
+impl CmapEventHandler for PoolStats {
+    fn handle_pool_cleared_event(&self, _event: PoolClearedEvent) {
+        self.cleared.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn handle_connection_created_event(&self, _event: ConnectionCreatedEvent) {
+        self.open.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn handle_connection_closed_event(&self, _event: ConnectionClosedEvent) {
+        decrement(&self.open);
+    }
+
+    fn handle_connection_checkout_failed_event(&self, _event: ConnectionCheckoutFailedEvent) {
+        self.checkout_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn handle_connection_checked_out_event(&self, _event: ConnectionCheckedOutEvent) {
+        self.in_use.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn handle_connection_checked_in_event(&self, _event: ConnectionCheckedInEvent) {
+        decrement(&self.in_use);
+    }
+}
+
+/// Liveness: the process is up and serving HTTP.
+pub async fn healthz() -> Json<HealthResponse> {
+    Json(HealthResponse { status: "ok" })
+}
+
+/// Readiness: pings every cluster. Returns 503 when the default cluster is
+/// unreachable or the gateway is shutting down; other clusters being down only
+/// marks the gateway degraded.
+pub async fn readyz(
+    State(state): State<AppState>,
+    Query(query): Query<ReadinessQuery>,
+) -> Response {
+    if state.drain().is_draining() {
+        let response = ReadinessResponse {
+            status: "shutting_down",
+            clusters: BTreeMap::new(),
+        };
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(response)).into_response();
+    }
+    let verbose = query.is_verbose();
+    let probes = state
+        .clusters()
+        .iter()
+        .map(|(name, client, pool)| async move {
+            let mut health = probe(client, verbose).await;
+            health.pool = pool.map(PoolStats::snapshot);
+            (name.to_string(), health)
+        });
+    let clusters: BTreeMap<_, _> = futures::future::join_all(probes)
+        .await
+        .into_iter()
+        .collect();
+
+    let default_ok = clusters
+        .get(DEFAULT_CLUSTER)
+        .is_some_and(|health| health.status == "ok");
+    let all_ok = clusters.values().all(|health| health.status == "ok");
+    let (status, overall) = match (default_ok, all_ok) {
+        (true, true) => (StatusCode::OK, "ready"),
+        (true, false) => (StatusCode::OK, "degraded"),
+        (false, _) => (StatusCode::SERVICE_UNAVAILABLE, "unavailable"),
+    };
+    if status != StatusCode::OK || !all_ok {
+        tracing::warn!(
+            target = "http",
+            endpoint = READYZ_PATH,
+            status = %status,
+            readiness = overall,
+            "readiness check failed"
+        );
+    }
+    let response = ReadinessResponse {
+        status: overall,
+        clusters,
+    };
+    (status, Json(response)).into_response()
+}
+
+async fn probe(client: &Client, verbose: bool) -> ClusterHealth {
+    let started = Instant::now();
+    let outcome = tokio::time::timeout(PROBE_TIMEOUT, async {
+        // Any reachable member will do; primary availability is reported separately
+        let admin = client.database("admin");
+        let nearest = SelectionCriteria::ReadPreference(ReadPreference::Nearest {
+            options: Default::default(),
+        });
+        admin
+            .run_command(doc! { "ping": 1 }, nearest.clone())
+            .await?;
+        admin.run_command(doc! { "hello": 1 }, nearest).await
+    })
+    .await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    let error = match outcome {
+        Ok(Ok(hello)) => return describe(&hello, latency_ms, verbose),
+        Ok(Err(err)) => err.to_string(),
+        Err(_) => format!("ping timed out after {}ms", PROBE_TIMEOUT.as_millis()),
+    };
+    ClusterHealth {
+        status: "unavailable",
+        latency_ms,
+        error: verbose.then_some(error),
+        ..Default::default()
+    }
+}
+
+/// Derives topology and primary availability from a `hello` reply.
+fn describe(hello: &Document, latency_ms: u64, verbose: bool) -> ClusterHealth {
+    let writable = hello.get_bool("isWritablePrimary").unwrap_or(false);
+    let set_name = hello.get_str("setName").ok().map(str::to_string);
+    let primary = hello.get_str("primary").ok().map(str::to_string);
+    let topology = if hello.get_str("msg") == Ok("isdbgrid") {
+        "sharded"
+    } else if set_name.is_some() {
+        "replicaSet"
+    } else {
+        "single"
+    };
+    let hosts = hello.get_array("hosts").ok().map(|hosts| {
+        hosts
+            .iter()
+            .filter_map(|host| host.as_str().map(str::to_string))
+            .collect()
+    });
+    ClusterHealth {
+        status: "ok",
+        latency_ms,
+        topology: Some(topology),
+        primary_available: Some(writable || primary.is_some()),
+        set_name: set_name.filter(|_| verbose),
+        primary: primary.filter(|_| verbose),
+        hosts: hosts.filter(|_| verbose),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_replica_set_without_primary() {
+        let hello = doc! {
+            "isWritablePrimary": false,
+            "secondary": true,
+            "setName": "rs0",
+            "hosts": ["db1:27017", "db2:27017"],
+        };
+        let health = describe(&hello, 3, true);
+        assert_eq!(health.topology, Some("replicaSet"));
+        assert_eq!(health.primary_available, Some(false));
+        assert_eq!(health.hosts.unwrap().len(), 2);
+
+        let terse = describe(&hello, 3, false);
+        assert!(terse.set_name.is_none() && terse.hosts.is_none());
+    }
+
+    #[test]
+    fn describes_mongos_and_standalone() {
+        let mongos = describe(
+            &doc! { "isWritablePrimary": true, "msg": "isdbgrid" },
+            1,
+            false,
+        );
+        assert_eq!(mongos.topology, Some("sharded"));
+        assert_eq!(mongos.primary_available, Some(true));
+        let single = describe(&doc! { "isWritablePrimary": true }, 1, false);
+        assert_eq!(single.topology, Some("single"));
+    }
+
+    #[test]
+    fn pool_stats_track_checkouts_without_underflow() {
+        let stats = PoolStats::default();
+        stats.open.fetch_add(3, Ordering::Relaxed);
+        stats.in_use.fetch_add(1, Ordering::Relaxed);
+        decrement(&stats.in_use);
+        decrement(&stats.in_use);
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.open, 3);
+        assert_eq!(snapshot.in_use, 0);
+        assert_eq!(snapshot.idle, 3);
+    }
+
+    #[test]
+    fn verbose_query_flag() {
+        let query = |verbose: Option<&str>| ReadinessQuery {
+            verbose: verbose.map(str::to_string),
+        };
+        assert!(query(Some("")).is_verbose());
+        assert!(query(Some("true")).is_verbose());
+        assert!(!query(Some("0")).is_verbose());
+        assert!(!query(None).is_verbose());
+    }
+}
@@ -0,0 +1,552 @@
+use futures::TryStreamExt;
+use mongodb::bson::{doc, Bson, DateTime, Document};
+use mongodb::error::Error;
+use mongodb::options::{
+    DeleteOptions, FindOneOptions, FindOptions, ReadPreference, ReplaceOptions, SelectionCriteria,
+    TransactionOptions, UpdateModifications, UpdateOptions, WriteConcern,
+};
+use mongodb::results::{DeleteResult, UpdateResult};
+use mongodb::{Client, ClientSession, Collection};
+use std::sync::Arc;
+
+use crate::error::ApiError;
+
+/// Version counter maintained on documents of versioned namespaces.
+pub const VERSION_FIELD: &str = "_version";
+/// Appended to a collection's name to get its history collection.
+const HISTORY_SUFFIX: &str = "_history";
+/// Most documents one write may change in a versioned namespace unless its
+/// `[[namespaces]]` rule sets `max_versioned_documents`.
+pub const DEFAULT_MAX_DOCUMENTS: usize = 1000;
+
+/// Writes to a versioned namespace. Every change bumps [`VERSION_FIELD`] and
+/// copies the previous state of each affected document to
+/// `<collection>_history`, inside a transaction when the deployment has them.
+#[derive(Debug)]
+pub struct History {
+    collection: Collection<Document>,
+    history: Collection<Document>,
+    transactions: bool,
+    actor: Option<Arc<str>>,
+    max_documents: usize,
+}
+
+/// Payload of the [`Error::custom`] returned when a write matches more
+/// documents than a versioned namespace allows.
+#[derive(Debug)]
+struct TooManyDocuments(usize);
+
+/// The validation error behind `err` when a versioned write refused to
+/// change too many documents.
+pub fn rejection(err: &Error) -> Option<ApiError> {
+    err.get_custom::<TooManyDocuments>().map(|TooManyDocuments(max)| {
+        ApiError::validation(format!(
+            "the filter matches more than {max} documents, the most one write may change in a versioned namespace"
+        ))
+    })
+}
+
+/// Whether the deployment behind `client` is a replica set or sharded
+/// cluster, and so supports transactions.
+pub async fn supports_transactions(client: &Client) -> Result<bool, Error> {
+    let reply = client
+        .database("admin")
+        .run_command(doc! { "hello": 1 }, None)
+        .await?;
+    Ok(reply.contains_key("setName") || reply.get_str("msg") == Ok("isdbgrid"))
+}
+
+/// The collection whose history `collection` would hold, going by its name.
+pub fn source_collection(collection: &str) -> Option<&str> {
+    collection
+        .strip_suffix(HISTORY_SUFFIX)
+        .filter(|source| !source.is_empty())
+}
+
+/// Rejects updates that set the version counter themselves.
+pub fn check_update(update: &Document) -> Result<(), ApiError> {
+    let touches_version = update.values().any(|fields| match fields {
+        Bson::Document(fields) => fields
+            .keys()
+            .any(|field| field == VERSION_FIELD || field.starts_with(&format!("{VERSION_FIELD}."))),
+        _ => false,
+    });
+    if touches_version {
+        return Err(ApiError::validation(format!(
+            "`{VERSION_FIELD}` is managed by the gateway in versioned namespaces"
+        )));
+    }
+    Ok(())
+}
+
+impl History {
+    pub fn new(
+        collection: Collection<Document>,
+        transactions: bool,
+        actor: Option<Arc<str>>,
+        max_documents: usize,
+    ) -> Self {
+        let namespace = collection.namespace();
+        let history = collection
+            .client()
+            .database(&namespace.db)
+            .collection(&format!("{}{HISTORY_SUFFIX}", namespace.coll));
+        Self {
+            collection,
+            history,
+            transactions,
+            actor,
+            max_documents,
+        }
+    }
+
+    pub async fn update(
+        &self,
+        filter: Document,
+        update: impl Into<UpdateModifications>,
+        mut options: Option<UpdateOptions>,
+        many: bool,
+    ) -> Result<UpdateResult, Error> {
+        let mut update = update.into();
+        let write_concern = self.take_write_concern(options.as_mut().map(|o| &mut o.write_concern));
+        let mut tx = self.begin(write_concern).await?;
+        let priors = self.priors(&mut tx, filter.clone(), many).await?;
+        let filter = pin(filter, &priors);
+        tx.insert(&self.history, self.entries(priors, "update"))
+            .await?;
+        match &mut update {
+            UpdateModifications::Document(update) => match update.get_mut("$inc") {
+                Some(Bson::Document(increments)) => {
+                    increments.insert(VERSION_FIELD, 1_i64);
+                }
+                _ => {
+                    update.insert("$inc", doc! { VERSION_FIELD: 1_i64 });
+                }
+            },
+            UpdateModifications::Pipeline(stages) => {
+                let current = format!("${VERSION_FIELD}");
+                stages.push(doc! {
+                    "$set": { VERSION_FIELD: { "$add": [{ "$ifNull": [current, 0_i64] }, 1_i64] } }
+                });
+            }
+            _ => {}
+        }
+        let result = tx
+            .update(&self.collection, filter, update, options, many)
+            .await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn replace(
+        &self,
+        filter: Document,
+        mut replacement: Document,
+        mut options: Option<ReplaceOptions>,
+    ) -> Result<UpdateResult, Error> {
+        let write_concern = self.take_write_concern(options.as_mut().map(|o| &mut o.write_concern));
+        let mut tx = self.begin(write_concern).await?;
+        let priors = tx.find(&self.collection, filter.clone(), 1).await?;
+        let next = priors.first().map_or(0, version_of) + 1;
+        let filter = pin(filter, &priors);
+        tx.insert(&self.history, self.entries(priors, "replace"))
+            .await?;
+        replacement.insert(VERSION_FIELD, next);
+        let result = tx
+            .replace(&self.collection, filter, replacement, options)
+            .await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    pub async fn delete(
+        &self,
+        filter: Document,
+        mut options: Option<DeleteOptions>,
+        many: bool,
+    ) -> Result<DeleteResult, Error> {
+        let write_concern = self.take_write_concern(options.as_mut().map(|o| &mut o.write_concern));
+        let mut tx = self.begin(write_concern).await?;
+        let priors = self.priors(&mut tx, filter.clone(), many).await?;
+        let filter = pin(filter, &priors);
+        tx.insert(&self.history, self.entries(priors, "delete"))
+            .await?;
+        let result = tx.delete(&self.collection, filter, options, many).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    /// Recorded versions of the document with `_id` `id`, newest first, and
+    /// the version it is at now (`None` once deleted).
+    pub async fn versions(
+        &self,
+        id: &Bson,
+        limit: Option<i64>,
+    ) -> Result<(Option<i64>, Vec<Document>), Error> {
+        let current = self
+            .collection
+            .find_one(doc! { "_id": id.clone() }, None)
+            .await?;
+        let options = FindOptions::builder()
+            .sort(doc! { "version": -1 })
+            .limit(limit)
+            .build();
+        let versions = self
+            .history
+            .find(doc! { "document_id": id.clone() }, options)
+            .await?
+            .try_collect()
+            .await?;
+        Ok((current.as_ref().map(version_of), versions))
+    }
+
+    /// Writes recorded `version` of the document back as a new version,
+    /// recreating the document if it was deleted. Returns the new version, or
+    /// `None` when that version was never recorded.
+    pub async fn restore(&self, id: &Bson, version: i64) -> Result<Option<i64>, Error> {
+        let mut tx = self.begin(None).await?;
+        let entry = tx
+            .find_one(
+                &self.history,
+                doc! { "document_id": id.clone(), "version": version },
+                None,
+            )
+            .await?;
+        let Some(mut restored) = entry.and_then(|mut entry| match entry.remove("document") {
+            Some(Bson::Document(document)) => Some(document),
+            _ => None,
+        }) else {
+            return Ok(None);
+        };
+        let current = tx
+            .find_one(&self.collection, doc! { "_id": id.clone() }, None)
+            .await?;
+        let latest = tx
+            .find_one(
+                &self.history,
+                doc! { "document_id": id.clone() },
+                FindOneOptions::builder()
+                    .sort(doc! { "version": -1 })
+                    .build(),
+            )
+            .await?;
+        let recorded = latest.and_then(|entry| entry.get_i64("version").ok());
+        let next = current
+            .as_ref()
+            .map(version_of)
+            .into_iter()
+            .chain(recorded)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let filter = match current {
+            Some(current) => {
+                let filter = doc! { "_id": id.clone(), VERSION_FIELD: version_match(&current) };
+                tx.insert(&self.history, self.entries(vec![current], "restore"))
+                    .await?;
+                filter
+            }
+            None => doc! { "_id": id.clone() },
+        };
+        restored.insert("_id", id.clone());
+        restored.insert(VERSION_FIELD, next);
+        let options = ReplaceOptions::builder().upsert(true).build();
+        tx.replace(&self.collection, filter, restored, options)
+            .await?;
+        tx.commit().await?;
+        Ok(Some(next))
+    }
+
+    /// Current state of the documents a write with `filter` changes. Writes
+    /// to more than `max_documents` are refused before anything changes.
+    async fn priors(
+        &self,
+        tx: &mut Tx,
+        filter: Document,
+        many: bool,
+    ) -> Result<Vec<Document>, Error> {
+        if !many {
+            return tx.find(&self.collection, filter, 1).await;
+        }
+        let limit = i64::try_from(self.max_documents).map_or(i64::MAX, |max| max + 1);
+        let priors = tx.find(&self.collection, filter, limit).await?;
+        if priors.len() > self.max_documents {
+            return Err(Error::custom(TooManyDocuments(self.max_documents)));
+        }
+        Ok(priors)
+    }
+
+    /// Inside a transaction the write concern belongs to the transaction, so
+    /// it is moved off the operation.
+    fn take_write_concern(
+        &self,
+        write_concern: Option<&mut Option<WriteConcern>>,
+    ) -> Option<WriteConcern> {
+        if self.transactions {
+            write_concern.and_then(Option::take)
+        } else {
+            None
+        }
+    }
+
+    async fn begin(&self, write_concern: Option<WriteConcern>) -> Result<Tx, Error> {
+        if !self.transactions {
+            return Ok(Tx { session: None });
+        }
+        let mut session = self.collection.client().start_session(None).await?;
+        let options = TransactionOptions::builder()
+            .write_concern(write_concern.or_else(|| self.collection.write_concern().cloned()))
+            .selection_criteria(SelectionCriteria::ReadPreference(ReadPreference::Primary))
+            .build();
+        session.start_transaction(options).await?;
+        Ok(Tx {
+            session: Some(session),
+        })
+    }
+
+    fn entries(&self, documents: Vec<Document>, operation: &str) -> Vec<Document> {
+        let changed_by = self
+            .actor
+            .as_deref()
+            .map_or(Bson::Null, |actor| Bson::String(actor.to_string()));
+        documents
+            .into_iter()
+            .map(|document| {
+                doc! {
+                    "document_id": document.get("_id").cloned().unwrap_or(Bson::Null),
+                    "version": version_of(&document),
+                    "operation": operation,
+                    "changed_at": DateTime::now(),
+                    "changed_by": changed_by.clone(),
+                    "document": document,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Current version of `document`; documents written before versioning was
+/// enabled are at version 0.
+fn version_of(document: &Document) -> i64 {
+    match document.get(VERSION_FIELD) {
+        Some(Bson::Int64(version)) => *version,
+        Some(Bson::Int32(version)) => i64::from(*version),
+        _ => 0,
+    }
+}
+
+fn version_match(document: &Document) -> Bson {
+    match document.get(VERSION_FIELD) {
+        Some(version) => version.clone(),
+        None => Bson::Document(doc! { "$exists": false }),
+    }
+}
+
+/// Narrows `filter` to the documents whose previous state was recorded, so
+/// nothing changes without a history entry.
+fn pin(filter: Document, priors: &[Document]) -> Document {
+    let id = |document: &Document| document.get("_id").cloned().unwrap_or(Bson::Null);
+    match priors {
+        [] => filter,
+        [prior] => doc! {
+            "$and": [filter, { "_id": id(prior), VERSION_FIELD: version_match(prior) }]
+        },
+        _ => {
+            let ids: Vec<Bson> = priors.iter().map(id).collect();
+            doc! { "$and": [filter, { "_id": { "$in": ids } }] }
+        }
+    }
+}
+
+/// Runs operations in a session's transaction, or directly when the
+/// deployment has no transactions.
+struct Tx {
+    session: Option<ClientSession>,
+}
+
+impl Tx {
+    async fn find(
+        &mut self,
+        collection: &Collection<Document>,
+        filter: Document,
+        limit: i64,
+    ) -> Result<Vec<Document>, Error> {
+        match &mut self.session {
+            Some(session) => {
+                let options = FindOptions::builder().limit(limit).build();
+                let mut cursor = collection
+                    .find_with_session(filter, options, session)
+                    .await?;
+                cursor.stream(session).try_collect().await
+            }
+            None => {
+                // Secondaries may not have the latest state yet
+                let options = FindOptions::builder()
+                    .limit(limit)
+                    .selection_criteria(SelectionCriteria::ReadPreference(ReadPreference::Primary))
+                    .build();
+                collection.find(filter, options).await?.try_collect().await
+            }
+        }
+    }
+
+    async fn find_one(
+        &mut self,
+        collection: &Collection<Document>,
+        filter: Document,
+        options: impl Into<Option<FindOneOptions>>,
+    ) -> Result<Option<Document>, Error> {
+        match &mut self.session {
+            Some(session) => {
+                collection
+                    .find_one_with_session(filter, options, session)
+                    .await
+            }
+            None => {
+                let mut options = options.into().unwrap_or_default();
+                options.selection_criteria =
+                    Some(SelectionCriteria::ReadPreference(ReadPreference::Primary));
+                collection.find_one(filter, options).await
+            }
+        }
+    }
+
+    async fn insert(
+        &mut self,
+        collection: &Collection<Document>,
+        documents: Vec<Document>,
+    ) -> Result<(), Error> {
+        if documents.is_empty() {
+            return Ok(());
+        }
+        match &mut self.session {
+            Some(session) => {
+                collection
+                    .insert_many_with_session(documents, None, session)
+                    .await?;
+            }
+            None => {
+                collection.insert_many(documents, None).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn update(
+        &mut self,
+        collection: &Collection<Document>,
+        filter: Document,
+        update: UpdateModifications,
+        options: Option<UpdateOptions>,
+        many: bool,
+    ) -> Result<UpdateResult, Error> {
+        match (&mut self.session, many) {
+            (Some(session), false) => {
+                collection
+                    .update_one_with_session(filter, update, options, session)
+                    .await
+            }
+            (Some(session), true) => {
+                collection
+                    .update_many_with_session(filter, update, options, session)
+                    .await
+            }
+            (None, false) => collection.update_one(filter, update, options).await,
+            (None, true) => collection.update_many(filter, update, options).await,
+        }
+    }
+
+    async fn replace(
+        &mut self,
+        collection: &Collection<Document>,
+        filter: Document,
+        replacement: Document,
+        options: impl Into<Option<ReplaceOptions>>,
+    ) -> Result<UpdateResult, Error> {
+        match &mut self.session {
+            Some(session) => {
+                collection
+                    .replace_one_with_session(filter, replacement, options, session)
+                    .await
+            }
+            None => collection.replace_one(filter, replacement, options).await,
+        }
+    }
+
+    async fn delete(
+        &mut self,
+        collection: &Collection<Document>,
+        filter: Document,
+        options: Option<DeleteOptions>,
+        many: bool,
+    ) -> Result<DeleteResult, Error> {
+        match (&mut self.session, many) {
+            (Some(session), false) => {
+                collection
+                    .delete_one_with_session(filter, options, session)
+                    .await
+            }
+            (Some(session), true) => {
+                collection
+                    .delete_many_with_session(filter, options, session)
+                    .await
+            }
+            (None, false) => collection.delete_one(filter, options).await,
+            (None, true) => collection.delete_many(filter, options).await,
+        }
+    }
+
+    async fn commit(self) -> Result<(), Error> {
+        if let Some(mut session) = self.session {
+            session.commit_transaction().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_updates_to_the_version_counter() {
+        assert!(check_update(&doc! { "$set": { "name": "a" } }).is_ok());
+        assert!(check_update(&doc! { "$inc": { "_version": 5 } }).is_err());
+        assert!(check_update(&doc! { "$unset": { "_version.x": "" } }).is_err());
+        // Only the exact field is managed
+        assert!(check_update(&doc! { "$set": { "_versionNote": "a" } }).is_ok());
+    }
+
+    #[test]
+    fn pins_filters_to_recorded_versions() {
+        let filter = doc! { "sku": "a1" };
+        assert_eq!(pin(filter.clone(), &[]), filter);
+        assert_eq!(
+            pin(filter.clone(), &[doc! { "_id": 1, "_version": 3_i64 }]),
+            doc! { "$and": [{ "sku": "a1" }, { "_id": 1, "_version": 3_i64 }] }
+        );
+        assert_eq!(
+            pin(filter.clone(), &[doc! { "_id": 1 }]),
+            doc! { "$and": [{ "sku": "a1" }, { "_id": 1, "_version": { "$exists": false } }] }
+        );
+        assert_eq!(
+            pin(filter, &[doc! { "_id": 1 }, doc! { "_id": 2 }]),
+            doc! { "$and": [{ "sku": "a1" }, { "_id": { "$in": [1, 2] } }] }
+        );
+    }
+
+    #[test]
+    fn oversized_writes_are_validation_errors() {
+        let err = rejection(&Error::custom(TooManyDocuments(1000))).expect("rejection");
+        assert_eq!(err.status().as_u16(), 400);
+        assert!(err.body().details.contains("1000"));
+        assert!(rejection(&Error::custom("driver boom")).is_none());
+    }
+
+    #[test]
+    fn versions_start_at_zero() {
+        assert_eq!(version_of(&doc! {}), 0);
+        assert_eq!(version_of(&doc! { "_version": 4 }), 4);
+        assert_eq!(version_of(&doc! { "_version": 7_i64 }), 7);
+    }
+}
@@ -0,0 +1,722 @@
+use axum::body::Bytes;
+use futures::{future, Stream, StreamExt};
+use mongodb::bson::{doc, oid::ObjectId, Bson, DateTime, Document};
+use mongodb::error::{BulkWriteFailure, ErrorKind, WriteFailure};
+use mongodb::options::{InsertManyOptions, UpdateOptions};
+use mongodb::Collection;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::Arc;
+
+use crate::encryption::FieldCipher;
+use crate::error::ApiError;
+use crate::history::{self, History};
+use crate::models::ImportResponse;
+use crate::payload::PayloadLimits;
+use crate::stamps::Stamps;
+
+pub const DEFAULT_BATCH_SIZE: usize = 1000;
+pub const MAX_BATCH_SIZE: usize = 10_000;
+/// Rejected rows listed in an import summary; the rest are only counted.
+pub const MAX_REPORTED_REJECTIONS: usize = 1000;
+/// Longest row accepted, the BSON document size limit.
+const MAX_ROW_BYTES: usize = 16 * 1024 * 1024;
+
+/// Layout of an import body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    /// One JSON object per line.
+    Ndjson,
+    /// A header row naming the fields, then one row per document.
+    Csv,
+}
+
+impl ImportFormat {
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let essence = content_type.split(';').next()?.trim();
+        match essence.to_ascii_lowercase().as_str() {
+            "application/x-ndjson" | "application/ndjson" | "application/jsonl" => {
+                Some(ImportFormat::Ndjson)
+            }
+            "text/csv" => Some(ImportFormat::Csv),
+            _ => None,
+        }
+    }
+}
+
+/// Type a CSV column is converted to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColumnType {
+    /// Booleans and plain numbers are converted, anything else kept as text.
+    #[default]
+    Auto,
+    String,
+    Int,
+    Long,
+    Double,
+    Bool,
+    /// RFC 3339, e.g. `2024-05-01T12:00:00Z`.
+    Date,
+    ObjectId,
+}
+
+impl ColumnType {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name.trim().to_ascii_lowercase().as_str() {
+            "auto" => ColumnType::Auto,
+            "string" => ColumnType::String,
+            "int" => ColumnType::Int,
+            "long" => ColumnType::Long,
+            "double" => ColumnType::Double,
+            "bool" => ColumnType::Bool,
+            "date" => ColumnType::Date,
+            "objectid" => ColumnType::ObjectId,
+            _ => return None,
+        })
+    }
+
+    /// Converts a cell. Empty cells are left out of the document, except in
+    /// `string` columns.
+    fn convert(self, value: &str) -> Result<Option<Bson>, String> {
+        if value.is_empty() {
+            return Ok((self == ColumnType::String).then(|| Bson::String(String::new())));
+        }
+        let invalid = |kind: &str| format!("`{value}` is not a valid {kind}");
+        let converted = match self {
+            ColumnType::Auto => infer(value),
+            ColumnType::String => Bson::String(value.to_string()),
+            ColumnType::Int => Bson::Int32(value.trim().parse().map_err(|_| invalid("int"))?),
+            ColumnType::Long => Bson::Int64(value.trim().parse().map_err(|_| invalid("long"))?),
+            ColumnType::Double => {
+                Bson::Double(value.trim().parse().map_err(|_| invalid("double"))?)
+            }
+            ColumnType::Bool => match value.trim().to_ascii_lowercase().as_str() {
+                "true" => Bson::Boolean(true),
+                "false" => Bson::Boolean(false),
+                _ => return Err(invalid("bool")),
+            },
+            ColumnType::Date => Bson::DateTime(
+                DateTime::parse_rfc3339_str(value.trim()).map_err(|_| invalid("date"))?,
+            ),
+            ColumnType::ObjectId => {
+                Bson::ObjectId(ObjectId::parse_str(value.trim()).map_err(|_| invalid("objectId"))?)
+            }
+        };
+        Ok(Some(converted))
+    }
+}
+
+/// Reads `true`/`false` and plain decimal numbers; everything else, including
+/// numbers with leading zeros such as postal codes, stays a string.
+fn infer(value: &str) -> Bson {
+    match value {
+        "true" => return Bson::Boolean(true),
+        "false" => return Bson::Boolean(false),
+        _ => {}
+    }
+    let digits = value.strip_prefix('-').unwrap_or(value);
+    let numeric = digits.starts_with(|c: char| c.is_ascii_digit())
+        && digits
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '-' | '+'))
+        && !(digits.len() > 1 && digits.starts_with('0') && !digits[1..].starts_with('.'));
+    if numeric {
+        if let Ok(int) = value.parse::<i32>() {
+            return Bson::Int32(int);
+        }
+        if let Ok(long) = value.parse::<i64>() {
+            return Bson::Int64(long);
+        }
+        if let Ok(double) = value.parse::<f64>() {
+            if double.is_finite() {
+                return Bson::Double(double);
+            }
+        }
+    }
+    Bson::String(value.to_string())
+}
+
+/// How CSV headers become fields: renames and declared types from the query
+/// string. Headers renamed to nothing are dropped.
+#[derive(Debug, Default)]
+pub struct ColumnMapping {
+    renames: HashMap<String, String>,
+    types: HashMap<String, ColumnType>,
+}
+
+impl ColumnMapping {
+    /// Parses `header:field,...` renames and `field:type,...` types.
+    pub fn parse(rename: Option<&str>, types: Option<&str>) -> Result<Self, ApiError> {
+        let mut mapping = ColumnMapping::default();
+        for (header, field) in pairs(rename, "rename")? {
+            mapping.renames.insert(header, field);
+        }
+        for (field, name) in pairs(types, "types")? {
+            let kind = ColumnType::parse(&name).ok_or_else(|| {
+                ApiError::validation(format!(
+                    "unknown column type `{name}` for `{field}`; expected auto, string, int, \
+                     long, double, bool, date or objectId"
+                ))
+            })?;
+            mapping.types.insert(field, kind);
+        }
+        Ok(mapping)
+    }
+
+    fn columns(&self, headers: Vec<String>) -> Result<Vec<Option<Column>>, ApiError> {
+        let columns: Vec<_> = headers
+            .into_iter()
+            .map(|header| {
+                let field = self.renames.get(&header).cloned().unwrap_or(header);
+                (!field.is_empty()).then(|| Column {
+                    kind: self.types.get(&field).copied().unwrap_or_default(),
+                    field,
+                })
+            })
+            .collect();
+        let unknown = self.types.keys().find(|field| {
+            !columns
+                .iter()
+                .flatten()
+                .any(|column| &column.field == *field)
+        });
+        match unknown {
+            Some(field) => Err(ApiError::validation(format!(
+                "`types` names `{field}`, which is not a column"
+            ))),
+            None => Ok(columns),
+        }
+    }
+}
+
+fn pairs(list: Option<&str>, parameter: &str) -> Result<Vec<(String, String)>, ApiError> {
+    list.into_iter()
+        .flat_map(|list| list.split(','))
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| {
+            pair.split_once(':')
+                .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+                .ok_or_else(|| {
+                    ApiError::validation(format!("`{parameter}` entry `{pair}` has no `:`"))
+                })
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+struct Column {
+    field: String,
+    kind: ColumnType,
+}
+
+/// One parsed row and the line it starts on.
+#[derive(Debug)]
+pub struct Row {
+    pub line: u64,
+    pub document: Result<Document, String>,
+}
+
+/// Reads an import body row by row, holding only the row being parsed.
+pub struct Rows<S> {
+    body: S,
+    format: ImportFormat,
+    mapping: ColumnMapping,
+    columns: Option<Vec<Option<Column>>>,
+    buffer: Vec<u8>,
+    /// Bytes of `buffer` already searched for the end of the row
+    scanned: usize,
+    quoted: bool,
+    lines: u64,
+    finished: bool,
+}
+
+impl<S, E> Rows<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Display,
+{
+    pub fn new(body: S, format: ImportFormat, mapping: ColumnMapping) -> Self {
+        Self {
+            body,
+            format,
+            mapping,
+            columns: None,
+            buffer: Vec::new(),
+            scanned: 0,
+            quoted: false,
+            lines: 0,
+            finished: false,
+        }
+    }
+
+    /// Next row, skipping blank lines and the CSV header.
+    pub async fn next(&mut self) -> Result<Option<Row>, ApiError> {
+        while let Some((line, record)) = self.record().await? {
+            let record = String::from_utf8(record);
+            let row = match self.format {
+                ImportFormat::Ndjson => {
+                    let record = match record {
+                        Ok(record) if record.trim().is_empty() => continue,
+                        Ok(record) => record,
+                        Err(_) => return Ok(Some(invalid_utf8(line))),
+                    };
+                    serde_json::from_str::<Document>(&record)
+                        .map_err(|err| format!("invalid JSON object: {err}"))
+                }
+                ImportFormat::Csv => {
+                    let record = match record {
+                        Ok(record) if record.is_empty() => continue,
+                        Ok(record) => record,
+                        Err(_) if self.columns.is_none() => {
+                            return Err(ApiError::validation("CSV header is not valid UTF-8"));
+                        }
+                        Err(_) => return Ok(Some(invalid_utf8(line))),
+                    };
+                    let fields = split_csv(&record);
+                    match &self.columns {
+                        Some(columns) => csv_document(columns, fields),
+                        None => {
+                            self.columns = Some(self.mapping.columns(fields)?);
+                            continue;
+                        }
+                    }
+                }
+            };
+            return Ok(Some(Row {
+                line,
+                document: row,
+            }));
+        }
+        Ok(None)
+    }
+
+    /// Raw bytes of the next record without its line break, and the line it
+    /// starts on. CSV records end at the first line break outside quotes.
+    async fn record(&mut self) -> Result<Option<(u64, Vec<u8>)>, ApiError> {
+        loop {
+            let csv = self.format == ImportFormat::Csv;
+            let mut quoted = self.quoted;
+            let end = self.buffer[self.scanned..]
+                .iter()
+                .position(|&byte| {
+                    if csv && byte == b'"' {
+                        quoted = !quoted;
+                    }
+                    byte == b'\n' && !quoted
+                })
+                .map(|at| self.scanned + at);
+            self.quoted = quoted;
+            match end {
+                Some(end) => {
+                    let mut record: Vec<u8> = self.buffer.drain(..=end).collect();
+                    record.pop();
+                    return Ok(Some(self.finish_record(record)));
+                }
+                None if self.finished => {
+                    if self.buffer.is_empty() {
+                        return Ok(None);
+                    }
+                    let record = std::mem::take(&mut self.buffer);
+                    return Ok(Some(self.finish_record(record)));
+                }
+                None => {
+                    self.scanned = self.buffer.len();
+                    if self.buffer.len() > MAX_ROW_BYTES {
+                        return Err(ApiError::validation(format!(
+                            "line {} is longer than {MAX_ROW_BYTES} bytes",
+                            self.lines + 1
+                        )));
+                    }
+                    self.fill().await?;
+                }
+            }
+        }
+    }
+
+    fn finish_record(&mut self, mut record: Vec<u8>) -> (u64, Vec<u8>) {
+        let line = self.lines + 1;
+        self.lines += 1 + record.iter().filter(|&&byte| byte == b'\n').count() as u64;
+        self.scanned = 0;
+        self.quoted = false;
+        if record.last() == Some(&b'\r') {
+            record.pop();
+        }
+        if line == 1 && record.starts_with(b"\xEF\xBB\xBF") {
+            record.drain(..3);
+        }
+        (line, record)
+    }
+
+    async fn fill(&mut self) -> Result<(), ApiError> {
+        match self.body.next().await {
+            Some(Ok(bytes)) => self.buffer.extend_from_slice(&bytes),
+            Some(Err(err)) => {
+                return Err(ApiError::validation(format!(
+                    "failed to read request body: {err}"
+                )))
+            }
+            None => self.finished = true,
+        }
+        Ok(())
+    }
+}
+
+fn invalid_utf8(line: u64) -> Row {
+    Row {
+        line,
+        document: Err("row is not valid UTF-8".to_string()),
+    }
+}
+
+/// Splits a CSV record on commas outside quotes. Doubled quotes inside a
+/// quoted field stand for one quote.
+fn split_csv(record: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = record.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek().is_none() => {}
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+fn csv_document(columns: &[Option<Column>], values: Vec<String>) -> Result<Document, String> {
+    if values.len() != columns.len() {
+        return Err(format!(
+            "expected {} fields, found {}",
+            columns.len(),
+            values.len()
+        ));
+    }
+    let mut document = Document::new();
+    for (column, value) in columns.iter().zip(values) {
+        let Some(column) = column else {
+            continue;
+        };
+        let converted = column
+            .kind
+            .convert(&value)
+            .map_err(|err| format!("`{}`: {err}", column.field))?;
+        if let Some(value) = converted {
+            insert_path(&mut document, &column.field, value)?;
+        }
+    }
+    Ok(document)
+}
+
+/// Inserts `value` at a dotted `path`, creating the documents along it.
+fn insert_path(document: &mut Document, path: &str, value: Bson) -> Result<(), String> {
+    match path.split_once('.') {
+        None => {
+            if document.contains_key(path) {
+                return Err(format!("field `{path}` is set twice"));
+            }
+            document.insert(path, value);
+            Ok(())
+        }
+        Some((head, rest)) => {
+            let child = document
+                .entry(head.to_string())
+                .or_insert_with(|| Bson::Document(Document::new()));
+            match child {
+                Bson::Document(child) => insert_path(child, rest, value),
+                _ => Err(format!("field `{head}` is both a value and a document")),
+            }
+        }
+    }
+}
+
+fn get_path<'a>(document: &'a Document, path: &str) -> Option<&'a Bson> {
+    match path.split_once('.') {
+        None => document.get(path),
+        Some((head, rest)) => get_path(document.get_document(head).ok()?, rest),
+    }
+}
+
+/// Writes batches of rows to one namespace, inserting them or, with upsert
+/// keys, updating the document each row's key identifies.
+pub struct Importer {
+    pub collection: Collection<Document>,
+    pub cipher: FieldCipher,
+    pub stamps: Option<Stamps>,
+    pub history: Option<History>,
+    pub upsert_keys: Vec<String>,
+    pub limits: Arc<PayloadLimits>,
+}
+
+/// A batch ready to be written: documents to insert, or filters and updates
+/// in upsert mode, each with its line.
+pub struct Batch {
+    lines: Vec<u64>,
+    writes: Vec<Write>,
+}
+
+enum Write {
+    Insert(Document),
+    Upsert { filter: Document, update: Document },
+}
+
+impl Batch {
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Documents as written, for the audit log.
+    pub fn documents(&self) -> Vec<Document> {
+        self.writes
+            .iter()
+            .map(|write| match write {
+                Write::Insert(document) => document.clone(),
+                Write::Upsert { filter, update } => doc! { "filter": filter, "update": update },
+            })
+            .collect()
+    }
+}
+
+impl Importer {
+    /// Encrypts and stamps `rows`, rejecting the ones that cannot be written
+    /// into `summary`.
+    pub fn prepare(&self, rows: Vec<(u64, Document)>, summary: &mut ImportResponse) -> Batch {
+        let mut batch = Batch {
+            lines: Vec::with_capacity(rows.len()),
+            writes: Vec::with_capacity(rows.len()),
+        };
+        for (line, document) in rows {
+            let write = if self.upsert_keys.is_empty() {
+                self.insert(document)
+            } else {
+                self.upsert(document)
+            };
+            match write {
+                Ok(write) => {
+                    batch.lines.push(line);
+                    batch.writes.push(write);
+                }
+                Err(err) => summary.reject(line, err),
+            }
+        }
+        batch
+    }
+
+    fn insert(&self, mut document: Document) -> Result<Write, String> {
+        if let Some(stamps) = &self.stamps {
+            stamps.insert(&mut document);
+        }
+        self.cipher
+            .encrypt_document(&mut document)
+            .map_err(|err| err.body().details.clone())?;
+        self.limits
+            .check_document(&document, "document")
+            .map_err(|err| err.body().details.clone())?;
+        Ok(Write::Insert(document))
+    }
+
+    fn upsert(&self, mut document: Document) -> Result<Write, String> {
+        let mut filter = Document::new();
+        for key in &self.upsert_keys {
+            let value =
+                get_path(&document, key).ok_or_else(|| format!("missing upsert key `{key}`"))?;
+            filter.insert(key, value.clone());
+        }
+        let id = document.remove("_id");
+        let mut update = doc! { "$set": document };
+        if let Some(id) = id.filter(|_| !self.upsert_keys.iter().any(|key| key == "_id")) {
+            update.insert("$setOnInsert", doc! { "_id": id });
+        }
+        if let Some(stamps) = &self.stamps {
+            stamps.update(&mut update);
+        }
+        self.cipher
+            .encrypt_filter(&mut filter)
+            .and_then(|()| self.cipher.encrypt_update(&mut update))
+            .map_err(|err| err.body().details.clone())?;
+        if self.history.is_some() {
+            history::check_update(&update).map_err(|err| err.body().details.clone())?;
+        }
+        self.limits
+            .check_document(&update, "update")
+            .map_err(|err| err.body().details.clone())?;
+        Ok(Write::Upsert { filter, update })
+    }
+
+    /// Writes `batch`, counting it into a new summary. Rows the server
+    /// rejects are reported by line; any other failure ends the import.
+    pub async fn write(&self, batch: Batch) -> Result<ImportResponse, mongodb::error::Error> {
+        let mut summary = ImportResponse::default();
+        let Batch { lines, writes } = batch;
+        if self.upsert_keys.is_empty() {
+            let documents: Vec<_> = writes
+                .into_iter()
+                .filter_map(|write| match write {
+                    Write::Insert(document) => Some(document),
+                    Write::Upsert { .. } => None,
+                })
+                .collect();
+            let count = documents.len() as u64;
+            let options = InsertManyOptions::builder().ordered(false).build();
+            match self.collection.insert_many(documents, options).await {
+                Ok(_) => summary.inserted_count = count,
+                Err(err) => match err.kind.as_ref() {
+                    ErrorKind::BulkWrite(BulkWriteFailure {
+                        write_errors: Some(errors),
+                        write_concern_error: None,
+                        ..
+                    }) => {
+                        summary.inserted_count = count - errors.len() as u64;
+                        for error in errors {
+                            summary.reject(lines[error.index], error.message.as_str());
+                        }
+                    }
+                    _ => return Err(err),
+                },
+            }
+            return Ok(summary);
+        }
+        let results = future::join_all(writes.into_iter().map(|write| async move {
+            let Write::Upsert { filter, update } = write else {
+                unreachable!("upsert imports only prepare upserts");
+            };
+            let options = UpdateOptions::builder().upsert(true).build();
+            match &self.history {
+                Some(history) => history.update(filter, update, Some(options), false).await,
+                None => self.collection.update_one(filter, update, options).await,
+            }
+        }))
+        .await;
+        for (line, result) in lines.into_iter().zip(results) {
+            match result {
+                Ok(result) if result.upserted_id.is_some() => summary.inserted_count += 1,
+                Ok(_) => summary.updated_count += 1,
+                Err(err) => match err.kind.as_ref() {
+                    ErrorKind::Write(WriteFailure::WriteError(error)) => {
+                        summary.reject(line, error.message.as_str())
+                    }
+                    _ => return Err(err),
+                },
+            }
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+
+    async fn read(format: ImportFormat, mapping: ColumnMapping, body: &str) -> Vec<Row> {
+        // One byte per read exercises rows split across reads
+        let reads: Vec<Result<Bytes, Infallible>> = body
+            .bytes()
+            .map(|byte| Ok(Bytes::copy_from_slice(&[byte])))
+            .collect();
+        let mut rows = Rows::new(futures::stream::iter(reads), format, mapping);
+        let mut read = Vec::new();
+        while let Some(row) = rows.next().await.unwrap() {
+            read.push(row);
+        }
+        read
+    }
+
+    #[tokio::test]
+    async fn reads_ndjson_rows_with_line_numbers() {
+        let rows = read(
+            ImportFormat::Ndjson,
+            ColumnMapping::default(),
+            "{\"a\":1}\n\n[1]\r\n{\"b\":{\"$oid\":\"507f1f77bcf86cd799439011\"}}",
+        )
+        .await;
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].line, 1);
+        assert_eq!(rows[0].document.as_ref().unwrap(), &doc! { "a": 1 });
+        assert_eq!(rows[1].line, 3);
+        assert!(rows[1].document.is_err());
+        assert_eq!(rows[2].line, 4);
+        assert!(rows[2]
+            .document
+            .as_ref()
+            .unwrap()
+            .get_object_id("b")
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn reads_csv_with_renames_types_and_quoted_line_breaks() {
+        let mapping = ColumnMapping::parse(
+            Some("Name:name,City:address.city,Notes:"),
+            Some("zip:string"),
+        )
+        .unwrap();
+        let body = "Name,City,zip,qty,Notes\r\n\
+                    \"Lovelace, Ada\",London,01234,3,x\r\n\
+                    \"Said \"\"hi\"\"\nthere\",,02139,2.5,y\r\n\
+                    short,row\r\n";
+        let rows = read(ImportFormat::Csv, mapping, body).await;
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].line, 2);
+        assert_eq!(
+            rows[0].document.as_ref().unwrap(),
+            &doc! { "name": "Lovelace, Ada", "address": { "city": "London" }, "zip": "01234", "qty": 3 }
+        );
+        assert_eq!(rows[1].line, 3);
+        assert_eq!(
+            rows[1].document.as_ref().unwrap(),
+            &doc! { "name": "Said \"hi\"\nthere", "zip": "02139", "qty": 2.5 }
+        );
+        assert_eq!(rows[2].line, 5);
+        assert_eq!(
+            rows[2].document.as_ref().unwrap_err(),
+            "expected 5 fields, found 2"
+        );
+    }
+
+    #[test]
+    fn infers_and_converts_cell_types() {
+        assert_eq!(infer("42"), Bson::Int32(42));
+        assert_eq!(infer("-5000000000"), Bson::Int64(-5_000_000_000));
+        assert_eq!(infer("0.5"), Bson::Double(0.5));
+        assert_eq!(infer("007"), Bson::String("007".to_string()));
+        assert_eq!(infer("NaN"), Bson::String("NaN".to_string()));
+        assert_eq!(infer("true"), Bson::Boolean(true));
+        assert_eq!(ColumnType::Auto.convert(""), Ok(None));
+        assert!(ColumnType::Int.convert("4.5").is_err());
+        assert!(matches!(
+            ColumnType::Date.convert("2024-05-01T12:00:00Z"),
+            Ok(Some(Bson::DateTime(_)))
+        ));
+    }
+
+    #[test]
+    fn rejects_types_for_unknown_columns() {
+        let mapping = ColumnMapping::parse(None, Some("age:int")).unwrap();
+        assert!(mapping.columns(vec!["name".to_string()]).is_err());
+        assert!(ColumnMapping::parse(None, Some("age:integer")).is_err());
+        assert!(ColumnMapping::parse(Some("age"), None).is_err());
+    }
+
+    #[test]
+    fn reads_format_from_content_type() {
+        assert_eq!(
+            ImportFormat::from_content_type("text/csv; charset=utf-8"),
+            Some(ImportFormat::Csv)
+        );
+        assert_eq!(
+            ImportFormat::from_content_type("application/x-ndjson"),
+            Some(ImportFormat::Ndjson)
+        );
+        assert_eq!(ImportFormat::from_content_type("application/json"), None);
+    }
+}
@@ -1,5 +1,30 @@
+pub mod audit;
+pub mod auth;
+pub mod cache;
+pub mod cluster;
+pub mod codec;
 pub mod config;
+pub mod consistency;
+pub mod encryption;
 pub mod error;
+pub mod etag;
+pub mod explain;
+pub mod export;
+pub mod fields;
+pub mod files;
+pub mod graphql;
+pub mod grpc;
+pub mod health;
+pub mod history;
+pub mod import;
+pub mod limits;
 pub mod models;
+pub mod payload;
+pub mod policy;
+pub mod reload;
 pub mod routes;
+pub mod shutdown;
+pub mod soft_delete;
+pub mod stamps;
 pub mod state;
+pub mod tls;
@@ -0,0 +1,297 @@
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::LimitsConfig;
+
+/// Endpoint category used to pick a rate-limit bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RateSpec {
+    per_second: f64,
+    burst: f64,
+}
+
+impl RateSpec {
+    fn new(per_second: Option<u32>, burst: Option<u32>) -> Option<Self> {
+        let per_second = per_second?;
+        Some(Self {
+            per_second: f64::from(per_second),
+            burst: f64::from(burst.unwrap_or(per_second)),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn full(spec: RateSpec, now: Instant) -> Self {
+        Self {
+            tokens: spec.burst,
+            updated: now,
+        }
+    }
+
+    fn take(&mut self, spec: RateSpec, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * spec.per_second).min(spec.burst);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / spec.per_second,
+            ))
+        }
+    }
+
+    /// Whether the bucket would have refilled by `now`, making it no
+    /// different from a new one.
+    fn is_full(&self, spec: RateSpec, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens + elapsed * spec.per_second >= spec.burst
+    }
+}
+
+/// How often full buckets are dropped.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Token buckets per client, kept separately for read and write endpoints.
+pub struct RateLimiter {
+    read: Option<RateSpec>,
+    write: Option<RateSpec>,
+    buckets: DashMap<(String, AccessKind), TokenBucket>,
+    last_sweep: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &LimitsConfig) -> Self {
+        Self {
+            read: RateSpec::new(config.read_per_second, config.read_burst),
+            write: RateSpec::new(config.write_per_second, config.write_burst),
+            buckets: DashMap::new(),
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Consumes a token for `client`, or returns how long to wait for the next one.
+    pub fn check(&self, client: &str, kind: AccessKind) -> Result<(), Duration> {
+        self.check_at(client, kind, Instant::now())
+    }
+
+    fn check_at(&self, client: &str, kind: AccessKind, now: Instant) -> Result<(), Duration> {
+        let Some(spec) = self.spec(kind) else {
+            return Ok(());
+        };
+        self.sweep(now);
+        self.buckets
+            .entry((client.to_owned(), kind))
+            .or_insert_with(|| TokenBucket::full(spec, now))
+            .take(spec, now)
+    }
+
+    fn spec(&self, kind: AccessKind) -> Option<RateSpec> {
+        match kind {
+            AccessKind::Read => self.read,
+            AccessKind::Write => self.write,
+        }
+    }
+
+    /// Drops buckets that have refilled, so idle clients do not keep an
+    /// entry. At most one caller sweeps per [`SWEEP_INTERVAL`].
+    fn sweep(&self, now: Instant) {
+        let Ok(mut last_sweep) = self.last_sweep.try_lock() else {
+            return;
+        };
+        if now.saturating_duration_since(*last_sweep) < SWEEP_INTERVAL {
+            return;
+        }
+        *last_sweep = now;
+        drop(last_sweep);
+        self.buckets.retain(|(_, kind), bucket| {
+            self.spec(*kind)
+                .is_some_and(|spec| !bucket.is_full(spec, now))
+        });
+    }
+}
+
+/// Caps the number of concurrent driver calls per namespace.
+pub struct InFlightLimiter {
+    max: Option<usize>,
+    // Shared with limiters resized on reload, so slots held across a reload
+    // still count against the new limit
+    counters: Arc<DashMap<String, Arc<AtomicUsize>>>,
+}
+
+/// Releases its in-flight slot when dropped, and the namespace's counter
+/// with the last slot, so names that are no longer used keep no entry.
+#[derive(Debug)]
+pub struct InFlightGuard {
+    counters: Arc<DashMap<String, Arc<AtomicUsize>>>,
+    namespace: String,
+    counter: Arc<AtomicUsize>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.counter.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // Slots are only taken under the entry's lock, so one taken since
+            // the decrement keeps the counter in place
+            self.counters.remove_if(&self.namespace, |_, counter| {
+                counter.load(Ordering::Acquire) == 0
+            });
+        }
+    }
+}
+
+impl InFlightLimiter {
+    pub fn new(config: &LimitsConfig) -> Self {
+        Self {
+            max: config.max_in_flight_per_namespace.map(|max| max as usize),
+            counters: Arc::default(),
+        }
+    }
+
+    /// A limiter enforcing `config`'s limit over this limiter's live counts.
+    pub fn resized(&self, config: &LimitsConfig) -> Self {
+        Self {
+            max: config.max_in_flight_per_namespace.map(|max| max as usize),
+            counters: self.counters.clone(),
+        }
+    }
+
+    /// Returns `None` when the namespace is already at its limit. Slots are
+    /// counted even without a limit, so one enabled on reload sees them.
+    pub fn try_acquire(&self, namespace: &str) -> Option<InFlightGuard> {
+        let max = self.max.unwrap_or(usize::MAX);
+        let entry = self.counters.entry(namespace.to_owned()).or_default();
+        if entry.load(Ordering::Acquire) >= max {
+            return None;
+        }
+        entry.fetch_add(1, Ordering::AcqRel);
+        let counter = entry.clone();
+        drop(entry);
+        Some(InFlightGuard {
+            counters: self.counters.clone(),
+            namespace: namespace.to_owned(),
+            counter,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(read: Option<u32>, burst: Option<u32>) -> LimitsConfig {
+        LimitsConfig {
+            read_per_second: read,
+            read_burst: burst,
+            max_in_flight_per_namespace: Some(2),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn bucket_allows_burst_then_reports_retry_after() {
+        let limiter = RateLimiter::new(&limits(Some(2), Some(3)));
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter
+                .check_at("ip:1.2.3.4", AccessKind::Read, now)
+                .is_ok());
+        }
+        let wait = limiter
+            .check_at("ip:1.2.3.4", AccessKind::Read, now)
+            .expect_err("bucket should be empty");
+        assert_eq!(wait, Duration::from_millis(500));
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let limiter = RateLimiter::new(&limits(Some(1), None));
+        let now = Instant::now();
+        assert!(limiter.check_at("client", AccessKind::Read, now).is_ok());
+        assert!(limiter.check_at("client", AccessKind::Read, now).is_err());
+        let later = now + Duration::from_secs(1);
+        assert!(limiter.check_at("client", AccessKind::Read, later).is_ok());
+    }
+
+    #[test]
+    fn buckets_are_separate_per_client_and_kind() {
+        let limiter = RateLimiter::new(&limits(Some(1), None));
+        let now = Instant::now();
+        assert!(limiter.check_at("a", AccessKind::Read, now).is_ok());
+        assert!(limiter.check_at("b", AccessKind::Read, now).is_ok());
+        // Writes are unlimited because no write rate is configured
+        for _ in 0..10 {
+            assert!(limiter.check_at("a", AccessKind::Write, now).is_ok());
+        }
+    }
+
+    #[test]
+    fn sweep_drops_refilled_buckets() {
+        let limiter = RateLimiter::new(&limits(Some(1), Some(100)));
+        let now = Instant::now();
+        assert!(limiter.check_at("idle", AccessKind::Read, now).is_ok());
+        for _ in 0..100 {
+            let _ = limiter.check_at("busy", AccessKind::Read, now);
+        }
+        // "idle" has refilled by the sweep, "busy" is still short of its burst
+        let later = now + SWEEP_INTERVAL;
+        assert!(limiter.check_at("new", AccessKind::Read, later).is_ok());
+        assert!(!limiter
+            .buckets
+            .contains_key(&("idle".to_string(), AccessKind::Read)));
+        assert!(limiter
+            .buckets
+            .contains_key(&("busy".to_string(), AccessKind::Read)));
+        assert_eq!(limiter.buckets.len(), 2);
+    }
+
+    #[test]
+    fn in_flight_limit_releases_on_drop() {
+        let limiter = InFlightLimiter::new(&limits(None, None));
+        let first = limiter.try_acquire("app.users").expect("first slot");
+        let _second = limiter.try_acquire("app.users").expect("second slot");
+        assert!(limiter.try_acquire("app.users").is_none());
+        assert!(limiter.try_acquire("app.orders").is_some());
+        drop(first);
+        assert!(limiter.try_acquire("app.users").is_some());
+    }
+
+    #[test]
+    fn in_flight_counters_are_dropped_with_the_last_slot() {
+        let limiter = InFlightLimiter::new(&LimitsConfig::default());
+        let first = limiter.try_acquire("app.random_1").expect("unlimited");
+        let second = limiter.try_acquire("app.random_1").expect("unlimited");
+        drop(first);
+        assert!(limiter.counters.contains_key("app.random_1"));
+        drop(second);
+        assert!(limiter.counters.is_empty());
+    }
+
+    #[test]
+    fn resized_limiter_keeps_held_slots() {
+        let limiter = InFlightLimiter::new(&LimitsConfig::default());
+        let held = limiter.try_acquire("app.users").expect("unlimited");
+        let resized = limiter.resized(&LimitsConfig {
+            max_in_flight_per_namespace: Some(1),
+            ..Default::default()
+        });
+        assert!(resized.try_acquire("app.users").is_none());
+        drop(held);
+        assert!(resized.try_acquire("app.users").is_some());
+    }
+}
@@ -1,17 +1,21 @@
-mod config;
-mod error;
-mod models;
-mod routes;
-mod state;
-
 use axum::Router;
-use config::Config;
-use mongodb::options::ClientOptions;
-use mongodb::Client;
+use futures::{future, FutureExt};
+use hello_rust::audit::Auditor;
+use hello_rust::cluster::Clusters;
+use hello_rust::config::{AuditSink, Config};
+use hello_rust::encryption::Encryptor;
+use hello_rust::grpc;
+use hello_rust::reload;
+use hello_rust::routes;
+use hello_rust::shutdown;
+use hello_rust::state::{AppState, Services};
+use hello_rust::tls::{self, ReloadingAcceptor};
+use std::future::IntoFuture;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
 use tracing_subscriber::EnvFilter;
 
-use crate::state::AppState;
-
 #[tokio::main]
 async fn main() {
     dotenvy::dotenv().ok();
@@ -22,7 +26,11 @@ async fn main() {
 }
 
 async fn run() -> Result<(), Box<dyn std::error::Error>> {
-    let config = Config::from_env()?;
+    let config_path = Config::file_path()?;
+    let config = match &config_path {
+        Some(path) => Config::from_file(path)?,
+        None => Config::from_env()?,
+    };
 
     let env_filter = config
         .log_level
@@ -34,28 +42,127 @@ async fn run() -> Result<(), Box<dyn std::error::Error>> {
 
     tracing::info!("starting api gateway");
 
-    let mut client_options = ClientOptions::parse(&config.mongodb_uri).await?;
-    client_options.app_name = Some("hello_rust_gateway".to_string());
-    if let Some(min_pool_size) = config.pool_min_size {
-        client_options.min_pool_size = Some(min_pool_size);
+    let clusters = Clusters::connect(&config).await?;
+    for name in config.clusters.keys() {
+        tracing::info!("registered mongodb cluster `{name}`");
     }
-    if let Some(max_pool_size) = config.pool_max_size {
-        client_options.max_pool_size = Some(max_pool_size);
+    let auditor = Auditor::start(&config.audit, &clusters).await?;
+    match &config.audit.sink {
+        Some(AuditSink::File(path)) => tracing::info!("audit log writing to {}", path.display()),
+        Some(AuditSink::Collection {
+            database,
+            collection,
+        }) => tracing::info!("audit log writing to collection {database}.{collection}"),
+        None => tracing::info!("audit log disabled"),
     }
-    if let Some(timeout) = config.connect_timeout {
-        client_options.connect_timeout = Some(timeout);
+    let encryptor = Encryptor::load(&config.encryption)?;
+    if let Some(key) = encryptor.active_key() {
+        tracing::info!("field encryption enabled; encrypting with key `{key}`");
     }
-    if let Some(timeout) = config.server_selection_timeout {
-        client_options.server_selection_timeout = Some(timeout);
+    let state = AppState::with_services(clusters, Services { auditor, encryptor }, &config);
+    match config_path {
+        Some(path) => {
+            tracing::info!("watching {} for config changes", path.display());
+            reload::spawn(state.clone(), path);
+        }
+        None => tracing::info!("no config file given; hot reload disabled"),
     }
 
-    let client = Client::with_options(client_options)?;
-    let state = AppState::new(client, &config);
-
-    let app: Router = routes::router(state);
+    let app: Router = routes::router(state.clone());
 
     let listener = tokio::net::TcpListener::bind(&config.bind_address).await?;
-    tracing::info!("listening on {}", config.bind_address);
-    axum::serve(listener, app).await?;
+    let stop = || {
+        let state = state.clone();
+        async move { state.drain().started().await }
+    };
+    let acceptor = match config.tls.clone() {
+        Some(settings) => {
+            let acceptor = Arc::new(ReloadingAcceptor::new(settings)?);
+            acceptor.clone().spawn_watcher();
+            Some(acceptor)
+        }
+        None => None,
+    };
+    let http = match acceptor.clone() {
+        Some(acceptor) => {
+            let mutual = config
+                .tls
+                .as_ref()
+                .is_some_and(|settings| settings.client_ca_path.is_some());
+            tracing::info!(
+                "listening on https://{} (mutual TLS {})",
+                config.bind_address,
+                if mutual { "enabled" } else { "disabled" }
+            );
+            tls::serve(listener, app, acceptor, stop()).boxed()
+        }
+        None => {
+            tracing::info!("listening on {}", config.bind_address);
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(stop())
+            .into_future()
+            .boxed()
+        }
+    };
+    let grpc = match &config.grpc_bind_address {
+        Some(address) => {
+            let listener = tokio::net::TcpListener::bind(address).await?;
+            match acceptor {
+                Some(_) => tracing::info!("serving grpc over TLS on {address}"),
+                None => tracing::info!("serving grpc on {address}"),
+            }
+            grpc::serve(state.clone(), listener, acceptor, stop()).boxed()
+        }
+        None => future::ok(()).boxed(),
+    };
+    let mut server = tokio::spawn(async move { tokio::try_join!(http, grpc).map(|_| ()) });
+
+    tokio::select! {
+        result = &mut server => return Ok(result??),
+        _ = shutdown::signal() => {}
+    }
+
+    // The deadline is read now so a reload can change it while running
+    let deadline = state.config().shutdown_timeout();
+    let started = Instant::now();
+    tracing::info!(
+        in_flight = state.drain().active(),
+        timeout_ms = deadline.as_millis() as u64,
+        "shutting down; draining in-flight requests"
+    );
+    state.drain().begin();
+    match tokio::time::timeout(deadline, &mut server).await {
+        Ok(result) => result??,
+        Err(_) => {
+            tracing::warn!(
+                in_flight = state.drain().active(),
+                "drain deadline reached; closing remaining connections"
+            );
+            // Dropping the connections drops their cursors, which the client
+            // shutdown below then kills on the server
+            server.abort();
+        }
+    }
+
+    // Pending audit events are written before the clients they may need go away
+    let remaining = deadline.saturating_sub(started.elapsed());
+    if tokio::time::timeout(remaining, state.auditor().flush())
+        .await
+        .is_err()
+    {
+        tracing::warn!(
+            target = "audit",
+            "audit log flush timed out during shutdown"
+        );
+    }
+
+    state
+        .clusters()
+        .shutdown(deadline.saturating_sub(started.elapsed()))
+        .await;
+    tracing::info!("shutdown complete");
     Ok(())
 }
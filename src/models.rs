@@ -1,18 +1,50 @@
 use mongodb::bson::{doc, Bson, Document};
+use mongodb::gridfs::FilesCollectionDocument;
 use mongodb::options::{
-    DeleteOptions, FindOneOptions, FindOptions, InsertManyOptions, InsertOneOptions,
-    ReplaceOptions, UpdateOptions,
+    AggregateOptions, CountOptions, DeleteOptions, FindOneOptions, FindOptions, GridFsFindOptions,
+    InsertManyOptions, InsertOneOptions, ReplaceOptions, UpdateOptions,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::cache::NamespaceCacheStats;
+use crate::consistency::Consistency;
+use crate::export::{Compression, ExportFormat};
+use crate::files::DEFAULT_BUCKET;
+use crate::import::{ImportFormat, MAX_REPORTED_REJECTIONS};
 
 fn empty_document() -> Document {
     doc! {}
 }
 
+/// Verbosity passed to the `explain` command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ExplainVerbosity {
+    QueryPlanner,
+    ExecutionStats,
+    AllPlansExecution,
+}
+
+impl ExplainVerbosity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ExplainVerbosity::QueryPlanner => "queryPlanner",
+            ExplainVerbosity::ExecutionStats => "executionStats",
+            ExplainVerbosity::AllPlansExecution => "allPlansExecution",
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct NamespacePayload {
     pub database: String,
     pub collection: String,
+    /// Named cluster to use instead of the routing rules.
+    #[serde(default)]
+    pub cluster: Option<String>,
+    #[serde(flatten)]
+    pub consistency: Consistency,
 }
 
 #[derive(Debug, Deserialize)]
@@ -51,6 +83,11 @@ pub struct FindOneRequest {
     pub filter: Document,
     #[serde(default)]
     pub options: Option<FindOneOptions>,
+    #[serde(default)]
+    pub explain: Option<ExplainVerbosity>,
+    /// Also match soft-deleted documents.
+    #[serde(default)]
+    pub include_deleted: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -66,6 +103,11 @@ pub struct FindManyRequest {
     pub filter: Document,
     #[serde(default)]
     pub options: Option<FindOptions>,
+    #[serde(default)]
+    pub explain: Option<ExplainVerbosity>,
+    /// Also match soft-deleted documents.
+    #[serde(default)]
+    pub include_deleted: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -81,6 +123,14 @@ pub struct UpdateRequest {
     pub update: Document,
     #[serde(default)]
     pub options: Option<UpdateOptions>,
+    /// Required to run `update_many` with an empty filter.
+    #[serde(default)]
+    pub confirm_all: bool,
+    #[serde(default)]
+    pub explain: Option<ExplainVerbosity>,
+    /// Report the matched count without modifying anything.
+    #[serde(default)]
+    pub dry_run: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -99,6 +149,11 @@ pub struct ReplaceOneRequest {
     pub replacement: Document,
     #[serde(default)]
     pub options: Option<ReplaceOptions>,
+    #[serde(default)]
+    pub explain: Option<ExplainVerbosity>,
+    /// Report the matched count without modifying anything.
+    #[serde(default)]
+    pub dry_run: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -108,6 +163,14 @@ pub struct DeleteRequest {
     pub filter: Document,
     #[serde(default)]
     pub options: Option<DeleteOptions>,
+    /// Required to run `delete_many` with an empty filter.
+    #[serde(default)]
+    pub confirm_all: bool,
+    #[serde(default)]
+    pub explain: Option<ExplainVerbosity>,
+    /// Report the matched count without modifying anything.
+    #[serde(default)]
+    pub dry_run: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -115,9 +178,318 @@ pub struct DeleteResponse {
     pub deleted_count: u64,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct RestoreRequest {
+    #[serde(flatten)]
+    pub namespace: NamespacePayload,
+    pub filter: Document,
+    /// Required to restore with an empty filter.
+    #[serde(default)]
+    pub confirm_all: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RestoreResponse {
+    pub restored_count: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PurgeRequest {
+    #[serde(flatten)]
+    pub namespace: NamespacePayload,
+    #[serde(default = "empty_document")]
+    pub filter: Document,
+    /// Only documents soft-deleted at least this many days ago are removed.
+    pub older_than_days: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryRequest {
+    #[serde(flatten)]
+    pub namespace: NamespacePayload,
+    /// `_id` of the document.
+    pub id: Bson,
+    /// Most recent versions to return; all of them by default.
+    #[serde(default)]
+    pub limit: Option<i64>,
+}
+
+/// Recorded versions of a document, newest first.
+#[derive(Debug, Serialize)]
+pub struct HistoryResponse {
+    /// Version the document is at now, or `None` once deleted.
+    pub current_version: Option<i64>,
+    pub versions: Vec<Document>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RestoreVersionRequest {
+    #[serde(flatten)]
+    pub namespace: NamespacePayload,
+    /// `_id` of the document.
+    pub id: Bson,
+    /// Recorded version to restore.
+    pub version: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RestoreVersionResponse {
+    pub restored_version: i64,
+    /// Version the document is at after the restore.
+    pub version: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AggregateRequest {
+    #[serde(flatten)]
+    pub namespace: NamespacePayload,
+    pub pipeline: Vec<Document>,
+    #[serde(default)]
+    pub options: Option<AggregateOptions>,
+    #[serde(default)]
+    pub explain: Option<ExplainVerbosity>,
+    /// Also match soft-deleted documents.
+    #[serde(default)]
+    pub include_deleted: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AggregateResponse {
+    pub documents: Vec<Document>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CountRequest {
+    #[serde(flatten)]
+    pub namespace: NamespacePayload,
+    #[serde(default = "empty_document")]
+    pub filter: Document,
+    #[serde(default)]
+    pub options: Option<CountOptions>,
+    #[serde(default)]
+    pub explain: Option<ExplainVerbosity>,
+    /// Also match soft-deleted documents.
+    #[serde(default)]
+    pub include_deleted: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CountResponse {
+    pub count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExplainResponse {
+    pub plan: Document,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DryRunResponse {
+    pub dry_run: bool,
+    pub matched_count: u64,
+}
+
+impl DryRunResponse {
+    pub fn new(matched_count: u64) -> Self {
+        Self {
+            dry_run: true,
+            matched_count,
+        }
+    }
+}
+
+/// A GridFS bucket. Namespace rules, routing and limits apply to it as the
+/// namespace `<database>.<bucket>.files`.
+#[derive(Debug, Deserialize)]
+pub struct BucketPayload {
+    pub database: String,
+    /// Defaults to `fs`.
+    #[serde(default)]
+    pub bucket: Option<String>,
+    #[serde(default)]
+    pub cluster: Option<String>,
+}
+
+impl BucketPayload {
+    pub fn name(&self) -> &str {
+        self.bucket
+            .as_deref()
+            .map(str::trim)
+            .filter(|bucket| !bucket.is_empty())
+            .unwrap_or(DEFAULT_BUCKET)
+    }
+
+    pub fn files_namespace(&self) -> NamespacePayload {
+        NamespacePayload {
+            database: self.database.clone(),
+            collection: format!("{}.files", self.name()),
+            cluster: self.cluster.clone(),
+            consistency: Consistency::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FileUploadQuery {
+    #[serde(flatten)]
+    pub bucket: BucketPayload,
+    /// Required unless a multipart file part names the file.
+    #[serde(default)]
+    pub filename: Option<String>,
+    /// JSON object stored as the file's metadata.
+    #[serde(default)]
+    pub metadata: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileUploadResponse {
+    pub id: Bson,
+    pub filename: String,
+    pub length: u64,
+    pub content_type: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FileDownloadQuery {
+    #[serde(flatten)]
+    pub bucket: BucketPayload,
+    /// An ObjectId in hex, or a string id.
+    pub id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FileFindRequest {
+    #[serde(flatten)]
+    pub bucket: BucketPayload,
+    /// Filter on the files collection, e.g. `{"metadata.owner": "ada"}`.
+    #[serde(default = "empty_document")]
+    pub filter: Document,
+    #[serde(default)]
+    pub options: Option<GridFsFindOptions>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FilesResponse {
+    pub files: Vec<FilesCollectionDocument>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FileDeleteRequest {
+    #[serde(flatten)]
+    pub bucket: BucketPayload,
+    pub id: Bson,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportRequest {
+    #[serde(flatten)]
+    pub namespace: NamespacePayload,
+    #[serde(default = "empty_document")]
+    pub filter: Document,
+    #[serde(default)]
+    pub projection: Option<Document>,
+    #[serde(default)]
+    pub sort: Option<Document>,
+    #[serde(default)]
+    pub limit: Option<i64>,
+    #[serde(default)]
+    pub format: ExportFormat,
+    /// Dotted paths of the CSV columns, in order.
+    #[serde(default)]
+    pub columns: Option<Vec<String>>,
+    #[serde(default)]
+    pub compression: Option<Compression>,
+    /// Also export soft-deleted documents.
+    #[serde(default)]
+    pub include_deleted: bool,
+}
+
+/// Query string of an import. The body carries the rows.
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    pub database: String,
+    pub collection: String,
+    #[serde(default)]
+    pub cluster: Option<String>,
+    /// Defaults to the format named by `Content-Type`.
+    #[serde(default)]
+    pub format: Option<ImportFormat>,
+    /// Rows per `insert_many` call.
+    #[serde(default)]
+    pub batch_size: Option<usize>,
+    /// Comma-separated fields identifying the document each row upserts.
+    #[serde(default)]
+    pub upsert_key: Option<String>,
+    /// CSV header renames as `header:field,...`.
+    #[serde(default)]
+    pub rename: Option<String>,
+    /// CSV column types as `field:type,...`.
+    #[serde(default)]
+    pub types: Option<String>,
+}
+
+impl ImportQuery {
+    pub fn namespace(&self) -> NamespacePayload {
+        NamespacePayload {
+            database: self.database.clone(),
+            collection: self.collection.clone(),
+            cluster: self.cluster.clone(),
+            consistency: Consistency::default(),
+        }
+    }
+
+    pub fn upsert_keys(&self) -> Vec<String> {
+        self.upsert_key
+            .iter()
+            .flat_map(|keys| keys.split(','))
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RejectedRow {
+    pub line: u64,
+    pub error: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportResponse {
+    pub inserted_count: u64,
+    pub updated_count: u64,
+    pub failed_count: u64,
+    /// The first [`MAX_REPORTED_REJECTIONS`] rejected rows.
+    pub rejected: Vec<RejectedRow>,
+}
+
+impl ImportResponse {
+    pub fn reject(&mut self, line: u64, error: impl Into<String>) {
+        self.failed_count += 1;
+        if self.rejected.len() < MAX_REPORTED_REJECTIONS {
+            self.rejected.push(RejectedRow {
+                line,
+                error: error.into(),
+            });
+        }
+    }
+
+    pub fn merge(&mut self, other: ImportResponse) {
+        self.inserted_count += other.inserted_count;
+        self.updated_count += other.updated_count;
+        self.failed_count += other.failed_count - other.rejected.len() as u64;
+        for row in other.rejected {
+            self.reject(row.line, row.error);
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct CollectionQuery {
     pub database: String,
+    #[serde(default)]
+    pub cluster: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -125,6 +497,66 @@ pub struct CollectionsResponse {
     pub collections: Vec<String>,
 }
 
+/// Hit and miss counts of every namespace cached since startup or the last
+/// change to `[[caches]]`.
+#[derive(Debug, Serialize)]
+pub struct CacheStatsResponse {
+    pub namespaces: Vec<NamespaceCacheStats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthResponse {
+    pub status: &'static str,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ReadinessQuery {
+    #[serde(default)]
+    pub verbose: Option<String>,
+}
+
+impl ReadinessQuery {
+    /// `?verbose`, `?verbose=1` and `?verbose=true` all enable verbose output.
+    pub fn is_verbose(&self) -> bool {
+        matches!(self.verbose.as_deref(), Some(value) if value != "0" && value != "false")
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReadinessResponse {
+    pub status: &'static str,
+    pub clusters: BTreeMap<String, ClusterHealth>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ClusterHealth {
+    pub status: &'static str,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topology: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primary_available: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pool: Option<PoolSnapshot>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub set_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primary: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hosts: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct PoolSnapshot {
+    pub open: u64,
+    pub in_use: u64,
+    pub idle: u64,
+    pub cleared: u64,
+    pub checkout_failures: u64,
+}
+
 impl UpdateResponse {
     pub fn from_update_result(result: mongodb::results::UpdateResult) -> Self {
         Self::from_parts(
@@ -195,6 +627,15 @@ mod tests {
         assert_eq!(response.upserted_id, None);
     }
 
+    #[test]
+    fn explain_verbosity_parses_camel_case() {
+        let verbosity: ExplainVerbosity =
+            serde_json::from_value(serde_json::json!("executionStats")).unwrap();
+        assert_eq!(verbosity, ExplainVerbosity::ExecutionStats);
+        assert_eq!(verbosity.as_str(), "executionStats");
+        assert!(serde_json::from_value::<ExplainVerbosity>(serde_json::json!("verbose")).is_err());
+    }
+
     #[test]
     fn insert_many_response_handles_empty_ids() {
         let inserted_ids: HashMap<usize, Bson> = HashMap::new();
@@ -0,0 +1,223 @@
+use axum::body::Body;
+use axum::BoxError;
+use futures::StreamExt;
+use mongodb::bson::{Bson, Document};
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::config::PayloadConfig;
+use crate::error::ApiError;
+
+/// Size and shape limits on request bodies and the documents decoded from them.
+#[derive(Debug, Clone)]
+pub struct PayloadLimits {
+    max_body_bytes: u64,
+    route_body_bytes: BTreeMap<String, u64>,
+    max_insert_documents: Option<usize>,
+    max_document_bytes: usize,
+    max_depth: usize,
+}
+
+impl PayloadLimits {
+    pub fn new(config: &PayloadConfig) -> Self {
+        Self {
+            max_body_bytes: config.max_body_bytes,
+            route_body_bytes: config.route_body_bytes.clone(),
+            max_insert_documents: config.max_insert_documents.map(|count| count as usize),
+            max_document_bytes: config.max_document_bytes as usize,
+            max_depth: config.max_depth as usize,
+        }
+    }
+
+    /// Body limit for `path`. Streaming routes never buffer the whole body,
+    /// so only a per-route entry limits them.
+    pub fn body_limit(&self, path: &str, streaming: bool) -> Option<u64> {
+        match self.route_body_bytes.get(path) {
+            Some(limit) => Some(*limit),
+            None if streaming => None,
+            None => Some(self.max_body_bytes),
+        }
+    }
+
+    pub fn check_insert_count(&self, count: usize) -> Result<(), ApiError> {
+        match self.max_insert_documents {
+            Some(max) if count > max => Err(ApiError::validation(format!(
+                "documents holds {count} documents, exceeding the maximum of {max} per insert_many"
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Validates nesting depth and encoded size of a document about to be
+    /// written, naming it `root` in errors.
+    pub fn check_document(&self, document: &Document, root: &str) -> Result<(), ApiError> {
+        self.walk_document(document, root, 1)?;
+        let mut size = ByteCount(0);
+        document
+            .to_writer(&mut size)
+            .map_err(|err| ApiError::validation(format!("invalid {root}: {err}")))?;
+        if size.0 > self.max_document_bytes {
+            return Err(ApiError::validation(format!(
+                "`{root}` is {} bytes, exceeding the maximum document size of {} bytes",
+                size.0, self.max_document_bytes
+            )));
+        }
+        Ok(())
+    }
+
+    fn walk_document(&self, document: &Document, path: &str, depth: usize) -> Result<(), ApiError> {
+        self.check_depth(path, depth)?;
+        document
+            .iter()
+            .try_for_each(|(key, value)| self.walk_value(value, &format!("{path}.{key}"), depth))
+    }
+
+    fn walk_value(&self, value: &Bson, path: &str, depth: usize) -> Result<(), ApiError> {
+        match value {
+            Bson::Document(document) => self.walk_document(document, path, depth + 1),
+            Bson::Array(items) => {
+                self.check_depth(path, depth + 1)?;
+                items.iter().enumerate().try_for_each(|(index, item)| {
+                    self.walk_value(item, &format!("{path}.{index}"), depth + 1)
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_depth(&self, path: &str, depth: usize) -> Result<(), ApiError> {
+        if depth > self.max_depth {
+            return Err(ApiError::validation(format!(
+                "nesting depth at `{path}` exceeds the maximum of {}",
+                self.max_depth
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Error returned for a body that declares or streams more than `limit` bytes.
+pub fn body_too_large(path: &str, limit: u64) -> ApiError {
+    ApiError::payload_too_large(format!(
+        "request body exceeds the limit of {limit} bytes for {path}"
+    ))
+}
+
+/// Wraps `body` so reading past `limit` bytes fails, setting the returned
+/// flag so the caller can report the limit instead of the extractor's error.
+pub fn limit_body(body: Body, limit: u64) -> (Body, Arc<AtomicBool>) {
+    let exceeded = Arc::new(AtomicBool::new(false));
+    let flag = exceeded.clone();
+    let mut read = 0u64;
+    let stream = body.into_data_stream().map(move |chunk| {
+        let chunk = chunk.map_err(BoxError::from)?;
+        read += chunk.len() as u64;
+        if read > limit {
+            flag.store(true, Ordering::Relaxed);
+            return Err(BoxError::from(io::Error::other(format!(
+                "request body exceeds the limit of {limit} bytes"
+            ))));
+        }
+        Ok(chunk)
+    });
+    (Body::from_stream(stream), exceeded)
+}
+
+/// Counts encoded bytes without keeping them.
+struct ByteCount(usize);
+
+impl io::Write for ByteCount {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+
+    fn limits() -> PayloadLimits {
+        PayloadLimits::new(&PayloadConfig {
+            max_body_bytes: 1024,
+            route_body_bytes: BTreeMap::from([("/api/v1/documents/import".to_string(), 4096)]),
+            max_insert_documents: Some(2),
+            max_document_bytes: 64,
+            max_depth: 3,
+        })
+    }
+
+    #[test]
+    fn body_limit_prefers_route_override() {
+        let limits = limits();
+        assert_eq!(
+            limits.body_limit("/api/v1/documents/insert-one", false),
+            Some(1024)
+        );
+        assert_eq!(
+            limits.body_limit("/api/v1/documents/import", true),
+            Some(4096)
+        );
+        assert_eq!(limits.body_limit("/api/v1/files/upload", true), None);
+    }
+
+    #[test]
+    fn rejects_too_many_documents() {
+        assert!(limits().check_insert_count(2).is_ok());
+        let err = limits().check_insert_count(3).expect_err("too many");
+        assert_eq!(err.status().as_u16(), 400);
+        assert!(err.body().details.contains("maximum of 2"));
+    }
+
+    #[test]
+    fn rejects_deep_document_with_path() {
+        let err = limits()
+            .check_document(&doc! { "a": { "b": { "c": { "d": 1 } } } }, "document")
+            .expect_err("too deep");
+        assert!(err
+            .body()
+            .details
+            .contains("`document.a.b.c` exceeds the maximum of 3"));
+        let err = limits()
+            .check_document(&doc! { "a": [[[1]]] }, "document")
+            .expect_err("too deep");
+        assert!(err.body().details.contains("`document.a.0.0`"));
+        assert!(limits()
+            .check_document(&doc! { "a": { "b": { "c": 1 } } }, "document")
+            .is_ok());
+    }
+
+    #[test]
+    fn rejects_oversized_document() {
+        let document = doc! { "name": "x".repeat(100) };
+        let err = limits()
+            .check_document(&document, "documents.1")
+            .expect_err("too large");
+        assert!(err
+            .body()
+            .details
+            .contains("maximum document size of 64 bytes"));
+        assert!(err.body().details.contains("`documents.1`"));
+    }
+
+    #[tokio::test]
+    async fn limited_body_fails_past_the_limit() {
+        let (body, exceeded) = limit_body(Body::from("x".repeat(16)), 8);
+        assert!(axum::body::to_bytes(body, usize::MAX).await.is_err());
+        assert!(exceeded.load(Ordering::Relaxed));
+
+        let (body, exceeded) = limit_body(Body::from("x".repeat(8)), 8);
+        assert_eq!(
+            axum::body::to_bytes(body, usize::MAX).await.unwrap().len(),
+            8
+        );
+        assert!(!exceeded.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,415 @@
+use mongodb::bson::{Bson, Document};
+use mongodb::options::{FindOneOptions, FindOptions};
+use std::time::Duration;
+
+use crate::config::QueryPolicyConfig;
+use crate::error::ApiError;
+
+/// Stages that write their input to a collection. Aggregations are reads,
+/// so these would bypass auditing, history and the write rate limit.
+const WRITE_STAGES: [&str; 2] = ["$out", "$merge"];
+
+/// Response header carrying the limit applied to a `find` that did not set
+/// one, so clients can tell a full page from a truncated result.
+pub const LIMIT_APPLIED_HEADER: &str = "x-limit-applied";
+
+/// Server-side checks run on every filter, update and find option set
+/// before the driver sees them.
+#[derive(Debug, Clone)]
+pub struct QueryPolicy {
+    blocked_operators: Vec<String>,
+    max_limit: Option<i64>,
+    default_max_time: Option<Duration>,
+    max_depth: Option<usize>,
+    max_bytes: Option<usize>,
+    export_max_limit: Option<i64>,
+    export_max_time: Option<Duration>,
+}
+
+impl QueryPolicy {
+    pub fn new(config: &QueryPolicyConfig) -> Self {
+        Self {
+            blocked_operators: config.blocked_operators.clone(),
+            max_limit: config.max_limit.map(i64::from),
+            default_max_time: config.default_max_time,
+            max_depth: config.max_filter_depth.map(|depth| depth as usize),
+            max_bytes: config.max_filter_bytes.map(|bytes| bytes as usize),
+            export_max_limit: config.export_max_limit.map(i64::from),
+            export_max_time: config.export_max_time.or(config.default_max_time),
+        }
+    }
+
+    /// Validates a query filter: blocked operators, nesting depth and encoded size.
+    pub fn check_filter(&self, filter: &Document) -> Result<(), ApiError> {
+        if let Some(max_bytes) = self.max_bytes {
+            let size = mongodb::bson::to_vec(filter)
+                .map_err(|err| ApiError::validation(format!("invalid filter: {err}")))?
+                .len();
+            if size > max_bytes {
+                return Err(ApiError::validation(format!(
+                    "filter is {size} bytes, exceeding the maximum of {max_bytes} bytes"
+                )));
+            }
+        }
+        self.walk_document(filter, "filter", 1)
+    }
+
+    /// Validates an update, replacement or pipeline stage for blocked operators.
+    pub fn check_document(&self, document: &Document, root: &str) -> Result<(), ApiError> {
+        self.walk_document(document, root, 1)
+    }
+
+    /// Validates every stage of an aggregation pipeline for blocked
+    /// operators, and rejects `$out` and `$merge` at any depth.
+    pub fn check_pipeline(&self, pipeline: &[Document]) -> Result<(), ApiError> {
+        pipeline.iter().enumerate().try_for_each(|(index, stage)| {
+            let path = format!("pipeline.{index}");
+            check_write_stages(stage, &path)?;
+            self.check_document(stage, &path)
+        })
+    }
+
+    /// Rejects an empty filter on a multi-document write unless the caller confirmed it.
+    pub fn check_bounded(
+        &self,
+        operation: &str,
+        filter: &Document,
+        confirm_all: bool,
+    ) -> Result<(), ApiError> {
+        if filter.is_empty() && !confirm_all {
+            return Err(ApiError::validation(format!(
+                "{operation} with an empty filter affects every document; \
+                 pass \"confirm_all\": true to proceed"
+            )));
+        }
+        Ok(())
+    }
+
+    /// The limit [`QueryPolicy::apply_find_options`] fills in when the
+    /// request sets none.
+    pub fn implied_limit(&self, limit: Option<i64>) -> Option<i64> {
+        self.max_limit.filter(|_| limit.is_none())
+    }
+
+    /// Caps `limit` and fills in the default `maxTimeMS` for `find`. With a
+    /// maximum configured, a missing limit becomes the maximum and a limit of
+    /// 0, which MongoDB reads as "no limit", is rejected.
+    pub fn apply_find_options(
+        &self,
+        options: Option<FindOptions>,
+    ) -> Result<Option<FindOptions>, ApiError> {
+        cap_find_options(
+            options,
+            "options.limit",
+            self.max_limit,
+            self.default_max_time,
+        )
+    }
+
+    /// The limit [`QueryPolicy::apply_export_options`] fills in when the
+    /// export sets none.
+    pub fn implied_export_limit(&self, limit: Option<i64>) -> Option<i64> {
+        self.export_max_limit.filter(|_| limit.is_none())
+    }
+
+    /// [`QueryPolicy::apply_find_options`] for exports, with the export
+    /// limit and time cap, which fall back to no limit and the default
+    /// `maxTimeMS`.
+    pub fn apply_export_options(
+        &self,
+        options: Option<FindOptions>,
+    ) -> Result<Option<FindOptions>, ApiError> {
+        cap_find_options(
+            options,
+            "limit",
+            self.export_max_limit,
+            self.export_max_time,
+        )
+    }
+
+    /// Fills in the default `maxTimeMS` for `findOne`.
+    pub fn apply_find_one_options(
+        &self,
+        options: Option<FindOneOptions>,
+    ) -> Option<FindOneOptions> {
+        let Some(default_max_time) = self.default_max_time else {
+            return options;
+        };
+        let mut options = options.unwrap_or_default();
+        options.max_time.get_or_insert(default_max_time);
+        Some(options)
+    }
+
+    fn walk_document(&self, document: &Document, path: &str, depth: usize) -> Result<(), ApiError> {
+        self.check_depth(path, depth)?;
+        for (key, value) in document {
+            let child = format!("{path}.{key}");
+            if key.starts_with('$') && self.blocked_operators.iter().any(|op| op == key) {
+                return Err(ApiError::validation(format!(
+                    "operator `{key}` is not allowed at `{child}`"
+                )));
+            }
+            self.walk_value(value, &child, depth)?;
+        }
+        Ok(())
+    }
+
+    fn walk_value(&self, value: &Bson, path: &str, depth: usize) -> Result<(), ApiError> {
+        match value {
+            Bson::Document(document) => self.walk_document(document, path, depth + 1),
+            Bson::Array(items) => {
+                self.check_depth(path, depth + 1)?;
+                items.iter().enumerate().try_for_each(|(index, item)| {
+                    self.walk_value(item, &format!("{path}.{index}"), depth + 1)
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_depth(&self, path: &str, depth: usize) -> Result<(), ApiError> {
+        match self.max_depth {
+            Some(max_depth) if depth > max_depth => Err(ApiError::validation(format!(
+                "nesting depth at `{path}` exceeds the maximum of {max_depth}"
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn check_write_stages(document: &Document, path: &str) -> Result<(), ApiError> {
+    document.iter().try_for_each(|(key, value)| {
+        let child = format!("{path}.{key}");
+        if WRITE_STAGES.contains(&key.as_str()) {
+            return Err(ApiError::validation(format!(
+                "stage `{key}` writes to a collection and is not allowed at `{child}`"
+            )));
+        }
+        match value {
+            Bson::Document(document) => check_write_stages(document, &child),
+            Bson::Array(items) => {
+                items
+                    .iter()
+                    .enumerate()
+                    .try_for_each(|(index, item)| match item {
+                        Bson::Document(document) => {
+                            check_write_stages(document, &format!("{child}.{index}"))
+                        }
+                        _ => Ok(()),
+                    })
+            }
+            _ => Ok(()),
+        }
+    })
+}
+
+/// Caps `limit`, named `field` in the request, at `max_limit` and fills in
+/// `max_time` as `maxTimeMS`.
+fn cap_find_options(
+    options: Option<FindOptions>,
+    field: &str,
+    max_limit: Option<i64>,
+    max_time: Option<Duration>,
+) -> Result<Option<FindOptions>, ApiError> {
+    if max_limit.is_none() && max_time.is_none() {
+        return Ok(options);
+    }
+    let mut options = options.unwrap_or_default();
+    if let Some(max_limit) = max_limit {
+        match options.limit {
+            // `unsigned_abs`, since `i64::MIN` has no positive counterpart
+            Some(limit) if limit.unsigned_abs() > max_limit.unsigned_abs() => {
+                return Err(ApiError::validation(format!(
+                    "{field} of {limit} exceeds the maximum of {max_limit}"
+                )));
+            }
+            Some(0) => {
+                return Err(ApiError::validation(format!(
+                    "{field} of 0 requests no limit; pass at most {max_limit}"
+                )));
+            }
+            None => options.limit = Some(max_limit),
+            Some(_) => {}
+        }
+    }
+    if options.max_time.is_none() {
+        options.max_time = max_time;
+    }
+    Ok(Some(options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::IntoResponse;
+    use mongodb::bson::doc;
+
+    fn policy() -> QueryPolicy {
+        QueryPolicy::new(&QueryPolicyConfig {
+            max_limit: Some(100),
+            default_max_time: Some(Duration::from_secs(5)),
+            max_filter_depth: Some(3),
+            ..Default::default()
+        })
+    }
+
+    async fn details(error: ApiError) -> String {
+        let body = axum::body::to_bytes(error.into_response().into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        value["details"].as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn rejects_blocked_operator_with_path() {
+        let filter = doc! { "$or": [{ "a": 1 }, { "$where": "sleep(1000)" }] };
+        let err = policy().check_filter(&filter).expect_err("blocked");
+        assert_eq!(err.status().as_u16(), 400);
+        assert!(details(err).await.contains("`filter.$or.1.$where`"));
+    }
+
+    #[tokio::test]
+    async fn rejects_blocked_operator_in_update_pipeline() {
+        let update =
+            doc! { "$set": { "x": { "$function": { "body": "f", "args": [], "lang": "js" } } } };
+        let err = policy()
+            .check_document(&update, "update")
+            .expect_err("blocked");
+        assert!(details(err).await.contains("`update.$set.x.$function`"));
+    }
+
+    #[tokio::test]
+    async fn rejects_write_stages_in_pipeline() {
+        let pipeline = [doc! { "$match": {} }, doc! { "$out": "copy" }];
+        let err = policy().check_pipeline(&pipeline).expect_err("$out");
+        assert!(details(err).await.contains("`pipeline.1.$out`"));
+
+        let nested = [doc! {
+            "$facet": { "a": [{ "$merge": { "into": "copy" } }] }
+        }];
+        let err = policy().check_pipeline(&nested).expect_err("$merge");
+        assert!(details(err)
+            .await
+            .contains("`pipeline.0.$facet.a.0.$merge`"));
+
+        assert!(policy()
+            .check_pipeline(&[doc! { "$group": { "_id": "$a" } }])
+            .is_ok());
+    }
+
+    #[test]
+    fn accepts_ordinary_filter() {
+        let filter = doc! { "age": { "$gte": 21 }, "tags": ["a", "b"] };
+        assert!(policy().check_filter(&filter).is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_deeply_nested_filter() {
+        let filter = doc! { "a": { "b": { "c": { "d": 1 } } } };
+        let err = policy().check_filter(&filter).expect_err("too deep");
+        assert!(details(err).await.contains("`filter.a.b.c`"));
+    }
+
+    #[test]
+    fn rejects_oversized_filter() {
+        let policy = QueryPolicy::new(&QueryPolicyConfig {
+            max_filter_bytes: Some(16),
+            ..Default::default()
+        });
+        let filter = doc! { "name": "a string long enough to exceed the cap" };
+        assert!(policy.check_filter(&filter).is_err());
+    }
+
+    #[test]
+    fn empty_filter_requires_confirmation() {
+        let policy = policy();
+        assert!(policy
+            .check_bounded("delete_many", &doc! {}, false)
+            .is_err());
+        assert!(policy.check_bounded("delete_many", &doc! {}, true).is_ok());
+        assert!(policy
+            .check_bounded("delete_many", &doc! { "a": 1 }, false)
+            .is_ok());
+    }
+
+    #[test]
+    fn find_options_apply_limit_and_max_time() {
+        let options = policy().apply_find_options(None).unwrap().unwrap();
+        assert_eq!(options.limit, Some(100));
+        assert_eq!(options.max_time, Some(Duration::from_secs(5)));
+
+        let requested = FindOptions::builder()
+            .limit(10)
+            .max_time(Duration::from_secs(1))
+            .build();
+        let options = policy()
+            .apply_find_options(Some(requested))
+            .unwrap()
+            .unwrap();
+        assert_eq!(options.limit, Some(10));
+        assert_eq!(options.max_time, Some(Duration::from_secs(1)));
+
+        let too_many = FindOptions::builder().limit(1000).build();
+        assert!(policy().apply_find_options(Some(too_many)).is_err());
+        let single_batch = FindOptions::builder().limit(-1000).build();
+        assert!(policy().apply_find_options(Some(single_batch)).is_err());
+    }
+
+    #[test]
+    fn find_options_reject_most_negative_limit() {
+        let overflowing = FindOptions::builder().limit(i64::MIN).build();
+        assert!(policy().apply_find_options(Some(overflowing)).is_err());
+    }
+
+    #[tokio::test]
+    async fn unlimited_find_is_rejected_and_missing_limit_reported() {
+        let unlimited = FindOptions::builder().limit(0).build();
+        let err = policy()
+            .apply_find_options(Some(unlimited))
+            .expect_err("no limit");
+        assert!(details(err).await.contains("pass at most 100"));
+
+        assert_eq!(policy().implied_limit(None), Some(100));
+        assert_eq!(policy().implied_limit(Some(10)), None);
+        assert_eq!(
+            QueryPolicy::new(&Default::default()).implied_limit(None),
+            None
+        );
+    }
+
+    #[test]
+    fn export_options_use_export_caps() {
+        let policy = QueryPolicy::new(&QueryPolicyConfig {
+            max_limit: Some(100),
+            default_max_time: Some(Duration::from_secs(5)),
+            export_max_limit: Some(100_000),
+            ..Default::default()
+        });
+        let options = policy.apply_export_options(None).unwrap().unwrap();
+        assert_eq!(options.limit, Some(100_000));
+        assert_eq!(options.max_time, Some(Duration::from_secs(5)));
+        assert_eq!(policy.implied_export_limit(None), Some(100_000));
+
+        let large = FindOptions::builder().limit(50_000).build();
+        let options = policy.apply_export_options(Some(large)).unwrap().unwrap();
+        assert_eq!(options.limit, Some(50_000));
+        let too_many = FindOptions::builder().limit(200_000).build();
+        assert!(policy.apply_export_options(Some(too_many)).is_err());
+
+        let policy = QueryPolicy::new(&QueryPolicyConfig {
+            default_max_time: Some(Duration::from_secs(5)),
+            export_max_time: Some(Duration::from_secs(600)),
+            ..Default::default()
+        });
+        let options = policy.apply_export_options(None).unwrap().unwrap();
+        assert_eq!(options.limit, None);
+        assert_eq!(options.max_time, Some(Duration::from_secs(600)));
+    }
+
+    #[test]
+    fn find_one_options_get_default_max_time() {
+        let options = policy().apply_find_one_options(None).unwrap();
+        assert_eq!(options.max_time, Some(Duration::from_secs(5)));
+    }
+}
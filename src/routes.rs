@@ -1,14 +1,52 @@
-use axum::extract::{Query, State};
-use axum::http::StatusCode;
+use axum::body::{Body, Bytes, HttpBody};
+use axum::extract::{ConnectInfo, DefaultBodyLimit, FromRequest, Query, Request, State};
+use axum::http::header::{
+    ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, RANGE,
+};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
 use axum::routing::{get, post};
-use axum::{Json, Router};
-use futures::TryStreamExt;
-use mongodb::bson::Document;
+use axum::{Extension, Json, Router};
+use futures::{future, Stream, StreamExt, TryStreamExt};
+use hyper::body::{Frame, SizeHint};
+use mongodb::bson::{doc, Document};
+use mongodb::options::{
+    CountOptions, FindOneOptions, FindOptions, ReadPreference, SelectionCriteria,
+};
 use mongodb::Collection;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
 use tracing::instrument;
+use uuid::Uuid;
 
+use crate::audit::{AuditContext, AuditStats, CorrelationId, CORRELATION_ID_HEADER};
+use crate::auth::{ClientCertificate, Identity, API_KEY_HEADER};
+use crate::cache::{self, CacheControl, CACHE_STATUS_HEADER};
+use crate::cluster::RequestedCluster;
+use crate::codec::{bson_document_stream, BodyFormat, Payload, Reply};
+use crate::encryption::FieldCipher;
 use crate::error::{ApiError, ApiResult};
+use crate::etag::{EntityTags, Preconditions, Versioning};
+use crate::explain;
+use crate::export::{self, Compression};
+use crate::fields::FieldPolicy;
+use crate::files::{self, ByteRange, Multipart, DEFAULT_CONTENT_TYPE};
+use crate::graphql::{self, RequestScope, GRAPHQL_PATH};
+use crate::health::{self, HEALTHZ_PATH, READYZ_PATH};
+use crate::history;
+use crate::import::{self, ColumnMapping, ImportFormat, Importer, Rows};
+use crate::limits::{AccessKind, InFlightGuard};
 use crate::models::*;
+use crate::payload;
+use crate::policy::LIMIT_APPLIED_HEADER;
+use crate::shutdown::DrainGuard;
+use crate::soft_delete;
+use crate::stamps;
 use crate::state::AppState;
 
 const INSERT_ONE_PATH: &str = "/api/v1/documents/insert-one";
@@ -20,23 +58,269 @@ const UPDATE_MANY_PATH: &str = "/api/v1/documents/update-many";
 const REPLACE_ONE_PATH: &str = "/api/v1/documents/replace-one";
 const DELETE_ONE_PATH: &str = "/api/v1/documents/delete-one";
 const DELETE_MANY_PATH: &str = "/api/v1/documents/delete-many";
+const AGGREGATE_PATH: &str = "/api/v1/documents/aggregate";
+const COUNT_PATH: &str = "/api/v1/documents/count";
 const LIST_COLLECTIONS_PATH: &str = "/api/v1/collections";
+const CACHE_STATS_PATH: &str = "/api/v1/cache/stats";
+const AUDIT_STATS_PATH: &str = "/api/v1/audit/stats";
+const HISTORY_PATH: &str = "/api/v1/documents/history";
+const RESTORE_VERSION_PATH: &str = "/api/v1/documents/restore-version";
+const RESTORE_PATH: &str = "/api/v1/documents/restore";
+const PURGE_PATH: &str = "/api/v1/documents/purge";
+const IMPORT_PATH: &str = "/api/v1/documents/import";
+const EXPORT_PATH: &str = "/api/v1/documents/export";
+const FILES_UPLOAD_PATH: &str = "/api/v1/files/upload";
+const FILES_DOWNLOAD_PATH: &str = "/api/v1/files/download";
+const FILES_FIND_PATH: &str = "/api/v1/files/find";
+const FILES_DELETE_PATH: &str = "/api/v1/files/delete";
+const DOCUMENT_CHANGED: &str = "document has changed since it was read";
+const NOT_VERSIONED: &str = "namespace does not keep document history";
+const NOT_SOFT_DELETE: &str = "namespace does not use soft delete";
+/// Full reads `find_one_tagged` makes before giving up on a document that
+/// keeps changing.
+const TAGGED_READ_ATTEMPTS: usize = 3;
 
 pub fn router(state: AppState) -> Router {
-    Router::new()
-        .route(INSERT_ONE_PATH, post(insert_one))
-        .route(INSERT_MANY_PATH, post(insert_many))
+    let reads = Router::new()
         .route(FIND_ONE_PATH, post(find_one))
         .route(FIND_MANY_PATH, post(find_many))
+        .route(AGGREGATE_PATH, post(aggregate))
+        .route(COUNT_PATH, post(count))
+        .route(EXPORT_PATH, post(export_documents))
+        .route(HISTORY_PATH, post(document_history))
+        .route(LIST_COLLECTIONS_PATH, get(list_collections))
+        .route(CACHE_STATS_PATH, get(cache_stats))
+        .route(AUDIT_STATS_PATH, get(audit_stats))
+        .route(FILES_DOWNLOAD_PATH, get(download_file))
+        .route(FILES_FIND_PATH, post(find_files))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            limit_read_requests,
+        ));
+    let writes = Router::new()
+        .route(INSERT_ONE_PATH, post(insert_one))
+        .route(INSERT_MANY_PATH, post(insert_many))
         .route(UPDATE_ONE_PATH, post(update_one))
         .route(UPDATE_MANY_PATH, post(update_many))
         .route(REPLACE_ONE_PATH, post(replace_one))
         .route(DELETE_ONE_PATH, post(delete_one))
         .route(DELETE_MANY_PATH, post(delete_many))
-        .route(LIST_COLLECTIONS_PATH, get(list_collections))
+        .route(RESTORE_VERSION_PATH, post(restore_version))
+        .route(RESTORE_PATH, post(restore))
+        .route(PURGE_PATH, post(purge))
+        .route(IMPORT_PATH, post(import_documents))
+        .route(FILES_UPLOAD_PATH, post(upload_file))
+        .route(FILES_DELETE_PATH, post(delete_file))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            limit_write_requests,
+        ));
+    reads
+        .merge(writes)
+        // Rate limited by operation type once the query is parsed
+        .route(GRAPHQL_PATH, post(graphql_query))
+        // Replaces axum's fixed 2 MB default with the configured limits
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            limit_request_body,
+        ))
+        .route_layer(DefaultBodyLimit::disable())
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            authenticate_request,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            track_in_flight,
+        ))
+        .route_layer(middleware::from_fn(assign_correlation_id))
+        // Probes are added after the auth layer so they need no API key
+        .route(HEALTHZ_PATH, get(health::healthz))
+        .route(READYZ_PATH, get(health::readyz))
         .with_state(state)
 }
 
+/// Tags the request with a [`CorrelationId`], reusing a well-formed
+/// `X-Correlation-Id` from the client, and echoes it on the response.
+async fn assign_correlation_id(mut request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(CORRELATION_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| {
+            (1..=128).contains(&value.len()) && value.bytes().all(|byte| byte.is_ascii_graphic())
+        })
+        .map_or_else(|| Uuid::new_v4().to_string(), str::to_string);
+    let header = HeaderValue::from_str(&id).ok();
+    request
+        .extensions_mut()
+        .insert(CorrelationId(Arc::from(id)));
+    let mut response = next.run(request).await;
+    if let Some(header) = header {
+        response.headers_mut().insert(CORRELATION_ID_HEADER, header);
+    }
+    response
+}
+
+/// Counts the request as in flight for shutdown draining, refusing it with 503
+/// once the gateway is shutting down. The count lasts until the response body
+/// has been sent, so streamed responses are drained as well.
+async fn track_in_flight(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    match state.drain().enter() {
+        Some(guard) => next.run(request).await.map(|inner| {
+            Body::new(DrainingBody {
+                inner,
+                _drain: guard,
+            })
+        }),
+        None => log_request_failure(
+            request.uri().path(),
+            None,
+            ApiError::unavailable("gateway is shutting down"),
+        )
+        .into_response(),
+    }
+}
+
+/// Response body that holds its request's [`DrainGuard`] until it is sent or
+/// dropped.
+struct DrainingBody {
+    inner: Body,
+    _drain: DrainGuard,
+}
+
+impl HttpBody for DrainingBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        Pin::new(&mut self.inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Resolves the caller's [`Identity`] and makes it available to later layers.
+async fn authenticate_request(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let api_key = request
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok());
+    let certificate = request.extensions().get::<ClientCertificate>();
+    match state.authenticate(api_key, certificate) {
+        Ok(identity) => {
+            if let Some(identity) = identity {
+                request.extensions_mut().insert(identity);
+            }
+            next.run(request).await
+        }
+        Err(err) => {
+            // Failed attempts cost the peer tokens, so keys cannot be guessed
+            // faster than the configured rates. With authentication enabled
+            // only failing callers use peer buckets, so both kinds are charged.
+            let client = peer_key(&request);
+            let err = [AccessKind::Read, AccessKind::Write]
+                .into_iter()
+                .find_map(|kind| state.check_rate_limit(&client, kind).err())
+                .unwrap_or(err);
+            log_request_failure(request.uri().path(), None, err).into_response()
+        }
+    }
+}
+
+/// Enforces the body size limit for the route, refusing bodies that declare a
+/// larger `Content-Length` outright and cutting off those that stream past it.
+async fn limit_request_body(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path().to_string();
+    let streaming = matches!(path.as_str(), IMPORT_PATH | FILES_UPLOAD_PATH);
+    let Some(limit) = state.payload_limits().body_limit(&path, streaming) else {
+        return next.run(request).await;
+    };
+    let declared = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if declared.is_some_and(|length| length > limit) {
+        return log_request_failure(&path, None, payload::body_too_large(&path, limit))
+            .into_response();
+    }
+    let (parts, body) = request.into_parts();
+    let (body, exceeded) = payload::limit_body(body, limit);
+    let response = next.run(Request::from_parts(parts, body)).await;
+    if exceeded.load(Ordering::Relaxed) {
+        // The handler saw a failed read; report the limit instead
+        return log_request_failure(&path, None, payload::body_too_large(&path, limit))
+            .into_response();
+    }
+    response
+}
+
+/// Identifies the caller for rate limiting: authenticated identity, then peer
+/// IP. An unauthenticated `X-Api-Key` is ignored, since a caller could send a
+/// new one per request.
+fn client_key(request: &Request) -> String {
+    match request.extensions().get::<Identity>() {
+        Some(identity) => format!("identity:{}", identity.name),
+        None => peer_key(request),
+    }
+}
+
+/// Rate-limit key of an unauthenticated caller: its peer IP.
+fn peer_key(request: &Request) -> String {
+    match request.extensions().get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(addr)) => format!("ip:{}", addr.ip()),
+        None => "anonymous".to_string(),
+    }
+}
+
+async fn limit_read_requests(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    enforce_rate_limit(&state, AccessKind::Read, request, next).await
+}
+
+async fn limit_write_requests(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    enforce_rate_limit(&state, AccessKind::Write, request, next).await
+}
+
+async fn enforce_rate_limit(
+    state: &AppState,
+    kind: AccessKind,
+    request: Request,
+    next: Next,
+) -> Response {
+    let client = client_key(&request);
+    match state.check_rate_limit(&client, kind) {
+        Ok(()) => next.run(request).await,
+        Err(err) => log_request_failure(request.uri().path(), None, err).into_response(),
+    }
+}
+
 fn namespace_fields(namespace: &NamespacePayload) -> (&str, &str) {
     (namespace.database.trim(), namespace.collection.trim())
 }
@@ -73,6 +357,16 @@ fn log_namespace_success(
     affected: Option<u64>,
 ) {
     let (database, collection) = namespace_fields(namespace);
+    log_success(endpoint, database, collection, status, affected);
+}
+
+fn log_success(
+    endpoint: &str,
+    database: &str,
+    collection: &str,
+    status: StatusCode,
+    affected: Option<u64>,
+) {
     match affected {
         Some(count) => tracing::info!(
             target = "http",
@@ -123,7 +417,7 @@ fn log_request_failure(
 }
 
 fn map_driver_error(err: mongodb::error::Error) -> ApiError {
-    ApiError::driver(format!("mongodb error: {err}"))
+    history::rejection(&err).unwrap_or_else(|| ApiError::driver(format!("mongodb error: {err}")))
 }
 
 fn ensure_non_empty(namespace: &NamespacePayload) -> Result<(), ApiError> {
@@ -139,45 +433,75 @@ fn ensure_non_empty(namespace: &NamespacePayload) -> Result<(), ApiError> {
 fn collection_from_state(
     state: &AppState,
     namespace: &NamespacePayload,
-) -> Result<Collection<Document>, ApiError> {
+    cluster: &RequestedCluster,
+) -> Result<(Collection<Document>, InFlightGuard), ApiError> {
     ensure_non_empty(namespace)?;
-    state.collection(namespace)
+    state.checkout_collection(namespace, cluster.0.as_deref())
 }
 
 #[instrument(skip_all)]
 async fn insert_one(
     State(state): State<AppState>,
-    Json(payload): Json<InsertOneRequest>,
-) -> ApiResult<Json<InsertOneResponse>> {
+    format: BodyFormat,
+    cluster: RequestedCluster,
+    caller: Option<Extension<Identity>>,
+    context: AuditContext,
+    Payload(payload): Payload<InsertOneRequest>,
+) -> ApiResult<Reply<InsertOneResponse>> {
     let InsertOneRequest {
         namespace,
-        document,
+        mut document,
         options,
     } = payload;
     log_namespace_received(INSERT_ONE_PATH, &namespace, Some(1));
-    let collection = collection_from_state(&state, &namespace)
+    if let Some(stamps) = state.stamps(&namespace, caller.as_deref()) {
+        stamps.insert(&mut document);
+    }
+    state
+        .encryption(&namespace)
+        .encrypt_document(&mut document)
+        .map_err(|err| log_request_failure(INSERT_ONE_PATH, Some(&namespace), err))?;
+    state
+        .payload_limits()
+        .check_document(&document, "document")
         .map_err(|err| log_request_failure(INSERT_ONE_PATH, Some(&namespace), err))?;
-    let result = collection
-        .insert_one(document, options)
+    let (collection, _in_flight) = collection_from_state(&state, &namespace, &cluster)
+        .map_err(|err| log_request_failure(INSERT_ONE_PATH, Some(&namespace), err))?;
+    let audit = state
+        .begin_audit(&context, "insert_one", &namespace, cluster.0.as_deref())
         .await
-        .map_err(|err| {
-            log_request_failure(INSERT_ONE_PATH, Some(&namespace), map_driver_error(err))
-        })?;
-    let response = Json(InsertOneResponse {
+        .map_err(|err| log_request_failure(INSERT_ONE_PATH, Some(&namespace), err))?
+        .documents(std::slice::from_ref(&document));
+    let result = collection.insert_one(document, options).await;
+    // Invalidate even on failure; a failed write may have partly applied
+    state.invalidate_cache(&namespace, cluster.0.as_deref());
+    let result = result.map_err(|err| {
+        log_request_failure(
+            INSERT_ONE_PATH,
+            Some(&namespace),
+            audit.failed(map_driver_error(err)),
+        )
+    })?;
+    let response = InsertOneResponse {
         inserted_id: result.inserted_id,
-    });
+    };
+    audit.succeeded(&response);
     log_namespace_success(INSERT_ONE_PATH, &namespace, StatusCode::OK, Some(1));
-    Ok(response)
+    Ok(Reply::new(format, response))
 }
 
 #[instrument(skip_all)]
 async fn insert_many(
     State(state): State<AppState>,
-    Json(payload): Json<InsertManyRequest>,
-) -> ApiResult<Json<InsertManyResponse>> {
+    format: BodyFormat,
+    cluster: RequestedCluster,
+    caller: Option<Extension<Identity>>,
+    context: AuditContext,
+    Payload(payload): Payload<InsertManyRequest>,
+) -> ApiResult<Reply<InsertManyResponse>> {
     let InsertManyRequest {
         namespace,
-        documents,
+        mut documents,
         options,
     } = payload;
     log_namespace_received(INSERT_MANY_PATH, &namespace, Some(documents.len()));
@@ -188,46 +512,154 @@ async fn insert_many(
             ApiError::validation("documents must not be empty"),
         ));
     }
-    let collection = collection_from_state(&state, &namespace)
+    let limits = state.payload_limits();
+    limits
+        .check_insert_count(documents.len())
+        .map_err(|err| log_request_failure(INSERT_MANY_PATH, Some(&namespace), err))?;
+    if let Some(stamps) = state.stamps(&namespace, caller.as_deref()) {
+        documents
+            .iter_mut()
+            .for_each(|document| stamps.insert(document));
+    }
+    let cipher = state.encryption(&namespace);
+    documents
+        .iter_mut()
+        .try_for_each(|document| cipher.encrypt_document(document))
+        .map_err(|err| log_request_failure(INSERT_MANY_PATH, Some(&namespace), err))?;
+    documents
+        .iter()
+        .enumerate()
+        .try_for_each(|(index, document)| {
+            limits.check_document(document, &format!("documents.{index}"))
+        })
         .map_err(|err| log_request_failure(INSERT_MANY_PATH, Some(&namespace), err))?;
-    let result = collection
-        .insert_many(documents, options)
+    let (collection, _in_flight) = collection_from_state(&state, &namespace, &cluster)
+        .map_err(|err| log_request_failure(INSERT_MANY_PATH, Some(&namespace), err))?;
+    let audit = state
+        .begin_audit(&context, "insert_many", &namespace, cluster.0.as_deref())
         .await
-        .map_err(|err| {
-            log_request_failure(INSERT_MANY_PATH, Some(&namespace), map_driver_error(err))
-        })?;
-    let response = Json(InsertManyResponse::from_result(result));
+        .map_err(|err| log_request_failure(INSERT_MANY_PATH, Some(&namespace), err))?
+        .documents(&documents);
+    let result = collection.insert_many(documents, options).await;
+    // Invalidate even on failure; a failed write may have partly applied
+    state.invalidate_cache(&namespace, cluster.0.as_deref());
+    let result = result.map_err(|err| {
+        log_request_failure(
+            INSERT_MANY_PATH,
+            Some(&namespace),
+            audit.failed(map_driver_error(err)),
+        )
+    })?;
+    let response = InsertManyResponse::from_result(result);
+    audit.succeeded(&response);
     log_namespace_success(
         INSERT_MANY_PATH,
         &namespace,
         StatusCode::OK,
         Some(response.inserted_ids.len() as u64),
     );
-    Ok(response)
+    Ok(Reply::new(format, response))
 }
 
 #[instrument(skip_all)]
 async fn find_one(
     State(state): State<AppState>,
-    Json(payload): Json<FindOneRequest>,
-) -> ApiResult<Json<FindOneResponse>> {
+    format: BodyFormat,
+    cluster: RequestedCluster,
+    caller: Option<Extension<Identity>>,
+    control: CacheControl,
+    preconditions: Preconditions,
+    Payload(payload): Payload<FindOneRequest>,
+) -> ApiResult<Response> {
     let FindOneRequest {
         namespace,
-        filter,
+        mut filter,
         options,
+        explain,
+        include_deleted,
     } = payload;
     log_namespace_received(FIND_ONE_PATH, &namespace, None);
-    let collection = collection_from_state(&state, &namespace)
+    let policy = state.query_policy();
+    let fields = state.field_policy(&namespace, caller.as_deref());
+    let options = policy
+        .check_filter(&filter)
+        .and_then(|()| fields.check_filter(&filter))
+        .and_then(|()| fields.apply_find_one_options(policy.apply_find_one_options(options)))
         .map_err(|err| log_request_failure(FIND_ONE_PATH, Some(&namespace), err))?;
-    let result = collection.find_one(filter, options).await.map_err(|err| {
-        log_request_failure(FIND_ONE_PATH, Some(&namespace), map_driver_error(err))
-    })?;
+    let cipher = state.encryption(&namespace);
+    cipher
+        .encrypt_filter(&mut filter)
+        .map_err(|err| log_request_failure(FIND_ONE_PATH, Some(&namespace), err))?;
+    if !include_deleted && state.soft_delete(&namespace) {
+        filter = soft_delete::exclude_deleted(filter);
+    }
+    let cached = explain
+        .is_none()
+        .then(|| state.response_cache(&namespace, cluster.0.as_deref()))
+        .flatten()
+        .map(|cache| (cache::key("find_one", &filter, &options), cache));
+    let hit = cached
+        .as_ref()
+        .filter(|_| !control.no_cache)
+        .and_then(|(key, cache)| cache.get(key));
+    let cache_status = cache_status(cached.is_some(), hit.is_some());
+    let result = match hit {
+        // Entries hold the document followed by its tag
+        Some(documents) => documents.first().cloned().zip(
+            documents
+                .get(1)
+                .and_then(|tag| tag.get_str("etag").ok())
+                .map(str::to_string),
+        ),
+        None => {
+            let (collection, _in_flight) = collection_from_state(&state, &namespace, &cluster)
+                .map_err(|err| log_request_failure(FIND_ONE_PATH, Some(&namespace), err))?;
+            if let Some(verbosity) = explain {
+                let command =
+                    explain::find_one_command(collection.name(), &filter, options.as_ref());
+                return explain_response(
+                    FIND_ONE_PATH,
+                    &namespace,
+                    format,
+                    &collection,
+                    command,
+                    verbosity,
+                )
+                .await;
+            }
+            let generation = cached.as_ref().map(|(_, cache)| cache.generation());
+            let versioning = state.versioning(&namespace);
+            let result = find_one_tagged(&collection, &cipher, &versioning, filter, options)
+                .await
+                .map_err(|err| log_request_failure(FIND_ONE_PATH, Some(&namespace), err))?;
+            if let (Some((key, cache)), Some(generation)) = (cached, generation) {
+                if !control.no_store {
+                    let entry: Vec<Document> = match &result {
+                        Some((document, etag)) => vec![document.clone(), doc! { "etag": etag }],
+                        None => Vec::new(),
+                    };
+                    cache.insert(key, generation, &entry);
+                }
+            }
+            result
+        }
+    };
 
     match result {
-        Some(document) => {
-            let response = Json(FindOneResponse { document });
+        Some((mut document, etag)) => {
+            if preconditions
+                .if_none_match
+                .is_some_and(|tags| tags.matches_weak(&etag))
+            {
+                log_namespace_success(FIND_ONE_PATH, &namespace, StatusCode::NOT_MODIFIED, None);
+                let response = with_etag(StatusCode::NOT_MODIFIED.into_response(), &etag);
+                return Ok(with_cache_status(response, cache_status));
+            }
+            fields.redact(&mut document);
+            let response = FindOneResponse { document };
             log_namespace_success(FIND_ONE_PATH, &namespace, StatusCode::OK, Some(1));
-            Ok(response)
+            let response = with_etag(Reply::new(format, response).into_response(), &etag);
+            Ok(with_cache_status(response, cache_status))
         }
         None => Err(log_request_failure(
             FIND_ONE_PATH,
@@ -240,258 +672,1919 @@ async fn find_one(
 #[instrument(skip_all)]
 async fn find_many(
     State(state): State<AppState>,
-    Json(payload): Json<FindManyRequest>,
-) -> ApiResult<Json<FindManyResponse>> {
+    format: BodyFormat,
+    cluster: RequestedCluster,
+    caller: Option<Extension<Identity>>,
+    control: CacheControl,
+    Payload(payload): Payload<FindManyRequest>,
+) -> ApiResult<Response> {
     let FindManyRequest {
         namespace,
-        filter,
+        mut filter,
         options,
+        explain,
+        include_deleted,
     } = payload;
     log_namespace_received(FIND_MANY_PATH, &namespace, None);
-    let collection = collection_from_state(&state, &namespace)
+    let policy = state.query_policy();
+    let fields = state.field_policy(&namespace, caller.as_deref());
+    let implied_limit = policy.implied_limit(options.as_ref().and_then(|options| options.limit));
+    let options = policy
+        .check_filter(&filter)
+        .and_then(|()| fields.check_filter(&filter))
+        .and_then(|()| policy.apply_find_options(options))
+        .and_then(|options| fields.apply_find_options(options))
         .map_err(|err| log_request_failure(FIND_MANY_PATH, Some(&namespace), err))?;
-    let mut cursor = collection.find(filter, options).await.map_err(|err| {
-        log_request_failure(FIND_MANY_PATH, Some(&namespace), map_driver_error(err))
-    })?;
-    let mut documents = Vec::new();
-    while let Some(document) = cursor.try_next().await.map_err(|err| {
-        log_request_failure(FIND_MANY_PATH, Some(&namespace), map_driver_error(err))
-    })? {
-        documents.push(document);
+    let cipher = state.encryption(&namespace);
+    cipher
+        .encrypt_filter(&mut filter)
+        .map_err(|err| log_request_failure(FIND_MANY_PATH, Some(&namespace), err))?;
+    if !include_deleted && state.soft_delete(&namespace) {
+        filter = soft_delete::exclude_deleted(filter);
     }
-    let response = Json(FindManyResponse { documents });
-    let count = response.documents.len() as u64;
+    let cached = explain
+        .is_none()
+        .then(|| state.response_cache(&namespace, cluster.0.as_deref()))
+        .flatten()
+        .map(|cache| (cache::key("find", &filter, &options), cache));
+    let hit = cached
+        .as_ref()
+        .filter(|_| !control.no_cache)
+        .and_then(|(key, cache)| cache.get(key));
+    let cache_status = cache_status(cached.is_some(), hit.is_some());
+    let mut documents = match hit {
+        Some(documents) => documents.to_vec(),
+        None => {
+            let (collection, in_flight) = collection_from_state(&state, &namespace, &cluster)
+                .map_err(|err| log_request_failure(FIND_MANY_PATH, Some(&namespace), err))?;
+            if let Some(verbosity) = explain {
+                let command = explain::find_command(collection.name(), &filter, options.as_ref());
+                return explain_response(
+                    FIND_MANY_PATH,
+                    &namespace,
+                    format,
+                    &collection,
+                    command,
+                    verbosity,
+                )
+                .await;
+            }
+            let generation = cached.as_ref().map(|(_, cache)| cache.generation());
+            let mut cursor = collection.find(filter, options).await.map_err(|err| {
+                log_request_failure(FIND_MANY_PATH, Some(&namespace), map_driver_error(err))
+            })?;
+            if format == BodyFormat::Bson && cached.is_none() {
+                let response = stream_documents(
+                    FIND_MANY_PATH,
+                    &namespace,
+                    cursor,
+                    cipher,
+                    fields,
+                    in_flight,
+                );
+                return Ok(with_limit_applied(response, implied_limit));
+            }
+            let mut documents = Vec::new();
+            while let Some(mut document) = cursor.try_next().await.map_err(|err| {
+                log_request_failure(FIND_MANY_PATH, Some(&namespace), map_driver_error(err))
+            })? {
+                cipher
+                    .decrypt(&mut document)
+                    .map_err(|err| log_request_failure(FIND_MANY_PATH, Some(&namespace), err))?;
+                documents.push(document);
+            }
+            if let (Some((key, cache)), Some(generation)) = (cached, generation) {
+                if !control.no_store {
+                    cache.insert(key, generation, &documents);
+                }
+            }
+            documents
+        }
+    };
+    documents
+        .iter_mut()
+        .for_each(|document| fields.redact(document));
+    let count = documents.len() as u64;
     log_namespace_success(FIND_MANY_PATH, &namespace, StatusCode::OK, Some(count));
-    Ok(response)
+    let response = match format {
+        // Cached results are already buffered, so they are sent in one go
+        BodyFormat::Bson => {
+            bson_document_stream(futures::stream::iter(documents.into_iter().map(Ok)))
+        }
+        BodyFormat::Json => Reply::new(format, FindManyResponse { documents }).into_response(),
+    };
+    Ok(with_limit_applied(
+        with_cache_status(response, cache_status),
+        implied_limit,
+    ))
+}
+
+/// Streams decrypted, redacted documents from `cursor` as BSON. The namespace
+/// slot is held until the client stops reading, and success is logged with
+/// the document count once the cursor is exhausted.
+fn stream_documents(
+    endpoint: &'static str,
+    namespace: &NamespacePayload,
+    cursor: mongodb::Cursor<Document>,
+    cipher: FieldCipher,
+    fields: Arc<FieldPolicy>,
+    in_flight: InFlightGuard,
+) -> Response {
+    let (database, collection) = namespace_fields(namespace);
+    let (database, collection) = (database.to_owned(), collection.to_owned());
+    let sent = Arc::new(AtomicU64::new(0));
+    let counted = sent.clone();
+    let finished = futures::stream::once(async move {
+        drop(in_flight);
+        let sent = sent.load(Ordering::Relaxed);
+        log_success(endpoint, &database, &collection, StatusCode::OK, Some(sent));
+    })
+    .filter_map(|()| future::ready(None));
+    let documents = cursor
+        .and_then(move |mut document| {
+            let result = cipher
+                .decrypt(&mut document)
+                .map(|()| {
+                    fields.redact(&mut document);
+                    document
+                })
+                .map_err(|err| mongodb::error::Error::custom(err.body().details.clone()));
+            future::ready(result)
+        })
+        .inspect_ok(move |_| {
+            counted.fetch_add(1, Ordering::Relaxed);
+        })
+        .inspect_err(move |err| {
+            tracing::warn!(
+                target = "http",
+                endpoint,
+                error = %err,
+                "bson stream aborted"
+            );
+        })
+        .chain(finished);
+    bson_document_stream(documents)
+}
+
+fn with_etag(mut response: Response, etag: &str) -> Response {
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        response.headers_mut().insert(ETAG, value);
+    }
+    response
+}
+
+/// `X-Cache` value for a read; `None` when the namespace is not cached.
+fn cache_status(cacheable: bool, hit: bool) -> Option<&'static str> {
+    match (cacheable, hit) {
+        (false, _) => None,
+        (true, true) => Some("HIT"),
+        (true, false) => Some("MISS"),
+    }
+}
+
+fn with_cache_status(mut response: Response, status: Option<&'static str>) -> Response {
+    if let Some(status) = status {
+        response
+            .headers_mut()
+            .insert(CACHE_STATUS_HEADER, HeaderValue::from_static(status));
+    }
+    response
+}
+
+fn with_limit_applied(mut response: Response, limit: Option<i64>) -> Response {
+    if let Some(limit) = limit {
+        response
+            .headers_mut()
+            .insert(LIMIT_APPLIED_HEADER, HeaderValue::from(limit));
+    }
+    response
 }
 
 #[instrument(skip_all)]
 async fn update_one(
     State(state): State<AppState>,
-    Json(payload): Json<UpdateRequest>,
-) -> ApiResult<Json<UpdateResponse>> {
+    format: BodyFormat,
+    cluster: RequestedCluster,
+    caller: Option<Extension<Identity>>,
+    context: AuditContext,
+    preconditions: Preconditions,
+    Payload(payload): Payload<UpdateRequest>,
+) -> ApiResult<Response> {
     let UpdateRequest {
         namespace,
-        filter,
-        update,
+        mut filter,
+        mut update,
         options,
+        explain,
+        dry_run,
+        ..
     } = payload;
     log_namespace_received(UPDATE_ONE_PATH, &namespace, None);
-    let collection = collection_from_state(&state, &namespace)
+    let policy = state.query_policy();
+    let fields = state.field_policy(&namespace, caller.as_deref());
+    policy
+        .check_filter(&filter)
+        .and_then(|()| fields.check_filter(&filter))
+        .and_then(|()| fields.check_update(&update))
+        .and_then(|()| policy.check_document(&update, "update"))
+        .and_then(|()| state.payload_limits().check_document(&update, "update"))
+        .and_then(|()| {
+            check_conditional_upsert(
+                &preconditions,
+                options.as_ref().and_then(|opt| opt.upsert).unwrap_or(false),
+            )
+        })
+        .map_err(|err| log_request_failure(UPDATE_ONE_PATH, Some(&namespace), err))?;
+    if let Some(stamps) = state.stamps(&namespace, caller.as_deref()) {
+        stamps.update(&mut update);
+    }
+    let cipher = state.encryption(&namespace);
+    cipher
+        .encrypt_filter(&mut filter)
+        .and_then(|()| cipher.encrypt_update(&mut update))
+        .map_err(|err| log_request_failure(UPDATE_ONE_PATH, Some(&namespace), err))?;
+    let (collection, _in_flight) = collection_from_state(&state, &namespace, &cluster)
         .map_err(|err| log_request_failure(UPDATE_ONE_PATH, Some(&namespace), err))?;
-    let result = collection
-        .update_one(filter, update, options.clone())
+    if let Some(verbosity) = explain {
+        let command =
+            explain::update_command(collection.name(), &filter, &update, options.as_ref(), false);
+        return explain_response(
+            UPDATE_ONE_PATH,
+            &namespace,
+            format,
+            &collection,
+            command,
+            verbosity,
+        )
+        .await;
+    }
+    if dry_run {
+        return dry_run_response(
+            UPDATE_ONE_PATH,
+            &namespace,
+            format,
+            &collection,
+            filter,
+            true,
+        )
+        .await;
+    }
+    if let Some(expected) = &preconditions.if_match {
+        filter = pin_version(&state, &namespace, &collection, filter, expected)
+            .await
+            .map_err(|err| log_request_failure(UPDATE_ONE_PATH, Some(&namespace), err))?;
+    }
+    let versioned = state
+        .history(
+            &namespace,
+            cluster.0.as_deref(),
+            &collection,
+            caller.as_deref(),
+        )
+        .await;
+    if versioned.is_some() {
+        history::check_update(&update)
+            .map_err(|err| log_request_failure(UPDATE_ONE_PATH, Some(&namespace), err))?;
+    }
+    let audit = state
+        .begin_audit(&context, "update_one", &namespace, cluster.0.as_deref())
         .await
-        .map_err(|err| {
-            log_request_failure(UPDATE_ONE_PATH, Some(&namespace), map_driver_error(err))
-        })?;
-    if result.matched_count == 0
-        && result.upserted_id.is_none()
+        .map_err(|err| log_request_failure(UPDATE_ONE_PATH, Some(&namespace), err))?
+        .filter(&filter)
+        .update(&update);
+    let result = match &versioned {
+        Some(versioned) => {
+            versioned
+                .update(filter, update, options.clone(), false)
+                .await
+        }
+        None => collection.update_one(filter, update, options.clone()).await,
+    };
+    // Invalidate even on failure; a failed write may have partly applied
+    state.invalidate_cache(&namespace, cluster.0.as_deref());
+    let result = result.map_err(|err| {
+        log_request_failure(
+            UPDATE_ONE_PATH,
+            Some(&namespace),
+            audit.failed(map_driver_error(err)),
+        )
+    })?;
+    let response = UpdateResponse::from_update_result(result);
+    audit.succeeded(&response);
+    if response.matched_count == 0
+        && response.upserted_id.is_none()
         && !options.as_ref().and_then(|opt| opt.upsert).unwrap_or(false)
     {
         return Err(log_request_failure(
             UPDATE_ONE_PATH,
             Some(&namespace),
-            ApiError::not_found("no documents matched the filter"),
+            no_match(&preconditions),
         ));
     }
-    let response = Json(UpdateResponse::from_update_result(result));
     log_namespace_success(
         UPDATE_ONE_PATH,
         &namespace,
         StatusCode::OK,
         Some(response.modified_count),
     );
-    Ok(response)
+    Ok(Reply::new(format, response).into_response())
 }
 
 #[instrument(skip_all)]
 async fn update_many(
     State(state): State<AppState>,
-    Json(payload): Json<UpdateRequest>,
-) -> ApiResult<Json<UpdateResponse>> {
+    format: BodyFormat,
+    cluster: RequestedCluster,
+    caller: Option<Extension<Identity>>,
+    context: AuditContext,
+    Payload(payload): Payload<UpdateRequest>,
+) -> ApiResult<Response> {
     let UpdateRequest {
         namespace,
-        filter,
-        update,
+        mut filter,
+        mut update,
         options,
+        confirm_all,
+        explain,
+        dry_run,
     } = payload;
     log_namespace_received(UPDATE_MANY_PATH, &namespace, None);
-    let collection = collection_from_state(&state, &namespace)
+    let policy = state.query_policy();
+    let fields = state.field_policy(&namespace, caller.as_deref());
+    policy
+        .check_bounded("update_many", &filter, confirm_all)
+        .and_then(|()| policy.check_filter(&filter))
+        .and_then(|()| fields.check_filter(&filter))
+        .and_then(|()| fields.check_update(&update))
+        .and_then(|()| policy.check_document(&update, "update"))
+        .and_then(|()| state.payload_limits().check_document(&update, "update"))
+        .map_err(|err| log_request_failure(UPDATE_MANY_PATH, Some(&namespace), err))?;
+    if let Some(stamps) = state.stamps(&namespace, caller.as_deref()) {
+        stamps.update(&mut update);
+    }
+    let cipher = state.encryption(&namespace);
+    cipher
+        .encrypt_filter(&mut filter)
+        .and_then(|()| cipher.encrypt_update(&mut update))
+        .map_err(|err| log_request_failure(UPDATE_MANY_PATH, Some(&namespace), err))?;
+    let (collection, _in_flight) = collection_from_state(&state, &namespace, &cluster)
         .map_err(|err| log_request_failure(UPDATE_MANY_PATH, Some(&namespace), err))?;
-    let result = collection
-        .update_many(filter, update, options)
+    if let Some(verbosity) = explain {
+        let command =
+            explain::update_command(collection.name(), &filter, &update, options.as_ref(), true);
+        return explain_response(
+            UPDATE_MANY_PATH,
+            &namespace,
+            format,
+            &collection,
+            command,
+            verbosity,
+        )
+        .await;
+    }
+    if dry_run {
+        return dry_run_response(
+            UPDATE_MANY_PATH,
+            &namespace,
+            format,
+            &collection,
+            filter,
+            false,
+        )
+        .await;
+    }
+    let versioned = state
+        .history(
+            &namespace,
+            cluster.0.as_deref(),
+            &collection,
+            caller.as_deref(),
+        )
+        .await;
+    if versioned.is_some() {
+        history::check_update(&update)
+            .map_err(|err| log_request_failure(UPDATE_MANY_PATH, Some(&namespace), err))?;
+    }
+    let audit = state
+        .begin_audit(&context, "update_many", &namespace, cluster.0.as_deref())
         .await
-        .map_err(|err| {
-            log_request_failure(UPDATE_MANY_PATH, Some(&namespace), map_driver_error(err))
-        })?;
-    let response = Json(UpdateResponse::from_update_result(result));
+        .map_err(|err| log_request_failure(UPDATE_MANY_PATH, Some(&namespace), err))?
+        .filter(&filter)
+        .update(&update);
+    let result = match &versioned {
+        Some(versioned) => versioned.update(filter, update, options, true).await,
+        None => collection.update_many(filter, update, options).await,
+    };
+    // Invalidate even on failure; a failed write may have partly applied
+    state.invalidate_cache(&namespace, cluster.0.as_deref());
+    let result = result.map_err(|err| {
+        log_request_failure(
+            UPDATE_MANY_PATH,
+            Some(&namespace),
+            audit.failed(map_driver_error(err)),
+        )
+    })?;
+    let response = UpdateResponse::from_update_result(result);
+    audit.succeeded(&response);
     log_namespace_success(
         UPDATE_MANY_PATH,
         &namespace,
         StatusCode::OK,
         Some(response.modified_count),
     );
-    Ok(response)
+    Ok(Reply::new(format, response).into_response())
 }
 
 #[instrument(skip_all)]
 async fn replace_one(
     State(state): State<AppState>,
-    Json(payload): Json<ReplaceOneRequest>,
-) -> ApiResult<Json<UpdateResponse>> {
+    format: BodyFormat,
+    cluster: RequestedCluster,
+    caller: Option<Extension<Identity>>,
+    context: AuditContext,
+    preconditions: Preconditions,
+    Payload(payload): Payload<ReplaceOneRequest>,
+) -> ApiResult<Response> {
     let ReplaceOneRequest {
         namespace,
-        filter,
-        replacement,
+        mut filter,
+        mut replacement,
         options,
+        explain,
+        dry_run,
     } = payload;
     log_namespace_received(REPLACE_ONE_PATH, &namespace, None);
-    let collection = collection_from_state(&state, &namespace)
+    let policy = state.query_policy();
+    let fields = state.field_policy(&namespace, caller.as_deref());
+    policy
+        .check_filter(&filter)
+        .and_then(|()| fields.check_filter(&filter))
+        .and_then(|()| policy.check_document(&replacement, "replacement"))
+        .and_then(|()| {
+            state
+                .payload_limits()
+                .check_document(&replacement, "replacement")
+        })
+        .and_then(|()| {
+            check_conditional_upsert(
+                &preconditions,
+                options.as_ref().and_then(|opt| opt.upsert).unwrap_or(false),
+            )
+        })
+        .map_err(|err| log_request_failure(REPLACE_ONE_PATH, Some(&namespace), err))?;
+    let cipher = state.encryption(&namespace);
+    cipher
+        .encrypt_filter(&mut filter)
+        .and_then(|()| cipher.encrypt_document(&mut replacement))
+        .map_err(|err| log_request_failure(REPLACE_ONE_PATH, Some(&namespace), err))?;
+    // Stamped replacements become update pipelines that carry over the
+    // creation stamps of the replaced document
+    let stamped = state
+        .stamps(&namespace, caller.as_deref())
+        .map(|stamps| stamps.replace(&replacement, &cipher))
+        .transpose()
         .map_err(|err| log_request_failure(REPLACE_ONE_PATH, Some(&namespace), err))?;
-    let result = collection
-        .replace_one(filter, replacement, options.clone())
+    let (collection, _in_flight) = collection_from_state(&state, &namespace, &cluster)
+        .map_err(|err| log_request_failure(REPLACE_ONE_PATH, Some(&namespace), err))?;
+    if let Some(verbosity) = explain {
+        let command =
+            explain::replace_command(collection.name(), &filter, &replacement, options.as_ref());
+        return explain_response(
+            REPLACE_ONE_PATH,
+            &namespace,
+            format,
+            &collection,
+            command,
+            verbosity,
+        )
+        .await;
+    }
+    if dry_run {
+        return dry_run_response(
+            REPLACE_ONE_PATH,
+            &namespace,
+            format,
+            &collection,
+            filter,
+            true,
+        )
+        .await;
+    }
+    if let Some(expected) = &preconditions.if_match {
+        filter = pin_version(&state, &namespace, &collection, filter, expected)
+            .await
+            .map_err(|err| log_request_failure(REPLACE_ONE_PATH, Some(&namespace), err))?;
+    }
+    let versioned = state
+        .history(
+            &namespace,
+            cluster.0.as_deref(),
+            &collection,
+            caller.as_deref(),
+        )
+        .await;
+    let audit = state
+        .begin_audit(&context, "replace_one", &namespace, cluster.0.as_deref())
         .await
-        .map_err(|err| {
-            log_request_failure(REPLACE_ONE_PATH, Some(&namespace), map_driver_error(err))
-        })?;
-    if result.matched_count == 0
-        && result.upserted_id.is_none()
+        .map_err(|err| log_request_failure(REPLACE_ONE_PATH, Some(&namespace), err))?
+        .filter(&filter)
+        .replacement(&replacement);
+    let result = match (stamped, &versioned) {
+        (Some(pipeline), Some(versioned)) => {
+            versioned
+                .update(
+                    filter,
+                    pipeline,
+                    stamps::update_options(options.clone()),
+                    false,
+                )
+                .await
+        }
+        (Some(pipeline), None) => {
+            collection
+                .update_one(filter, pipeline, stamps::update_options(options.clone()))
+                .await
+        }
+        (None, Some(versioned)) => {
+            versioned
+                .replace(filter, replacement, options.clone())
+                .await
+        }
+        (None, None) => {
+            collection
+                .replace_one(filter, replacement, options.clone())
+                .await
+        }
+    };
+    // Invalidate even on failure; a failed write may have partly applied
+    state.invalidate_cache(&namespace, cluster.0.as_deref());
+    let result = result.map_err(|err| {
+        log_request_failure(
+            REPLACE_ONE_PATH,
+            Some(&namespace),
+            audit.failed(map_driver_error(err)),
+        )
+    })?;
+    let response = UpdateResponse::from_update_result(result);
+    audit.succeeded(&response);
+    if response.matched_count == 0
+        && response.upserted_id.is_none()
         && !options.as_ref().and_then(|opt| opt.upsert).unwrap_or(false)
     {
         return Err(log_request_failure(
             REPLACE_ONE_PATH,
             Some(&namespace),
-            ApiError::not_found("no documents matched the filter"),
+            no_match(&preconditions),
         ));
     }
-    let response = Json(UpdateResponse::from_update_result(result));
     log_namespace_success(
         REPLACE_ONE_PATH,
         &namespace,
         StatusCode::OK,
         Some(response.modified_count),
     );
-    Ok(response)
+    Ok(Reply::new(format, response).into_response())
 }
 
 #[instrument(skip_all)]
 async fn delete_one(
     State(state): State<AppState>,
-    Json(payload): Json<DeleteRequest>,
-) -> ApiResult<Json<DeleteResponse>> {
+    format: BodyFormat,
+    cluster: RequestedCluster,
+    caller: Option<Extension<Identity>>,
+    context: AuditContext,
+    preconditions: Preconditions,
+    Payload(payload): Payload<DeleteRequest>,
+) -> ApiResult<Response> {
     let DeleteRequest {
         namespace,
-        filter,
+        mut filter,
         options,
+        explain,
+        dry_run,
+        ..
     } = payload;
     log_namespace_received(DELETE_ONE_PATH, &namespace, None);
-    let collection = collection_from_state(&state, &namespace)
+    let policy = state.query_policy();
+    let fields = state.field_policy(&namespace, caller.as_deref());
+    policy
+        .check_filter(&filter)
+        .and_then(|()| fields.check_filter(&filter))
+        .map_err(|err| log_request_failure(DELETE_ONE_PATH, Some(&namespace), err))?;
+    state
+        .encryption(&namespace)
+        .encrypt_filter(&mut filter)
+        .map_err(|err| log_request_failure(DELETE_ONE_PATH, Some(&namespace), err))?;
+    let soft = state.soft_delete(&namespace);
+    if soft {
+        filter = soft_delete::exclude_deleted(filter);
+    }
+    let (collection, _in_flight) = collection_from_state(&state, &namespace, &cluster)
         .map_err(|err| log_request_failure(DELETE_ONE_PATH, Some(&namespace), err))?;
-    let result = collection
-        .delete_one(filter, options)
+    if let Some(verbosity) = explain {
+        let command = if soft {
+            explain::update_command(
+                collection.name(),
+                &filter,
+                &soft_delete::delete_update(),
+                soft_delete::update_options(options.clone()).as_ref(),
+                false,
+            )
+        } else {
+            explain::delete_command(collection.name(), &filter, options.as_ref(), false)
+        };
+        return explain_response(
+            DELETE_ONE_PATH,
+            &namespace,
+            format,
+            &collection,
+            command,
+            verbosity,
+        )
+        .await;
+    }
+    if dry_run {
+        return dry_run_response(
+            DELETE_ONE_PATH,
+            &namespace,
+            format,
+            &collection,
+            filter,
+            true,
+        )
+        .await;
+    }
+    if let Some(expected) = &preconditions.if_match {
+        filter = pin_version(&state, &namespace, &collection, filter, expected)
+            .await
+            .map_err(|err| log_request_failure(DELETE_ONE_PATH, Some(&namespace), err))?;
+    }
+    let versioned = state
+        .history(
+            &namespace,
+            cluster.0.as_deref(),
+            &collection,
+            caller.as_deref(),
+        )
+        .await;
+    let audit = state
+        .begin_audit(&context, "delete_one", &namespace, cluster.0.as_deref())
         .await
-        .map_err(|err| {
-            log_request_failure(DELETE_ONE_PATH, Some(&namespace), map_driver_error(err))
-        })?;
-    if result.deleted_count == 0 {
+        .map_err(|err| log_request_failure(DELETE_ONE_PATH, Some(&namespace), err))?
+        .filter(&filter);
+    let result = if soft {
+        let update = soft_delete::delete_update();
+        let options = soft_delete::update_options(options);
+        match &versioned {
+            Some(versioned) => versioned.update(filter, update, options, false).await,
+            None => collection.update_one(filter, update, options).await,
+        }
+        .map(|result| result.modified_count)
+    } else {
+        match &versioned {
+            Some(versioned) => versioned.delete(filter, options, false).await,
+            None => collection.delete_one(filter, options).await,
+        }
+        .map(|result| result.deleted_count)
+    };
+    // Invalidate even on failure; a failed write may have partly applied
+    state.invalidate_cache(&namespace, cluster.0.as_deref());
+    let result = result.map_err(|err| {
+        log_request_failure(
+            DELETE_ONE_PATH,
+            Some(&namespace),
+            audit.failed(map_driver_error(err)),
+        )
+    })?;
+    let response = DeleteResponse {
+        deleted_count: result,
+    };
+    audit.succeeded(&response);
+    if response.deleted_count == 0 {
         return Err(log_request_failure(
             DELETE_ONE_PATH,
             Some(&namespace),
-            ApiError::not_found("no documents matched the filter"),
+            no_match(&preconditions),
         ));
     }
-    let response = Json(DeleteResponse {
-        deleted_count: result.deleted_count,
-    });
     log_namespace_success(
         DELETE_ONE_PATH,
         &namespace,
         StatusCode::OK,
         Some(response.deleted_count),
     );
-    Ok(response)
+    Ok(Reply::new(format, response).into_response())
 }
 
 #[instrument(skip_all)]
 async fn delete_many(
     State(state): State<AppState>,
-    Json(payload): Json<DeleteRequest>,
-) -> ApiResult<Json<DeleteResponse>> {
+    format: BodyFormat,
+    cluster: RequestedCluster,
+    caller: Option<Extension<Identity>>,
+    context: AuditContext,
+    Payload(payload): Payload<DeleteRequest>,
+) -> ApiResult<Response> {
     let DeleteRequest {
         namespace,
-        filter,
+        mut filter,
         options,
+        confirm_all,
+        explain,
+        dry_run,
     } = payload;
     log_namespace_received(DELETE_MANY_PATH, &namespace, None);
-    let collection = collection_from_state(&state, &namespace)
+    let policy = state.query_policy();
+    let fields = state.field_policy(&namespace, caller.as_deref());
+    policy
+        .check_bounded("delete_many", &filter, confirm_all)
+        .and_then(|()| policy.check_filter(&filter))
+        .and_then(|()| fields.check_filter(&filter))
+        .map_err(|err| log_request_failure(DELETE_MANY_PATH, Some(&namespace), err))?;
+    state
+        .encryption(&namespace)
+        .encrypt_filter(&mut filter)
+        .map_err(|err| log_request_failure(DELETE_MANY_PATH, Some(&namespace), err))?;
+    let soft = state.soft_delete(&namespace);
+    if soft {
+        filter = soft_delete::exclude_deleted(filter);
+    }
+    let (collection, _in_flight) = collection_from_state(&state, &namespace, &cluster)
         .map_err(|err| log_request_failure(DELETE_MANY_PATH, Some(&namespace), err))?;
-    let result = collection
-        .delete_many(filter, options)
+    if let Some(verbosity) = explain {
+        let command = if soft {
+            explain::update_command(
+                collection.name(),
+                &filter,
+                &soft_delete::delete_update(),
+                soft_delete::update_options(options.clone()).as_ref(),
+                true,
+            )
+        } else {
+            explain::delete_command(collection.name(), &filter, options.as_ref(), true)
+        };
+        return explain_response(
+            DELETE_MANY_PATH,
+            &namespace,
+            format,
+            &collection,
+            command,
+            verbosity,
+        )
+        .await;
+    }
+    if dry_run {
+        return dry_run_response(
+            DELETE_MANY_PATH,
+            &namespace,
+            format,
+            &collection,
+            filter,
+            false,
+        )
+        .await;
+    }
+    let versioned = state
+        .history(
+            &namespace,
+            cluster.0.as_deref(),
+            &collection,
+            caller.as_deref(),
+        )
+        .await;
+    let audit = state
+        .begin_audit(&context, "delete_many", &namespace, cluster.0.as_deref())
         .await
-        .map_err(|err| {
-            log_request_failure(DELETE_MANY_PATH, Some(&namespace), map_driver_error(err))
-        })?;
-    let response = Json(DeleteResponse {
-        deleted_count: result.deleted_count,
-    });
+        .map_err(|err| log_request_failure(DELETE_MANY_PATH, Some(&namespace), err))?
+        .filter(&filter);
+    let result = if soft {
+        let update = soft_delete::delete_update();
+        let options = soft_delete::update_options(options);
+        match &versioned {
+            Some(versioned) => versioned.update(filter, update, options, true).await,
+            None => collection.update_many(filter, update, options).await,
+        }
+        .map(|result| result.modified_count)
+    } else {
+        match &versioned {
+            Some(versioned) => versioned.delete(filter, options, true).await,
+            None => collection.delete_many(filter, options).await,
+        }
+        .map(|result| result.deleted_count)
+    };
+    // Invalidate even on failure; a failed write may have partly applied
+    state.invalidate_cache(&namespace, cluster.0.as_deref());
+    let result = result.map_err(|err| {
+        log_request_failure(
+            DELETE_MANY_PATH,
+            Some(&namespace),
+            audit.failed(map_driver_error(err)),
+        )
+    })?;
+    let response = DeleteResponse {
+        deleted_count: result,
+    };
+    audit.succeeded(&response);
     log_namespace_success(
         DELETE_MANY_PATH,
         &namespace,
         StatusCode::OK,
         Some(response.deleted_count),
     );
-    Ok(response)
+    Ok(Reply::new(format, response).into_response())
 }
 
 #[instrument(skip_all)]
-async fn list_collections(
+async fn restore(
     State(state): State<AppState>,
-    Query(query): Query<CollectionQuery>,
-) -> ApiResult<Json<CollectionsResponse>> {
-    tracing::info!(
-        target = "http",
-        endpoint = LIST_COLLECTIONS_PATH,
-        database = %query.database.trim(),
-        "received request"
-    );
-    if query.database.trim().is_empty() {
-        return Err(log_request_failure(
-            LIST_COLLECTIONS_PATH,
-            None,
-            ApiError::validation("database must be provided"),
-        ));
-    }
-    let names = state
-        .client()
-        .database(&query.database)
-        .list_collection_names(None)
+    format: BodyFormat,
+    cluster: RequestedCluster,
+    caller: Option<Extension<Identity>>,
+    context: AuditContext,
+    Payload(payload): Payload<RestoreRequest>,
+) -> ApiResult<Reply<RestoreResponse>> {
+    let RestoreRequest {
+        namespace,
+        mut filter,
+        confirm_all,
+    } = payload;
+    log_namespace_received(RESTORE_PATH, &namespace, None);
+    let policy = state.query_policy();
+    let fields = state.field_policy(&namespace, caller.as_deref());
+    policy
+        .check_bounded("restore", &filter, confirm_all)
+        .and_then(|()| policy.check_filter(&filter))
+        .and_then(|()| fields.check_filter(&filter))
+        .and_then(|()| {
+            if state.soft_delete(&namespace) {
+                Ok(())
+            } else {
+                Err(ApiError::validation(NOT_SOFT_DELETE))
+            }
+        })
+        .map_err(|err| log_request_failure(RESTORE_PATH, Some(&namespace), err))?;
+    state
+        .encryption(&namespace)
+        .encrypt_filter(&mut filter)
+        .map_err(|err| log_request_failure(RESTORE_PATH, Some(&namespace), err))?;
+    let filter = soft_delete::only_deleted(filter);
+    let (collection, _in_flight) = collection_from_state(&state, &namespace, &cluster)
+        .map_err(|err| log_request_failure(RESTORE_PATH, Some(&namespace), err))?;
+    let versioned = state
+        .history(
+            &namespace,
+            cluster.0.as_deref(),
+            &collection,
+            caller.as_deref(),
+        )
+        .await;
+    let update = soft_delete::restore_update();
+    let audit = state
+        .begin_audit(&context, "restore", &namespace, cluster.0.as_deref())
         .await
-        .map_err(|err| log_request_failure(LIST_COLLECTIONS_PATH, None, map_driver_error(err)))?;
-    let response = Json(CollectionsResponse { collections: names });
-    tracing::info!(
-        target = "http",
-        endpoint = LIST_COLLECTIONS_PATH,
-        database = %query.database.trim(),
-        status = %StatusCode::OK,
-        collections = response.collections.len() as u64,
-        "request completed"
+        .map_err(|err| log_request_failure(RESTORE_PATH, Some(&namespace), err))?
+        .filter(&filter)
+        .update(&update);
+    let result = match &versioned {
+        Some(versioned) => versioned.update(filter, update, None, true).await,
+        None => collection.update_many(filter, update, None).await,
+    };
+    // Invalidate even on failure; a failed write may have partly applied
+    state.invalidate_cache(&namespace, cluster.0.as_deref());
+    let result = result.map_err(|err| {
+        log_request_failure(
+            RESTORE_PATH,
+            Some(&namespace),
+            audit.failed(map_driver_error(err)),
+        )
+    })?;
+    let response = RestoreResponse {
+        restored_count: result.modified_count,
+    };
+    audit.succeeded(&response);
+    log_namespace_success(
+        RESTORE_PATH,
+        &namespace,
+        StatusCode::OK,
+        Some(response.restored_count),
     );
-    Ok(response)
+    Ok(Reply::new(format, response))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use axum::body::Body;
-    use axum::http::{Request, StatusCode};
-    use mongodb::Client;
-    use tower::ServiceExt;
+#[instrument(skip_all)]
+async fn purge(
+    State(state): State<AppState>,
+    format: BodyFormat,
+    cluster: RequestedCluster,
+    caller: Option<Extension<Identity>>,
+    context: AuditContext,
+    Payload(payload): Payload<PurgeRequest>,
+) -> ApiResult<Reply<DeleteResponse>> {
+    let PurgeRequest {
+        namespace,
+        mut filter,
+        older_than_days,
+    } = payload;
+    log_namespace_received(PURGE_PATH, &namespace, None);
+    let policy = state.query_policy();
+    let fields = state.field_policy(&namespace, caller.as_deref());
+    policy
+        .check_filter(&filter)
+        .and_then(|()| fields.check_filter(&filter))
+        .and_then(|()| {
+            if state.soft_delete(&namespace) {
+                Ok(())
+            } else {
+                Err(ApiError::validation(NOT_SOFT_DELETE))
+            }
+        })
+        .map_err(|err| log_request_failure(PURGE_PATH, Some(&namespace), err))?;
+    state
+        .encryption(&namespace)
+        .encrypt_filter(&mut filter)
+        .map_err(|err| log_request_failure(PURGE_PATH, Some(&namespace), err))?;
+    let age = Duration::from_secs(u64::from(older_than_days) * 86_400);
+    let filter = soft_delete::deleted_before(filter, age);
+    let (collection, _in_flight) = collection_from_state(&state, &namespace, &cluster)
+        .map_err(|err| log_request_failure(PURGE_PATH, Some(&namespace), err))?;
+    let versioned = state
+        .history(
+            &namespace,
+            cluster.0.as_deref(),
+            &collection,
+            caller.as_deref(),
+        )
+        .await;
+    let audit = state
+        .begin_audit(&context, "purge", &namespace, cluster.0.as_deref())
+        .await
+        .map_err(|err| log_request_failure(PURGE_PATH, Some(&namespace), err))?
+        .filter(&filter);
+    let result = match &versioned {
+        Some(versioned) => versioned.delete(filter, None, true).await,
+        None => collection.delete_many(filter, None).await,
+    };
+    // Invalidate even on failure; a failed write may have partly applied
+    state.invalidate_cache(&namespace, cluster.0.as_deref());
+    let result = result.map_err(|err| {
+        log_request_failure(
+            PURGE_PATH,
+            Some(&namespace),
+            audit.failed(map_driver_error(err)),
+        )
+    })?;
+    let response = DeleteResponse {
+        deleted_count: result.deleted_count,
+    };
+    audit.succeeded(&response);
+    log_namespace_success(
+        PURGE_PATH,
+        &namespace,
+        StatusCode::OK,
+        Some(response.deleted_count),
+    );
+    Ok(Reply::new(format, response))
+}
+
+#[instrument(skip_all)]
+async fn import_documents(
+    State(state): State<AppState>,
+    format: BodyFormat,
+    cluster: RequestedCluster,
+    caller: Option<Extension<Identity>>,
+    context: AuditContext,
+    Query(query): Query<ImportQuery>,
+    request: Request,
+) -> ApiResult<Reply<ImportResponse>> {
+    let namespace = query.namespace();
+    log_namespace_received(IMPORT_PATH, &namespace, None);
+    let (import_format, batch_size, mapping) = import_options(&query, request.headers())
+        .map_err(|err| log_request_failure(IMPORT_PATH, Some(&namespace), err))?;
+    let (collection, _in_flight) = collection_from_state(&state, &namespace, &cluster)
+        .map_err(|err| log_request_failure(IMPORT_PATH, Some(&namespace), err))?;
+    let upsert_keys = query.upsert_keys();
+    // Inserts never change existing documents, so only upserts keep history
+    let history = if upsert_keys.is_empty() {
+        None
+    } else {
+        state
+            .history(
+                &namespace,
+                cluster.0.as_deref(),
+                &collection,
+                caller.as_deref(),
+            )
+            .await
+    };
+    let importer = Importer {
+        cipher: state.encryption(&namespace),
+        stamps: state.stamps(&namespace, caller.as_deref()),
+        collection,
+        history,
+        upsert_keys,
+        limits: state.payload_limits(),
+    };
+    let body = request.into_body().into_data_stream();
+    let mut rows = Rows::new(body, import_format, mapping);
+    let mut summary = ImportResponse::default();
+    let mut pending = Vec::with_capacity(batch_size);
+    loop {
+        let row = rows
+            .next()
+            .await
+            .map_err(|err| log_request_failure(IMPORT_PATH, Some(&namespace), err))?;
+        let done = row.is_none();
+        if let Some(row) = row {
+            match row.document {
+                Ok(document) => pending.push((row.line, document)),
+                Err(err) => summary.reject(row.line, err),
+            }
+        }
+        if pending.len() >= batch_size || (done && !pending.is_empty()) {
+            let batch = importer.prepare(std::mem::take(&mut pending), &mut summary);
+            if !batch.is_empty() {
+                let audit = state
+                    .begin_audit(&context, "import", &namespace, cluster.0.as_deref())
+                    .await
+                    .map_err(|err| log_request_failure(IMPORT_PATH, Some(&namespace), err))?
+                    .documents(&batch.documents());
+                let result = importer.write(batch).await;
+                // Invalidate even on failure; a failed write may have partly applied
+                state.invalidate_cache(&namespace, cluster.0.as_deref());
+                let written = result.map_err(|err| {
+                    log_request_failure(
+                        IMPORT_PATH,
+                        Some(&namespace),
+                        audit.failed(map_driver_error(err)),
+                    )
+                })?;
+                audit.succeeded(&written);
+                summary.merge(written);
+            }
+        }
+        if done {
+            break;
+        }
+    }
+    log_namespace_success(
+        IMPORT_PATH,
+        &namespace,
+        StatusCode::OK,
+        Some(summary.inserted_count + summary.updated_count),
+    );
+    Ok(Reply::new(format, summary))
+}
+
+#[instrument(skip_all)]
+async fn export_documents(
+    State(state): State<AppState>,
+    cluster: RequestedCluster,
+    caller: Option<Extension<Identity>>,
+    Payload(payload): Payload<ExportRequest>,
+) -> ApiResult<Response> {
+    let ExportRequest {
+        namespace,
+        mut filter,
+        projection,
+        sort,
+        limit,
+        format,
+        columns,
+        compression,
+        include_deleted,
+    } = payload;
+    log_namespace_received(EXPORT_PATH, &namespace, None);
+    let policy = state.query_policy();
+    let fields = state.field_policy(&namespace, caller.as_deref());
+    let implied_limit = policy.implied_export_limit(limit);
+    let options = FindOptions::builder()
+        .projection(projection)
+        .sort(sort)
+        .limit(limit)
+        .build();
+    // Exports have their own limit and time caps, sized for bulk reads
+    let options = policy
+        .check_filter(&filter)
+        .and_then(|()| fields.check_filter(&filter))
+        .and_then(|()| policy.apply_export_options(Some(options)))
+        .and_then(|options| fields.apply_find_options(options))
+        .map_err(|err| log_request_failure(EXPORT_PATH, Some(&namespace), err))?;
+    let cipher = state.encryption(&namespace);
+    cipher
+        .encrypt_filter(&mut filter)
+        .map_err(|err| log_request_failure(EXPORT_PATH, Some(&namespace), err))?;
+    if !include_deleted && state.soft_delete(&namespace) {
+        filter = soft_delete::exclude_deleted(filter);
+    }
+    let (collection, in_flight) = collection_from_state(&state, &namespace, &cluster)
+        .map_err(|err| log_request_failure(EXPORT_PATH, Some(&namespace), err))?;
+    let cursor = collection
+        .find(filter, options)
+        .await
+        .map_err(|err| log_request_failure(EXPORT_PATH, Some(&namespace), map_driver_error(err)))?;
+    // Documents are streamed as they arrive, so the count is not known here.
+    log_namespace_success(EXPORT_PATH, &namespace, StatusCode::OK, None);
+    let documents = cursor
+        .map_err(std::io::Error::other)
+        .and_then(move |mut document| {
+            let result = cipher
+                .decrypt(&mut document)
+                .map(|()| {
+                    fields.redact(&mut document);
+                    document
+                })
+                .map_err(|err| std::io::Error::other(err.body().details.clone()));
+            future::ready(result)
+        });
+    let chunks = export::encode(documents, format, columns).inspect_err(|err| {
+        tracing::warn!(
+            target = "http",
+            endpoint = EXPORT_PATH,
+            error = %err,
+            "export stream aborted"
+        );
+    });
+    let mut filename = format!("{}.{}", collection.name(), format.extension());
+    let (content_type, body) = match compression {
+        Some(Compression::Gzip) => {
+            filename.push_str(".gz");
+            (
+                "application/gzip",
+                streaming_body(export::gzip(chunks), in_flight),
+            )
+        }
+        None => (format.content_type(), streaming_body(chunks, in_flight)),
+    };
+    let mut response = (StatusCode::OK, body).into_response();
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    if let Ok(value) = HeaderValue::from_str(&format!("attachment; filename=\"{filename}\"")) {
+        headers.insert(CONTENT_DISPOSITION, value);
+    }
+    if let Some(limit) = implied_limit {
+        headers.insert(LIMIT_APPLIED_HEADER, HeaderValue::from(limit));
+    }
+    Ok(response)
+}
+
+/// Body format, batch size and CSV column mapping of an import.
+fn import_options(
+    query: &ImportQuery,
+    headers: &HeaderMap,
+) -> Result<(ImportFormat, usize, ColumnMapping), ApiError> {
+    let import_format = query
+        .format
+        .or_else(|| {
+            headers
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(ImportFormat::from_content_type)
+        })
+        .ok_or(ApiError::validation(
+            "format must be `ndjson` or `csv`, in the query or as the Content-Type",
+        ))?;
+    let batch_size = query.batch_size.unwrap_or(import::DEFAULT_BATCH_SIZE);
+    if !(1..=import::MAX_BATCH_SIZE).contains(&batch_size) {
+        return Err(ApiError::validation(format!(
+            "batch_size must be between 1 and {}",
+            import::MAX_BATCH_SIZE
+        )));
+    }
+    let mapping = ColumnMapping::parse(query.rename.as_deref(), query.types.as_deref())?;
+    Ok((import_format, batch_size, mapping))
+}
+
+#[instrument(skip_all)]
+async fn upload_file(
+    State(state): State<AppState>,
+    format: BodyFormat,
+    cluster: RequestedCluster,
+    context: AuditContext,
+    headers: HeaderMap,
+    Query(query): Query<FileUploadQuery>,
+    body: Body,
+) -> ApiResult<Reply<FileUploadResponse>> {
+    let FileUploadQuery {
+        bucket: target,
+        filename,
+        metadata,
+    } = query;
+    let namespace = target.files_namespace();
+    log_namespace_received(FILES_UPLOAD_PATH, &namespace, None);
+    let metadata = metadata
+        .map(|metadata| files::parse_metadata(metadata.as_bytes()))
+        .transpose()
+        .map_err(|err| log_request_failure(FILES_UPLOAD_PATH, Some(&namespace), err))?;
+    let (collection, _in_flight) = collection_from_state(&state, &namespace, &cluster)
+        .map_err(|err| log_request_failure(FILES_UPLOAD_PATH, Some(&namespace), err))?;
+    let bucket = files::bucket(&collection, target.name());
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    let audit = state
+        .begin_audit(&context, "upload_file", &namespace, cluster.0.as_deref())
+        .await
+        .map_err(|err| log_request_failure(FILES_UPLOAD_PATH, Some(&namespace), err))?;
+    let contents = body.into_data_stream();
+    let result = match content_type.and_then(files::multipart_boundary) {
+        Some(boundary) => {
+            let form = Multipart::new(contents, boundary);
+            files::store_multipart(&bucket, form, filename, metadata).await
+        }
+        None => match filename {
+            Some(filename) => {
+                let content_type = content_type.unwrap_or(DEFAULT_CONTENT_TYPE).to_string();
+                let contents = contents.map_err(|err| {
+                    ApiError::validation(format!("failed to read request body: {err}"))
+                });
+                files::store(&bucket, filename, content_type, metadata, contents).await
+            }
+            None => Err(ApiError::validation("filename must be provided")),
+        },
+    };
+    let response = result.map_err(|err| {
+        log_request_failure(FILES_UPLOAD_PATH, Some(&namespace), audit.failed(err))
+    })?;
+    audit.succeeded(&response);
+    log_namespace_success(
+        FILES_UPLOAD_PATH,
+        &namespace,
+        StatusCode::OK,
+        Some(response.length),
+    );
+    Ok(Reply::new(format, response))
+}
+
+#[instrument(skip_all)]
+async fn download_file(
+    State(state): State<AppState>,
+    cluster: RequestedCluster,
+    headers: HeaderMap,
+    Query(query): Query<FileDownloadQuery>,
+) -> ApiResult<Response> {
+    let namespace = query.bucket.files_namespace();
+    log_namespace_received(FILES_DOWNLOAD_PATH, &namespace, None);
+    let (collection, in_flight) = collection_from_state(&state, &namespace, &cluster)
+        .map_err(|err| log_request_failure(FILES_DOWNLOAD_PATH, Some(&namespace), err))?;
+    let bucket = files::bucket(&collection, query.bucket.name());
+    let found = match bucket
+        .find(doc! { "_id": files::parse_id(&query.id) }, None)
+        .await
+    {
+        Ok(mut cursor) => cursor.try_next().await,
+        Err(err) => Err(err),
+    };
+    let file = found
+        .map_err(map_driver_error)
+        .and_then(|file| file.ok_or(ApiError::not_found("file not found")))
+        .map_err(|err| log_request_failure(FILES_DOWNLOAD_PATH, Some(&namespace), err))?;
+    let content_type = HeaderValue::from_str(files::content_type(&file))
+        .unwrap_or(HeaderValue::from_static(DEFAULT_CONTENT_TYPE));
+    let range = headers
+        .get(RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|range| ByteRange::parse(range, file.length));
+    let (status, length, contents) = match range {
+        Some(Err(err)) => {
+            let mut response =
+                log_request_failure(FILES_DOWNLOAD_PATH, Some(&namespace), err).into_response();
+            if let Ok(value) = HeaderValue::from_str(&format!("bytes */{}", file.length)) {
+                response.headers_mut().insert(CONTENT_RANGE, value);
+            }
+            return Ok(response);
+        }
+        // Streaming outlives the handler, so the stream holds the in-flight slot
+        Some(Ok(range)) => {
+            let contents = files::download_range(&collection, query.bucket.name(), &file, range)
+                .await
+                .map(|contents| streaming_body(contents, in_flight));
+            (StatusCode::PARTIAL_CONTENT, range.byte_count(), contents)
+        }
+        None => {
+            let contents = files::download(&bucket, &file)
+                .await
+                .map(|contents| streaming_body(contents, in_flight));
+            (StatusCode::OK, file.length, contents)
+        }
+    };
+    let contents = contents.map_err(|err| {
+        log_request_failure(FILES_DOWNLOAD_PATH, Some(&namespace), map_driver_error(err))
+    })?;
+    log_namespace_success(FILES_DOWNLOAD_PATH, &namespace, status, Some(length));
+    let mut response = (status, contents).into_response();
+    let response_headers = response.headers_mut();
+    response_headers.insert(CONTENT_TYPE, content_type);
+    response_headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+    response_headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if let Some(Ok(range)) = range {
+        if let Ok(value) = HeaderValue::from_str(&range.content_range(file.length)) {
+            response_headers.insert(CONTENT_RANGE, value);
+        }
+    }
+    Ok(response)
+}
+
+/// Response body for `contents` that holds the namespace's in-flight slot
+/// until it has been sent.
+fn streaming_body<S>(contents: S, in_flight: InFlightGuard) -> Body
+where
+    S: Stream<Item = std::io::Result<Bytes>> + Send + 'static,
+{
+    Body::from_stream(contents.map(move |chunk| {
+        let _held = &in_flight;
+        chunk
+    }))
+}
+
+#[instrument(skip_all)]
+async fn find_files(
+    State(state): State<AppState>,
+    format: BodyFormat,
+    cluster: RequestedCluster,
+    Payload(payload): Payload<FileFindRequest>,
+) -> ApiResult<Reply<FilesResponse>> {
+    let FileFindRequest {
+        bucket: target,
+        filter,
+        options,
+    } = payload;
+    let namespace = target.files_namespace();
+    log_namespace_received(FILES_FIND_PATH, &namespace, None);
+    state
+        .query_policy()
+        .check_filter(&filter)
+        .map_err(|err| log_request_failure(FILES_FIND_PATH, Some(&namespace), err))?;
+    let (collection, _in_flight) = collection_from_state(&state, &namespace, &cluster)
+        .map_err(|err| log_request_failure(FILES_FIND_PATH, Some(&namespace), err))?;
+    let found = files::bucket(&collection, target.name())
+        .find(filter, options)
+        .await;
+    let files = match found {
+        Ok(cursor) => cursor.try_collect().await,
+        Err(err) => Err(err),
+    }
+    .map_err(|err| log_request_failure(FILES_FIND_PATH, Some(&namespace), map_driver_error(err)))?;
+    let response = FilesResponse { files };
+    log_namespace_success(
+        FILES_FIND_PATH,
+        &namespace,
+        StatusCode::OK,
+        Some(response.files.len() as u64),
+    );
+    Ok(Reply::new(format, response))
+}
+
+#[instrument(skip_all)]
+async fn delete_file(
+    State(state): State<AppState>,
+    format: BodyFormat,
+    cluster: RequestedCluster,
+    context: AuditContext,
+    Payload(payload): Payload<FileDeleteRequest>,
+) -> ApiResult<Reply<DeleteResponse>> {
+    let FileDeleteRequest { bucket: target, id } = payload;
+    let namespace = target.files_namespace();
+    log_namespace_received(FILES_DELETE_PATH, &namespace, None);
+    let (collection, _in_flight) = collection_from_state(&state, &namespace, &cluster)
+        .map_err(|err| log_request_failure(FILES_DELETE_PATH, Some(&namespace), err))?;
+    let audit = state
+        .begin_audit(&context, "delete_file", &namespace, cluster.0.as_deref())
+        .await
+        .map_err(|err| log_request_failure(FILES_DELETE_PATH, Some(&namespace), err))?
+        .filter(&doc! { "_id": id.clone() });
+    files::bucket(&collection, target.name())
+        .delete(id)
+        .await
+        .map_err(|err| {
+            let err = if files::is_not_found(&err) {
+                ApiError::not_found("file not found")
+            } else {
+                map_driver_error(err)
+            };
+            log_request_failure(FILES_DELETE_PATH, Some(&namespace), audit.failed(err))
+        })?;
+    let response = DeleteResponse { deleted_count: 1 };
+    audit.succeeded(&response);
+    log_namespace_success(FILES_DELETE_PATH, &namespace, StatusCode::OK, Some(1));
+    Ok(Reply::new(format, response))
+}
+
+#[instrument(skip_all)]
+async fn document_history(
+    State(state): State<AppState>,
+    format: BodyFormat,
+    cluster: RequestedCluster,
+    caller: Option<Extension<Identity>>,
+    Payload(payload): Payload<HistoryRequest>,
+) -> ApiResult<Reply<HistoryResponse>> {
+    let HistoryRequest {
+        namespace,
+        id,
+        limit,
+    } = payload;
+    log_namespace_received(HISTORY_PATH, &namespace, None);
+    let (collection, _in_flight) = collection_from_state(&state, &namespace, &cluster)
+        .map_err(|err| log_request_failure(HISTORY_PATH, Some(&namespace), err))?;
+    let versioned = state
+        .history(
+            &namespace,
+            cluster.0.as_deref(),
+            &collection,
+            caller.as_deref(),
+        )
+        .await
+        .ok_or(ApiError::validation(NOT_VERSIONED))
+        .map_err(|err| log_request_failure(HISTORY_PATH, Some(&namespace), err))?;
+    let (current_version, mut versions) = versioned.versions(&id, limit).await.map_err(|err| {
+        log_request_failure(HISTORY_PATH, Some(&namespace), map_driver_error(err))
+    })?;
+    let cipher = state.encryption(&namespace);
+    let fields = state.field_policy(&namespace, caller.as_deref());
+    for entry in &mut versions {
+        if let Ok(document) = entry.get_document_mut("document") {
+            cipher
+                .decrypt(document)
+                .map_err(|err| log_request_failure(HISTORY_PATH, Some(&namespace), err))?;
+            fields.redact(document);
+        }
+    }
+    log_namespace_success(
+        HISTORY_PATH,
+        &namespace,
+        StatusCode::OK,
+        Some(versions.len() as u64),
+    );
+    Ok(Reply::new(
+        format,
+        HistoryResponse {
+            current_version,
+            versions,
+        },
+    ))
+}
+
+#[instrument(skip_all)]
+async fn restore_version(
+    State(state): State<AppState>,
+    format: BodyFormat,
+    cluster: RequestedCluster,
+    caller: Option<Extension<Identity>>,
+    context: AuditContext,
+    Payload(payload): Payload<RestoreVersionRequest>,
+) -> ApiResult<Reply<RestoreVersionResponse>> {
+    let RestoreVersionRequest {
+        namespace,
+        id,
+        version,
+    } = payload;
+    log_namespace_received(RESTORE_VERSION_PATH, &namespace, None);
+    let (collection, _in_flight) = collection_from_state(&state, &namespace, &cluster)
+        .map_err(|err| log_request_failure(RESTORE_VERSION_PATH, Some(&namespace), err))?;
+    let versioned = state
+        .history(
+            &namespace,
+            cluster.0.as_deref(),
+            &collection,
+            caller.as_deref(),
+        )
+        .await
+        .ok_or(ApiError::validation(NOT_VERSIONED))
+        .map_err(|err| log_request_failure(RESTORE_VERSION_PATH, Some(&namespace), err))?;
+    let audit = state
+        .begin_audit(
+            &context,
+            "restore_version",
+            &namespace,
+            cluster.0.as_deref(),
+        )
+        .await
+        .map_err(|err| log_request_failure(RESTORE_VERSION_PATH, Some(&namespace), err))?
+        .filter(&doc! { "_id": id.clone(), "version": version });
+    let result = versioned.restore(&id, version).await;
+    // Invalidate even on failure; a failed write may have partly applied
+    state.invalidate_cache(&namespace, cluster.0.as_deref());
+    let result = result.map_err(|err| {
+        log_request_failure(
+            RESTORE_VERSION_PATH,
+            Some(&namespace),
+            audit.failed(map_driver_error(err)),
+        )
+    })?;
+    let Some(next) = result else {
+        return Err(log_request_failure(
+            RESTORE_VERSION_PATH,
+            Some(&namespace),
+            audit.failed(ApiError::not_found("version not found")),
+        ));
+    };
+    let response = RestoreVersionResponse {
+        restored_version: version,
+        version: next,
+    };
+    audit.succeeded(&response);
+    log_namespace_success(RESTORE_VERSION_PATH, &namespace, StatusCode::OK, Some(1));
+    Ok(Reply::new(format, response))
+}
+
+/// Checks `If-Match` against the document `filter` selects now, then pins the
+/// filter to that version so a change made in between makes the write match
+/// nothing instead of silently overwriting it.
+async fn pin_version(
+    state: &AppState,
+    namespace: &NamespacePayload,
+    collection: &Collection<Document>,
+    filter: Document,
+    expected: &EntityTags,
+) -> Result<Document, ApiError> {
+    // A lagging secondary would report a version that is already gone
+    let primary = FindOneOptions::builder()
+        .selection_criteria(SelectionCriteria::ReadPreference(ReadPreference::Primary))
+        .build();
+    let stored = collection
+        .find_one(filter.clone(), primary)
+        .await
+        .map_err(map_driver_error)?
+        .ok_or_else(|| ApiError::precondition_failed("no documents matched the filter"))?;
+    let mut current = stored.clone();
+    state.encryption(namespace).decrypt(&mut current)?;
+    let versioning = state.versioning(namespace);
+    if !expected.matches_strong(&versioning.etag(&current)) {
+        return Err(ApiError::precondition_failed(DOCUMENT_CHANGED));
+    }
+    Ok(doc! { "$and": [filter, versioning.guard(&stored)] })
+}
+
+/// Reads the document `options` select together with its ETag. The tag is
+/// taken from the whole stored document, as in [`pin_version`], so under a
+/// projection the document is read in full first and then again, projected
+/// and pinned to the version that was tagged.
+async fn find_one_tagged(
+    collection: &Collection<Document>,
+    cipher: &FieldCipher,
+    versioning: &Versioning,
+    filter: Document,
+    options: Option<FindOneOptions>,
+) -> Result<Option<(Document, String)>, ApiError> {
+    let Some(projection) = options
+        .as_ref()
+        .and_then(|options| options.projection.clone())
+    else {
+        let Some(mut document) = collection
+            .find_one(filter, options)
+            .await
+            .map_err(map_driver_error)?
+        else {
+            return Ok(None);
+        };
+        cipher.decrypt(&mut document)?;
+        let etag = versioning.etag(&document);
+        return Ok(Some((document, etag)));
+    };
+    let mut full = options.unwrap_or_default();
+    full.projection = None;
+    // The version guard names the document, so ordering and index bounds no
+    // longer apply
+    let mut pinned = full.clone();
+    pinned.projection = Some(projection);
+    pinned.sort = None;
+    pinned.skip = None;
+    pinned.hint = None;
+    pinned.min = None;
+    pinned.max = None;
+    for _ in 0..TAGGED_READ_ATTEMPTS {
+        let Some(stored) = collection
+            .find_one(filter.clone(), full.clone())
+            .await
+            .map_err(map_driver_error)?
+        else {
+            return Ok(None);
+        };
+        let mut current = stored.clone();
+        cipher.decrypt(&mut current)?;
+        let etag = versioning.etag(&current);
+        let projected = collection
+            .find_one(versioning.guard(&stored), pinned.clone())
+            .await
+            .map_err(map_driver_error)?;
+        if let Some(mut document) = projected {
+            cipher.decrypt(&mut document)?;
+            return Ok(Some((document, etag)));
+        }
+    }
+    Err(ApiError::unavailable(
+        "document kept changing while it was read; retry the request",
+    ))
+}
+
+/// An upsert cannot honor `If-Match`: when the version changes it would
+/// insert a second document instead of failing.
+fn check_conditional_upsert(preconditions: &Preconditions, upsert: bool) -> Result<(), ApiError> {
+    if upsert && preconditions.if_match.is_some() {
+        return Err(ApiError::validation(
+            "`If-Match` cannot be combined with upsert",
+        ));
+    }
+    Ok(())
+}
+
+/// Error for a single-document write that matched nothing.
+fn no_match(preconditions: &Preconditions) -> ApiError {
+    if preconditions.if_match.is_some() {
+        // The version checked by `pin_version` was changed or removed since
+        ApiError::precondition_failed(DOCUMENT_CHANGED)
+    } else {
+        ApiError::not_found("no documents matched the filter")
+    }
+}
+
+async fn explain_response(
+    endpoint: &str,
+    namespace: &NamespacePayload,
+    format: BodyFormat,
+    collection: &Collection<Document>,
+    command: Document,
+    verbosity: ExplainVerbosity,
+) -> ApiResult<Response> {
+    let plan = explain::run(collection, command, verbosity)
+        .await
+        .map_err(|err| log_request_failure(endpoint, Some(namespace), map_driver_error(err)))?;
+    log_namespace_success(endpoint, namespace, StatusCode::OK, None);
+    Ok(Reply::new(format, ExplainResponse { plan }).into_response())
+}
+
+/// Counts what a write would match, capped at one for single-document writes.
+async fn dry_run_response(
+    endpoint: &str,
+    namespace: &NamespacePayload,
+    format: BodyFormat,
+    collection: &Collection<Document>,
+    filter: Document,
+    single: bool,
+) -> ApiResult<Response> {
+    let options = single.then(|| CountOptions::builder().limit(1).build());
+    let matched_count = collection
+        .count_documents(filter, options)
+        .await
+        .map_err(|err| log_request_failure(endpoint, Some(namespace), map_driver_error(err)))?;
+    log_namespace_success(endpoint, namespace, StatusCode::OK, Some(matched_count));
+    Ok(Reply::new(format, DryRunResponse::new(matched_count)).into_response())
+}
+
+#[instrument(skip_all)]
+async fn aggregate(
+    State(state): State<AppState>,
+    format: BodyFormat,
+    cluster: RequestedCluster,
+    caller: Option<Extension<Identity>>,
+    Payload(payload): Payload<AggregateRequest>,
+) -> ApiResult<Response> {
+    let AggregateRequest {
+        namespace,
+        pipeline,
+        options,
+        explain,
+        include_deleted,
+    } = payload;
+    log_namespace_received(AGGREGATE_PATH, &namespace, Some(pipeline.len()));
+    let policy = state.query_policy();
+    let fields = state.field_policy(&namespace, caller.as_deref());
+    policy
+        .check_pipeline(&pipeline)
+        .and_then(|()| fields.check_pipeline(&pipeline))
+        .and_then(|()| state.check_joins(&namespace, &pipeline, caller.as_deref()))
+        .map_err(|err| log_request_failure(AGGREGATE_PATH, Some(&namespace), err))?;
+    let mut pipeline = fields.apply_pipeline(pipeline);
+    if !include_deleted && state.soft_delete(&namespace) {
+        soft_delete::exclude_deleted_stages(&mut pipeline);
+    }
+    let cipher = state.encryption(&namespace);
+    cipher
+        .encrypt_pipeline(&mut pipeline)
+        .map_err(|err| log_request_failure(AGGREGATE_PATH, Some(&namespace), err))?;
+    let (collection, in_flight) = collection_from_state(&state, &namespace, &cluster)
+        .map_err(|err| log_request_failure(AGGREGATE_PATH, Some(&namespace), err))?;
+    if let Some(verbosity) = explain {
+        let command = explain::aggregate_command(collection.name(), &pipeline, options.as_ref());
+        return explain_response(
+            AGGREGATE_PATH,
+            &namespace,
+            format,
+            &collection,
+            command,
+            verbosity,
+        )
+        .await;
+    }
+    let mut cursor = collection
+        .aggregate(pipeline, options)
+        .await
+        .map_err(|err| {
+            log_request_failure(AGGREGATE_PATH, Some(&namespace), map_driver_error(err))
+        })?;
+    if format == BodyFormat::Bson {
+        return Ok(stream_documents(
+            AGGREGATE_PATH,
+            &namespace,
+            cursor,
+            cipher,
+            fields,
+            in_flight,
+        ));
+    }
+    let mut documents = Vec::new();
+    while let Some(mut document) = cursor.try_next().await.map_err(|err| {
+        log_request_failure(AGGREGATE_PATH, Some(&namespace), map_driver_error(err))
+    })? {
+        cipher
+            .decrypt(&mut document)
+            .map_err(|err| log_request_failure(AGGREGATE_PATH, Some(&namespace), err))?;
+        fields.redact(&mut document);
+        documents.push(document);
+    }
+    let response = AggregateResponse { documents };
+    let count = response.documents.len() as u64;
+    log_namespace_success(AGGREGATE_PATH, &namespace, StatusCode::OK, Some(count));
+    Ok(Reply::new(format, response).into_response())
+}
+
+#[instrument(skip_all)]
+async fn count(
+    State(state): State<AppState>,
+    format: BodyFormat,
+    cluster: RequestedCluster,
+    caller: Option<Extension<Identity>>,
+    Payload(payload): Payload<CountRequest>,
+) -> ApiResult<Response> {
+    let CountRequest {
+        namespace,
+        mut filter,
+        options,
+        explain,
+        include_deleted,
+    } = payload;
+    log_namespace_received(COUNT_PATH, &namespace, None);
+    state
+        .query_policy()
+        .check_filter(&filter)
+        .and_then(|()| {
+            state
+                .field_policy(&namespace, caller.as_deref())
+                .check_filter(&filter)
+        })
+        .map_err(|err| log_request_failure(COUNT_PATH, Some(&namespace), err))?;
+    state
+        .encryption(&namespace)
+        .encrypt_filter(&mut filter)
+        .map_err(|err| log_request_failure(COUNT_PATH, Some(&namespace), err))?;
+    if !include_deleted && state.soft_delete(&namespace) {
+        filter = soft_delete::exclude_deleted(filter);
+    }
+    let (collection, _in_flight) = collection_from_state(&state, &namespace, &cluster)
+        .map_err(|err| log_request_failure(COUNT_PATH, Some(&namespace), err))?;
+    if let Some(verbosity) = explain {
+        let command = explain::count_command(collection.name(), &filter, options.as_ref());
+        return explain_response(
+            COUNT_PATH,
+            &namespace,
+            format,
+            &collection,
+            command,
+            verbosity,
+        )
+        .await;
+    }
+    let count = collection
+        .count_documents(filter, options)
+        .await
+        .map_err(|err| log_request_failure(COUNT_PATH, Some(&namespace), map_driver_error(err)))?;
+    log_namespace_success(COUNT_PATH, &namespace, StatusCode::OK, Some(count));
+    Ok(Reply::new(format, CountResponse { count }).into_response())
+}
+
+#[instrument(skip_all)]
+async fn list_collections(
+    State(state): State<AppState>,
+    format: BodyFormat,
+    cluster: RequestedCluster,
+    Query(query): Query<CollectionQuery>,
+) -> ApiResult<Reply<CollectionsResponse>> {
+    tracing::info!(
+        target = "http",
+        endpoint = LIST_COLLECTIONS_PATH,
+        database = %query.database.trim(),
+        "received request"
+    );
+    if query.database.trim().is_empty() {
+        return Err(log_request_failure(
+            LIST_COLLECTIONS_PATH,
+            None,
+            ApiError::validation("database must be provided"),
+        ));
+    }
+    let requested = query.cluster.as_deref().or(cluster.0.as_deref());
+    let names = state
+        .database(requested, &query.database)
+        .map_err(|err| log_request_failure(LIST_COLLECTIONS_PATH, None, err))?
+        .list_collection_names(None)
+        .await
+        .map_err(|err| log_request_failure(LIST_COLLECTIONS_PATH, None, map_driver_error(err)))?;
+    let response = CollectionsResponse { collections: names };
+    tracing::info!(
+        target = "http",
+        endpoint = LIST_COLLECTIONS_PATH,
+        database = %query.database.trim(),
+        status = %StatusCode::OK,
+        collections = response.collections.len() as u64,
+        "request completed"
+    );
+    Ok(Reply::new(format, response))
+}
+
+#[instrument(skip_all)]
+async fn cache_stats(
+    State(state): State<AppState>,
+    format: BodyFormat,
+) -> Reply<CacheStatsResponse> {
+    Reply::new(
+        format,
+        CacheStatsResponse {
+            namespaces: state.cache_stats(),
+        },
+    )
+}
+
+async fn audit_stats(State(state): State<AppState>, format: BodyFormat) -> Reply<AuditStats> {
+    Reply::new(format, state.auditor().stats())
+}
+
+/// Runs a GraphQL request. Queries count against the read rate limit, any
+/// mutation against the write limit.
+async fn graphql_query(
+    State(state): State<AppState>,
+    cluster: RequestedCluster,
+    caller: Option<Extension<Identity>>,
+    context: AuditContext,
+    request: Request,
+) -> ApiResult<Response> {
+    let client = client_key(&request);
+    let payload = match Payload::<async_graphql::Request>::from_request(request, &state).await {
+        Ok(Payload(payload)) => payload,
+        Err(rejection) => return Ok(rejection),
+    };
+    state
+        .check_rate_limit(&client, graphql::access_kind(&payload.query))
+        .map_err(|err| log_request_failure(GRAPHQL_PATH, None, err))?;
+    tracing::info!(
+        target = "http",
+        endpoint = GRAPHQL_PATH,
+        operation = payload.operation_name.as_deref().unwrap_or_default(),
+        "received request"
+    );
+    let scope = RequestScope {
+        state: state.clone(),
+        identity: caller.map(|Extension(identity)| identity),
+        context,
+        cluster: cluster.0,
+    };
+    let response = state
+        .graphql()
+        .execute(scope, payload)
+        .await
+        .map_err(|err| log_request_failure(GRAPHQL_PATH, None, err))?;
+    for error in &response.errors {
+        tracing::warn!(
+            target = "http",
+            endpoint = GRAPHQL_PATH,
+            path = ?error.path,
+            error = %error.message,
+            "graphql field failed"
+        );
+    }
+    tracing::info!(
+        target = "http",
+        endpoint = GRAPHQL_PATH,
+        status = %StatusCode::OK,
+        errors = response.errors.len() as u64,
+        "request completed"
+    );
+    Ok(Json(response).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{test_config, Config};
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use mongodb::Client;
+    use tower::ServiceExt;
 
     fn namespace(database: &str, collection: &str) -> NamespacePayload {
         NamespacePayload {
             database: database.into(),
             collection: collection.into(),
+            cluster: None,
+            consistency: Default::default(),
         }
     }
 
@@ -531,29 +2624,45 @@ mod tests {
     }
 
     async fn test_state() -> AppState {
+        test_state_with_payload(Default::default()).await
+    }
+
+    async fn test_state_with_payload(payload: crate::config::PayloadConfig) -> AppState {
         let client = Client::with_uri_str("mongodb://localhost:27017")
             .await
             .expect("client");
-        let config = crate::config::Config {
-            mongodb_uri: "mongodb://localhost:27017".into(),
-            default_database: None,
-            default_collection: None,
-            pool_min_size: None,
-            pool_max_size: None,
-            connect_timeout: None,
-            server_selection_timeout: None,
-            log_level: None,
-            bind_address: "127.0.0.1:3000".into(),
+        let config = Config {
+            payload,
+            ..test_config()
         };
         AppState::new(client, &config)
     }
 
+    #[tokio::test]
+    async fn responses_count_as_in_flight_until_their_body_is_sent() {
+        let state = test_state().await;
+        let request = Request::builder()
+            .uri(COUNT_PATH)
+            .method("POST")
+            .header("content-type", "application/json")
+            .header("x-mongodb-cluster", "analytics")
+            .body(Body::from(r#"{"database":"app","collection":"users"}"#))
+            .unwrap();
+        let response = router(state.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(state.drain().active(), 1);
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(state.drain().active(), 0);
+    }
+
     #[tokio::test]
     async fn collection_from_state_requires_database() {
         let state = test_state().await;
         let payload = namespace("   ", "users");
-        let err =
-            collection_from_state(&state, &payload).expect_err("expected missing database error");
+        let err = collection_from_state(&state, &payload, &RequestedCluster::default())
+            .expect_err("expected missing database error");
         assert_eq!(err.status(), StatusCode::BAD_REQUEST);
     }
 
@@ -561,7 +2670,9 @@ mod tests {
     async fn collection_from_state_returns_collection_handle() {
         let state = test_state().await;
         let payload = namespace("app", "users");
-        let collection = collection_from_state(&state, &payload).expect("collection handle");
+        let (collection, _in_flight) =
+            collection_from_state(&state, &payload, &RequestedCluster::default())
+                .expect("collection handle");
         assert_eq!(collection.name(), "users");
     }
 
@@ -587,6 +2698,697 @@ mod tests {
         assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
+    fn small_payload_limits() -> crate::config::PayloadConfig {
+        crate::config::PayloadConfig {
+            max_body_bytes: 64,
+            ..Default::default()
+        }
+    }
+
+    async fn error_details(response: Response) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn body_over_declared_limit_is_rejected() {
+        let app = router(test_state_with_payload(small_payload_limits()).await);
+        let payload = serde_json::json!({
+            "database": "app",
+            "collection": "users",
+            "document": { "bio": "x".repeat(100) }
+        })
+        .to_string();
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri(INSERT_ONE_PATH)
+                    .method("POST")
+                    .header("content-type", "application/json")
+                    .header("content-length", payload.len())
+                    .body(Body::from(payload))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = error_details(response).await;
+        assert_eq!(body["error"], "payload_too_large");
+        assert!(body["details"].as_str().unwrap().contains("64 bytes"));
+    }
+
+    #[tokio::test]
+    async fn streamed_body_past_limit_is_rejected() {
+        let app = router(test_state_with_payload(small_payload_limits()).await);
+        let chunks = [
+            "{\"database\": \"app\", \"collection\": \"users\", ",
+            "\"document\": {\"bio\": \"",
+            "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx",
+            "\"}}",
+        ];
+        let body = Body::from_stream(futures::stream::iter(chunks.map(Ok::<_, std::io::Error>)));
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri(INSERT_ONE_PATH)
+                    .method("POST")
+                    .header("content-type", "application/json")
+                    .body(body)
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn insert_many_rejects_too_many_documents() {
+        let app = router(
+            test_state_with_payload(crate::config::PayloadConfig {
+                max_insert_documents: Some(1),
+                ..Default::default()
+            })
+            .await,
+        );
+        let payload = serde_json::json!({
+            "database": "app",
+            "collection": "users",
+            "documents": [{ "name": "ada" }, { "name": "grace" }]
+        });
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri(INSERT_MANY_PATH)
+                    .method("POST")
+                    .header("content-type", "application/json")
+                    .body(Body::from(payload.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = error_details(response).await;
+        assert_eq!(body["error"], "validation_error");
+        assert!(body["details"].as_str().unwrap().contains("maximum of 1"));
+    }
+
+    #[tokio::test]
+    async fn if_match_cannot_be_combined_with_upsert() {
+        let app = router(test_state().await);
+        let payload = serde_json::json!({
+            "database": "app",
+            "collection": "users",
+            "filter": { "name": "ada" },
+            "replacement": { "name": "ada", "rev": 2 },
+            "options": { "upsert": true }
+        });
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri(REPLACE_ONE_PATH)
+                    .method("POST")
+                    .header("content-type", "application/json")
+                    .header("if-match", r#""0123""#)
+                    .body(Body::from(payload.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn history_requires_a_versioned_namespace() {
+        let app = router(test_state().await);
+        for (path, payload) in [
+            (
+                HISTORY_PATH,
+                serde_json::json!({ "database": "app", "collection": "users", "id": 1 }),
+            ),
+            (
+                RESTORE_VERSION_PATH,
+                serde_json::json!({
+                    "database": "app",
+                    "collection": "users",
+                    "id": 1,
+                    "version": 2
+                }),
+            ),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri(path)
+                        .method("POST")
+                        .header("content-type", "application/json")
+                        .body(Body::from(payload.to_string()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn restore_and_purge_require_soft_delete() {
+        let app = router(test_state().await);
+        for (path, payload) in [
+            (
+                RESTORE_PATH,
+                serde_json::json!({
+                    "database": "app",
+                    "collection": "users",
+                    "filter": { "name": "ada" }
+                }),
+            ),
+            (
+                PURGE_PATH,
+                serde_json::json!({
+                    "database": "app",
+                    "collection": "users",
+                    "older_than_days": 30
+                }),
+            ),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri(path)
+                        .method("POST")
+                        .header("content-type", "application/json")
+                        .body(Body::from(payload.to_string()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn insert_many_accepts_bson_body() {
+        let app = router(test_state().await);
+        let payload = mongodb::bson::to_vec(&mongodb::bson::doc! {
+            "database": "app",
+            "collection": "users",
+            "documents": []
+        })
+        .unwrap();
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/v1/documents/insert-many")
+                    .method("POST")
+                    .header("content-type", "application/bson")
+                    .body(Body::from(payload))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn write_rate_limit_returns_429_with_retry_after() {
+        let client = Client::with_uri_str("mongodb://localhost:27017")
+            .await
+            .expect("client");
+        let config = Config {
+            limits: crate::config::LimitsConfig {
+                write_per_second: Some(1),
+                ..Default::default()
+            },
+            ..test_config()
+        };
+        let app = router(AppState::new(client, &config));
+        let request = |api_key: &str| {
+            Request::builder()
+                .uri("/api/v1/documents/insert-many")
+                .method("POST")
+                .header("content-type", "application/json")
+                .header("x-api-key", api_key)
+                .body(Body::from(
+                    r#"{"database":"app","collection":"users","documents":[]}"#,
+                ))
+                .unwrap()
+        };
+
+        let first = app.clone().oneshot(request("batch-job")).await.unwrap();
+        assert_eq!(first.status(), StatusCode::BAD_REQUEST);
+        // Without authentication a fresh key does not get a fresh bucket
+        let second = app.oneshot(request("another-job")).await.unwrap();
+        assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(second.headers().get("retry-after").unwrap(), "1");
+        let body = axum::body::to_bytes(second.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(!String::from_utf8_lossy(&body).contains("job"));
+    }
+
+    #[tokio::test]
+    async fn configured_api_keys_require_authentication() {
+        let client = Client::with_uri_str("mongodb://localhost:27017")
+            .await
+            .expect("client");
+        let config = Config {
+            auth: crate::config::AuthConfig {
+                api_keys: vec![crate::config::ApiKeyConfig {
+                    key: "k-123".into(),
+                    identity: "batch-job".into(),
+                    roles: Vec::new(),
+                }],
+                client_certs: Vec::new(),
+            },
+            ..test_config()
+        };
+        let app = router(AppState::new(client, &config));
+        let request = |key: Option<&str>| {
+            let builder = Request::builder()
+                .uri("/api/v1/documents/insert-many")
+                .method("POST")
+                .header("content-type", "application/json");
+            let builder = match key {
+                Some(key) => builder.header("x-api-key", key),
+                None => builder,
+            };
+            builder
+                .body(Body::from(
+                    r#"{"database":"app","collection":"users","documents":[]}"#,
+                ))
+                .unwrap()
+        };
+
+        let missing = app.clone().oneshot(request(None)).await.unwrap();
+        assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+        let invalid = app.clone().oneshot(request(Some("nope"))).await.unwrap();
+        assert_eq!(invalid.status(), StatusCode::UNAUTHORIZED);
+        let valid = app.oneshot(request(Some("k-123"))).await.unwrap();
+        assert_eq!(valid.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn failed_authentication_is_rate_limited() {
+        let client = Client::with_uri_str("mongodb://localhost:27017")
+            .await
+            .expect("client");
+        let config = Config {
+            limits: crate::config::LimitsConfig {
+                write_per_second: Some(1),
+                write_burst: Some(2),
+                ..Default::default()
+            },
+            auth: crate::config::AuthConfig {
+                api_keys: vec![crate::config::ApiKeyConfig {
+                    key: "k-123".into(),
+                    identity: "batch-job".into(),
+                    roles: Vec::new(),
+                }],
+                client_certs: Vec::new(),
+            },
+            ..test_config()
+        };
+        let app = router(AppState::new(client, &config));
+        let guess = |key: &str| {
+            Request::builder()
+                .uri("/api/v1/documents/find-one")
+                .method("POST")
+                .header("content-type", "application/json")
+                .header("x-api-key", key)
+                .body(Body::from(r#"{"database":"app","collection":"users"}"#))
+                .unwrap()
+        };
+
+        for key in ["guess-1", "guess-2"] {
+            let response = app.clone().oneshot(guess(key)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        let limited = app.clone().oneshot(guess("guess-3")).await.unwrap();
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(limited.headers().contains_key("retry-after"));
+    }
+
+    #[tokio::test]
+    async fn field_policy_rejects_restricted_filters_by_role() {
+        // Requests that pass the policy fail fast on the unreachable server
+        let uri = "mongodb://localhost:27017/?serverSelectionTimeoutMS=100";
+        let client = Client::with_uri_str(uri).await.expect("client");
+        let api_key = |key: &str, roles: &[&str]| crate::config::ApiKeyConfig {
+            key: key.into(),
+            identity: key.into(),
+            roles: roles.iter().map(|role| role.to_string()).collect(),
+        };
+        let policies: Vec<crate::config::FieldPolicyConfig> =
+            serde_json::from_value(serde_json::json!([
+                { "namespace": "hr.employees", "roles": ["pii-reader"] },
+                { "namespace": "hr.*", "mask": [{ "field": "ssn", "keep_last": 4 }] },
+            ]))
+            .unwrap();
+        let config = crate::config::Config {
+            mongodb_uri: uri.into(),
+            auth: crate::config::AuthConfig {
+                api_keys: vec![
+                    api_key("clerk", &["reader"]),
+                    api_key("hr", &["pii-reader"]),
+                ],
+                client_certs: Vec::new(),
+            },
+            field_policies: policies,
+            ..Default::default()
+        };
+        let app = router(AppState::new(client, &config));
+        let request = |key: &str| {
+            Request::builder()
+                .uri("/api/v1/documents/count")
+                .method("POST")
+                .header("content-type", "application/json")
+                .header("x-api-key", key)
+                .body(Body::from(
+                    r#"{"database":"hr","collection":"employees","filter":{"ssn":"123-45-6789"}}"#,
+                ))
+                .unwrap()
+        };
+
+        let clerk = app.clone().oneshot(request("clerk")).await.unwrap();
+        assert_eq!(clerk.status(), StatusCode::FORBIDDEN);
+        let hr = app.oneshot(request("hr")).await.unwrap();
+        assert_eq!(hr.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn cache_counts_lookups_and_writes_invalidate() {
+        let uri = "mongodb://127.0.0.1:1/?serverSelectionTimeoutMS=100";
+        let client = Client::with_uri_str(uri).await.expect("client");
+        let caches = serde_json::from_value(serde_json::json!([
+            { "namespace": "catalog.products", "ttl_ms": 60000 },
+        ]))
+        .unwrap();
+        let config = crate::config::Config {
+            mongodb_uri: uri.into(),
+            caches,
+            ..Default::default()
+        };
+        let app = router(AppState::new(client, &config));
+        let post = |path: &str, cache_control: Option<&str>| {
+            let mut builder = Request::builder()
+                .uri(path)
+                .method("POST")
+                .header("content-type", "application/json");
+            if let Some(value) = cache_control {
+                builder = builder.header("cache-control", value);
+            }
+            builder
+                .body(Body::from(
+                    r#"{"database":"catalog","collection":"products","filter":{},"document":{}}"#,
+                ))
+                .unwrap()
+        };
+        let stats = |app: Router| async move {
+            let response = app
+                .oneshot(
+                    Request::builder()
+                        .uri(CACHE_STATS_PATH)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let value: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            value["namespaces"][0].clone()
+        };
+
+        let miss = app
+            .clone()
+            .oneshot(post(FIND_MANY_PATH, None))
+            .await
+            .unwrap();
+        assert_eq!(miss.status(), StatusCode::BAD_GATEWAY);
+        // `no-cache` skips the lookup entirely
+        let bypass = app
+            .clone()
+            .oneshot(post(FIND_ONE_PATH, Some("no-cache")))
+            .await
+            .unwrap();
+        assert_eq!(bypass.status(), StatusCode::BAD_GATEWAY);
+        let write = app
+            .clone()
+            .oneshot(post(INSERT_ONE_PATH, None))
+            .await
+            .unwrap();
+        assert_eq!(write.status(), StatusCode::BAD_GATEWAY);
+
+        let namespace = stats(app).await;
+        assert_eq!(namespace["namespace"], "catalog.products");
+        assert_eq!(namespace["hits"], 0);
+        assert_eq!(namespace["misses"], 1);
+        assert_eq!(namespace["invalidations"], 1);
+    }
+
+    #[tokio::test]
+    async fn health_probes_skip_auth_and_report_unreachable_cluster() {
+        let uri = "mongodb://127.0.0.1:1/?serverSelectionTimeoutMS=100";
+        let client = Client::with_uri_str(uri).await.expect("client");
+        let config = crate::config::Config {
+            mongodb_uri: uri.into(),
+            auth: crate::config::AuthConfig {
+                api_keys: vec![crate::config::ApiKeyConfig {
+                    key: "k-123".into(),
+                    identity: "batch-job".into(),
+                    roles: Vec::new(),
+                }],
+                client_certs: Vec::new(),
+            },
+            ..Default::default()
+        };
+        let app = router(AppState::new(client, &config));
+        let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        let live = app.clone().oneshot(get("/healthz")).await.unwrap();
+        assert_eq!(live.status(), StatusCode::OK);
+
+        let ready = app.oneshot(get("/readyz?verbose")).await.unwrap();
+        assert_eq!(ready.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = axum::body::to_bytes(ready.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(value["status"], "unavailable");
+        assert_eq!(value["clusters"]["default"]["status"], "unavailable");
+        assert!(value["clusters"]["default"]["error"].is_string());
+    }
+
+    #[tokio::test]
+    async fn draining_rejects_requests_and_fails_readiness() {
+        let state = test_state().await;
+        let app = router(state.clone());
+        state.drain().begin();
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/v1/documents/count")
+                    .method("POST")
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"database":"app","collection":"users"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(state.drain().active(), 0);
+
+        let ready = app
+            .oneshot(
+                Request::builder()
+                    .uri("/readyz")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(ready.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = axum::body::to_bytes(ready.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(value["status"], "shutting_down");
+    }
+
+    #[tokio::test]
+    async fn correlation_id_is_echoed_or_generated() {
+        let app = router(test_state().await);
+        let request = |correlation: Option<&str>| {
+            let mut builder = Request::builder()
+                .uri("/api/v1/documents/insert-many")
+                .method("POST")
+                .header("content-type", "application/json");
+            if let Some(correlation) = correlation {
+                builder = builder.header(CORRELATION_ID_HEADER, correlation);
+            }
+            builder
+                .body(Body::from(
+                    r#"{"database":"app","collection":"users","documents":[]}"#,
+                ))
+                .unwrap()
+        };
+
+        let echoed = app.clone().oneshot(request(Some("req-42"))).await.unwrap();
+        assert_eq!(echoed.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(echoed.headers()[CORRELATION_ID_HEADER], "req-42");
+
+        // Malformed ids are replaced rather than propagated into the audit log
+        for correlation in [None, Some("has space")] {
+            let response = app.clone().oneshot(request(correlation)).await.unwrap();
+            let generated = response.headers()[CORRELATION_ID_HEADER].to_str().unwrap();
+            assert!(Uuid::parse_str(generated).is_ok(), "{generated}");
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_cluster_header_is_rejected() {
+        let app = router(test_state().await);
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/v1/documents/count")
+                    .method("POST")
+                    .header("content-type", "application/json")
+                    .header("x-mongodb-cluster", "analytics")
+                    .body(Body::from(r#"{"database":"app","collection":"users"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn delete_many_rejects_empty_filter_without_confirmation() {
+        let app = router(test_state().await);
+        let payload = serde_json::json!({
+            "database": "app",
+            "collection": "users",
+            "filter": {}
+        });
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/v1/documents/delete-many")
+                    .method("POST")
+                    .header("content-type", "application/json")
+                    .body(Body::from(payload.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn find_one_rejects_where_operator() {
+        let app = router(test_state().await);
+        let payload = serde_json::json!({
+            "database": "app",
+            "collection": "users",
+            "filter": { "$where": "sleep(10000) || true" }
+        });
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/v1/documents/find-one")
+                    .method("POST")
+                    .header("content-type", "application/json")
+                    .body(Body::from(payload.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "validation_error");
+        assert!(body["details"].as_str().unwrap().contains("filter.$where"));
+    }
+
+    #[tokio::test]
+    async fn graphql_applies_the_query_policy() {
+        let state = test_state().await;
+        let post = |app: Router, body: serde_json::Value| async move {
+            let response = app
+                .oneshot(
+                    Request::builder()
+                        .uri(GRAPHQL_PATH)
+                        .method("POST")
+                        .header("content-type", "application/json")
+                        .body(Body::from(body.to_string()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (
+                status,
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            )
+        };
+        let query = serde_json::json!({ "query": "{ orderList { _id } }" });
+        let (status, body) = post(router(state.clone()), query).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"], "not_found");
+
+        let mut config = (*state.config()).clone();
+        config.graphql = vec![crate::config::GraphqlTypeConfig {
+            name: "Order".into(),
+            namespace: "shop.orders".into(),
+            cluster: None,
+            fields: [("total".to_string(), "Float".to_string())].into(),
+            sample_size: 100,
+            references: Vec::new(),
+        }];
+        state.apply_config(config);
+        let (status, body) = post(
+            router(state.clone()),
+            serde_json::json!({
+                "query": "query($filter: JSON) { orderList(filter: $filter) { _id total } }",
+                "variables": { "filter": { "$where": "sleep(100)" } }
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["errors"][0]["extensions"]["code"], "validation_error");
+        assert!(body["errors"][0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("filter.$where"));
+
+        let (_, body) = post(
+            router(state),
+            serde_json::json!({
+                "query": "mutation { deleteOrder(filter: {}, many: true) { deleted_count } }"
+            }),
+        )
+        .await;
+        assert_eq!(body["errors"][0]["extensions"]["status"], 400);
+        assert!(body["errors"][0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("empty filter"));
+    }
+
     #[tokio::test]
     async fn namespace_fields_trims_whitespace() {
         let payload = namespace("  db  ", "  coll  ");
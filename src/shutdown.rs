@@ -0,0 +1,137 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::Notify;
+
+/// Tracks in-flight API requests and whether the gateway has started shutting
+/// down. Once draining, new requests are refused and readiness fails.
+#[derive(Debug, Default)]
+pub struct Drain {
+    draining: AtomicBool,
+    active: Arc<AtomicUsize>,
+    started: Notify,
+}
+
+/// Marks one request as in flight until dropped.
+#[derive(Debug)]
+pub struct DrainGuard {
+    active: Arc<AtomicUsize>,
+}
+
+impl Drop for DrainGuard {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl Drain {
+    /// Starts draining. Calling it again has no further effect.
+    pub fn begin(&self) {
+        if !self.draining.swap(true, Ordering::AcqRel) {
+            self.started.notify_waiters();
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Acquire)
+    }
+
+    /// Number of requests currently holding a [`DrainGuard`].
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Acquire)
+    }
+
+    /// Returns `None` once draining has begun.
+    pub fn enter(&self) -> Option<DrainGuard> {
+        if self.is_draining() {
+            return None;
+        }
+        self.active.fetch_add(1, Ordering::AcqRel);
+        Some(DrainGuard {
+            active: self.active.clone(),
+        })
+    }
+
+    /// Resolves once [`Drain::begin`] has been called.
+    pub async fn started(&self) {
+        loop {
+            let notified = self.started.notified();
+            tokio::pin!(notified);
+            // Register before checking the flag so a concurrent `begin` is not missed
+            notified.as_mut().enable();
+            if self.is_draining() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Resolves on SIGINT (Ctrl-C) or, on Unix, SIGTERM.
+pub async fn signal() {
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::warn!(error = %err, "cannot listen for SIGINT");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                tracing::warn!(error = %err, "cannot listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => tracing::info!("received SIGINT"),
+        _ = terminate => tracing::info!("received SIGTERM"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn counts_requests_until_draining() {
+        let drain = Drain::default();
+        let first = drain.enter().expect("accepting");
+        let _second = drain.enter().expect("accepting");
+        assert_eq!(drain.active(), 2);
+        drop(first);
+        assert_eq!(drain.active(), 1);
+
+        drain.begin();
+        assert!(drain.is_draining());
+        assert!(drain.enter().is_none());
+        assert_eq!(drain.active(), 1);
+    }
+
+    #[tokio::test]
+    async fn started_resolves_after_begin() {
+        let drain = Arc::new(Drain::default());
+        let waiter = tokio::spawn({
+            let drain = drain.clone();
+            async move { drain.started().await }
+        });
+        tokio::task::yield_now().await;
+        drain.begin();
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .expect("started resolves")
+            .unwrap();
+        // Already draining: resolves immediately
+        drain.started().await;
+    }
+}
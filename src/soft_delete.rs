@@ -0,0 +1,110 @@
+use mongodb::bson::{doc, DateTime, Document};
+use mongodb::options::{DeleteOptions, UpdateOptions};
+use std::time::Duration;
+
+use crate::fields::LEADING_STAGES;
+
+/// Set on documents deleted in soft-delete namespaces.
+pub const DELETED_AT_FIELD: &str = "deleted_at";
+
+/// Narrows `filter` to documents that have not been soft-deleted.
+pub fn exclude_deleted(filter: Document) -> Document {
+    and(filter, doc! { DELETED_AT_FIELD: { "$exists": false } })
+}
+
+/// Narrows `filter` to soft-deleted documents.
+pub fn only_deleted(filter: Document) -> Document {
+    and(filter, doc! { DELETED_AT_FIELD: { "$exists": true } })
+}
+
+/// Narrows `filter` to documents soft-deleted more than `age` ago.
+pub fn deleted_before(filter: Document, age: Duration) -> Document {
+    let age = i64::try_from(age.as_millis()).unwrap_or(i64::MAX);
+    let cutoff = DateTime::from_millis(DateTime::now().timestamp_millis().saturating_sub(age));
+    and(filter, doc! { DELETED_AT_FIELD: { "$lte": cutoff } })
+}
+
+/// Hides soft-deleted documents from `pipeline`, after any stage that has to
+/// come first and before field exclusions.
+pub fn exclude_deleted_stages(pipeline: &mut Vec<Document>) {
+    let position = usize::from(
+        pipeline
+            .first()
+            .and_then(|stage| stage.keys().next())
+            .is_some_and(|name| LEADING_STAGES.contains(&name.as_str())),
+    );
+    pipeline.insert(
+        position,
+        doc! { "$match": exclude_deleted(Document::new()) },
+    );
+}
+
+/// The update that soft-deletes documents, stamped with the server's clock.
+pub fn delete_update() -> Document {
+    doc! { "$currentDate": { DELETED_AT_FIELD: true } }
+}
+
+/// The update that brings soft-deleted documents back.
+pub fn restore_update() -> Document {
+    doc! { "$unset": { DELETED_AT_FIELD: "" } }
+}
+
+/// Carries delete options over to the update that replaces the delete.
+pub fn update_options(options: Option<DeleteOptions>) -> Option<UpdateOptions> {
+    options.map(|options| {
+        UpdateOptions::builder()
+            .collation(options.collation)
+            .hint(options.hint)
+            .write_concern(options.write_concern)
+            .let_vars(options.let_vars)
+            .comment(options.comment)
+            .build()
+    })
+}
+
+fn and(filter: Document, condition: Document) -> Document {
+    if filter.is_empty() {
+        condition
+    } else {
+        doc! { "$and": [filter, condition] }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_keep_the_caller_filter() {
+        assert_eq!(
+            exclude_deleted(Document::new()),
+            doc! { "deleted_at": { "$exists": false } }
+        );
+        assert_eq!(
+            only_deleted(doc! { "sku": "a1" }),
+            doc! { "$and": [{ "sku": "a1" }, { "deleted_at": { "$exists": true } }] }
+        );
+        let purge = deleted_before(Document::new(), Duration::from_secs(86_400));
+        let cutoff = purge
+            .get_document("deleted_at")
+            .and_then(|condition| condition.get_datetime("$lte"))
+            .unwrap();
+        assert!(cutoff.timestamp_millis() <= DateTime::now().timestamp_millis() - 86_400_000);
+    }
+
+    #[test]
+    fn pipeline_match_follows_leading_stages() {
+        let hidden = doc! { "$match": { "deleted_at": { "$exists": false } } };
+        let mut pipeline = vec![doc! { "$group": { "_id": "$sku" } }];
+        exclude_deleted_stages(&mut pipeline);
+        assert_eq!(pipeline[0], hidden);
+
+        let mut pipeline = vec![doc! { "$geoNear": { "near": [0, 0] } }];
+        exclude_deleted_stages(&mut pipeline);
+        assert_eq!(pipeline[1], hidden);
+
+        let mut pipeline = Vec::new();
+        exclude_deleted_stages(&mut pipeline);
+        assert_eq!(pipeline, vec![hidden]);
+    }
+}
@@ -0,0 +1,298 @@
+use mongodb::bson::{doc, Bson, DateTime, Document};
+use mongodb::options::{ReplaceOptions, UpdateOptions};
+use std::sync::Arc;
+
+use crate::encryption::FieldCipher;
+use crate::error::ApiError;
+
+pub const CREATED_AT_FIELD: &str = "createdAt";
+pub const CREATED_BY_FIELD: &str = "createdBy";
+pub const UPDATED_AT_FIELD: &str = "updatedAt";
+pub const UPDATED_BY_FIELD: &str = "updatedBy";
+const STAMP_FIELDS: [&str; 4] = [
+    CREATED_AT_FIELD,
+    CREATED_BY_FIELD,
+    UPDATED_AT_FIELD,
+    UPDATED_BY_FIELD,
+];
+
+/// Creation and modification stamps for writes to a namespace with
+/// `timestamps` enabled. The gateway owns the stamp fields: values sent by
+/// clients are dropped before the stamps are added.
+#[derive(Debug, Clone, Default)]
+pub struct Stamps {
+    actor: Option<Arc<str>>,
+}
+
+impl Stamps {
+    /// `actor` is the authenticated identity; without one, only the
+    /// timestamps are written.
+    pub fn new(actor: Option<Arc<str>>) -> Self {
+        Self { actor }
+    }
+
+    /// Stamps a document about to be inserted.
+    pub fn insert(&self, document: &mut Document) {
+        strip(document);
+        document.insert(CREATED_AT_FIELD, DateTime::now());
+        if let Some(actor) = self.actor() {
+            document.insert(CREATED_BY_FIELD, actor);
+        }
+    }
+
+    /// Adds `updatedAt` (server time) and `updatedBy` to an update, plus the
+    /// creation stamps through `$setOnInsert` in case it upserts.
+    pub fn update(&self, update: &mut Document) {
+        for (operator, fields) in update.iter_mut() {
+            if let Bson::Document(fields) = fields {
+                strip(fields);
+                if operator == "$rename" {
+                    strip_rename_targets(fields);
+                }
+            }
+        }
+        drop_empty_operators(update);
+        set(update, "$currentDate", UPDATED_AT_FIELD, true);
+        set(update, "$setOnInsert", CREATED_AT_FIELD, DateTime::now());
+        if let Some(actor) = self.actor() {
+            set(update, "$set", UPDATED_BY_FIELD, actor.clone());
+            set(update, "$setOnInsert", CREATED_BY_FIELD, actor);
+        }
+    }
+
+    /// Turns an encrypted replacement into an update pipeline that keeps the
+    /// creation stamps of the document it replaces and stamps the change with
+    /// server time. The actor is encrypted with `cipher` where its stamp
+    /// fields are.
+    pub fn replace(
+        &self,
+        replacement: &Document,
+        cipher: &FieldCipher,
+    ) -> Result<Vec<Document>, ApiError> {
+        let mut replacement = replacement.clone();
+        strip(&mut replacement);
+        let mut stamps = doc! {
+            CREATED_AT_FIELD: { "$ifNull": [format!("${CREATED_AT_FIELD}"), "$$NOW"] },
+            CREATED_BY_FIELD: format!("${CREATED_BY_FIELD}"),
+            UPDATED_AT_FIELD: "$$NOW",
+        };
+        if let Some(actor) = self.actor() {
+            let mut actors = doc! { CREATED_BY_FIELD: actor.clone(), UPDATED_BY_FIELD: actor };
+            cipher.encrypt_document(&mut actors)?;
+            let actor = |field| actors.get(field).cloned().unwrap_or(Bson::Null);
+            stamps.insert(
+                CREATED_BY_FIELD,
+                doc! { "$ifNull": [format!("${CREATED_BY_FIELD}"), { "$literal": actor(CREATED_BY_FIELD) }] },
+            );
+            stamps.insert(
+                UPDATED_BY_FIELD,
+                doc! { "$literal": actor(UPDATED_BY_FIELD) },
+            );
+        }
+        Ok(vec![doc! {
+            "$replaceWith": {
+                "$mergeObjects": [{ "_id": "$_id" }, { "$literal": replacement }, stamps]
+            }
+        }])
+    }
+
+    fn actor(&self) -> Option<Bson> {
+        self.actor
+            .as_deref()
+            .map(|actor| Bson::String(actor.to_string()))
+    }
+}
+
+/// Carries replace options over to the update pipeline that replaces it.
+pub fn update_options(options: Option<ReplaceOptions>) -> Option<UpdateOptions> {
+    options.map(|options| {
+        UpdateOptions::builder()
+            .bypass_document_validation(options.bypass_document_validation)
+            .upsert(options.upsert)
+            .collation(options.collation)
+            .hint(options.hint)
+            .write_concern(options.write_concern)
+            .let_vars(options.let_vars)
+            .comment(options.comment)
+            .build()
+    })
+}
+
+/// Drops stamp fields, and paths inside them, set by the client.
+fn strip(fields: &mut Document) {
+    let stamped: Vec<String> = fields
+        .keys()
+        .filter(|key| is_stamp_path(key))
+        .cloned()
+        .collect();
+    for key in stamped {
+        fields.remove(&key);
+    }
+}
+
+/// Drops `$rename`s that would move a client field onto a stamp.
+fn strip_rename_targets(renames: &mut Document) {
+    let stamped: Vec<String> = renames
+        .iter()
+        .filter(|(_, target)| matches!(target, Bson::String(target) if is_stamp_path(target)))
+        .map(|(source, _)| source.clone())
+        .collect();
+    for source in stamped {
+        renames.remove(&source);
+    }
+}
+
+fn is_stamp_path(path: &str) -> bool {
+    STAMP_FIELDS.iter().any(|field| {
+        path.strip_prefix(field)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+    })
+}
+
+/// Adds `field` to `operator`, keeping the fields the update already has there.
+fn set(update: &mut Document, operator: &str, field: &str, value: impl Into<Bson>) {
+    let mut fields = match update.remove(operator) {
+        Some(Bson::Document(fields)) => fields,
+        _ => Document::new(),
+    };
+    fields.insert(field, value);
+    update.insert(operator, fields);
+}
+
+/// Removes operators left empty once stamp fields were stripped, which older
+/// servers reject.
+fn drop_empty_operators(update: &mut Document) {
+    let empty: Vec<String> = update
+        .iter()
+        .filter(|(_, fields)| matches!(fields, Bson::Document(fields) if fields.is_empty()))
+        .map(|(name, _)| name.clone())
+        .collect();
+    for name in empty {
+        update.remove(&name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{EncryptedFieldConfig, EncryptionConfig, EncryptionMode};
+    use crate::encryption::Encryptor;
+    use base64::Engine;
+
+    fn stamps() -> Stamps {
+        Stamps::new(Some(Arc::from("ci")))
+    }
+
+    #[test]
+    fn insert_overrides_client_stamps() {
+        let mut document = doc! { "sku": "a1", "createdBy": "mallory", "createdAt.x": 1 };
+        stamps().insert(&mut document);
+        assert_eq!(document.get_str("createdBy"), Ok("ci"));
+        assert!(document.get_datetime("createdAt").is_ok());
+        assert!(!document.contains_key("createdAt.x"));
+
+        let mut anonymous = doc! { "sku": "a1" };
+        Stamps::default().insert(&mut anonymous);
+        assert!(!anonymous.contains_key("createdBy"));
+    }
+
+    #[test]
+    fn update_uses_current_date_and_set_on_insert() {
+        let mut update = doc! {
+            "$set": { "stock": 4, "updatedBy": "mallory" },
+            "$unset": { "createdAt": "" },
+        };
+        stamps().update(&mut update);
+        assert_eq!(
+            update.get_document("$set").unwrap(),
+            &doc! { "stock": 4, "updatedBy": "ci" }
+        );
+        assert!(!update.contains_key("$unset"));
+        assert_eq!(
+            update.get_document("$currentDate").unwrap(),
+            &doc! { "updatedAt": true }
+        );
+        let on_insert = update.get_document("$setOnInsert").unwrap();
+        assert!(on_insert.get_datetime("createdAt").is_ok());
+        assert_eq!(on_insert.get_str("createdBy"), Ok("ci"));
+    }
+
+    #[test]
+    fn replace_encrypts_actor_stamps() {
+        let key_file =
+            std::env::temp_dir().join(format!("hello_rust_stamp_keys_{}.json", std::process::id()));
+        let key = base64::engine::general_purpose::STANDARD.encode([7_u8; 32]);
+        let keys = serde_json::json!({ "active_key": "k1", "keys": { "k1": key } });
+        std::fs::write(&key_file, keys.to_string()).unwrap();
+        let encryptor = Encryptor::load(&EncryptionConfig {
+            key_file: Some(key_file),
+            fields: vec![EncryptedFieldConfig {
+                namespace: "app.items".into(),
+                field: UPDATED_BY_FIELD.into(),
+                mode: EncryptionMode::Randomized,
+            }],
+        })
+        .unwrap();
+
+        let pipeline = stamps()
+            .replace(&doc! { "sku": "a1" }, &encryptor.cipher("app", "items"))
+            .unwrap();
+        let merged = pipeline[0]
+            .get_document("$replaceWith")
+            .and_then(|stage| stage.get_array("$mergeObjects"))
+            .unwrap();
+        let stamps = merged[2].as_document().unwrap();
+        assert!(matches!(
+            stamps.get_document("updatedBy").unwrap().get("$literal"),
+            Some(Bson::Binary(_))
+        ));
+        assert_eq!(
+            stamps.get_document("createdBy").unwrap(),
+            &doc! { "$ifNull": ["$createdBy", { "$literal": "ci" }] }
+        );
+    }
+
+    #[test]
+    fn update_drops_renames_onto_stamps() {
+        let mut update = doc! {
+            "$rename": { "x": "createdBy", "y": "updatedAt.at", "z": "notes" },
+        };
+        stamps().update(&mut update);
+        assert_eq!(
+            update.get_document("$rename").unwrap(),
+            &doc! { "z": "notes" }
+        );
+
+        let mut update = doc! { "$rename": { "x": "updatedBy" } };
+        stamps().update(&mut update);
+        assert!(!update.contains_key("$rename"));
+    }
+
+    #[test]
+    fn replace_keeps_creation_stamps() {
+        let pipeline = stamps()
+            .replace(
+                &doc! { "sku": "a1", "updatedAt": 0 },
+                &Encryptor::disabled().cipher("app", "items"),
+            )
+            .unwrap();
+        let merged = pipeline[0]
+            .get_document("$replaceWith")
+            .and_then(|stage| stage.get_array("$mergeObjects"))
+            .unwrap();
+        assert_eq!(
+            merged[1],
+            Bson::Document(doc! { "$literal": { "sku": "a1" } })
+        );
+        let stamps = merged[2].as_document().unwrap();
+        assert_eq!(
+            stamps.get_document("createdAt").unwrap(),
+            &doc! { "$ifNull": ["$createdAt", "$$NOW"] }
+        );
+        assert_eq!(stamps.get_str("updatedAt"), Ok("$$NOW"));
+        assert_eq!(
+            stamps.get_document("updatedBy").unwrap(),
+            &doc! { "$literal": "ci" }
+        );
+    }
+}
@@ -1,13 +1,31 @@
 use dashmap::DashMap;
 use mongodb::bson::Document;
 use mongodb::Client;
-use mongodb::Collection;
+use mongodb::{Collection, Database};
+use std::collections::BTreeSet;
 use std::hash::{Hash, Hasher};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
+use crate::audit::{AuditContext, AuditEntry, Auditor};
+use crate::auth::{Authenticator, ClientCertificate, Identity};
+use crate::cache::{self, NamespaceCache, NamespaceCacheStats, ResponseCache};
+use crate::cluster::{Clusters, DEFAULT_CLUSTER};
 use crate::config::Config;
+use crate::consistency::{Consistency, ConsistencyRules};
+use crate::encryption::{Encryptor, FieldCipher};
 use crate::error::ApiError;
+use crate::etag::{EtagKey, Versioning};
+use crate::fields::{self, FieldPolicies, FieldPolicy};
+use crate::graphql::GraphqlSchema;
+use crate::history::{self, History};
+use crate::limits::{AccessKind, InFlightGuard, InFlightLimiter, RateLimiter};
 use crate::models::NamespacePayload;
+use crate::payload::PayloadLimits;
+use crate::policy::QueryPolicy;
+use crate::reload::{self, ConfigChange};
+use crate::shutdown::Drain;
+use crate::stamps::Stamps;
 
 #[derive(Clone)]
 pub struct AppState {
@@ -15,21 +33,120 @@ pub struct AppState {
 }
 
 struct AppStateInner {
-    client: Client,
+    clusters: Clusters,
+    collections: DashMap<NamespaceKey, Collection<Document>>,
+    settings: RwLock<Arc<Settings>>,
+    drain: Drain,
+    auditor: Auditor,
+    encryptor: Encryptor,
+    etag_key: EtagKey,
+    /// Whether each cluster supports transactions, once known.
+    transactions: DashMap<Arc<str>, bool>,
+}
+
+/// Startup-only components shared by every request.
+#[derive(Default)]
+pub struct Services {
+    pub auditor: Auditor,
+    pub encryptor: Encryptor,
+}
+
+/// Everything derived from the reloadable part of the config. Requests take a
+/// snapshot, so a reload never changes settings halfway through a request.
+struct Settings {
+    config: Arc<Config>,
     default_database: Option<Arc<str>>,
     default_collection: Option<Arc<str>>,
-    collections: DashMap<NamespaceKey, Collection<Document>>,
+    rate_limiter: Arc<RateLimiter>,
+    in_flight: Arc<InFlightLimiter>,
+    query_policy: Arc<QueryPolicy>,
+    payload_limits: Arc<PayloadLimits>,
+    authenticator: Arc<Authenticator>,
+    consistency: Arc<ConsistencyRules>,
+    field_policies: Arc<FieldPolicies>,
+    response_cache: Arc<ResponseCache>,
+    graphql: Arc<GraphqlSchema>,
+}
+
+impl Settings {
+    fn new(config: Config) -> Self {
+        Self {
+            default_database: config.default_database.as_deref().map(Arc::<str>::from),
+            default_collection: config.default_collection.as_deref().map(Arc::<str>::from),
+            rate_limiter: Arc::new(RateLimiter::new(&config.limits)),
+            in_flight: Arc::new(InFlightLimiter::new(&config.limits)),
+            query_policy: Arc::new(QueryPolicy::new(&config.query_policy)),
+            payload_limits: Arc::new(PayloadLimits::new(&config.payload)),
+            authenticator: Arc::new(Authenticator::new(&config.auth)),
+            consistency: Arc::new(ConsistencyRules::new(&config.namespaces)),
+            field_policies: Arc::new(FieldPolicies::new(&config.field_policies)),
+            response_cache: Arc::new(ResponseCache::new(&config.caches)),
+            graphql: Arc::new(GraphqlSchema::new(&config.graphql)),
+            config: Arc::new(config),
+        }
+    }
+
+    /// Builds settings for `config`, keeping components whose inputs did not
+    /// change so rate-limit buckets and in-flight counters survive a reload.
+    fn reloaded(&self, config: Config) -> Self {
+        let (old, new) = (&self.config, &config);
+        let same_rates = old.limits.read_per_second == new.limits.read_per_second
+            && old.limits.read_burst == new.limits.read_burst
+            && old.limits.write_per_second == new.limits.write_per_second
+            && old.limits.write_burst == new.limits.write_burst;
+        let same_in_flight =
+            old.limits.max_in_flight_per_namespace == new.limits.max_in_flight_per_namespace;
+        let same_policy = old.query_policy == new.query_policy;
+        let same_auth = old.auth == new.auth;
+        let same_namespaces = old.namespaces == new.namespaces;
+        let same_field_policies = old.field_policies == new.field_policies;
+        let same_caches = old.caches == new.caches;
+        // Inferred types leave out restricted fields
+        let same_graphql = old.graphql == new.graphql && same_field_policies;
+
+        let mut next = Self::new(config);
+        if same_rates {
+            next.rate_limiter = self.rate_limiter.clone();
+        }
+        next.in_flight = if same_in_flight {
+            self.in_flight.clone()
+        } else {
+            Arc::new(self.in_flight.resized(&next.config.limits))
+        };
+        if same_policy {
+            next.query_policy = self.query_policy.clone();
+        }
+        if same_auth {
+            next.authenticator = self.authenticator.clone();
+        }
+        if same_namespaces {
+            next.consistency = self.consistency.clone();
+        }
+        if same_field_policies {
+            next.field_policies = self.field_policies.clone();
+        }
+        if same_caches {
+            next.response_cache = self.response_cache.clone();
+        }
+        if same_graphql {
+            // Keeps the built schema, and with it any sampled fields
+            next.graphql = self.graphql.clone();
+        }
+        next
+    }
 }
 
 #[derive(Clone)]
 struct NamespaceKey {
+    cluster: Arc<str>,
     database: Arc<str>,
     collection: Arc<str>,
 }
 
 impl PartialEq for NamespaceKey {
     fn eq(&self, other: &Self) -> bool {
-        self.database.as_ref() == other.database.as_ref()
+        self.cluster.as_ref() == other.cluster.as_ref()
+            && self.database.as_ref() == other.database.as_ref()
             && self.collection.as_ref() == other.collection.as_ref()
     }
 }
@@ -38,14 +155,16 @@ impl Eq for NamespaceKey {}
 
 impl Hash for NamespaceKey {
     fn hash<H: Hasher>(&self, state: &mut H) {
+        self.cluster.as_ref().hash(state);
         self.database.as_ref().hash(state);
         self.collection.as_ref().hash(state);
     }
 }
 
 impl NamespaceKey {
-    fn new(database: Arc<str>, collection: Arc<str>) -> Self {
+    fn new(cluster: Arc<str>, database: Arc<str>, collection: Arc<str>) -> Self {
         Self {
+            cluster,
             database,
             collection,
         }
@@ -55,40 +174,407 @@ impl NamespaceKey {
         self.database.as_ref()
     }
 
+    /// `db.coll`, prefixed with the cluster name outside the default cluster.
+    fn qualified_name(&self) -> String {
+        if self.cluster.as_ref() == DEFAULT_CLUSTER {
+            format!("{}.{}", self.database(), self.collection())
+        } else {
+            format!("{}:{}.{}", self.cluster, self.database(), self.collection())
+        }
+    }
+
     fn collection(&self) -> &str {
         self.collection.as_ref()
     }
 }
 
 impl AppState {
+    /// State backed by a single `default` cluster.
     pub fn new(client: Client, config: &Config) -> Self {
+        Self::with_clusters(Clusters::new(client, config), config)
+    }
+
+    pub fn with_clusters(clusters: Clusters, config: &Config) -> Self {
+        Self::with_services(clusters, Services::default(), config)
+    }
+
+    pub fn with_services(clusters: Clusters, services: Services, config: &Config) -> Self {
         let inner = AppStateInner {
-            client,
-            default_database: config.default_database.as_deref().map(Arc::<str>::from),
-            default_collection: config.default_collection.as_deref().map(Arc::<str>::from),
+            clusters,
             collections: DashMap::new(),
+            settings: RwLock::new(Arc::new(Settings::new(config.clone()))),
+            drain: Drain::default(),
+            auditor: services.auditor,
+            encryptor: services.encryptor,
+            etag_key: EtagKey::new(config.etag_secret.as_deref()),
+            transactions: DashMap::new(),
         };
         Self {
             inner: Arc::new(inner),
         }
     }
 
+    pub fn clusters(&self) -> &Clusters {
+        &self.inner.clusters
+    }
+
+    /// Client for the `default` cluster.
     pub fn client(&self) -> &Client {
-        &self.inner.client
+        self.inner.clusters.default_client()
+    }
+
+    /// Database handle on the requested cluster, or the cluster routed to
+    /// `database` when none is requested.
+    pub fn database(&self, requested: Option<&str>, database: &str) -> Result<Database, ApiError> {
+        let (_, client) = self.inner.clusters.resolve(requested, database, None)?;
+        Ok(client.database(database))
+    }
+
+    /// In-flight request tracking used for graceful shutdown.
+    pub fn drain(&self) -> &Drain {
+        &self.inner.drain
+    }
+
+    pub fn auditor(&self) -> &Auditor {
+        &self.inner.auditor
+    }
+
+    /// Starts an audit record for a write to `namespace`, resolved the same way
+    /// as [`AppState::checkout_collection`]. Waits for or refuses the write
+    /// when the audit queue is full, per [`crate::config::AuditOverflow`].
+    pub async fn begin_audit(
+        &self,
+        context: &AuditContext,
+        operation: &'static str,
+        namespace: &NamespacePayload,
+        cluster: Option<&str>,
+    ) -> Result<AuditEntry, ApiError> {
+        if !self.inner.auditor.is_enabled() {
+            return Ok(AuditEntry::disabled());
+        }
+        match self.resolve_namespace(namespace, cluster) {
+            Ok(key) => {
+                self.inner
+                    .auditor
+                    .begin(
+                        context,
+                        operation,
+                        &key.cluster,
+                        key.database(),
+                        key.collection(),
+                    )
+                    .await
+            }
+            Err(_) => Ok(AuditEntry::disabled()),
+        }
+    }
+
+    /// The config currently in effect.
+    pub fn config(&self) -> Arc<Config> {
+        self.settings().config.clone()
+    }
+
+    pub fn query_policy(&self) -> Arc<QueryPolicy> {
+        self.settings().query_policy.clone()
+    }
+
+    pub fn payload_limits(&self) -> Arc<PayloadLimits> {
+        self.settings().payload_limits.clone()
+    }
+
+    /// Swaps in a new, already validated config and returns what changed.
+    ///
+    /// Settings that only take effect at startup keep their running values and
+    /// are reported with `restart_required` set.
+    pub fn apply_config(&self, mut config: Config) -> Vec<ConfigChange> {
+        let mut settings = self
+            .inner
+            .settings
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let changes = reload::diff(&settings.config, &config);
+        reload::retain_startup_settings(&settings.config, &mut config);
+        if *settings.config != config {
+            let namespaces_changed = settings.config.namespaces != config.namespaces;
+            *settings = Arc::new(settings.reloaded(config));
+            if namespaces_changed {
+                // Cached handles carry the old namespace defaults
+                self.inner.collections.clear();
+            }
+        }
+        changes
     }
 
     pub fn collection(
         &self,
         namespace: &NamespacePayload,
-    ) -> Result<mongodb::Collection<Document>, ApiError> {
-        let resolved = self.resolve_namespace(namespace)?;
-        Ok(self.inner.collection_for(&resolved))
+    ) -> Result<Collection<Document>, ApiError> {
+        let resolved = self.resolve_namespace(namespace, None)?;
+        self.inner.collection_for(
+            &resolved,
+            &self.settings().consistency,
+            &namespace.consistency,
+        )
     }
 
-    fn resolve_namespace(&self, namespace: &NamespacePayload) -> Result<NamespaceKey, ApiError> {
-        let database = match namespace.database.trim() {
-            "" => self
+    /// Resolves the collection and reserves an in-flight slot for its namespace.
+    ///
+    /// `cluster` is used when the payload does not name one itself. The slot is
+    /// held until the returned guard is dropped.
+    pub fn checkout_collection(
+        &self,
+        namespace: &NamespacePayload,
+        cluster: Option<&str>,
+    ) -> Result<(Collection<Document>, InFlightGuard), ApiError> {
+        let resolved = self.resolve_namespace(namespace, cluster)?;
+        // History entries keep documents as written, outside every field
+        // policy and cipher; they are only read through `History::versions`
+        if self
+            .settings()
+            .consistency
+            .is_history(resolved.database(), resolved.collection())
+        {
+            return Err(ApiError::forbidden(format!(
+                "`{}.{}` holds the history of a versioned namespace and cannot be used directly",
+                resolved.database(),
+                resolved.collection()
+            )));
+        }
+        let name = resolved.qualified_name();
+        let guard = self
+            .settings()
+            .in_flight
+            .try_acquire(&name)
+            .ok_or_else(|| {
+                ApiError::rate_limited(
+                    format!("too many in-flight requests for namespace `{name}`"),
+                    Duration::from_secs(1),
+                )
+            })?;
+        let collection = self.inner.collection_for(
+            &resolved,
+            &self.settings().consistency,
+            &namespace.consistency,
+        )?;
+        Ok((collection, guard))
+    }
+
+    /// Field restrictions for `identity` on `namespace`. A namespace that
+    /// cannot be resolved gets none; using it fails with a clearer error.
+    pub fn field_policy(
+        &self,
+        namespace: &NamespacePayload,
+        identity: Option<&Identity>,
+    ) -> Arc<FieldPolicy> {
+        let settings = self.settings();
+        match self.resolve_namespace(namespace, None) {
+            Ok(key) => settings
+                .field_policies
+                .resolve(key.database(), key.collection(), identity),
+            Err(_) => Arc::default(),
+        }
+    }
+
+    /// Fields restricted for any caller on `namespace`, kept out of views
+    /// shared by every caller such as the inferred GraphQL schema.
+    pub fn restricted_fields(&self, namespace: &NamespacePayload) -> BTreeSet<String> {
+        match self.resolve_namespace(namespace, None) {
+            Ok(key) => self
+                .settings()
+                .field_policies
+                .restricted_fields(key.database(), key.collection()),
+            Err(_) => BTreeSet::new(),
+        }
+    }
+
+    /// Rejects joins from `namespace` into namespaces on which `identity` has
+    /// restricted fields (see [`FieldPolicies::check_joins`]) or into the
+    /// history of a versioned namespace.
+    pub fn check_joins(
+        &self,
+        namespace: &NamespacePayload,
+        pipeline: &[Document],
+        identity: Option<&Identity>,
+    ) -> Result<(), ApiError> {
+        let Ok(key) = self.resolve_namespace(namespace, None) else {
+            return Ok(());
+        };
+        let settings = self.settings();
+        settings
+            .field_policies
+            .check_joins(key.database(), pipeline, identity)?;
+        for join in fields::joins(key.database(), pipeline) {
+            if settings
+                .consistency
+                .is_history(join.database, join.collection)
+            {
+                return Err(ApiError::forbidden(format!(
+                    "{}: `{}` reads `{}.{}`, the history of a versioned namespace",
+                    join.location, join.stage, join.database, join.collection
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// How document versions of `namespace` are identified in ETags.
+    pub fn versioning(&self, namespace: &NamespacePayload) -> Versioning {
+        let settings = self.settings();
+        let field = self
+            .resolve_namespace(namespace, None)
+            .ok()
+            .and_then(|key| {
+                settings
+                    .consistency
+                    .version_field(key.database(), key.collection())
+                    .map(str::to_string)
+            });
+        Versioning::new(field, self.inner.etag_key.clone())
+    }
+
+    /// Whether deletes in `namespace` only set `deleted_at`.
+    pub fn soft_delete(&self, namespace: &NamespacePayload) -> bool {
+        self.resolve_namespace(namespace, None).is_ok_and(|key| {
+            self.settings()
+                .consistency
+                .soft_delete(key.database(), key.collection())
+        })
+    }
+
+    /// Write stamps for `namespace` on behalf of `caller`, or `None` unless
+    /// its `[[namespaces]]` rule sets `timestamps`.
+    pub fn stamps(
+        &self,
+        namespace: &NamespacePayload,
+        caller: Option<&Identity>,
+    ) -> Option<Stamps> {
+        let key = self.resolve_namespace(namespace, None).ok()?;
+        self.settings()
+            .consistency
+            .timestamps(key.database(), key.collection())
+            .then(|| Stamps::new(caller.map(|identity| identity.name.clone())))
+    }
+
+    /// Versioned writer for `namespace` through `collection`, or `None` unless
+    /// its `[[namespaces]]` rule sets `versioned`.
+    pub async fn history(
+        &self,
+        namespace: &NamespacePayload,
+        cluster: Option<&str>,
+        collection: &Collection<Document>,
+        actor: Option<&Identity>,
+    ) -> Option<History> {
+        let key = self.resolve_namespace(namespace, cluster).ok()?;
+        let consistency = &self.settings().consistency;
+        if !consistency.versioned(key.database(), key.collection()) {
+            return None;
+        }
+        let max_documents = consistency.max_versioned_documents(key.database(), key.collection());
+        let transactions = match self.inner.transactions.get(&key.cluster) {
+            Some(known) => *known,
+            None => match history::supports_transactions(collection.client()).await {
+                Ok(supported) => {
+                    self.inner
+                        .transactions
+                        .insert(key.cluster.clone(), supported);
+                    supported
+                }
+                // The write itself will report the failure
+                Err(_) => false,
+            },
+        };
+        Some(History::new(
+            collection.clone(),
+            transactions,
+            actor.map(|identity| identity.name.clone()),
+            max_documents,
+        ))
+    }
+
+    /// Encrypted fields of `namespace`; decryption works for any namespace.
+    pub fn encryption(&self, namespace: &NamespacePayload) -> FieldCipher {
+        match self.resolve_namespace(namespace, None) {
+            Ok(key) => self
                 .inner
+                .encryptor
+                .cipher(key.database(), key.collection()),
+            Err(_) => self.inner.encryptor.cipher("", ""),
+        }
+    }
+
+    /// Response cache for reads of `namespace`, if one is configured. Requests
+    /// that override the namespace's read settings are not cached.
+    pub fn response_cache(
+        &self,
+        namespace: &NamespacePayload,
+        cluster: Option<&str>,
+    ) -> Option<Arc<NamespaceCache>> {
+        if !namespace.consistency.is_empty() {
+            return None;
+        }
+        let key = self.resolve_namespace(namespace, cluster).ok()?;
+        let name = key.qualified_name();
+        let cache =
+            self.settings()
+                .response_cache
+                .namespace(&name, key.database(), key.collection())?;
+        if cache.claim_watch() {
+            // The namespace was just resolved, so its cluster exists
+            if let Ok((_, client)) = self.inner.clusters.resolve(
+                Some(&key.cluster),
+                key.database(),
+                Some(key.collection()),
+            ) {
+                let collection = client.database(key.database()).collection(key.collection());
+                cache::watch(&cache, name, collection);
+            }
+        }
+        Some(cache)
+    }
+
+    /// Drops cached responses for `namespace` after the gateway wrote to it.
+    pub fn invalidate_cache(&self, namespace: &NamespacePayload, cluster: Option<&str>) {
+        if let Ok(key) = self.resolve_namespace(namespace, cluster) {
+            self.settings()
+                .response_cache
+                .invalidate(&key.qualified_name());
+        }
+    }
+
+    /// Schema served on `/graphql`.
+    pub fn graphql(&self) -> Arc<GraphqlSchema> {
+        self.settings().graphql.clone()
+    }
+
+    pub fn cache_stats(&self) -> Vec<NamespaceCacheStats> {
+        self.settings().response_cache.stats()
+    }
+
+    pub fn authenticate(
+        &self,
+        api_key: Option<&str>,
+        certificate: Option<&ClientCertificate>,
+    ) -> Result<Option<Identity>, ApiError> {
+        self.settings()
+            .authenticator
+            .authenticate(api_key, certificate)
+    }
+
+    pub fn check_rate_limit(&self, client: &str, kind: AccessKind) -> Result<(), ApiError> {
+        self.settings()
+            .rate_limiter
+            .check(client, kind)
+            .map_err(|retry_after| ApiError::rate_limited("rate limit exceeded", retry_after))
+    }
+
+    fn resolve_namespace(
+        &self,
+        namespace: &NamespacePayload,
+        cluster: Option<&str>,
+    ) -> Result<NamespaceKey, ApiError> {
+        let settings = self.settings();
+        let database = match namespace.database.trim() {
+            "" => settings
                 .default_database
                 .as_ref()
                 .cloned()
@@ -97,8 +583,7 @@ impl AppState {
         };
 
         let collection = match namespace.collection.trim() {
-            "" => self
-                .inner
+            "" => settings
                 .default_collection
                 .as_ref()
                 .cloned()
@@ -106,50 +591,74 @@ impl AppState {
             value => Arc::<str>::from(value.to_owned()),
         };
 
-        Ok(NamespaceKey::new(database, collection))
+        let requested = namespace.cluster.as_deref().or(cluster);
+        let (cluster, _) = self
+            .inner
+            .clusters
+            .resolve(requested, &database, Some(&collection))?;
+        Ok(NamespaceKey::new(cluster, database, collection))
+    }
+
+    fn settings(&self) -> Arc<Settings> {
+        self.inner
+            .settings
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
     }
 }
 
 impl AppStateInner {
-    fn collection_for(&self, namespace: &NamespaceKey) -> Collection<Document> {
-        if let Some(entry) = self.collections.get(namespace) {
-            return entry.clone();
+    /// Handles built from namespace defaults are cached; requests with their
+    /// own read/write settings get a fresh handle.
+    fn collection_for(
+        &self,
+        namespace: &NamespaceKey,
+        rules: &ConsistencyRules,
+        request: &Consistency,
+    ) -> Result<Collection<Document>, ApiError> {
+        let cacheable = request.is_empty();
+        if cacheable {
+            if let Some(entry) = self.collections.get(namespace) {
+                return Ok(entry.clone());
+            }
         }
 
-        let collection = self
-            .client
+        let options = rules.options(namespace.database(), namespace.collection(), request)?;
+        let (_, client) = self.clusters.resolve(
+            Some(&namespace.cluster),
+            namespace.database(),
+            Some(namespace.collection()),
+        )?;
+        let collection = client
             .database(namespace.database())
-            .collection::<Document>(namespace.collection());
-        self.collections
-            .insert(namespace.clone(), collection.clone());
-        collection
+            .collection_with_options::<Document>(namespace.collection(), options);
+        if cacheable {
+            self.collections
+                .insert(namespace.clone(), collection.clone());
+        }
+        Ok(collection)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::test_config;
+    use mongodb::bson::doc;
 
     #[tokio::test]
     async fn collection_requires_namespace_values() {
         let client = Client::with_uri_str("mongodb://localhost:27017")
             .await
             .expect("client");
-        let config = Config {
-            mongodb_uri: "mongodb://localhost:27017".into(),
-            default_database: None,
-            default_collection: None,
-            pool_min_size: None,
-            pool_max_size: None,
-            connect_timeout: None,
-            server_selection_timeout: None,
-            log_level: None,
-            bind_address: "127.0.0.1:3000".into(),
-        };
+        let config = test_config();
         let state = AppState::new(client, &config);
         let payload = NamespacePayload {
             database: "".into(),
             collection: "users".into(),
+            cluster: None,
+            consistency: Default::default(),
         };
         let err = state
             .collection(&payload)
@@ -163,20 +672,16 @@ mod tests {
             .await
             .expect("client");
         let config = Config {
-            mongodb_uri: "mongodb://localhost:27017".into(),
             default_database: Some("app".into()),
             default_collection: Some("users".into()),
-            pool_min_size: None,
-            pool_max_size: None,
-            connect_timeout: None,
-            server_selection_timeout: None,
-            log_level: None,
-            bind_address: "127.0.0.1:3000".into(),
+            ..test_config()
         };
         let state = AppState::new(client, &config);
         let payload = NamespacePayload {
             database: "   ".into(),
             collection: "   ".into(),
+            cluster: None,
+            consistency: Default::default(),
         };
 
         let collection = state.collection(&payload).expect("collection handle");
@@ -190,25 +695,19 @@ mod tests {
         let client = Client::with_uri_str("mongodb://localhost:27017")
             .await
             .expect("client");
-        let config = Config {
-            mongodb_uri: "mongodb://localhost:27017".into(),
-            default_database: None,
-            default_collection: None,
-            pool_min_size: None,
-            pool_max_size: None,
-            connect_timeout: None,
-            server_selection_timeout: None,
-            log_level: None,
-            bind_address: "127.0.0.1:3000".into(),
-        };
+        let config = test_config();
         let state = AppState::new(client, &config);
         let payload1 = NamespacePayload {
             database: "test_db".into(),
             collection: "test_coll".into(),
+            cluster: None,
+            consistency: Default::default(),
         };
         let payload2 = NamespacePayload {
             database: "test_db".into(),
             collection: "test_coll".into(),
+            cluster: None,
+            consistency: Default::default(),
         };
 
         let collection1 = state.collection(&payload1).expect("collection handle");
@@ -224,25 +723,19 @@ mod tests {
         let client = Client::with_uri_str("mongodb://localhost:27017")
             .await
             .expect("client");
-        let config = Config {
-            mongodb_uri: "mongodb://localhost:27017".into(),
-            default_database: None,
-            default_collection: None,
-            pool_min_size: None,
-            pool_max_size: None,
-            connect_timeout: None,
-            server_selection_timeout: None,
-            log_level: None,
-            bind_address: "127.0.0.1:3000".into(),
-        };
+        let config = test_config();
         let state = AppState::new(client, &config);
         let payload1 = NamespacePayload {
             database: "db1".into(),
             collection: "coll1".into(),
+            cluster: None,
+            consistency: Default::default(),
         };
         let payload2 = NamespacePayload {
             database: "db2".into(),
             collection: "coll2".into(),
+            cluster: None,
+            consistency: Default::default(),
         };
 
         let collection1 = state.collection(&payload1).expect("collection handle");
@@ -253,25 +746,180 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn collection_trims_whitespace() {
+    async fn checkout_collection_enforces_in_flight_limit() {
         let client = Client::with_uri_str("mongodb://localhost:27017")
             .await
             .expect("client");
         let config = Config {
-            mongodb_uri: "mongodb://localhost:27017".into(),
-            default_database: None,
-            default_collection: None,
-            pool_min_size: None,
-            pool_max_size: None,
-            connect_timeout: None,
-            server_selection_timeout: None,
-            log_level: None,
-            bind_address: "127.0.0.1:3000".into(),
+            limits: crate::config::LimitsConfig {
+                max_in_flight_per_namespace: Some(1),
+                ..Default::default()
+            },
+            ..test_config()
         };
         let state = AppState::new(client, &config);
+        let payload = NamespacePayload {
+            database: "app".into(),
+            collection: "users".into(),
+            cluster: None,
+            consistency: Default::default(),
+        };
+
+        let (_collection, guard) = state
+            .checkout_collection(&payload, None)
+            .expect("first slot");
+        let err = state
+            .checkout_collection(&payload, None)
+            .expect_err("expected in-flight limit");
+        assert_eq!(err.status().as_u16(), 429);
+        drop(guard);
+        assert!(state.checkout_collection(&payload, None).is_ok());
+    }
+
+    #[tokio::test]
+    async fn apply_config_swaps_reloadable_settings_only() {
+        let client = Client::with_uri_str("mongodb://localhost:27017")
+            .await
+            .expect("client");
+        let config = test_config();
+        let state = AppState::new(client, &config);
+        let payload = NamespacePayload {
+            database: "".into(),
+            collection: "".into(),
+            cluster: None,
+            consistency: Default::default(),
+        };
+        assert!(state.collection(&payload).is_err());
+        assert!(state.authenticate(None, None).is_ok());
+
+        let mut next = config.clone();
+        next.bind_address = "0.0.0.0:8080".into();
+        next.default_database = Some("app".into());
+        next.default_collection = Some("users".into());
+        next.auth.api_keys.push(crate::config::ApiKeyConfig {
+            key: "k-123".into(),
+            identity: "batch-job".into(),
+            roles: Vec::new(),
+        });
+        let changes = state.apply_config(next);
+        assert_eq!(changes.len(), 4);
+
+        assert_eq!(state.config().bind_address, "127.0.0.1:3000");
+        assert_eq!(state.collection(&payload).unwrap().name(), "users");
+        assert_eq!(
+            state
+                .authenticate(None, None)
+                .unwrap_err()
+                .status()
+                .as_u16(),
+            401
+        );
+        assert!(state.authenticate(Some("k-123"), None).unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn history_of_versioned_namespaces_is_not_readable() {
+        let client = Client::with_uri_str("mongodb://localhost:27017")
+            .await
+            .expect("client");
+        let namespaces: Vec<crate::config::NamespaceConfig> =
+            serde_json::from_value(serde_json::json!([{
+                "namespace": "app.users",
+                "versioned": true
+            }]))
+            .unwrap();
+        let config = Config {
+            namespaces,
+            ..test_config()
+        };
+        let state = AppState::new(client, &config);
+        let payload = |collection: &str| -> NamespacePayload {
+            serde_json::from_value(serde_json::json!({
+                "database": "app",
+                "collection": collection
+            }))
+            .unwrap()
+        };
+
+        let err = state
+            .checkout_collection(&payload("users_history"), None)
+            .expect_err("history collection");
+        assert_eq!(err.status().as_u16(), 403);
+        assert!(state
+            .checkout_collection(&payload("orders_history"), None)
+            .is_ok());
+
+        let users = payload("users");
+        for stage in [
+            doc! { "$unionWith": "users_history" },
+            doc! { "$lookup": { "from": { "db": "app", "coll": "users_history" }, "pipeline": [], "as": "h" } },
+            doc! { "$facet": { "h": [{ "$lookup": { "from": "users_history", "localField": "_id", "foreignField": "document_id", "as": "h" } }] } },
+        ] {
+            let err = state
+                .check_joins(&users, std::slice::from_ref(&stage), None)
+                .expect_err("join into history");
+            assert_eq!(err.status().as_u16(), 403, "{stage}");
+        }
+        state
+            .check_joins(&users, &[doc! { "$unionWith": "orders_history" }], None)
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn checkout_collection_applies_namespace_consistency() {
+        let client = Client::with_uri_str("mongodb://localhost:27017")
+            .await
+            .expect("client");
+        let namespaces: Vec<crate::config::NamespaceConfig> =
+            serde_json::from_value(serde_json::json!([{
+                "namespace": "app.orders",
+                "read_concern": "majority",
+                "min_write_concern": { "w": "majority" }
+            }]))
+            .unwrap();
+        let config = Config {
+            namespaces,
+            ..test_config()
+        };
+        let state = AppState::new(client, &config);
+
+        let payload: NamespacePayload = serde_json::from_value(serde_json::json!({
+            "database": "app",
+            "collection": "orders"
+        }))
+        .unwrap();
+        let (collection, _guard) = state.checkout_collection(&payload, None).unwrap();
+        assert_eq!(
+            collection.write_concern(),
+            Some(&mongodb::options::WriteConcern::MAJORITY)
+        );
+        assert!(collection.read_concern().is_some());
+
+        let payload: NamespacePayload = serde_json::from_value(serde_json::json!({
+            "database": "app",
+            "collection": "orders",
+            "read_preference": "secondary",
+            "write_concern": { "w": 1 }
+        }))
+        .unwrap();
+        let err = state
+            .checkout_collection(&payload, None)
+            .expect_err("downgraded write concern");
+        assert_eq!(err.status().as_u16(), 400);
+    }
+
+    #[tokio::test]
+    async fn collection_trims_whitespace() {
+        let client = Client::with_uri_str("mongodb://localhost:27017")
+            .await
+            .expect("client");
+        let config = test_config();
+        let state = AppState::new(client, &config);
         let payload = NamespacePayload {
             database: "  test_db  ".into(),
             collection: "  test_coll  ".into(),
+            cluster: None,
+            consistency: Default::default(),
         };
 
         let collection = state.collection(&payload).expect("collection handle");
@@ -0,0 +1,548 @@
+use axum::extract::ConnectInfo;
+use axum::Router;
+use futures::stream::{self, Stream};
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::GracefulShutdown;
+use std::future::Future;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::crypto::{ring, CryptoProvider};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{self, RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+
+use crate::auth::ClientCertificate;
+use crate::config::TlsConfig;
+
+/// How often certificate files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Connections that have not finished the handshake by then are dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Handshaken connections [`incoming`] holds before the server takes them.
+const ACCEPT_BACKLOG: usize = 64;
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("failed to read `{}`: {source}", path.display())]
+    Read { path: PathBuf, source: io::Error },
+    #[error("no certificates found in `{}`", .0.display())]
+    NoCertificates(PathBuf),
+    #[error("no private key found in `{}`", .0.display())]
+    NoPrivateKey(PathBuf),
+    #[error("invalid client CA bundle `{}`: {message}", path.display())]
+    ClientCa { path: PathBuf, message: String },
+    #[error("invalid TLS configuration: {0}")]
+    Rustls(#[from] rustls::Error),
+}
+
+/// Builds the rustls server config for `settings`, reading every file afresh.
+pub fn server_config(settings: &TlsConfig) -> Result<Arc<ServerConfig>, TlsError> {
+    let provider = Arc::new(ring::default_provider());
+    let certs = read_certs(&settings.cert_path)?;
+    let key = read_key(&settings.key_path)?;
+
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = match &settings.client_ca_path {
+        Some(path) => builder.with_client_cert_verifier(client_verifier(path, settings, provider)?),
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+fn client_verifier(
+    path: &Path,
+    settings: &TlsConfig,
+    provider: Arc<CryptoProvider>,
+) -> Result<Arc<dyn rustls::server::danger::ClientCertVerifier>, TlsError> {
+    let mut roots = RootCertStore::empty();
+    for cert in read_certs(path)? {
+        roots.add(cert).map_err(|err| TlsError::ClientCa {
+            path: path.to_path_buf(),
+            message: err.to_string(),
+        })?;
+    }
+    let builder = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+    let builder = if settings.client_cert_required {
+        builder
+    } else {
+        builder.allow_unauthenticated()
+    };
+    builder.build().map_err(|err| TlsError::ClientCa {
+        path: path.to_path_buf(),
+        message: err.to_string(),
+    })
+}
+
+fn open(path: &Path) -> Result<BufReader<std::fs::File>, TlsError> {
+    std::fs::File::open(path)
+        .map(BufReader::new)
+        .map_err(|source| TlsError::Read {
+            path: path.to_path_buf(),
+            source,
+        })
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let certs = rustls_pemfile::certs(&mut open(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|source| TlsError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificates(path.to_path_buf()));
+    }
+    Ok(certs)
+}
+
+fn read_key(path: &Path) -> Result<PrivateKeyDer<'static>, TlsError> {
+    rustls_pemfile::private_key(&mut open(path)?)
+        .map_err(|source| TlsError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?
+        .ok_or_else(|| TlsError::NoPrivateKey(path.to_path_buf()))
+}
+
+/// Hands out acceptors for the current certificate, which is swapped when the
+/// files on disk change. Connections keep the certificate they started with.
+pub struct ReloadingAcceptor {
+    settings: TlsConfig,
+    current: RwLock<Arc<ServerConfig>>,
+}
+
+impl ReloadingAcceptor {
+    pub fn new(settings: TlsConfig) -> Result<Self, TlsError> {
+        let current = RwLock::new(server_config(&settings)?);
+        Ok(Self { settings, current })
+    }
+
+    pub fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(
+            self.current
+                .read()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .clone(),
+        )
+    }
+
+    /// Re-reads the certificate, key and client CA. On error the running
+    /// certificate stays in place.
+    pub fn reload(&self) -> Result<(), TlsError> {
+        let config = server_config(&self.settings)?;
+        *self
+            .current
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = config;
+        Ok(())
+    }
+
+    /// Polls the certificate files and reloads when any of them changes.
+    pub fn spawn_watcher(self: Arc<Self>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            let mut last_modified = self.modified();
+            loop {
+                interval.tick().await;
+                let current = self.modified();
+                if current == last_modified {
+                    continue;
+                }
+                last_modified = current;
+                match self.reload() {
+                    Ok(()) => tracing::info!(
+                        target = "tls",
+                        cert = %self.settings.cert_path.display(),
+                        "certificate reloaded"
+                    ),
+                    Err(err) => tracing::error!(
+                        target = "tls",
+                        error = %err,
+                        "certificate reload failed; keeping the running certificate"
+                    ),
+                }
+            }
+        })
+    }
+
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        [
+            Some(&self.settings.cert_path),
+            Some(&self.settings.key_path),
+            self.settings.client_ca_path.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|path| {
+            std::fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+        .collect()
+    }
+}
+
+/// Serves `app` over TLS until `shutdown` resolves, then waits for open
+/// connections to finish. Requests carry the peer address as [`ConnectInfo`]
+/// and, with mutual TLS, the verified [`ClientCertificate`].
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    tls: Arc<ReloadingAcceptor>,
+    shutdown: impl Future<Output = ()>,
+) -> io::Result<()> {
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(shutdown);
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    tracing::warn!(target = "tls", error = %err, "failed to accept connection");
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+        let acceptor = tls.acceptor();
+        let app = app.clone();
+        let watcher = graceful.watcher();
+        tokio::spawn(async move {
+            let Some(stream) = handshake(&acceptor, stream, peer).await else {
+                return;
+            };
+            let certificate = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(|cert| ClientCertificate::from_der(cert));
+
+            let service =
+                hyper::service::service_fn(move |mut request: hyper::Request<Incoming>| {
+                    request.extensions_mut().insert(ConnectInfo(peer));
+                    if let Some(certificate) = &certificate {
+                        request.extensions_mut().insert(certificate.clone());
+                    }
+                    app.clone().oneshot(request)
+                });
+            let builder = auto::Builder::new(TokioExecutor::new());
+            let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+            if let Err(err) = watcher.watch(connection.into_owned()).await {
+                tracing::debug!(target = "tls", %peer, error = %err, "connection closed with error");
+            }
+        });
+    }
+    drop(listener);
+    graceful.shutdown().await;
+    Ok(())
+}
+
+/// Accepts connections on `listener` and yields them once their TLS
+/// handshake completes, for servers that take a stream of connections such
+/// as the gRPC API. Handshakes run concurrently, so a slow client does not
+/// hold up the others. Accepting stops when the stream is dropped.
+pub fn incoming(
+    listener: TcpListener,
+    tls: Arc<ReloadingAcceptor>,
+) -> impl Stream<Item = io::Result<TlsStream<TcpStream>>> {
+    let (sender, receiver) = mpsc::channel(ACCEPT_BACKLOG);
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        tracing::warn!(target = "tls", error = %err, "failed to accept connection");
+                        continue;
+                    }
+                },
+                _ = sender.closed() => break,
+            };
+            let acceptor = tls.acceptor();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                if let Some(stream) = handshake(&acceptor, stream, peer).await {
+                    let _ = sender.send(Ok(stream)).await;
+                }
+            });
+        }
+    });
+    stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|stream| (stream, receiver))
+    })
+}
+
+async fn handshake(
+    acceptor: &TlsAcceptor,
+    stream: TcpStream,
+    peer: SocketAddr,
+) -> Option<TlsStream<TcpStream>> {
+    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+        Ok(Ok(stream)) => Some(stream),
+        Ok(Err(err)) => {
+            tracing::debug!(target = "tls", %peer, error = %err, "TLS handshake failed");
+            None
+        }
+        Err(_) => {
+            tracing::debug!(target = "tls", %peer, "TLS handshake timed out");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{test_config, AuthConfig, ClientCertConfig, Config};
+    use crate::grpc::{self, proto, proto::documents_client::DocumentsClient};
+    use crate::state::AppState;
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+    use std::net::SocketAddrV4;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::rustls::pki_types::ServerName;
+    use tokio_rustls::rustls::ClientConfig;
+    use tokio_rustls::TlsConnector;
+    use tonic::transport::{Certificate, Channel, ClientTlsConfig};
+    use tonic::Code;
+
+    struct Issuer {
+        cert: rcgen::Certificate,
+        key: KeyPair,
+    }
+
+    fn certificate_authority() -> Issuer {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "test ca");
+        let key = KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        Issuer { cert, key }
+    }
+
+    /// Returns the PEM certificate and key for a leaf signed by `issuer`.
+    fn leaf(issuer: &Issuer, common_name: &str) -> (String, String) {
+        let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, common_name);
+        let key = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &issuer.cert, &issuer.key).unwrap();
+        (cert.pem(), key.serialize_pem())
+    }
+
+    fn write_files(name: &str, issuer: &Issuer) -> TlsConfig {
+        let dir =
+            std::env::temp_dir().join(format!("hello_rust_{}_tls_{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert, key) = leaf(issuer, "gateway");
+        std::fs::write(dir.join("server.crt"), cert).unwrap();
+        std::fs::write(dir.join("server.key"), key).unwrap();
+        std::fs::write(dir.join("ca.crt"), issuer.cert.pem()).unwrap();
+        TlsConfig {
+            cert_path: dir.join("server.crt"),
+            key_path: dir.join("server.key"),
+            client_ca_path: Some(dir.join("ca.crt")),
+            client_cert_required: true,
+        }
+    }
+
+    fn client_config(issuer: &Issuer, identity: Option<(String, String)>) -> Arc<ClientConfig> {
+        let mut roots = RootCertStore::empty();
+        roots.add(issuer.cert.der().clone()).unwrap();
+        let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let config = match identity {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(
+                    rustls_pemfile::certs(&mut cert.as_bytes())
+                        .collect::<Result<_, _>>()
+                        .unwrap(),
+                    rustls_pemfile::private_key(&mut key.as_bytes())
+                        .unwrap()
+                        .unwrap(),
+                )
+                .unwrap(),
+            None => builder.with_no_client_auth(),
+        };
+        Arc::new(config)
+    }
+
+    /// Sends one HTTP/1.1 request and returns the raw response, or the error
+    /// that ended the connection.
+    async fn send(
+        addr: SocketAddrV4,
+        client: Arc<ClientConfig>,
+        request: &str,
+    ) -> io::Result<String> {
+        let stream = tokio::net::TcpStream::connect(addr).await?;
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let mut stream = TlsConnector::from(client)
+            .connect(server_name, stream)
+            .await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        Ok(response)
+    }
+
+    /// Gateway state for `settings` that maps the `reporting` certificate.
+    async fn state(settings: &TlsConfig) -> AppState {
+        let client = mongodb::Client::with_uri_str("mongodb://localhost:27017")
+            .await
+            .expect("client");
+        let config = Config {
+            bind_address: "127.0.0.1:0".into(),
+            tls: Some(settings.clone()),
+            auth: AuthConfig {
+                api_keys: Vec::new(),
+                client_certs: vec![ClientCertConfig {
+                    subject: "reporting".into(),
+                    identity: "reporting-service".into(),
+                    roles: Vec::new(),
+                }],
+            },
+            ..test_config()
+        };
+        AppState::new(client, &config)
+    }
+
+    async fn start(settings: TlsConfig) -> SocketAddrV4 {
+        let app = crate::routes::router(state(&settings).await);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = match listener.local_addr().unwrap() {
+            std::net::SocketAddr::V4(addr) => addr,
+            other => panic!("unexpected address {other}"),
+        };
+        let acceptor = Arc::new(ReloadingAcceptor::new(settings).unwrap());
+        tokio::spawn(serve(listener, app, acceptor, std::future::pending()));
+        addr
+    }
+
+    const INSERT: &str = "POST /api/v1/documents/insert-many HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: 54\r\nConnection: close\r\n\r\n{\"database\":\"app\",\"collection\":\"users\",\"documents\":[]}";
+
+    #[tokio::test]
+    async fn mutual_tls_maps_client_certificates_to_identities() {
+        let issuer = certificate_authority();
+        let addr = start(write_files("mtls", &issuer)).await;
+
+        // Authenticated by certificate, so the request reaches validation
+        let mapped = client_config(&issuer, Some(leaf(&issuer, "reporting")));
+        let response = send(addr, mapped, INSERT).await.expect("response");
+        assert!(response.starts_with("HTTP/1.1 400"), "{response}");
+
+        let unmapped = client_config(&issuer, Some(leaf(&issuer, "intruder")));
+        let response = send(addr, unmapped, INSERT).await.expect("response");
+        assert!(response.starts_with("HTTP/1.1 401"), "{response}");
+        assert!(response.contains("CN=intruder"), "{response}");
+
+        // Required client certificates are enforced during the handshake
+        let anonymous = client_config(&issuer, None);
+        let refused = send(addr, anonymous, INSERT).await;
+        assert!(!refused.is_ok_and(|response| response.starts_with("HTTP/1.1")));
+
+        // Certificates from another CA are refused as well
+        let stranger = certificate_authority();
+        let foreign = client_config(&issuer, Some(leaf(&stranger, "reporting")));
+        let refused = send(addr, foreign, INSERT).await;
+        assert!(!refused.is_ok_and(|response| response.starts_with("HTTP/1.1")));
+    }
+
+    /// Calls gRPC `Insert` with no documents as `common_name`, which fails
+    /// validation once the caller is authenticated.
+    async fn grpc_insert(addr: SocketAddr, issuer: &Issuer, common_name: &str) -> Code {
+        let (cert, key) = leaf(issuer, common_name);
+        let tls = ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(issuer.cert.pem()))
+            .identity(tonic::transport::Identity::from_pem(cert, key))
+            .domain_name("localhost");
+        let channel = Channel::from_shared(format!("https://{addr}"))
+            .unwrap()
+            .tls_config(tls)
+            .unwrap()
+            .connect()
+            .await
+            .expect("TLS connection");
+        let namespace = proto::Namespace {
+            database: "app".into(),
+            collection: "users".into(),
+            cluster: None,
+        };
+        DocumentsClient::new(channel)
+            .insert(proto::InsertRequest {
+                namespace: Some(namespace),
+                documents: Vec::new(),
+            })
+            .await
+            .expect_err("empty insert")
+            .code()
+    }
+
+    #[tokio::test]
+    async fn grpc_uses_tls_and_maps_client_certificates() {
+        let issuer = certificate_authority();
+        let settings = write_files("grpc", &issuer);
+        let state = state(&settings).await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let plaintext = grpc::serve(state.clone(), listener, None, std::future::pending()).await;
+        assert!(plaintext.is_err());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = Arc::new(ReloadingAcceptor::new(settings).unwrap());
+        tokio::spawn(grpc::serve(
+            state,
+            listener,
+            Some(acceptor),
+            std::future::pending(),
+        ));
+        assert_eq!(
+            grpc_insert(addr, &issuer, "reporting").await,
+            Code::InvalidArgument
+        );
+        assert_eq!(
+            grpc_insert(addr, &issuer, "intruder").await,
+            Code::Unauthenticated
+        );
+    }
+
+    #[test]
+    fn reload_swaps_certificate_and_keeps_it_on_error() {
+        let issuer = certificate_authority();
+        let settings = write_files("reload", &issuer);
+        let acceptor = ReloadingAcceptor::new(settings.clone()).unwrap();
+        let current = |acceptor: &ReloadingAcceptor| acceptor.current.read().unwrap().clone();
+        let original = current(&acceptor);
+
+        let (cert, key) = leaf(&issuer, "gateway-rotated");
+        std::fs::write(&settings.cert_path, cert).unwrap();
+        std::fs::write(&settings.key_path, key).unwrap();
+        acceptor.reload().expect("reload");
+        let rotated = current(&acceptor);
+        assert!(!Arc::ptr_eq(&original, &rotated));
+
+        std::fs::write(&settings.key_path, "not a key").unwrap();
+        assert!(matches!(acceptor.reload(), Err(TlsError::NoPrivateKey(_))));
+        assert!(Arc::ptr_eq(&rotated, &current(&acceptor)));
+    }
+}
@@ -23,36 +23,34 @@ pub async fn mongodb_available() -> bool {
     match Client::with_uri_str(&uri).await {
         Ok(client) => {
             // Try to ping the server
-            match client
+            client
                 .database("admin")
                 .run_command(mongodb::bson::doc! { "ping": 1 }, None)
                 .await
-            {
-                Ok(_) => true,
-                Err(_) => false,
-            }
+                .is_ok()
         }
         Err(_) => false,
     }
 }
 
 pub async fn test_state() -> AppState {
-    let uri = mongodb_test_uri();
-    let client = Client::with_uri_str(&uri)
+    test_state_with(test_config()).await
+}
+
+pub async fn test_state_with(config: Config) -> AppState {
+    let client = Client::with_uri_str(&config.mongodb_uri)
         .await
         .expect("failed to create MongoDB client");
-    let config = Config {
-        mongodb_uri: uri.clone(),
+    AppState::new(client, &config)
+}
+
+pub fn test_config() -> Config {
+    Config {
+        mongodb_uri: mongodb_test_uri(),
         default_database: Some("test_db".into()),
         default_collection: Some("test_coll".into()),
-        pool_min_size: None,
-        pool_max_size: None,
-        connect_timeout: None,
-        server_selection_timeout: None,
-        log_level: None,
-        bind_address: "127.0.0.1:3000".into(),
-    };
-    AppState::new(client, &config)
+        ..Default::default()
+    }
 }
 
 pub fn unique_database() -> String {
@@ -2,8 +2,14 @@ mod common;
 
 use axum::body::Body;
 use axum::http::{Request, StatusCode};
+use hello_rust::config::{
+    FieldPolicyConfig, GraphqlReferenceConfig, GraphqlTypeConfig, NamespaceConfig,
+};
+use hello_rust::grpc;
+use hello_rust::grpc::proto::{self, documents_client::DocumentsClient};
 use hello_rust::routes;
 use serde_json::json;
+use std::num::NonZeroU32;
 use tower::ServiceExt;
 
 // Macro to skip tests if MongoDB is not available
@@ -912,8 +918,1147 @@ async fn test_find_many_with_options() {
     assert_eq!(response["documents"].as_array().unwrap().len(), 2);
 }
 
+#[tokio::test]
+async fn test_find_many_reports_implied_limit() {
+    skip_if_no_mongodb!();
+    let db = common::unique_database();
+    let coll = common::unique_collection();
+    let mut config = common::test_config();
+    config.query_policy.max_limit = Some(2);
+    let app = routes::router(common::test_state_with(config).await);
+    let post = |payload: serde_json::Value| {
+        Request::builder()
+            .uri("/api/v1/documents/find-many")
+            .method("POST")
+            .header("content-type", "application/json")
+            .body(Body::from(payload.to_string()))
+            .unwrap()
+    };
+
+    let response = app
+        .clone()
+        .oneshot(post(json!({ "database": db, "collection": coll })))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("x-limit-applied").unwrap(), "2");
+
+    let response = app
+        .clone()
+        .oneshot(post(
+            json!({ "database": db, "collection": coll, "options": { "limit": 1 } }),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("x-limit-applied").is_none());
+
+    let response = app
+        .oneshot(post(
+            json!({ "database": db, "collection": coll, "options": { "limit": 0 } }),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_bson_insert_many_and_streamed_find_many() {
+    skip_if_no_mongodb!();
+    let state = common::test_state().await;
+    let app = routes::router(state);
+    let db = common::unique_database();
+    let coll = common::unique_collection();
+
+    let insert_payload = mongodb::bson::to_vec(&mongodb::bson::doc! {
+        "database": &db,
+        "collection": &coll,
+        "documents": [
+            { "name": "a", "value": 1_i64 },
+            { "name": "b", "value": 2_i64 }
+        ]
+    })
+    .unwrap();
+
+    let insert_response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/documents/insert-many")
+                .method("POST")
+                .header("content-type", "application/bson")
+                .header("accept", "application/bson")
+                .body(Body::from(insert_payload))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(insert_response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(insert_response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: mongodb::bson::Document = mongodb::bson::from_slice(&body).unwrap();
+    assert_eq!(response.get_array("inserted_ids").unwrap().len(), 2);
+
+    let find_payload = json!({
+        "database": db,
+        "collection": coll,
+        "filter": {},
+        "options": { "sort": { "value": 1 } }
+    });
+
+    let find_response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/documents/find-many")
+                .method("POST")
+                .header("content-type", "application/json")
+                .header("accept", "application/bson")
+                .body(Body::from(find_payload.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(find_response.status(), StatusCode::OK);
+    assert_eq!(
+        find_response.headers().get("content-type").unwrap(),
+        "application/bson"
+    );
+    let body = axum::body::to_bytes(find_response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let mut reader = std::io::Cursor::new(body.to_vec());
+    let mut values = Vec::new();
+    while (reader.position() as usize) < reader.get_ref().len() {
+        let document = mongodb::bson::Document::from_reader(&mut reader).unwrap();
+        // Integer types survive the round trip without JSON's number coercion
+        values.push(document.get_i64("value").unwrap());
+    }
+    assert_eq!(values, vec![1, 2]);
+}
+
+#[tokio::test]
+async fn test_dry_run_explain_and_count() {
+    skip_if_no_mongodb!();
+    let state = common::test_state().await;
+    let app = routes::router(state);
+    let db = common::unique_database();
+    let coll = common::unique_collection();
+
+    let insert_payload = json!({
+        "database": db,
+        "collection": coll,
+        "documents": [
+            { "status": "stale", "value": 1 },
+            { "status": "stale", "value": 2 },
+            { "status": "fresh", "value": 3 }
+        ]
+    });
+    let insert_response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/documents/insert-many")
+                .method("POST")
+                .header("content-type", "application/json")
+                .body(Body::from(insert_payload.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(insert_response.status(), StatusCode::OK);
+
+    // Dry run reports the match count without deleting
+    let dry_run_payload = json!({
+        "database": db,
+        "collection": coll,
+        "filter": { "status": "stale" },
+        "dry_run": true
+    });
+    let dry_run_response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/documents/delete-many")
+                .method("POST")
+                .header("content-type", "application/json")
+                .body(Body::from(dry_run_payload.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(dry_run_response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(dry_run_response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(response["dry_run"], true);
+    assert_eq!(response["matched_count"], 2);
+
+    // Explain returns the query plan
+    let explain_payload = json!({
+        "database": db,
+        "collection": coll,
+        "filter": { "status": "stale" },
+        "update": { "$set": { "status": "archived" } },
+        "explain": "queryPlanner"
+    });
+    let explain_response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/documents/update-many")
+                .method("POST")
+                .header("content-type", "application/json")
+                .body(Body::from(explain_payload.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(explain_response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(explain_response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert!(response["plan"]["queryPlanner"].is_object());
+
+    // Nothing was modified by the dry run or the explain
+    let count_payload = json!({
+        "database": db,
+        "collection": coll,
+        "filter": { "status": "stale" }
+    });
+    let count_response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/documents/count")
+                .method("POST")
+                .header("content-type", "application/json")
+                .body(Body::from(count_payload.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(count_response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(count_response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(response["count"], 2);
+}
+
+#[tokio::test]
+async fn test_etag_conditional_find_and_replace() {
+    skip_if_no_mongodb!();
+    let state = common::test_state().await;
+    let app = routes::router(state);
+    let db = common::unique_database();
+    let coll = common::unique_collection();
+    let post = |uri: &str, header: Option<(&str, &str)>, payload: serde_json::Value| {
+        let mut builder = Request::builder()
+            .uri(uri)
+            .method("POST")
+            .header("content-type", "application/json");
+        if let Some((name, value)) = header {
+            builder = builder.header(name, value);
+        }
+        builder.body(Body::from(payload.to_string())).unwrap()
+    };
+
+    let insert =
+        json!({ "database": db, "collection": coll, "document": { "sku": "a1", "stock": 5 } });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/insert-one", None, insert))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let find = json!({ "database": db, "collection": coll, "filter": { "sku": "a1" } });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/find-one", None, find.clone()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response.headers()["etag"].to_str().unwrap().to_string();
+
+    let response = app
+        .clone()
+        .oneshot(post(
+            "/api/v1/documents/find-one",
+            Some(("if-none-match", &etag)),
+            find,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    let replace = json!({
+        "database": db,
+        "collection": coll,
+        "filter": { "sku": "a1" },
+        "replacement": { "sku": "a1", "stock": 4 }
+    });
+    let response = app
+        .clone()
+        .oneshot(post(
+            "/api/v1/documents/replace-one",
+            Some(("if-match", &etag)),
+            replace.clone(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // The second writer still holds the old version
+    let response = app
+        .oneshot(post(
+            "/api/v1/documents/replace-one",
+            Some(("if-match", &etag)),
+            replace,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+}
+
+#[tokio::test]
+async fn test_versioned_updates_keep_history_and_restore() {
+    skip_if_no_mongodb!();
+    let db = common::unique_database();
+    let coll = common::unique_collection();
+    let mut config = common::test_config();
+    config.namespaces.push(NamespaceConfig {
+        namespace: format!("{db}.{coll}"),
+        read_preference: None,
+        read_concern: None,
+        write_concern: None,
+        min_write_concern: None,
+        version_field: None,
+        versioned: true,
+        max_versioned_documents: NonZeroU32::new(2),
+        soft_delete: false,
+        timestamps: false,
+    });
+    let app = routes::router(common::test_state_with(config).await);
+    let post = |uri: &str, payload: serde_json::Value| {
+        Request::builder()
+            .uri(uri)
+            .method("POST")
+            .header("content-type", "application/json")
+            .body(Body::from(payload.to_string()))
+            .unwrap()
+    };
+
+    let insert =
+        json!({ "database": db, "collection": coll, "document": { "_id": "a1", "stock": 5 } });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/insert-one", insert))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    for stock in [4, 3] {
+        let update = json!({
+            "database": db,
+            "collection": coll,
+            "filter": { "_id": "a1" },
+            "update": { "$set": { "stock": stock } }
+        });
+        let response = app
+            .clone()
+            .oneshot(post("/api/v1/documents/update-one", update))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let history = json!({ "database": db, "collection": coll, "id": "a1" });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/history", history.clone()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["current_version"], 2);
+    let versions = body["versions"].as_array().unwrap();
+    assert_eq!(versions.len(), 2);
+    assert_eq!(versions[0]["version"], 1);
+    assert_eq!(versions[0]["document"]["stock"], 4);
+    assert_eq!(versions[1]["document"]["stock"], 5);
+
+    let restore = json!({ "database": db, "collection": coll, "id": "a1", "version": 0 });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/restore-version", restore))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let find = json!({ "database": db, "collection": coll, "filter": { "_id": "a1" } });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/find-one", find))
+        .await
+        .unwrap();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["document"]["stock"], 5);
+    assert_eq!(body["document"]["_version"], 3);
+
+    let find_history = json!({ "database": db, "collection": format!("{coll}_history") });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/find-many", find_history))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let insert = json!({
+        "database": db,
+        "collection": coll,
+        "documents": [{ "_id": "b1", "stock": 1 }, { "_id": "c1", "stock": 1 }]
+    });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/insert-many", insert))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let update = json!({
+        "database": db,
+        "collection": coll,
+        "filter": {},
+        "update": { "$inc": { "stock": 1 } }
+    });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/update-many", update))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let missing = json!({ "database": db, "collection": coll, "id": "a1", "version": 9 });
+    let response = app
+        .oneshot(post("/api/v1/documents/restore-version", missing))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_soft_delete_hides_restores_and_purges() {
+    skip_if_no_mongodb!();
+    let db = common::unique_database();
+    let coll = common::unique_collection();
+    let mut config = common::test_config();
+    config.namespaces.push(NamespaceConfig {
+        namespace: format!("{db}.{coll}"),
+        read_preference: None,
+        read_concern: None,
+        write_concern: None,
+        min_write_concern: None,
+        version_field: None,
+        versioned: false,
+        max_versioned_documents: None,
+        soft_delete: true,
+        timestamps: false,
+    });
+    let app = routes::router(common::test_state_with(config).await);
+    let post = |uri: &str, payload: serde_json::Value| {
+        Request::builder()
+            .uri(uri)
+            .method("POST")
+            .header("content-type", "application/json")
+            .body(Body::from(payload.to_string()))
+            .unwrap()
+    };
+    let count = |include_deleted: bool| json!({ "database": db, "collection": coll, "filter": {}, "include_deleted": include_deleted });
+    let read_count = |response: axum::response::Response| async move {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        body["count"].as_u64().unwrap()
+    };
+
+    let insert = json!({
+        "database": db,
+        "collection": coll,
+        "documents": [{ "sku": "a1" }, { "sku": "b2" }]
+    });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/insert-many", insert))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let delete = json!({ "database": db, "collection": coll, "filter": { "sku": "a1" } });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/delete-one", delete.clone()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    // Already deleted, so nothing matches the second time
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/delete-one", delete))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/count", count(false)))
+        .await
+        .unwrap();
+    assert_eq!(read_count(response).await, 1);
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/count", count(true)))
+        .await
+        .unwrap();
+    assert_eq!(read_count(response).await, 2);
+
+    let restore = json!({ "database": db, "collection": coll, "filter": { "sku": "a1" } });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/restore", restore))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/count", count(false)))
+        .await
+        .unwrap();
+    assert_eq!(read_count(response).await, 2);
+
+    let delete_all =
+        json!({ "database": db, "collection": coll, "filter": {}, "confirm_all": true });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/delete-many", delete_all))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let purge = json!({ "database": db, "collection": coll, "older_than_days": 0 });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/purge", purge))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .oneshot(post("/api/v1/documents/count", count(true)))
+        .await
+        .unwrap();
+    assert_eq!(read_count(response).await, 0);
+}
+
+#[tokio::test]
+async fn test_timestamps_survive_updates_and_replaces() {
+    skip_if_no_mongodb!();
+    let db = common::unique_database();
+    let coll = common::unique_collection();
+    let mut config = common::test_config();
+    config.namespaces.push(NamespaceConfig {
+        namespace: format!("{db}.{coll}"),
+        read_preference: None,
+        read_concern: None,
+        write_concern: None,
+        min_write_concern: None,
+        version_field: None,
+        versioned: false,
+        max_versioned_documents: None,
+        soft_delete: false,
+        timestamps: true,
+    });
+    let app = routes::router(common::test_state_with(config).await);
+    let post = |uri: &str, payload: serde_json::Value| {
+        Request::builder()
+            .uri(uri)
+            .method("POST")
+            .header("content-type", "application/json")
+            .body(Body::from(payload.to_string()))
+            .unwrap()
+    };
+    let find = json!({ "database": db, "collection": coll, "filter": { "_id": "a1" } });
+    let read = |response: axum::response::Response| async move {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        body["document"].clone()
+    };
+
+    let insert = json!({
+        "database": db,
+        "collection": coll,
+        "document": { "_id": "a1", "stock": 5, "createdAt": "forged" }
+    });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/insert-one", insert))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/find-one", find.clone()))
+        .await
+        .unwrap();
+    let inserted = read(response).await;
+    assert_ne!(inserted["createdAt"], json!("forged"));
+    assert!(inserted.get("updatedAt").is_none());
+
+    let update = json!({
+        "database": db,
+        "collection": coll,
+        "filter": { "_id": "a1" },
+        "update": { "$set": { "stock": 4, "updatedAt": "forged" } }
+    });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/update-one", update))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/find-one", find.clone()))
+        .await
+        .unwrap();
+    let updated = read(response).await;
+    assert_eq!(updated["stock"], 4);
+    assert_ne!(updated["updatedAt"], json!("forged"));
+
+    let replace = json!({
+        "database": db,
+        "collection": coll,
+        "filter": { "_id": "a1" },
+        "replacement": { "stock": 3 }
+    });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/replace-one", replace))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .oneshot(post("/api/v1/documents/find-one", find))
+        .await
+        .unwrap();
+    let replaced = read(response).await;
+    assert_eq!(replaced["stock"], 3);
+    assert_eq!(replaced["createdAt"], inserted["createdAt"]);
+    assert!(replaced.get("updatedAt").is_some());
+}
+
+#[tokio::test]
+async fn test_gridfs_upload_download_find_and_delete() {
+    skip_if_no_mongodb!();
+    let app = routes::router(common::test_state().await);
+    let db = common::unique_database();
+    let query = format!("database={db}&bucket=attachments");
+    let read_json = |response: axum::response::Response| async move {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+    };
+
+    let form = "--b0undary\r\n\
+         Content-Disposition: form-data; name=\"metadata\"\r\n\r\n\
+         {\"owner\":\"ada\"}\r\n--b0undary\r\n\
+         Content-Disposition: form-data; name=\"file\"; filename=\"notes.txt\"\r\n\
+         Content-Type: text/plain\r\n\r\n\
+         0123456789\r\n--b0undary--\r\n";
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("/api/v1/files/upload?{query}"))
+                .method("POST")
+                .header("content-type", "multipart/form-data; boundary=b0undary")
+                .body(Body::from(form))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let uploaded = read_json(response).await;
+    assert_eq!(uploaded["filename"], "notes.txt");
+    assert_eq!(uploaded["length"], 10);
+    let id = uploaded["id"]["$oid"].as_str().unwrap().to_string();
+
+    let download = |range: Option<&str>| {
+        let mut request = Request::builder()
+            .uri(format!("/api/v1/files/download?{query}&id={id}"))
+            .method("GET");
+        if let Some(range) = range {
+            request = request.header("range", range);
+        }
+        request.body(Body::empty()).unwrap()
+    };
+    let response = app.clone().oneshot(download(None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "text/plain");
+    assert_eq!(response.headers()["content-length"], "10");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(&body[..], b"0123456789");
+
+    let response = app
+        .clone()
+        .oneshot(download(Some("bytes=2-5")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()["content-range"], "bytes 2-5/10");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(&body[..], b"2345");
+
+    let response = app
+        .clone()
+        .oneshot(download(Some("bytes=10-")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+    let post = |uri: &str, payload: serde_json::Value| {
+        Request::builder()
+            .uri(uri)
+            .method("POST")
+            .header("content-type", "application/json")
+            .body(Body::from(payload.to_string()))
+            .unwrap()
+    };
+    let find = json!({
+        "database": db,
+        "bucket": "attachments",
+        "filter": { "metadata.owner": "ada" }
+    });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/files/find", find.clone()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        read_json(response).await["files"].as_array().unwrap().len(),
+        1
+    );
+
+    let delete = json!({ "database": db, "bucket": "attachments", "id": { "$oid": id } });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/files/delete", delete.clone()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/files/delete", delete))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = app.oneshot(post("/api/v1/files/find", find)).await.unwrap();
+    assert!(read_json(response).await["files"]
+        .as_array()
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_import_csv_and_upsert_ndjson() {
+    skip_if_no_mongodb!();
+    let app = routes::router(common::test_state().await);
+    let db = common::unique_database();
+    let coll = common::unique_collection();
+    let import = |query: &str, content_type: &str, body: &str| {
+        Request::builder()
+            .uri(format!(
+                "/api/v1/documents/import?database={db}&collection={coll}&{query}"
+            ))
+            .method("POST")
+            .header("content-type", content_type)
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+    let read_json = |response: axum::response::Response| async move {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+    };
+
+    let csv = "SKU,Stock,Zip\n\
+               a1,5,01234\n\
+               a2,seven,02139\n\
+               a1,1,00000\n\
+               a3,2,10001\n";
+    let response = app
+        .clone()
+        .oneshot(import(
+            "rename=SKU:_id,Stock:stock,Zip:zip&types=stock:int&batch_size=2",
+            "text/csv",
+            csv,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let summary = read_json(response).await;
+    assert_eq!(summary["inserted_count"], 2);
+    assert_eq!(summary["failed_count"], 2);
+    let lines: Vec<_> = summary["rejected"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|row| row["line"].as_u64().unwrap())
+        .collect();
+    assert_eq!(lines, vec![3, 4]);
+
+    let ndjson = "{\"_id\":\"a1\",\"stock\":9}\nnot json\n{\"_id\":\"a4\",\"stock\":1}\n";
+    let response = app
+        .clone()
+        .oneshot(import("upsert_key=_id", "application/x-ndjson", ndjson))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let summary = read_json(response).await;
+    assert_eq!(summary["inserted_count"], 1);
+    assert_eq!(summary["updated_count"], 1);
+    assert_eq!(summary["rejected"][0]["line"], 2);
+
+    let find = json!({ "database": db, "collection": coll, "filter": { "_id": "a1" } });
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/documents/find-one")
+                .method("POST")
+                .header("content-type", "application/json")
+                .body(Body::from(find.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let document = read_json(response).await["document"].clone();
+    assert_eq!(document["stock"], 9);
+    assert_eq!(document["zip"], "01234");
+}
+
+#[tokio::test]
+async fn test_export_streams_csv_and_ndjson() {
+    skip_if_no_mongodb!();
+    let app = routes::router(common::test_state().await);
+    let db = common::unique_database();
+    let coll = common::unique_collection();
+    let post = |uri: &str, payload: serde_json::Value| {
+        Request::builder()
+            .uri(uri)
+            .method("POST")
+            .header("content-type", "application/json")
+            .body(Body::from(payload.to_string()))
+            .unwrap()
+    };
+    let insert = json!({
+        "database": db,
+        "collection": coll,
+        "documents": [
+            { "_id": 1, "name": "Ada", "address": { "city": "London" } },
+            { "_id": 2, "name": "Grace", "address": { "city": "New York, NY" } },
+            { "_id": 3, "name": "Linus", "address": { "city": "Helsinki" } }
+        ]
+    });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/insert-many", insert))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let export = json!({
+        "database": db,
+        "collection": coll,
+        "filter": { "_id": { "$lte": 2 } },
+        "sort": { "_id": -1 },
+        "format": "csv",
+        "columns": ["name", "address.city"]
+    });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/export", export))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["content-disposition"],
+        format!("attachment; filename=\"{coll}.csv\"")
+    );
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(body.to_vec()).unwrap(),
+        "name,address.city\r\nGrace,\"New York, NY\"\r\nAda,London\r\n"
+    );
+
+    let export = json!({ "database": db, "collection": coll, "projection": { "name": 1 } });
+    let response = app
+        .oneshot(post("/api/v1/documents/export", export))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let lines: Vec<serde_json::Value> = String::from_utf8(body.to_vec())
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines.iter().all(|line| line.get("address").is_none()));
+}
+
+#[tokio::test]
+async fn test_graphql_queries_mutations_and_references() {
+    skip_if_no_mongodb!();
+    let db = common::unique_database();
+    let customers = common::unique_collection();
+    let orders = format!("{customers}_orders");
+    let client = mongodb::Client::with_uri_str(common::mongodb_test_uri())
+        .await
+        .unwrap();
+    client
+        .database(&db)
+        .collection::<mongodb::bson::Document>(&customers)
+        .insert_many(
+            vec![
+                mongodb::bson::doc! { "_id": 1, "name": "Ada", "vip": true },
+                mongodb::bson::doc! { "_id": 2, "name": "Grace", "vip": false },
+            ],
+            None,
+        )
+        .await
+        .unwrap();
+    let mut config = common::test_config();
+    config.graphql = vec![
+        GraphqlTypeConfig {
+            name: "Customer".into(),
+            namespace: format!("{db}.{customers}"),
+            cluster: None,
+            fields: Default::default(),
+            sample_size: 10,
+            references: Vec::new(),
+        },
+        GraphqlTypeConfig {
+            name: "Order".into(),
+            namespace: format!("{db}.{orders}"),
+            cluster: None,
+            fields: [
+                ("total".to_string(), "Float".to_string()),
+                ("customer_id".to_string(), "Int".to_string()),
+            ]
+            .into(),
+            sample_size: 100,
+            references: vec![GraphqlReferenceConfig {
+                field: "customer".into(),
+                from: "customer_id".into(),
+                target: "Customer".into(),
+                many: false,
+            }],
+        },
+    ];
+    let app = routes::router(common::test_state_with(config).await);
+    let graphql = |body: serde_json::Value| {
+        let app = app.clone();
+        async move {
+            let response = app
+                .oneshot(
+                    Request::builder()
+                        .uri("/graphql")
+                        .method("POST")
+                        .header("content-type", "application/json")
+                        .body(Body::from(body.to_string()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        }
+    };
+
+    let body = graphql(json!({
+        "query": "mutation { a: insertOrder(document: { _id: \"o10\", total: 5, customer_id: 1 }) { _id total } \
+                  b: insertOrder(document: { _id: \"o11\", total: 7.5, customer_id: 2 }) { _id } }"
+    }))
+    .await;
+    assert!(body.get("errors").is_none(), "{body}");
+    assert_eq!(body["data"]["a"], json!({ "_id": "o10", "total": 5.0 }));
+
+    let body = graphql(json!({
+        "query": "query($filter: JSON) { orderList(filter: $filter, sort: { total: -1 }) \
+                  { _id total customer { name vip } } }",
+        "variables": { "filter": { "total": { "$gt": 1 } } }
+    }))
+    .await;
+    assert!(body.get("errors").is_none(), "{body}");
+    assert_eq!(
+        body["data"]["orderList"],
+        json!([
+            { "_id": "o11", "total": 7.5, "customer": { "name": "Grace", "vip": false } },
+            { "_id": "o10", "total": 5.0, "customer": { "name": "Ada", "vip": true } }
+        ])
+    );
+
+    let body = graphql(json!({
+        "query": "mutation($update: JSON!) { updateOrder(filter: { _id: \"o10\" }, update: $update) \
+                  { matched_count modified_count } \
+                  deleteOrder(filter: { _id: \"o11\" }) { deleted_count } }",
+        "variables": { "update": { "$set": { "total": 6 } } }
+    }))
+    .await;
+    assert!(body.get("errors").is_none(), "{body}");
+    assert_eq!(body["data"]["updateOrder"]["modified_count"], 1);
+    assert_eq!(body["data"]["deleteOrder"]["deleted_count"], 1);
+
+    let body = graphql(json!({
+        "query": "{ order(id: \"o10\") { total } gone: order(id: \"o11\") { total } }"
+    }))
+    .await;
+    assert_eq!(
+        body["data"],
+        json!({ "order": { "total": 6.0 }, "gone": null })
+    );
+}
+
 // Cleanup test - runs last to clean up test databases
 // Named with 'zzz' prefix to ensure it runs last when tests execute sequentially
+#[tokio::test]
+async fn test_grpc_document_calls() {
+    skip_if_no_mongodb!();
+    let db = common::unique_database();
+    let coll = common::unique_collection();
+    let state = common::test_state().await;
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(grpc::serve(state, listener, None, async {
+        stopped.await.ok();
+    }));
+    let mut client = DocumentsClient::connect(format!("http://{address}"))
+        .await
+        .unwrap();
+    let namespace = || {
+        Some(proto::Namespace {
+            database: db.clone(),
+            collection: coll.clone(),
+            cluster: None,
+        })
+    };
+    let bson = |document: mongodb::bson::Document| mongodb::bson::to_vec(&document).unwrap();
+
+    let inserted = client
+        .insert(proto::InsertRequest {
+            namespace: namespace(),
+            documents: vec![
+                bson(mongodb::bson::doc! { "_id": 1, "sku": "a", "qty": 5 }),
+                bson(mongodb::bson::doc! { "_id": 2, "sku": "b", "qty": 0 }),
+            ],
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(inserted.inserted_ids.len(), 2);
+
+    let updated = client
+        .update(proto::UpdateRequest {
+            namespace: namespace(),
+            filter: bson(mongodb::bson::doc! { "_id": 2 }),
+            update: bson(mongodb::bson::doc! { "$set": { "qty": 3 } }),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(updated.modified_count, 1);
+
+    let mut found = client
+        .find(proto::FindRequest {
+            namespace: namespace(),
+            sort: bson(mongodb::bson::doc! { "_id": 1 }),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let mut quantities = Vec::new();
+    while let Some(document) = found.message().await.unwrap() {
+        let document: mongodb::bson::Document = mongodb::bson::from_slice(&document.bson).unwrap();
+        quantities.push(document.get_i32("qty").unwrap());
+    }
+    assert_eq!(quantities, vec![5, 3]);
+
+    let totals = client
+        .aggregate(proto::AggregateRequest {
+            namespace: namespace(),
+            pipeline: vec![bson(
+                mongodb::bson::doc! { "$group": { "_id": null, "total": { "$sum": "$qty" } } },
+            )],
+            include_deleted: false,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let total: mongodb::bson::Document = mongodb::bson::from_slice(&totals.documents[0]).unwrap();
+    assert_eq!(total.get_i32("total").unwrap(), 8);
+
+    let deleted = client
+        .delete(proto::DeleteRequest {
+            namespace: namespace(),
+            many: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(deleted.code(), tonic::Code::InvalidArgument);
+    let deleted = client
+        .delete(proto::DeleteRequest {
+            namespace: namespace(),
+            many: true,
+            confirm_all: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(deleted.deleted_count, 2);
+
+    stop.send(()).ok();
+    server.await.unwrap().unwrap();
+}
+
 #[tokio::test]
 async fn zzz_cleanup_test_databases() {
     skip_if_no_mongodb!();
@@ -921,3 +2066,90 @@ async fn zzz_cleanup_test_databases() {
     // This test should run last - use --test-threads=1 to ensure sequential execution
     common::cleanup_test_databases().await;
 }
+
+#[tokio::test]
+async fn test_etag_if_match_under_projection_and_field_policy() {
+    skip_if_no_mongodb!();
+    let db = common::unique_database();
+    let coll = common::unique_collection();
+    let mut config = common::test_config();
+    config.field_policies.push(FieldPolicyConfig {
+        namespace: format!("{db}.{coll}"),
+        roles: Vec::new(),
+        exclude: vec!["ssn".to_string()],
+        mask: Vec::new(),
+    });
+    let state = common::test_state_with(config).await;
+    let app = routes::router(state);
+    let post = |uri: &str, header: Option<(&str, &str)>, payload: serde_json::Value| {
+        let mut builder = Request::builder()
+            .uri(uri)
+            .method("POST")
+            .header("content-type", "application/json");
+        if let Some((name, value)) = header {
+            builder = builder.header(name, value);
+        }
+        builder.body(Body::from(payload.to_string())).unwrap()
+    };
+
+    let insert = json!({
+        "database": db,
+        "collection": coll,
+        "document": { "sku": "b2", "stock": 5, "ssn": "123-45-6789" }
+    });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/insert-one", None, insert))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // The excluded field and a client projection both shape the response,
+    // but not the tag
+    let find = json!({
+        "database": db,
+        "collection": coll,
+        "filter": { "sku": "b2" },
+        "options": { "projection": { "ssn": 0, "stock": 0 } }
+    });
+    let response = app
+        .clone()
+        .oneshot(post("/api/v1/documents/find-one", None, find))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response.headers()["etag"].to_str().unwrap().to_string();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert!(response["document"].get("ssn").is_none());
+    assert!(response["document"].get("stock").is_none());
+
+    let update = json!({
+        "database": db,
+        "collection": coll,
+        "filter": { "sku": "b2" },
+        "update": { "$inc": { "stock": -1 } }
+    });
+    let response = app
+        .clone()
+        .oneshot(post(
+            "/api/v1/documents/update-one",
+            Some(("if-match", &etag)),
+            update.clone(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .oneshot(post(
+            "/api/v1/documents/update-one",
+            Some(("if-match", &etag)),
+            update,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+}